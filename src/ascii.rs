@@ -0,0 +1,75 @@
+use crate::emulator::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// default number of characters per line of the ascii frame
+pub const DEFAULT_ASCII_WIDTH: usize = 80;
+
+// characters from the darkest to the lightest shade
+const ASCII_SHADES: [char; 4] = ['#', '+', '.', ' '];
+
+// terminal characters are about twice as high as wide
+const CHARACTER_ASPECT_RATIO: usize = 2;
+
+// convert a frame into lines of characters, each character is the average shade of a block of pixels
+// the width is clamped between 1 and the screen width
+pub fn frame_to_ascii(frame: &[u8], width: usize) -> String {
+    let width = width.clamp(1, SCREEN_WIDTH);
+    let height = (width * SCREEN_HEIGHT / (SCREEN_WIDTH * CHARACTER_ASPECT_RATIO)).max(1);
+
+    let mut ascii = String::with_capacity((width + 1) * height);
+
+    for row in 0..height {
+        let (y_begin, y_end) = (row * SCREEN_HEIGHT / height, (row + 1) * SCREEN_HEIGHT / height);
+
+        for column in 0..width {
+            let (x_begin, x_end) = (column * SCREEN_WIDTH / width, (column + 1) * SCREEN_WIDTH / width);
+
+            let mut sum = 0;
+            for y in y_begin..y_end {
+                for x in x_begin..x_end {
+                    sum += frame[y * SCREEN_WIDTH + x] as usize;
+                }
+            }
+            let shade = sum / ((y_end - y_begin) * (x_end - x_begin));
+
+            ascii.push(shade_to_char(shade as u8));
+        }
+        ascii.push('\n');
+    }
+
+    ascii
+}
+
+// the closest of the 4 gpu shades: 0, 96, 192 and 255
+fn shade_to_char(shade: u8) -> char {
+    match shade {
+        0..=47 => ASCII_SHADES[0],
+        48..=143 => ASCII_SHADES[1],
+        144..=223 => ASCII_SHADES[2],
+        _ => ASCII_SHADES[3],
+    }
+}
+
+#[cfg(test)]
+mod ascii_tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_to_ascii() {
+        // 4 vertical bands from black to white
+        let mut frame = [0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        for (i, shade) in frame.iter_mut().enumerate() {
+            *shade = [0, 96, 192, 255][(i % SCREEN_WIDTH) / (SCREEN_WIDTH / 4)];
+        }
+
+        assert_eq!(frame_to_ascii(&frame, 4), "#+. \n");
+        assert_eq!(frame_to_ascii(&frame, 8), "##++..  \n##++..  \n##++..  \n");
+
+        // a block mixing black and white is displayed as a gray
+        assert_eq!(frame_to_ascii(&frame, 2), "+.\n");
+
+        // the width can't exceed the screen width
+        let ascii = frame_to_ascii(&frame, 1000);
+        assert_eq!(ascii.lines().count(), SCREEN_HEIGHT / 2);
+        assert!(ascii.lines().all(|line| line.len() == SCREEN_WIDTH));
+    }
+}
@@ -1,4 +1,7 @@
+use std::io::{Read, Result, Write};
+
 use crate::cartridge::{MbcType, RomSize, RamSize, Mbc};
+use crate::savestate::{SaveState, read_bool, read_bytes, read_u8, write_bool, write_bytes, write_u8};
 
 const RAM_ENABLE_SPACE_START: u16 = 0x0000;
 const RAM_ENABLE_SPACE_END: u16 = 0x1FFF;
@@ -155,4 +158,35 @@ impl Mbc for Mbc1 {
 
     // not used for this mbc, doesn't do anything
     fn run (&mut self, _: u8) {}
+
+    fn get_ram(&self) -> &[u8] {
+        &self.ram_bank
+    }
+
+    fn get_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram_bank
+    }
+
+    fn banking(&self) -> String {
+        format!("MBC1 rom bank : {:#04x} / ram bank : {:#04x} / ram enabled : {} / banking mode : {}",
+            self.rom_bank_number, self.ram_bank_number, self.ram_enable, self.banking_mode as u8)
+    }
+}
+
+impl SaveState for Mbc1 {
+    fn save_state(&self, writer: &mut dyn Write) -> Result<()> {
+        write_bool(writer, self.ram_enable)?;
+        write_u8(writer, self.rom_bank_number)?;
+        write_u8(writer, self.ram_bank_number)?;
+        write_bool(writer, self.banking_mode)?;
+        write_bytes(writer, &self.ram_bank)
+    }
+
+    fn load_state(&mut self, reader: &mut dyn Read) -> Result<()> {
+        self.ram_enable = read_bool(reader)?;
+        self.rom_bank_number = read_u8(reader)?;
+        self.ram_bank_number = read_u8(reader)?;
+        self.banking_mode = read_bool(reader)?;
+        read_bytes(reader, &mut self.ram_bank)
+    }
 }
\ No newline at end of file
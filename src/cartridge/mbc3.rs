@@ -1,4 +1,7 @@
+use std::io::{Read, Result, Write};
+
 use crate::cartridge::{MbcType, RomSize, RamSize, Mbc};
+use crate::savestate::{SaveState, read_bool, read_bytes, read_u8, read_u64, write_bool, write_bytes, write_u8, write_u64};
 use crate::emulator::ONE_SECOND_IN_CYCLES;
 
 const RAM_ENABLE_SPACE_START: u16 = 0x0000;
@@ -255,4 +258,63 @@ impl Mbc for Mbc3 {
             self.latch_rtc_enable = false;
         }
     }
+
+    fn get_ram(&self) -> &[u8] {
+        &self.ram_bank
+    }
+
+    fn get_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram_bank
+    }
+
+    // ram banks 0x08 to 0x0C map the rtc registers
+    fn banking(&self) -> String {
+        format!("MBC3 rom bank : {:#04x} / ram bank : {:#04x} / ram enabled : {} / rtc halted : {}",
+            self.rom_bank_number, self.ram_bank_number, self.ram_enable, self.rtc_halt)
+    }
+}
+
+impl SaveState for Mbc3 {
+    fn save_state(&self, writer: &mut dyn Write) -> Result<()> {
+        write_bool(writer, self.ram_enable)?;
+        write_u8(writer, self.rom_bank_number)?;
+        write_u8(writer, self.ram_bank_number)?;
+        write_bytes(writer, &self.ram_bank)?;
+        write_bool(writer, self.latch_rtc_flag)?;
+        write_bool(writer, self.latch_rtc_enable)?;
+        write_u64(writer, self.rtc_cycles as u64)?;
+        write_u8(writer, self.rtc_sec)?;
+        write_u8(writer, self.rtc_min)?;
+        write_u8(writer, self.rtc_hours)?;
+        write_u8(writer, self.rtc_day_lo)?;
+        write_bool(writer, self.rtc_day_hi)?;
+        write_bool(writer, self.rtc_halt)?;
+        write_bool(writer, self.rtc_overflow)?;
+        write_u8(writer, self.rtc_sec_latch)?;
+        write_u8(writer, self.rtc_min_latch)?;
+        write_u8(writer, self.rtc_hours_latch)?;
+        write_u8(writer, self.rtc_day_latch)
+    }
+
+    fn load_state(&mut self, reader: &mut dyn Read) -> Result<()> {
+        self.ram_enable = read_bool(reader)?;
+        self.rom_bank_number = read_u8(reader)?;
+        self.ram_bank_number = read_u8(reader)?;
+        read_bytes(reader, &mut self.ram_bank)?;
+        self.latch_rtc_flag = read_bool(reader)?;
+        self.latch_rtc_enable = read_bool(reader)?;
+        self.rtc_cycles = read_u64(reader)? as usize;
+        self.rtc_sec = read_u8(reader)?;
+        self.rtc_min = read_u8(reader)?;
+        self.rtc_hours = read_u8(reader)?;
+        self.rtc_day_lo = read_u8(reader)?;
+        self.rtc_day_hi = read_bool(reader)?;
+        self.rtc_halt = read_bool(reader)?;
+        self.rtc_overflow = read_bool(reader)?;
+        self.rtc_sec_latch = read_u8(reader)?;
+        self.rtc_min_latch = read_u8(reader)?;
+        self.rtc_hours_latch = read_u8(reader)?;
+        self.rtc_day_latch = read_u8(reader)?;
+        Ok(())
+    }
 }
\ No newline at end of file
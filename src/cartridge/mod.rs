@@ -6,9 +6,32 @@ use rom::Rom;
 use mbc1::Mbc1;
 use mbc3::Mbc3;
 
+use std::io::{Read, Result, Write};
+
+use crate::savestate::{SaveState, invalid_data, read_bytes, read_u8, read_u16, write_bytes, write_u8, write_u16};
+
+pub const CARTRIDGE_TITLE_OFFSET: u16 = 0x134;
+pub const CARTRIDGE_TITLE_SIZE: usize = 16;
+pub const CARTRIDGE_CGB_FLAG_OFFSET: u16 = 0x143;
+pub const CARTRIDGE_NEW_LICENSEE_OFFSET: u16 = 0x144;
+pub const CARTRIDGE_SGB_FLAG_OFFSET: u16 = 0x146;
 pub const CARTRIDGE_TYPE_OFFSET: u16 = 0x147;
 pub const CARTRIDGE_ROM_SIZE_OFFSET: u16 = 0x148;
 pub const CARTRIDGE_RAM_SIZE_OFFSET: u16 = 0x149;
+pub const CARTRIDGE_DESTINATION_OFFSET: u16 = 0x14A;
+pub const CARTRIDGE_OLD_LICENSEE_OFFSET: u16 = 0x14B;
+pub const CARTRIDGE_VERSION_OFFSET: u16 = 0x14C;
+pub const CARTRIDGE_HEADER_CHECKSUM_OFFSET: u16 = 0x14D;
+pub const CARTRIDGE_GLOBAL_CHECKSUM_OFFSET: u16 = 0x14E;
+pub const CARTRIDGE_HEADER_END: u16 = 0x150;
+
+// hardware the game is started on, a game supporting the CGB is run as on a CGB
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HardwareMode {
+    DMG,
+    CGB,
+}
 
 #[allow(non_camel_case_types)]
 pub enum MbcType {
@@ -132,67 +155,192 @@ impl std::fmt::Display for RamSize {
     }
 }
 
-fn get_mbc_type(raw_data: u8) -> MbcType {
+fn get_mbc_type(raw_data: u8) -> std::result::Result<MbcType, String> {
     match raw_data {
-        0x00 => MbcType::ROM_ONLY,
-        0x01 => MbcType::MBC_1,
-        0x02 => MbcType::MBC_1_RAM,
-        0x03 => MbcType::MBC_1_RAM_BAT,
-        0x05 => MbcType::MBC_2,
-        0x06 => MbcType::MBC_2_BAT,
-        0x08 => MbcType::ROM_RAM,
-        0x09 => MbcType::ROM_RAM_BAT,
-        0x0B => MbcType::MMM01,
-        0x0C => MbcType::MMM01_RAM,
-        0x0D => MbcType::MMM01_RAM_BAT,
-        0x0F => MbcType::MBC_3_TIM_BAT,
-        0x10 => MbcType::MBC_3_TIM_RAM_BAT,
-        0x11 => MbcType::MBC_3,
-        0x12 => MbcType::MBC_3_RAM,
-        0x13 => MbcType::MBC_3_RAM_BAT,
-        0x19 => MbcType::MBC_5,
-        0x1A => MbcType::MBC_5_RAM,
-        0x1B => MbcType::MBC_5_RAM_BAT,
-        0x1C => MbcType::MBC_5_RUMBLE,
-        0x1D => MbcType::MBC_5_RUMBLE_RAM,
-        0x1E => MbcType::MBC_5_RUMBLE_RAM_BAT,
-        0x20 => MbcType::MBC_6,
-        0x22 => MbcType::MBC_7,
-        0xFC => MbcType::CAMERA,
-        0xFD => MbcType::TAMA_5,
-        0xFE => MbcType::HUC3,
-        0xFF => MbcType::HUC1,
-        _=> panic!("Catridge with mbc type {:x} is unknown", raw_data),
+        0x00 => Ok(MbcType::ROM_ONLY),
+        0x01 => Ok(MbcType::MBC_1),
+        0x02 => Ok(MbcType::MBC_1_RAM),
+        0x03 => Ok(MbcType::MBC_1_RAM_BAT),
+        0x05 => Ok(MbcType::MBC_2),
+        0x06 => Ok(MbcType::MBC_2_BAT),
+        0x08 => Ok(MbcType::ROM_RAM),
+        0x09 => Ok(MbcType::ROM_RAM_BAT),
+        0x0B => Ok(MbcType::MMM01),
+        0x0C => Ok(MbcType::MMM01_RAM),
+        0x0D => Ok(MbcType::MMM01_RAM_BAT),
+        0x0F => Ok(MbcType::MBC_3_TIM_BAT),
+        0x10 => Ok(MbcType::MBC_3_TIM_RAM_BAT),
+        0x11 => Ok(MbcType::MBC_3),
+        0x12 => Ok(MbcType::MBC_3_RAM),
+        0x13 => Ok(MbcType::MBC_3_RAM_BAT),
+        0x19 => Ok(MbcType::MBC_5),
+        0x1A => Ok(MbcType::MBC_5_RAM),
+        0x1B => Ok(MbcType::MBC_5_RAM_BAT),
+        0x1C => Ok(MbcType::MBC_5_RUMBLE),
+        0x1D => Ok(MbcType::MBC_5_RUMBLE_RAM),
+        0x1E => Ok(MbcType::MBC_5_RUMBLE_RAM_BAT),
+        0x20 => Ok(MbcType::MBC_6),
+        0x22 => Ok(MbcType::MBC_7),
+        0xFC => Ok(MbcType::CAMERA),
+        0xFD => Ok(MbcType::TAMA_5),
+        0xFE => Ok(MbcType::HUC3),
+        0xFF => Ok(MbcType::HUC1),
+        _ => Err(format!("Catridge with mbc type {:x} is unknown", raw_data)),
+    }
+}
+
+// mbc structures emulating the cartridges
+#[allow(non_camel_case_types)]
+enum EmulatedMbc {
+    ROM,
+    MBC_1,
+    MBC_3,
+}
+
+// mbc structure emulating the cartridges of the mbc type, none when they're not supported
+fn mbc_for(mbc_type: &MbcType) -> Option<EmulatedMbc> {
+    match mbc_type {
+        // the ram of the cartridges without mbc isn't banked
+        MbcType::ROM_ONLY | MbcType::ROM_RAM | MbcType::ROM_RAM_BAT => Some(EmulatedMbc::ROM),
+        MbcType::MBC_1 => Some(EmulatedMbc::MBC_1),
+        MbcType::MBC_3_RAM_BAT => Some(EmulatedMbc::MBC_3),
+        _ => None,
     }
 }
 
-fn get_rom_size(raw_data: u8) -> RomSize {
+fn has_battery(mbc_type: &MbcType) -> bool {
+    matches!(mbc_type,
+        MbcType::MBC_1_RAM_BAT | MbcType::MBC_2_BAT | MbcType::ROM_RAM_BAT | MbcType::MMM01_RAM_BAT
+        | MbcType::MBC_3_TIM_BAT | MbcType::MBC_3_TIM_RAM_BAT | MbcType::MBC_3_RAM_BAT
+        | MbcType::MBC_5_RAM_BAT | MbcType::MBC_5_RUMBLE_RAM_BAT)
+}
+
+// the old licensee code is 0x01, or 0x33 when the new two characters code is used
+fn is_licensed_by_nintendo(rom: &[u8]) -> bool {
+    let new_licensee = &rom[CARTRIDGE_NEW_LICENSEE_OFFSET as usize..CARTRIDGE_NEW_LICENSEE_OFFSET as usize + 2];
+
+    match rom[CARTRIDGE_OLD_LICENSEE_OFFSET as usize] {
+        0x01 => true,
+        0x33 => new_licensee == b"01",
+        _ => false,
+    }
+}
+
+// 0x80: the game supports the CGB, 0xC0: the game only runs on a CGB
+fn get_hardware_mode(raw_data: u8) -> HardwareMode {
     match raw_data {
-        0x00 => RomSize::SIZE_32_KB,
-        0x01 => RomSize::SIZE_64_KB,
-        0x02 => RomSize::SIZE_128_KB,
-        0x03 => RomSize::SIZE_256_KB,
-        0x04 => RomSize::SIZE_512_KB,
-        0x05 => RomSize::SIZE_1_MB,
-        0x06 => RomSize::SIZE_2_MB,
-        0x07 => RomSize::SIZE_4_MB,
-        0x08 => RomSize::SIZE_8_MB,
-        _=> panic!("Catridge with Rom size code {:x} is unknown", raw_data),
+        0x80 | 0xC0 => HardwareMode::CGB,
+        _ => HardwareMode::DMG,
     }
 }
 
-fn get_ram_size(raw_data: u8) -> RamSize {
+fn get_rom_size(raw_data: u8) -> std::result::Result<RomSize, String> {
     match raw_data {
-        0x00 => RamSize::NO_RAM,
-        0x02 => RamSize::SIZE_8_KB,
-        0x03 => RamSize::SIZE_32_KB,
-        0x04 => RamSize::SIZE_128_KB,
-        0x05 => RamSize::SIZE_64_KB,
-        _=> panic!("Catridge with Ram size code {:x} is unknown", raw_data),
+        0x00 => Ok(RomSize::SIZE_32_KB),
+        0x01 => Ok(RomSize::SIZE_64_KB),
+        0x02 => Ok(RomSize::SIZE_128_KB),
+        0x03 => Ok(RomSize::SIZE_256_KB),
+        0x04 => Ok(RomSize::SIZE_512_KB),
+        0x05 => Ok(RomSize::SIZE_1_MB),
+        0x06 => Ok(RomSize::SIZE_2_MB),
+        0x07 => Ok(RomSize::SIZE_4_MB),
+        0x08 => Ok(RomSize::SIZE_8_MB),
+        _ => Err(format!("Catridge with Rom size code {:x} is unknown", raw_data)),
+    }
+}
+
+fn get_ram_size(raw_data: u8) -> std::result::Result<RamSize, String> {
+    match raw_data {
+        0x00 => Ok(RamSize::NO_RAM),
+        0x02 => Ok(RamSize::SIZE_8_KB),
+        0x03 => Ok(RamSize::SIZE_32_KB),
+        0x04 => Ok(RamSize::SIZE_128_KB),
+        0x05 => Ok(RamSize::SIZE_64_KB),
+        _ => Err(format!("Catridge with Ram size code {:x} is unknown", raw_data)),
+    }
+}
+
+// the header checksum covers the title to the version, the global checksum the whole rom but itself
+fn header_checksum(rom: &[u8]) -> u8 {
+    rom[CARTRIDGE_TITLE_OFFSET as usize..CARTRIDGE_HEADER_CHECKSUM_OFFSET as usize]
+        .iter()
+        .fold(0u8, |checksum, byte| checksum.wrapping_sub(*byte).wrapping_sub(1))
+}
+
+fn global_checksum(rom: &[u8]) -> u16 {
+    let checksum_range = CARTRIDGE_GLOBAL_CHECKSUM_OFFSET as usize..CARTRIDGE_GLOBAL_CHECKSUM_OFFSET as usize + 2;
+
+    rom.iter()
+        .enumerate()
+        .filter(|(offset, _)| !checksum_range.contains(offset))
+        .fold(0u16, |checksum, (_, byte)| checksum.wrapping_add(*byte as u16))
+}
+
+// decoded header of a rom, without loading it: unknown codes are reported instead of failing
+// only a rom too small to hold a header is an error
+pub fn describe_header(rom: &[u8]) -> std::result::Result<String, String> {
+    if rom.len() < CARTRIDGE_HEADER_END as usize {
+        return Err(format!("rom of {} bytes is too small to hold a header", rom.len()));
     }
+
+    let title: String = rom[CARTRIDGE_TITLE_OFFSET as usize..CARTRIDGE_TITLE_OFFSET as usize + CARTRIDGE_TITLE_SIZE]
+        .iter()
+        .take_while(|byte| **byte != 0)
+        .map(|byte| if byte.is_ascii_graphic() || *byte == b' ' { *byte as char } else { '?' })
+        .collect();
+
+    let cgb_flag = rom[CARTRIDGE_CGB_FLAG_OFFSET as usize];
+    let cgb = match cgb_flag {
+        0x80 => "supported",
+        0xC0 => "only",
+        _ => "no",
+    };
+    let sgb = if rom[CARTRIDGE_SGB_FLAG_OFFSET as usize] == 0x03 { "supported" } else { "no" };
+
+    let type_code = rom[CARTRIDGE_TYPE_OFFSET as usize];
+    let cartridge_type = match get_mbc_type(type_code) {
+        Ok(mbc_type) => {
+            let supported = mbc_for(&mbc_type).is_some();
+            format!("{} ({:#04x}){}", mbc_type, type_code, if supported { "" } else { ", not supported" })
+        }
+        Err(_) => format!("unknown ({:#04x})", type_code),
+    };
+
+    let rom_size_code = rom[CARTRIDGE_ROM_SIZE_OFFSET as usize];
+    let rom_size = match get_rom_size(rom_size_code) {
+        Ok(rom_size) if rom.len() < rom_size as usize => format!("{}, truncated to {} bytes", rom_size, rom.len()),
+        Ok(rom_size) => rom_size.to_string(),
+        Err(_) => format!("unknown ({:#04x})", rom_size_code),
+    };
+    let ram_size_code = rom[CARTRIDGE_RAM_SIZE_OFFSET as usize];
+    let ram_size = get_ram_size(ram_size_code).map(|ram_size| ram_size.to_string()).unwrap_or_else(|_| format!("unknown ({:#04x})", ram_size_code));
+
+    let region = match rom[CARTRIDGE_DESTINATION_OFFSET as usize] {
+        0x00 => "japan",
+        _ => "overseas",
+    };
+
+    let header_checksum_valid = header_checksum(rom) == rom[CARTRIDGE_HEADER_CHECKSUM_OFFSET as usize];
+    let expected_global_checksum = ((rom[CARTRIDGE_GLOBAL_CHECKSUM_OFFSET as usize] as u16) << 8)
+                                   | (rom[CARTRIDGE_GLOBAL_CHECKSUM_OFFSET as usize + 1] as u16);
+    let global_checksum_valid = global_checksum(rom) == expected_global_checksum;
+    let validity = |valid: bool| if valid { "valid" } else { "invalid" };
+
+    Ok([
+        format!("title : {}", title),
+        format!("cgb : {} ({:#04x})", cgb, cgb_flag),
+        format!("sgb : {}", sgb),
+        format!("cartridge type : {}", cartridge_type),
+        format!("rom size : {}", rom_size),
+        format!("ram size : {}", ram_size),
+        format!("region : {}", region),
+        format!("version : {}", rom[CARTRIDGE_VERSION_OFFSET as usize]),
+        format!("header checksum : {:#04x}, {}", rom[CARTRIDGE_HEADER_CHECKSUM_OFFSET as usize], validity(header_checksum_valid)),
+        format!("global checksum : {:#06x}, {}", expected_global_checksum, validity(global_checksum_valid)),
+    ].join("\n"))
 }
 
-pub trait Mbc {
+pub trait Mbc: SaveState {
     fn read_bank_0 (&self, address: usize) -> u8;
 
     fn read_bank_n (&self, address: usize) -> u8;
@@ -206,30 +354,92 @@ pub trait Mbc {
     fn write_ram (&mut self, address: usize, data: u8);
 
     fn run(&mut self, cycles: u8);
+
+    // whole external ram, empty if the mbc has none
+    fn get_ram(&self) -> &[u8];
+
+    fn get_ram_mut(&mut self) -> &mut [u8];
+
+    // banks mapped and banking registers, for the state dump
+    fn banking(&self) -> String;
 }
 
 pub struct Cartridge {
     mbc: Box<dyn Mbc>,    
+    // used to check a saved state belongs to this game
+    header_checksum: u8,
+    global_checksum: u16,
+    // battery backed ram is kept in a save file, it's dirty until written back
+    has_battery: bool,
+    hardware_mode: HardwareMode,
+    // the CGB boot rom colors the Nintendo DMG games following their title
+    title: [u8; CARTRIDGE_TITLE_SIZE],
+    licensed_by_nintendo: bool,
+    ram_dirty: bool,
+    cycles_since_ram_write: u64,
 }
 
 impl Cartridge {
     pub fn new(rom: &[u8]) -> Cartridge {
+        Cartridge::try_new(rom).unwrap_or_else(|message| panic!("{}", message))
+    }
+
+    // an error is returned instead of panicking if the rom is invalid or not supported
+    pub fn try_new(rom: &[u8]) -> std::result::Result<Cartridge, String> {
+        if rom.len() < CARTRIDGE_HEADER_END as usize {
+            return Err(format!("Catridge rom of {} bytes is too small to hold a header", rom.len()));
+        }
+
         // find the mbctype in the rom data
-        let mbc_type = get_mbc_type(rom[CARTRIDGE_TYPE_OFFSET as usize]);
-        let rom_size = get_rom_size(rom[CARTRIDGE_ROM_SIZE_OFFSET as usize]);
-        let ram_size = get_ram_size(rom[CARTRIDGE_RAM_SIZE_OFFSET as usize]);
+        let mbc_type = get_mbc_type(rom[CARTRIDGE_TYPE_OFFSET as usize])?;
+        let rom_size = get_rom_size(rom[CARTRIDGE_ROM_SIZE_OFFSET as usize])?;
+        let ram_size = get_ram_size(rom[CARTRIDGE_RAM_SIZE_OFFSET as usize])?;
+
+        if rom.len() < rom_size as usize {
+            return Err(format!("Catridge rom of {} bytes is smaller than its rom size: {}", rom.len(), rom_size));
+        }
 
         println!("Catridge with mbc type {}, rom size: {}, ram_size: {}", mbc_type, rom_size, ram_size);
 
+        let header_checksum = rom[CARTRIDGE_HEADER_CHECKSUM_OFFSET as usize];
+        let global_checksum = ((rom[CARTRIDGE_GLOBAL_CHECKSUM_OFFSET as usize] as u16) << 8)
+                                | (rom[CARTRIDGE_GLOBAL_CHECKSUM_OFFSET as usize + 1] as u16);
+
+        let has_battery = has_battery(&mbc_type);
+        let hardware_mode = get_hardware_mode(rom[CARTRIDGE_CGB_FLAG_OFFSET as usize]);
+        let mut title = [0; CARTRIDGE_TITLE_SIZE];
+        title.copy_from_slice(&rom[CARTRIDGE_TITLE_OFFSET as usize..CARTRIDGE_TITLE_OFFSET as usize + CARTRIDGE_TITLE_SIZE]);
+        let licensed_by_nintendo = is_licensed_by_nintendo(rom);
+
         // find the correct mbc structure for the cartridge interface
-        Cartridge {
-            mbc: match mbc_type {
-                MbcType::ROM_ONLY => Box::new(Rom::new(rom)),
-                MbcType::MBC_1 => Box::new(Mbc1::new(mbc_type, rom_size, ram_size, rom)),
-                MbcType::MBC_3_RAM_BAT => Box::new(Mbc3::new(mbc_type, rom_size, ram_size, rom)),
-                _ => panic!("Catridge with mbc type {} is not supported", mbc_type),
-            },
-        }
+        let mbc: Box<dyn Mbc> = match mbc_for(&mbc_type) {
+            Some(EmulatedMbc::ROM) => Box::new(Rom::new(ram_size, rom)),
+            Some(EmulatedMbc::MBC_1) => Box::new(Mbc1::new(mbc_type, rom_size, ram_size, rom)),
+            Some(EmulatedMbc::MBC_3) => Box::new(Mbc3::new(mbc_type, rom_size, ram_size, rom)),
+            None => return Err(format!("Catridge with mbc type {} is not supported", mbc_type)),
+        };
+
+        Ok(Cartridge {
+            mbc,
+            header_checksum,
+            global_checksum,
+            has_battery,
+            hardware_mode,
+            title,
+            licensed_by_nintendo,
+            ram_dirty: false,
+            cycles_since_ram_write: 0,
+        })
+    }
+
+    // a rom only cartridge without any data, like an empty cartridge slot the bus reads 0xFF
+    pub fn blank() -> Cartridge {
+        let mut rom = vec![0xFF; RomSize::SIZE_32_KB as usize];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+
+        Cartridge::new(&rom)
     }
 
     pub fn read_bank_0(&self, address: usize) -> u8 {
@@ -254,9 +464,204 @@ impl Cartridge {
 
     pub fn write_ram(&mut self, address: usize, data: u8) {
         self.mbc.write_ram(address, data);
+
+        if self.has_battery {
+            self.ram_dirty = true;
+            self.cycles_since_ram_write = 0;
+        }
     }
 
     pub fn run(&mut self, cycles: u8) {
         self.mbc.run(cycles);
+
+        self.cycles_since_ram_write = self.cycles_since_ram_write.saturating_add(cycles as u64);
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.has_battery
+    }
+
+    pub fn hardware_mode(&self) -> HardwareMode {
+        self.hardware_mode
+    }
+
+    // title bytes, the last one is the CGB flag on the recent games
+    pub fn title(&self) -> &[u8] {
+        &self.title
+    }
+
+    pub fn is_licensed_by_nintendo(&self) -> bool {
+        self.licensed_by_nintendo
+    }
+
+    // the ram has been written since the last save
+    pub fn is_ram_dirty(&self) -> bool {
+        self.ram_dirty
+    }
+
+    pub fn cycles_since_ram_write(&self) -> u64 {
+        self.cycles_since_ram_write
+    }
+
+    pub fn banking(&self) -> String {
+        self.mbc.banking()
+    }
+
+    pub fn save_ram(&mut self, writer: &mut dyn Write) -> Result<()> {
+        write_bytes(writer, self.mbc.get_ram())?;
+        self.ram_dirty = false;
+        Ok(())
+    }
+
+    pub fn load_ram(&mut self, reader: &mut dyn Read) -> Result<()> {
+        read_bytes(reader, self.mbc.get_ram_mut())?;
+        self.ram_dirty = false;
+        Ok(())
+    }
+}
+
+impl SaveState for Cartridge {
+    fn save_state(&self, writer: &mut dyn Write) -> Result<()> {
+        write_u8(writer, self.header_checksum)?;
+        write_u16(writer, self.global_checksum)?;
+        self.mbc.save_state(writer)
+    }
+
+    fn load_state(&mut self, reader: &mut dyn Read) -> Result<()> {
+        if read_u8(reader)? != self.header_checksum || read_u16(reader)? != self.global_checksum {
+            return Err(invalid_data("the state has been saved with another game"));
+        }
+
+        self.mbc.load_state(reader)
+    }
+}
+
+#[cfg(test)]
+mod cartridge_tests {
+    use super::*;
+
+    fn create_mbc3_rom() -> Vec<u8> {
+        let mut rom = vec![0xFF; RomSize::SIZE_32_KB as usize];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x13;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x02;
+        rom
+    }
+
+    #[test]
+    fn test_ram_dirty_flag() {
+        let mut cartridge = Cartridge::new(&create_mbc3_rom());
+        assert!(cartridge.has_battery());
+        assert!(!cartridge.is_ram_dirty());
+
+        // enable the ram then write it
+        cartridge.write_bank_0(0x0000, 0x0A);
+        cartridge.run(100);
+        cartridge.write_ram(0xA010, 0x42);
+        assert!(cartridge.is_ram_dirty());
+        assert_eq!(cartridge.cycles_since_ram_write(), 0);
+        cartridge.run(100);
+        assert_eq!(cartridge.cycles_since_ram_write(), 100);
+
+        // a flush clears the flag
+        let mut sram: Vec<u8> = Vec::new();
+        cartridge.save_ram(&mut sram).unwrap();
+        assert!(!cartridge.is_ram_dirty());
+        assert_eq!(sram.len(), RamSize::SIZE_8_KB as usize);
+        assert_eq!(sram[0x10], 0x42);
+
+        // the saved ram is restored in another cartridge
+        let mut cartridge = Cartridge::new(&create_mbc3_rom());
+        cartridge.load_ram(&mut &sram[..]).unwrap();
+        cartridge.write_bank_0(0x0000, 0x0A);
+        assert_eq!(cartridge.read_ram(0xA010), 0x42);
+        assert!(!cartridge.is_ram_dirty());
+    }
+
+    #[test]
+    fn test_describe_header() {
+        let mut rom = create_mbc3_rom();
+        rom[CARTRIDGE_TITLE_OFFSET as usize..CARTRIDGE_TITLE_OFFSET as usize + 16].copy_from_slice(b"POKEMON RED\0\0\0\0\0");
+        rom[CARTRIDGE_CGB_FLAG_OFFSET as usize] = 0x80;
+        rom[CARTRIDGE_SGB_FLAG_OFFSET as usize] = 0x03;
+        rom[CARTRIDGE_DESTINATION_OFFSET as usize] = 0x01;
+        rom[CARTRIDGE_VERSION_OFFSET as usize] = 0x01;
+        rom[CARTRIDGE_HEADER_CHECKSUM_OFFSET as usize] = header_checksum(&rom);
+        let checksum = global_checksum(&rom);
+        rom[CARTRIDGE_GLOBAL_CHECKSUM_OFFSET as usize] = (checksum >> 8) as u8;
+        rom[CARTRIDGE_GLOBAL_CHECKSUM_OFFSET as usize + 1] = checksum as u8;
+
+        assert_eq!(describe_header(&rom).unwrap(), "title : POKEMON RED\n\
+            cgb : supported (0x80)\n\
+            sgb : supported\n\
+            cartridge type : MBC_3_RAM_BAT (0x13)\n\
+            rom size : SIZE_32_KB\n\
+            ram size : SIZE_8_KB\n\
+            region : overseas\n\
+            version : 1\n\
+            header checksum : 0x3c, valid\n\
+            global checksum : 0x6b03, valid");
+
+        // unknown codes and truncated roms are reported
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x19;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x01;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x42;
+        let info = describe_header(&rom).unwrap();
+        assert!(info.contains("cartridge type : MBC_5 (0x19), not supported\n"));
+        assert!(info.contains("rom size : SIZE_64_KB, truncated to 32768 bytes\n"));
+        assert!(info.contains("ram size : unknown (0x42)\n"));
+        assert!(info.contains("header checksum : 0x3c, invalid\n"));
+        assert!(info.ends_with("global checksum : 0x6b03, invalid"));
+
+        assert!(describe_header(&rom[..0x100]).is_err());
+    }
+
+    #[test]
+    fn test_rom_with_ram() {
+        let mut rom = vec![0xFF; RomSize::SIZE_32_KB as usize];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x09;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x02;
+        rom[0x4000] = 0x42;
+
+        let mut cartridge = Cartridge::new(&rom);
+        assert!(cartridge.has_battery());
+        assert_eq!(cartridge.banking(), "rom only");
+
+        // the ram is always enabled, writing the mbc registers doesn't bank anything
+        cartridge.write_ram(0xA000, 0x12);
+        cartridge.write_ram(0xBFFF, 0x34);
+        cartridge.write_bank_0(0x0000, 0x00);
+        cartridge.write_bank_0(0x2000, 0x02);
+        cartridge.write_bank_n(0x4000, 0x01);
+        assert_eq!(cartridge.read_ram(0xA000), 0x12);
+        assert_eq!(cartridge.read_ram(0xBFFF), 0x34);
+        assert_eq!(cartridge.read_bank_n(0x4000), 0x42);
+        assert!(cartridge.is_ram_dirty());
+
+        // the battery backed ram is saved and restored
+        let mut sram: Vec<u8> = Vec::new();
+        cartridge.save_ram(&mut sram).unwrap();
+        assert_eq!(sram.len(), RamSize::SIZE_8_KB as usize);
+        let mut cartridge = Cartridge::new(&rom);
+        cartridge.load_ram(&mut &sram[..]).unwrap();
+        assert_eq!(cartridge.read_ram(0xA000), 0x12);
+        assert_eq!(cartridge.read_ram(0xBFFF), 0x34);
+
+        // and it's part of the save states
+        let mut state: Vec<u8> = Vec::new();
+        cartridge.save_state(&mut state).unwrap();
+        let mut cartridge = Cartridge::new(&rom);
+        cartridge.load_state(&mut &state[..]).unwrap();
+        assert_eq!(cartridge.read_ram(0xBFFF), 0x34);
+    }
+
+    #[test]
+    fn test_no_battery() {
+        let mut cartridge = Cartridge::blank();
+        assert!(!cartridge.has_battery());
+
+        cartridge.write_ram(0xA000, 0x42);
+        assert!(!cartridge.is_ram_dirty());
     }
 }
\ No newline at end of file
@@ -1,12 +1,20 @@
-use crate::cartridge::Mbc;
+use std::io::{Read, Result, Write};
+
+use crate::cartridge::{Mbc, RamSize};
+use crate::savestate::{SaveState, read_bytes, write_bytes};
 use crate::soc::peripheral::{ROM_BANK_0_SIZE, ROM_BANK_N_SIZE};
 
+// without mbc, at most 8KB of ram are mapped at 0xA000-0xBFFF
+const RAM_ADDRESS_MASK: usize = 0x1FFF;
+
+// rom only cartridge, with an optional ram which is always enabled and never banked
 pub struct Rom {
     rom_bank: [u8; (ROM_BANK_0_SIZE + ROM_BANK_N_SIZE) as usize],
+    ram: Vec<u8>,
 }
 
 impl Rom {
-    pub fn new(rom: &[u8]) -> Rom {
+    pub fn new(ram_size: RamSize, rom: &[u8]) -> Rom {
         // copy  data
         let mut rom_bank = [0x00; (ROM_BANK_0_SIZE + ROM_BANK_N_SIZE) as usize];
         for rom_index in 0..(ROM_BANK_0_SIZE + ROM_BANK_N_SIZE) {
@@ -15,6 +23,7 @@ impl Rom {
 
         Rom {
             rom_bank : rom_bank,
+            ram: vec![0xFF; (ram_size as usize).min(RAM_ADDRESS_MASK + 1)],
         }
     }
 }
@@ -28,9 +37,9 @@ impl Mbc for Rom {
         self.rom_bank[address as usize]
     }
 
-    // not used for this mbc, returns 0xFF
-    fn read_ram (&self, _: usize) -> u8 {
-        0xFF
+    // 0xFF beyond the ram size or without ram
+    fn read_ram (&self, address: usize) -> u8 {
+        self.ram.get(address & RAM_ADDRESS_MASK).copied().unwrap_or(0xFF)
     }
 
     fn write_bank_0 (&mut self, _: usize, _: u8) {
@@ -39,8 +48,35 @@ impl Mbc for Rom {
     fn write_bank_n (&mut self, _: usize, _: u8) {
     }
 
-    // not used for this mbc, doesn't do anything
-    fn write_ram (&mut self, _: usize, _: u8) {}
+    fn write_ram (&mut self, address: usize, data: u8) {
+        if let Some(byte) = self.ram.get_mut(address & RAM_ADDRESS_MASK) {
+            *byte = data;
+        }
+    }
 
     fn run (&mut self, _: u8) {}
-}
\ No newline at end of file
+
+    fn get_ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn get_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    // the 2 banks are always mapped
+    fn banking(&self) -> String {
+        String::from("rom only")
+    }
+}
+
+// the rom can't be modified, only the ram is saved, nothing without ram
+impl SaveState for Rom {
+    fn save_state(&self, writer: &mut dyn Write) -> Result<()> {
+        write_bytes(writer, &self.ram)
+    }
+
+    fn load_state(&mut self, reader: &mut dyn Read) -> Result<()> {
+        read_bytes(reader, &mut self.ram)
+    }
+}
@@ -0,0 +1,64 @@
+// CGB colors are stored in RGB555 format: 5 bits per component, red in the lower bits
+const RGB555_COMPONENT_MASK: u16 = 0x1F;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ColorCorrection {
+    // components are only scaled from 5 to 8 bits
+    Raw,
+    // mimic the washed-out colors of the CGB LCD, the curve used by Gambatte
+    Gambatte,
+}
+
+// convert a RGB555 color into a 0x00RRGGBB host color
+pub fn rgb555_to_rgb888(color: u16, correction: ColorCorrection) -> u32 {
+    let r = (color & RGB555_COMPONENT_MASK) as u32;
+    let g = ((color >> 5) & RGB555_COMPONENT_MASK) as u32;
+    let b = ((color >> 10) & RGB555_COMPONENT_MASK) as u32;
+
+    let (r, g, b) = match correction {
+        ColorCorrection::Raw => ((r << 3) | (r >> 2), (g << 3) | (g >> 2), (b << 3) | (b >> 2)),
+        // each component is mixed with the others, the result fits in 8 bits
+        ColorCorrection::Gambatte => ((r * 13 + g * 2 + b) >> 1, (g * 3 + b) << 1, (r * 3 + g * 2 + b * 11) >> 1),
+    };
+
+    (r << 16) | (g << 8) | b
+}
+
+// the gray RGB555 color closest to a grayscale shade
+pub fn shade_to_rgb555(shade: u8) -> u16 {
+    let component = (shade >> 3) as u16;
+    (component << 10) | (component << 5) | component
+}
+
+#[cfg(test)]
+mod color_tests {
+    use super::*;
+
+    #[test]
+    fn test_raw_conversion() {
+        assert_eq!(rgb555_to_rgb888(0x7FFF, ColorCorrection::Raw), 0xFFFFFF);
+        assert_eq!(rgb555_to_rgb888(0x0000, ColorCorrection::Raw), 0x000000);
+        assert_eq!(rgb555_to_rgb888(0x001F, ColorCorrection::Raw), 0xFF0000);
+        assert_eq!(rgb555_to_rgb888(0x03E0, ColorCorrection::Raw), 0x00FF00);
+        assert_eq!(rgb555_to_rgb888(0x7C00, ColorCorrection::Raw), 0x0000FF);
+    }
+
+    #[test]
+    fn test_gambatte_correction() {
+        // pure colors bleed into the other components
+        assert_eq!(rgb555_to_rgb888(0x001F, ColorCorrection::Gambatte), 0xC9002E);
+        assert_eq!(rgb555_to_rgb888(0x03E0, ColorCorrection::Gambatte), 0x1FBA1F);
+        assert_eq!(rgb555_to_rgb888(0x7C00, ColorCorrection::Gambatte), 0x0F3EAA);
+
+        // grays stay gray
+        assert_eq!(rgb555_to_rgb888(0x7FFF, ColorCorrection::Gambatte), 0xF8F8F8);
+        assert_eq!(rgb555_to_rgb888(0x0000, ColorCorrection::Gambatte), 0x000000);
+    }
+
+    #[test]
+    fn test_shade_to_rgb555() {
+        assert_eq!(shade_to_rgb555(255), 0x7FFF);
+        assert_eq!(shade_to_rgb555(96), 0x318C);
+        assert_eq!(shade_to_rgb555(0), 0x0000);
+    }
+}
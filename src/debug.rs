@@ -1,7 +1,12 @@
-use crate::emulator::{Emulator, EmulatorState, ONE_FRAME_IN_NS, ONE_FRAME_IN_CYCLES};
-use crate::soc::peripheral::IoAccess;
-use std::time::Instant;
+use crate::emulator::{Emulator, SCREEN_HEIGHT};
+use crate::script::Script;
+use crate::snapshot::MemorySnapshot;
+use crate::memory_search::MemorySearch;
+use crate::soc::peripheral::{IoAccess, WORKING_RAM_BEGIN, WORKING_RAM_END, ZERO_PAGE_BEGIN, ZERO_PAGE_END};
+use crate::soc::peripheral::io::io_register;
 
+use std::fs;
+use std::path::PathBuf;
 use std::io::{stdin, stdout, Write};
 use std::thread;
 use std::sync::{Arc, Mutex};
@@ -14,24 +19,85 @@ const SCALE_FACTOR: usize = 3;
 const TILE_SIZE: usize = 8;
 const WINDOW_DIMENSIONS: [usize; 2] = [(NB_TILE_X * TILE_SIZE * SCALE_FACTOR), (NB_TILE_Y * TILE_SIZE * SCALE_FACTOR)];
 
+// CALL instructions: CALL nn, CALL NZ/Z/NC/C, nn
+const CALL_OPCODES: [u8; 5] = [0xCD, 0xC4, 0xCC, 0xD4, 0xDC];
+const CALL_INSTRUCTION_SIZE: u16 = 3;
+// RST instructions: 0b11xxx111
+const RST_OPCODE_MASK: u8 = 0xC7;
+// the call stack is walked up to this depth
+const MAX_BACKTRACE_DEPTH: usize = 16;
+// the search candidates are listed once they are this few
+const MAX_LISTED_CANDIDATES: usize = 16;
+// OAM unusable area, I/O registers: there is no code to return to
+const INVALID_RETURN_BEGIN: u16 = 0xFEA0;
+const INVALID_RETURN_END: u16 = 0xFF7F;
+
+// I/O registers written in the state dump: LCDC, STAT, SCY, SCX, LY, LYC, BGP, OBP0, OBP1, WY, WX
+const DUMP_GPU_REGISTERS: [u16; 11] = [0xFF40, 0xFF41, 0xFF42, 0xFF43, 0xFF44, 0xFF45, 0xFF47, 0xFF48, 0xFF49, 0xFF4A, 0xFF4B];
+// DIV, TIMA, TMA, TAC
+const DUMP_TIMER_REGISTERS: [u16; 4] = [0xFF04, 0xFF05, 0xFF06, 0xFF07];
+
+#[allow(non_camel_case_types)]
 #[derive(Clone, Copy)]
 pub enum DebuggerCommand {
     HALT,
     RUN,
-    STEP,
+    // run the given number of instructions
+    STEP(u32),
+    NEXT,
+    RUN_TO(u16),
+    RUN_UNTIL_VBLANK,
+    SNAPSHOT,
+    DIFF,
+    SEARCH_VALUE(u8),
+    SEARCH_RESET,
+    BACKTRACE,
+    GOTO(u16),
 }
 
+#[allow(non_camel_case_types)]
 pub enum DebuggerState {
     HALT,
     RUN,
     STEP,
+    NEXT,
+    RUN_TO,
+    RUN_UNTIL_VBLANK,
 }
 
 pub struct DebugCtx {
     cmd: Vec<DebuggerCommand>,
     breakpoint: u16,
     break_enabled: bool,
+    // temporary breakpoint used to step over a CALL, the stack pointer
+    // is checked too so that nested calls don't stop the cpu too early
+    step_over_addr: u16,
+    step_over_sp: u16,
+    // instructions left to run by the step command
+    steps_left: u32,
+    // one shot breakpoint used by the run-to command
+    run_to_addr: u16,
+    // last line drawn by the gpu, used to detect the vblank start
+    previous_line: u8,
+    // the stack pointer shall stay in WRAM or HRAM and above the floor
+    stack_floor: u16,
+    stack_check_enabled: bool,
+    stack_warning: bool,
+    stack_warnings_nb: u32,
+    previous_sp: u16,
+    // commands and hooks loaded from a script file
+    script: Option<Script>,
+    // memory captured by the snapshot command
+    snapshot: Option<MemorySnapshot>,
+    // addresses left by the search commands
+    memory_search: Option<MemorySearch>,
+    // game rom to insert, the cartridge is swapped by the host between two frames
+    pub cartridge_path: Option<PathBuf>,
+    // file to write the state dump to, written by the host between two frames
+    pub dump_path: Option<PathBuf>,
     debugger_state: DebuggerState,
+    // the limiter has allowed the current frame to run
+    frame_started: bool,
     display_cpu_reg: bool,
     vram_viewer_buffer: [u32; 32 * TILE_SIZE * 12 * TILE_SIZE],
 }
@@ -42,117 +108,345 @@ impl DebugCtx {
             cmd: Vec::new(),
             breakpoint: 0,
             break_enabled: false,
+            step_over_addr: 0,
+            step_over_sp: 0,
+            steps_left: 0,
+            run_to_addr: 0,
+            previous_line: 0,
+            stack_floor: WORKING_RAM_BEGIN,
+            stack_check_enabled: false,
+            stack_warning: false,
+            stack_warnings_nb: 0,
+            previous_sp: 0,
+            script: None,
+            snapshot: None,
+            memory_search: None,
+            cartridge_path: None,
+            dump_path: None,
             debugger_state: DebuggerState::HALT,
+            frame_started: false,
             display_cpu_reg: true,
             vram_viewer_buffer: [0; 32 * TILE_SIZE * 12 * TILE_SIZE],
         }
     }
 }
 
-pub fn run_debug_mode(emulator: &mut Emulator, dbg_ctx: &mut DebugCtx) {
-    match emulator.state {
-        EmulatorState::GetTime => {
-            emulator.frame_tick = Instant::now();
-
-            emulator.state = EmulatorState::RunMachine;
-        }
-        EmulatorState::RunMachine => {
-            match dbg_ctx.debugger_state {
-                DebuggerState::HALT => {
-                    // display cpu internal registers
-                    if dbg_ctx.display_cpu_reg {
-                        dbg_ctx.display_cpu_reg = false;
-                        println!("instruction byte : {:#04x} / pc : {:#06x} / sp : {:#04x}", emulator.soc.peripheral.read(emulator.soc.cpu.pc), emulator.soc.cpu.pc, emulator.soc.cpu.sp);
-                        println!("BC : {:#06x} / AF : {:#06x} / DE : {:#06x} / HL : {:#06x}", emulator.soc.cpu.registers.read_bc(), emulator.soc.cpu.registers.read_af(), emulator.soc.cpu.registers.read_de(), emulator.soc.cpu.registers.read_hl());
-                    }
+// run one instruction at a time so that the debugger can stop anywhere, true when a frame has ended
+pub fn run_debug_mode(emulator: &mut Emulator, dbg_ctx: &mut DebugCtx) -> bool {
+    // the limiter paces the start of each frame
+    if !dbg_ctx.frame_started {
+        if !emulator.limiter.frame_due() {
+            return false;
+        }
+        dbg_ctx.frame_started = true;
+    }
 
-                    // wait until a new debug command is entered
-                    let cmd = dbg_ctx.cmd.pop();
-                    if let Some(DebuggerCommand::RUN) = cmd {
-                        dbg_ctx.display_cpu_reg = true;
-                        dbg_ctx.debugger_state = DebuggerState::RUN;
-                    }
+    let mut frame_ended = false;
+    match dbg_ctx.debugger_state {
+        DebuggerState::HALT => {
+            // display cpu internal registers
+            if dbg_ctx.display_cpu_reg {
+                dbg_ctx.display_cpu_reg = false;
+                println!("{}", format_cpu_registers(emulator));
+            }
 
-                    if let Some(DebuggerCommand::STEP) = cmd {
-                        dbg_ctx.display_cpu_reg = true;
-                        dbg_ctx.debugger_state = DebuggerState::STEP;
-                    }
+            // run the commands of a newly loaded script
+            if let Some(script) = dbg_ctx.script.as_mut() {
+                script.run(emulator);
+            }
+
+            // wait until a new debug command is entered
+            let cmd = dbg_ctx.cmd.pop();
+            if let Some(DebuggerCommand::RUN) = cmd {
+                dbg_ctx.display_cpu_reg = true;
+                dbg_ctx.debugger_state = DebuggerState::RUN;
+            }
+
+            if let Some(DebuggerCommand::STEP(count)) = cmd {
+                dbg_ctx.display_cpu_reg = true;
+                dbg_ctx.steps_left = count;
+                dbg_ctx.debugger_state = DebuggerState::STEP;
+            }
+
+            if let Some(DebuggerCommand::NEXT) = cmd {
+                dbg_ctx.display_cpu_reg = true;
+
+                // run until the CALL returns, otherwise it's just a step
+                let opcode = emulator.soc.peripheral.peek_byte(emulator.soc.cpu.pc);
+                if CALL_OPCODES.contains(&opcode) {
+                    dbg_ctx.step_over_addr = emulator.soc.cpu.pc.wrapping_add(CALL_INSTRUCTION_SIZE);
+                    dbg_ctx.step_over_sp = emulator.soc.cpu.sp;
+                    dbg_ctx.debugger_state = DebuggerState::NEXT;
+                } else {
+                    dbg_ctx.steps_left = 1;
+                    dbg_ctx.debugger_state = DebuggerState::STEP;
                 }
-                DebuggerState::RUN => {
-                    // run the emulator as in normal mode
-                    emulator.cycles_elapsed_in_frame += emulator.soc.run() as usize;
+            }
 
-                    if emulator.cycles_elapsed_in_frame >= ONE_FRAME_IN_CYCLES {
-                        emulator.cycles_elapsed_in_frame = 0;
-                        emulator.state = EmulatorState::WaitNextFrame;
-                    }
+            if let Some(DebuggerCommand::RUN_TO(addr)) = cmd {
+                dbg_ctx.display_cpu_reg = true;
+                dbg_ctx.run_to_addr = addr;
+                dbg_ctx.debugger_state = DebuggerState::RUN_TO;
+            }
 
-                    // check if we have to break
-                    if dbg_ctx.break_enabled && (dbg_ctx.breakpoint == emulator.soc.cpu.pc) {
-                        // check pc
-                        dbg_ctx.display_cpu_reg = true;
-                        dbg_ctx.debugger_state = DebuggerState::HALT;
-                    }
+            if let Some(DebuggerCommand::RUN_UNTIL_VBLANK) = cmd {
+                dbg_ctx.display_cpu_reg = true;
+                dbg_ctx.previous_line = emulator.soc.peripheral.gpu.current_line;
+                dbg_ctx.debugger_state = DebuggerState::RUN_UNTIL_VBLANK;
+            }
 
-                    // wait until a new debug command is entered
-                    if let Some(DebuggerCommand::HALT) = dbg_ctx.cmd.pop() {
-                        dbg_ctx.display_cpu_reg = true;
-                        dbg_ctx.debugger_state = DebuggerState::HALT;
+            if let Some(DebuggerCommand::SNAPSHOT) = cmd {
+                let snapshot = MemorySnapshot::capture(&emulator.soc.peripheral);
+                println!("snapshot: {} bytes captured", snapshot.size());
+                dbg_ctx.snapshot = Some(snapshot);
+            }
+
+            if let Some(DebuggerCommand::DIFF) = cmd {
+                match &dbg_ctx.snapshot {
+                    Some(snapshot) => {
+                        let changes = snapshot.diff(&emulator.soc.peripheral);
+                        for change in changes.iter() {
+                            println!("[{:#06x}] {:#04x} -> {:#04x}", change.address, change.old, change.new);
+                        }
+                        println!("diff: {} bytes changed", changes.len());
                     }
+                    None => println!("diff: no snapshot, use the snapshot command first"),
                 }
-                DebuggerState::STEP => {
-                    // run the emulator once then go to halt state
-                    emulator.cycles_elapsed_in_frame += emulator.soc.run() as usize;
+            }
 
-                    if emulator.cycles_elapsed_in_frame >= ONE_FRAME_IN_CYCLES {
-                        emulator.cycles_elapsed_in_frame = 0;
-                        emulator.state = EmulatorState::WaitNextFrame;
+            if let Some(DebuggerCommand::SEARCH_VALUE(value)) = cmd {
+                // the first search lists the candidates, the next ones narrow them down
+                let memory = &emulator.soc.peripheral;
+                let search = match dbg_ctx.memory_search.take() {
+                    Some(mut search) => {
+                        search.narrow(memory, value);
+                        search
                     }
+                    None => MemorySearch::new(memory, value),
+                };
+                if search.candidates.len() <= MAX_LISTED_CANDIDATES {
+                    for address in search.candidates.iter() {
+                        println!("[{:#06x}] {:#04x}", address, value);
+                    }
+                }
+                println!("search: {} candidates", search.candidates.len());
+                dbg_ctx.memory_search = Some(search);
+            }
+
+            if let Some(DebuggerCommand::SEARCH_RESET) = cmd {
+                dbg_ctx.memory_search = None;
+            }
+
+            if let Some(DebuggerCommand::BACKTRACE) = cmd {
+                for (depth, return_addr) in backtrace(emulator).iter().enumerate() {
+                    println!("#{} {:#06x}", depth, return_addr);
+                }
+            }
 
+            if let Some(DebuggerCommand::GOTO(addr)) = cmd {
+                dbg_ctx.display_cpu_reg = true;
+                emulator.soc.cpu.pc = addr;
+            }
+        }
+        DebuggerState::RUN | DebuggerState::NEXT | DebuggerState::RUN_TO | DebuggerState::RUN_UNTIL_VBLANK => {
+            // run the emulator as in normal mode
+            frame_ended = emulator.step();
+            check_stack_pointer(emulator, dbg_ctx);
+
+            // a script hook may ask to halt
+            if let Some(script) = dbg_ctx.script.as_mut() {
+                if script.run_hooks(emulator) {
+                    dbg_ctx.display_cpu_reg = true;
                     dbg_ctx.debugger_state = DebuggerState::HALT;
                 }
             }
+
+            // check if we have to break
+            if dbg_ctx.break_enabled && (dbg_ctx.breakpoint == emulator.soc.cpu.pc) {
+                // check pc
+                dbg_ctx.display_cpu_reg = true;
+                dbg_ctx.debugger_state = DebuggerState::HALT;
+            }
+
+            // check if the bounded run is over
+            let current_line = emulator.soc.peripheral.gpu.current_line;
+            let run_done = match dbg_ctx.debugger_state {
+                // the stepped over CALL has returned
+                DebuggerState::NEXT => dbg_ctx.step_over_addr == emulator.soc.cpu.pc && dbg_ctx.step_over_sp == emulator.soc.cpu.sp,
+                DebuggerState::RUN_TO => dbg_ctx.run_to_addr == emulator.soc.cpu.pc,
+                // the gpu has just reached the first vblank line
+                DebuggerState::RUN_UNTIL_VBLANK => dbg_ctx.previous_line != current_line && current_line == SCREEN_HEIGHT as u8,
+                _ => false,
+            };
+            dbg_ctx.previous_line = current_line;
+
+            if run_done {
+                dbg_ctx.display_cpu_reg = true;
+                dbg_ctx.debugger_state = DebuggerState::HALT;
+            }
+
+            // wait until a new debug command is entered
+            if let Some(DebuggerCommand::HALT) = dbg_ctx.cmd.pop() {
+                dbg_ctx.display_cpu_reg = true;
+                dbg_ctx.debugger_state = DebuggerState::HALT;
+            }
         }
-        EmulatorState::WaitNextFrame => {
-            // check if 16,742706 ms have passed during this frame
-            if emulator.frame_tick.elapsed().as_nanos() >= ONE_FRAME_IN_NS as u128{
-                emulator.state = EmulatorState::DisplayFrame;
+        DebuggerState::STEP => {
+            // run the emulator once per call until the count is reached, then go to halt state
+            frame_ended = emulator.step();
+            check_stack_pointer(emulator, dbg_ctx);
+            dbg_ctx.steps_left = dbg_ctx.steps_left.saturating_sub(1);
+
+            // a breakpoint, a script hook or the halt command stops the steps early
+            let script_halt = match dbg_ctx.script.as_mut() {
+                Some(script) => script.run_hooks(emulator),
+                None => false,
+            };
+            let breakpoint_hit = dbg_ctx.break_enabled && (dbg_ctx.breakpoint == emulator.soc.cpu.pc);
+            let halt_command = matches!(dbg_ctx.cmd.last(), Some(DebuggerCommand::HALT));
+            if halt_command {
+                dbg_ctx.cmd.pop();
+            }
+
+            if dbg_ctx.steps_left == 0 || script_halt || breakpoint_hit || halt_command {
+                dbg_ctx.steps_left = 0;
+                dbg_ctx.display_cpu_reg = true;
+                dbg_ctx.debugger_state = DebuggerState::HALT;
             }
         }
-        EmulatorState::DisplayFrame => {
-            emulator.state = EmulatorState::GetTime;
+    }
 
-            // update vram debug buffer
-            for pixel_index in 0..NB_TILE_X * TILE_SIZE * NB_TILE_Y * TILE_SIZE {
-                // compute pixel_x and pixel_y indexes
-                let pixel_y_index = pixel_index / (NB_TILE_X * 8);
-                let pixel_x_index = pixel_index % (NB_TILE_X * 8);
+    if frame_ended {
+        dbg_ctx.frame_started = false;
+        update_vram_viewer(emulator, dbg_ctx);
+    }
 
-                // compute the tile index 
-                let tile_y_index = pixel_y_index / 8;
-                let tile_x_index = pixel_x_index / 8;
-                let tile_index = tile_y_index * NB_TILE_X + tile_x_index;
+    frame_ended
+}
 
-                // compute VRAM address from pixel_index
-                let tile_row_offset = pixel_y_index % 8 * 2;
+// cpu registers and next instruction, as displayed when the debugger halts
+fn format_cpu_registers(emulator: &Emulator) -> String {
+    [
+        format!("instruction byte : {:#04x} / pc : {:#06x} / sp : {:#04x}", emulator.soc.peripheral.peek_byte(emulator.soc.cpu.pc), emulator.soc.cpu.pc, emulator.soc.cpu.sp),
+        format!("BC : {:#06x} / AF : {:#06x} / DE : {:#06x} / HL : {:#06x}", emulator.soc.cpu.registers.read_bc(), emulator.soc.cpu.registers.read_af(), emulator.soc.cpu.registers.read_de(), emulator.soc.cpu.registers.read_hl()),
+        format!("cycles : {} / emulated time : {:.6} s", emulator.cycles(), emulator.emulated_seconds()),
+        emulator.soc.cpu.disassemble(&emulator.soc.peripheral).trace_line(&emulator.soc.peripheral, true),
+    ].join("\n")
+}
 
-                // get row for the needed pixel
-                let data_0 = emulator.soc.peripheral.gpu.vram[tile_index * 16 + tile_row_offset];
-                let data_1 = emulator.soc.peripheral.gpu.vram[tile_index * 16 + tile_row_offset + 1];
+// one "NAME : value" line per I/O register
+fn format_io_registers(emulator: &Emulator, addresses: &[u16]) -> String {
+    addresses.iter()
+        .map(|address| format!("{} : {:#04x}", io_register(*address).name, emulator.soc.peripheral.peek_byte(*address)))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
 
-                // get pixel bits
-                let bit_0 = data_0 >> (7 - (pixel_index % 8)) & 0x01;
-                let bit_1 = data_1 >> (7 - (pixel_index % 8)) & 0x01;
+// human readable snapshot of the whole system, to be attached to the bug reports
+pub fn dump_state(emulator: &Emulator) -> String {
+    let nvic = &emulator.soc.peripheral.nvic;
 
-                let pixel_color = emulator.soc.peripheral.gpu.get_bg_pixel_color_from_palette((bit_1 << 1) | bit_0);
+    [
+        format!("[cpu]\n{}", format_cpu_registers(emulator)),
+        format!("[interrupts]\nIME : {} / IE : {:#04x} / IF : {:#04x}", nvic.interrupt_master_enable, nvic.get_it_enable(), nvic.get_it_flag()),
+        format!("[gpu]\nmode : {:?}\n{}", emulator.soc.peripheral.gpu.mode, format_io_registers(emulator, &DUMP_GPU_REGISTERS)),
+        format!("[timer]\n{}", format_io_registers(emulator, &DUMP_TIMER_REGISTERS)),
+        format!("[mbc]\n{}", emulator.soc.peripheral.cartridge.banking()),
+    ].join("\n\n") + "\n"
+}
 
-                dbg_ctx.vram_viewer_buffer[pixel_index] =  0xFF << 24
-                            | (pixel_color as u32) << 16
-                            | (pixel_color as u32) << 8
-                            | (pixel_color as u32) << 0;
-            }
+fn update_vram_viewer(emulator: &Emulator, dbg_ctx: &mut DebugCtx) {
+    // update vram debug buffer
+    for pixel_index in 0..NB_TILE_X * TILE_SIZE * NB_TILE_Y * TILE_SIZE {
+        // compute pixel_x and pixel_y indexes
+        let pixel_y_index = pixel_index / (NB_TILE_X * 8);
+        let pixel_x_index = pixel_index % (NB_TILE_X * 8);
+
+        // compute the tile index 
+        let tile_y_index = pixel_y_index / 8;
+        let tile_x_index = pixel_x_index / 8;
+        let tile_index = tile_y_index * NB_TILE_X + tile_x_index;
+
+        // compute VRAM address from pixel_index
+        let tile_row_offset = pixel_y_index % 8 * 2;
+
+        // get row for the needed pixel
+        let data_0 = emulator.soc.peripheral.gpu.vram[tile_index * 16 + tile_row_offset];
+        let data_1 = emulator.soc.peripheral.gpu.vram[tile_index * 16 + tile_row_offset + 1];
+
+        // get pixel bits
+        let bit_0 = data_0 >> (7 - (pixel_index % 8)) & 0x01;
+        let bit_1 = data_1 >> (7 - (pixel_index % 8)) & 0x01;
+
+        let pixel_color = emulator.soc.peripheral.gpu.get_bg_pixel_color_from_palette((bit_1 << 1) | bit_0);
+
+        dbg_ctx.vram_viewer_buffer[pixel_index] =  0xFF << 24
+                    | (pixel_color as u32) << 16
+                    | (pixel_color as u32) << 8
+                    | pixel_color as u32;
+    }
+}
+
+// list the return addresses found on the stack, the innermost first. There is no frame
+// metadata so a word is taken as a return address when it follows a CALL or a RST,
+// other words are skipped as pushed data
+fn backtrace(emulator: &Emulator) -> Vec<u16> {
+    let peripheral = &emulator.soc.peripheral;
+    let mut return_addrs = Vec::new();
+
+    // the stack grows down from the top of the WRAM or of the HRAM
+    let mut addr = emulator.soc.cpu.sp;
+    let stack_top = if (ZERO_PAGE_BEGIN..=ZERO_PAGE_END).contains(&addr) {
+        ZERO_PAGE_END
+    } else if (WORKING_RAM_BEGIN..=WORKING_RAM_END).contains(&addr) {
+        WORKING_RAM_END
+    } else {
+        return return_addrs;
+    };
+
+    while addr < stack_top && return_addrs.len() < MAX_BACKTRACE_DEPTH {
+        let return_addr = peripheral.peek_byte(addr) as u16 | (peripheral.peek_byte(addr + 1) as u16) << 8;
+        addr += 2;
+
+        // the stack is garbage past this point
+        if (INVALID_RETURN_BEGIN..=INVALID_RETURN_END).contains(&return_addr) {
+            break;
+        }
+
+        let is_call = CALL_OPCODES.contains(&peripheral.peek_byte(return_addr.wrapping_sub(CALL_INSTRUCTION_SIZE)));
+        let is_rst = peripheral.peek_byte(return_addr.wrapping_sub(1)) & RST_OPCODE_MASK == RST_OPCODE_MASK;
+        if is_call || is_rst {
+            return_addrs.push(return_addr);
         }
     }
+
+    return_addrs
+}
+
+fn check_stack_pointer(emulator: &Emulator, dbg_ctx: &mut DebugCtx) {
+    if !dbg_ctx.stack_check_enabled {
+        return;
+    }
+
+    // only check the stack pointer when it's modified
+    let sp = emulator.soc.cpu.sp;
+    if sp == dbg_ctx.previous_sp {
+        return;
+    }
+    dbg_ctx.previous_sp = sp;
+
+    // sp points to the last pushed byte so it can be just above the working ram
+    let sp_in_stack_region = (WORKING_RAM_BEGIN..=WORKING_RAM_END + 1).contains(&sp)
+        || (ZERO_PAGE_BEGIN..=ZERO_PAGE_END).contains(&sp);
+
+    if sp_in_stack_region && sp >= dbg_ctx.stack_floor {
+        dbg_ctx.stack_warning = false;
+    } else if !dbg_ctx.stack_warning {
+        // warn only once until the stack pointer is back in its region
+        dbg_ctx.stack_warning = true;
+        dbg_ctx.stack_warnings_nb += 1;
+        println!("stack warning: sp {:#06x} out of the stack region at pc {:#06x}", sp, emulator.soc.cpu.pc);
+    }
 }
 
 pub fn debug_cli(debug_ctx: &Arc<Mutex<DebugCtx>>) {
@@ -167,6 +461,25 @@ pub fn debug_cli(debug_ctx: &Arc<Mutex<DebugCtx>>) {
             stdout().flush().unwrap();
             stdin().read_line(&mut command).expect("Incorrect string is read.");
 
+            // the script path is not parsed as other commands
+            if let Some(path) = command.trim().strip_prefix("script ") {
+                match fs::read_to_string(path.trim()).map_err(|error| error.to_string()).and_then(|source| Script::parse(&source)) {
+                    Ok(script) => debug_ctx_ref.lock().unwrap().script = Some(script),
+                    Err(message) => println!("cannot load script {}: {}", path.trim(), message),
+                }
+                continue;
+            }
+
+            if let Some(path) = command.trim().strip_prefix("load ") {
+                debug_ctx_ref.lock().unwrap().cartridge_path = Some(PathBuf::from(path.trim()));
+                continue;
+            }
+
+            if let Some(path) = command.trim().strip_prefix("dump ") {
+                debug_ctx_ref.lock().unwrap().dump_path = Some(PathBuf::from(path.trim()));
+                continue;
+            }
+
             // process command
             if command.trim().contains("break_set") {
                 let split: Vec<&str> = command.trim().split(" ").collect();
@@ -179,7 +492,28 @@ pub fn debug_cli(debug_ctx: &Arc<Mutex<DebugCtx>>) {
                 (*debug_ctx_ref.lock().unwrap()).break_enabled = false;
             }
 
-            if command.trim().contains("run") {
+            if let Some(addr) = command.trim().strip_prefix("stack_floor_set") {
+                match u16::from_str_radix(addr.trim(), 16) {
+                    Ok(stack_floor) => {
+                        let mut debug_ctx = debug_ctx_ref.lock().unwrap();
+                        debug_ctx.stack_floor = stack_floor;
+                        debug_ctx.stack_check_enabled = true;
+                    }
+                    Err(_) => println!("stack_floor_set: invalid address {}", addr.trim()),
+                }
+            }
+
+            if command.trim().contains("stack_floor_reset") {
+                debug_ctx_ref.lock().unwrap().stack_check_enabled = false;
+            }
+
+            if command.trim().contains("run-to") {
+                let split: Vec<&str> = command.trim().split(" ").collect();
+                let run_to_addr = u16::from_str_radix(split[1], 16).unwrap();
+                (*debug_ctx_ref.lock().unwrap()).cmd.push(DebuggerCommand::RUN_TO(run_to_addr));
+            } else if command.trim().contains("run-until vblank") {
+                (*debug_ctx_ref.lock().unwrap()).cmd.push(DebuggerCommand::RUN_UNTIL_VBLANK);
+            } else if command.trim().contains("run") {
                 (*debug_ctx_ref.lock().unwrap()).cmd.push(DebuggerCommand::RUN);
             }
 
@@ -187,12 +521,51 @@ pub fn debug_cli(debug_ctx: &Arc<Mutex<DebugCtx>>) {
                 (*debug_ctx_ref.lock().unwrap()).cmd.push(DebuggerCommand::HALT);
             }
 
-            if command.trim().contains("step") {
-                (*debug_ctx_ref.lock().unwrap()).cmd.push(DebuggerCommand::STEP);
+            if let Some(count) = command.trim().strip_prefix("step") {
+                // a single instruction is run without count
+                let parsed_count = if count.trim().is_empty() { Ok(1) } else { count.trim().parse::<u32>() };
+                match parsed_count {
+                    Ok(count) if count > 0 => (*debug_ctx_ref.lock().unwrap()).cmd.push(DebuggerCommand::STEP(count)),
+                    _ => println!("step: invalid count {}", count.trim()),
+                }
+            }
+
+            if command.trim().contains("next") {
+                (*debug_ctx_ref.lock().unwrap()).cmd.push(DebuggerCommand::NEXT);
+            }
+
+            if command.trim() == "snapshot" {
+                (*debug_ctx_ref.lock().unwrap()).cmd.push(DebuggerCommand::SNAPSHOT);
+            }
+
+            if command.trim() == "diff" {
+                (*debug_ctx_ref.lock().unwrap()).cmd.push(DebuggerCommand::DIFF);
+            }
+
+            if let Some(value) = command.trim().strip_prefix("search ") {
+                match u8::from_str_radix(value.trim(), 16) {
+                    Ok(value) => debug_ctx_ref.lock().unwrap().cmd.push(DebuggerCommand::SEARCH_VALUE(value)),
+                    Err(_) => println!("search: invalid value {}", value.trim()),
+                }
+            }
+
+            if command.trim() == "search_reset" {
+                debug_ctx_ref.lock().unwrap().cmd.push(DebuggerCommand::SEARCH_RESET);
+            }
+
+            if command.trim() == "backtrace" {
+                (*debug_ctx_ref.lock().unwrap()).cmd.push(DebuggerCommand::BACKTRACE);
+            }
+
+            if let Some(addr) = command.trim().strip_prefix("goto ") {
+                match u16::from_str_radix(addr.trim(), 16) {
+                    Ok(addr) => (*debug_ctx_ref.lock().unwrap()).cmd.push(DebuggerCommand::GOTO(addr)),
+                    Err(_) => println!("goto: invalid address {}", addr.trim()),
+                }
             }
 
             if command.trim().contains("help") {
-                println!("supported commands: break <addr>, run, halt, step");
+                println!("supported commands: break <addr>, run, run-to <addr>, run-until vblank, halt, step [count], next, stack_floor_set <addr>, stack_floor_reset, script <path>, load <path>, dump <path>, snapshot, diff, search <value>, search_reset, backtrace, goto <addr>");
             }
         }
     });
@@ -218,4 +591,245 @@ pub fn debug_vram(debug_ctx: &Arc<Mutex<DebugCtx>>) {
             window.update_with_buffer(&buffer, NB_TILE_X * TILE_SIZE, NB_TILE_Y * TILE_SIZE).unwrap();
         }
     });
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod debug_tests {
+    use super::*;
+    use crate::emulator::test_fixtures::blank_rom;
+
+    fn create_emulator(boot_rom: &[u8]) -> Emulator {
+        Emulator::new(boot_rom, &blank_rom(), true)
+    }
+
+    #[test]
+    fn test_step_over_call() {
+        let mut boot_rom = [0x00; 0x100];
+        // 0x0000: CALL 0x0010
+        boot_rom[0x00] = 0xCD;
+        boot_rom[0x01] = 0x10;
+        boot_rom[0x02] = 0x00;
+        // 0x0010: CALL 0x0020 / RET
+        boot_rom[0x10] = 0xCD;
+        boot_rom[0x11] = 0x20;
+        boot_rom[0x12] = 0x00;
+        boot_rom[0x13] = 0xC9;
+        // 0x0020: RET
+        boot_rom[0x20] = 0xC9;
+
+        let mut emulator = create_emulator(&boot_rom);
+        let mut dbg_ctx = DebugCtx::new();
+        emulator.limiter.unlimited = true;
+        emulator.soc.cpu.sp = 0xFFFE;
+
+        dbg_ctx.cmd.push(DebuggerCommand::NEXT);
+        for _ in 0..10 {
+            emulator.run(&mut dbg_ctx);
+        }
+
+        // the cpu is halted right after the CALL
+        assert!(matches!(dbg_ctx.debugger_state, DebuggerState::HALT));
+        assert_eq!(emulator.soc.cpu.pc, 0x0003);
+        assert_eq!(emulator.soc.cpu.sp, 0xFFFE);
+    }
+
+    #[test]
+    fn test_step_over_no_call() {
+        let boot_rom = [0x00; 0x100];
+
+        let mut emulator = create_emulator(&boot_rom);
+        let mut dbg_ctx = DebugCtx::new();
+        emulator.limiter.unlimited = true;
+
+        // NOP is executed just like a step
+        dbg_ctx.cmd.push(DebuggerCommand::NEXT);
+        for _ in 0..10 {
+            emulator.run(&mut dbg_ctx);
+        }
+
+        assert!(matches!(dbg_ctx.debugger_state, DebuggerState::HALT));
+        assert_eq!(emulator.soc.cpu.pc, 0x0001);
+    }
+
+    #[test]
+    fn test_step_count() {
+        let boot_rom = [0x00; 0x100];
+
+        let mut emulator = create_emulator(&boot_rom);
+        let mut dbg_ctx = DebugCtx::new();
+        emulator.limiter.unlimited = true;
+
+        // 10 NOPs are run then the cpu is halted
+        dbg_ctx.cmd.push(DebuggerCommand::STEP(10));
+        for _ in 0..20 {
+            emulator.run(&mut dbg_ctx);
+        }
+        assert!(matches!(dbg_ctx.debugger_state, DebuggerState::HALT));
+        assert_eq!(emulator.soc.cpu.pc, 0x000A);
+
+        // a breakpoint stops the steps early
+        dbg_ctx.breakpoint = 0x0010;
+        dbg_ctx.break_enabled = true;
+        dbg_ctx.cmd.push(DebuggerCommand::STEP(100));
+        for _ in 0..200 {
+            emulator.run(&mut dbg_ctx);
+        }
+        assert!(matches!(dbg_ctx.debugger_state, DebuggerState::HALT));
+        assert_eq!(emulator.soc.cpu.pc, 0x0010);
+    }
+
+    #[test]
+    fn test_run_to() {
+        let boot_rom = [0x00; 0x100];
+
+        let mut emulator = create_emulator(&boot_rom);
+        let mut dbg_ctx = DebugCtx::new();
+        emulator.limiter.unlimited = true;
+
+        // run through the NOPs until the target address
+        dbg_ctx.cmd.push(DebuggerCommand::RUN_TO(0x0080));
+        for _ in 0..0x100 {
+            emulator.run(&mut dbg_ctx);
+        }
+
+        assert!(matches!(dbg_ctx.debugger_state, DebuggerState::HALT));
+        assert_eq!(emulator.soc.cpu.pc, 0x0080);
+    }
+
+    #[test]
+    fn test_run_until_vblank() {
+        let boot_rom = [0x00; 0x100];
+
+        let mut emulator = create_emulator(&boot_rom);
+        let mut dbg_ctx = DebugCtx::new();
+        emulator.limiter.unlimited = true;
+        emulator.soc.peripheral.gpu.lcd_display_enabled = true;
+
+        // a frame is 17556 NOPs long, vblank starts before its end
+        dbg_ctx.cmd.push(DebuggerCommand::RUN_UNTIL_VBLANK);
+        for _ in 0..20000 {
+            emulator.run(&mut dbg_ctx);
+        }
+
+        assert!(matches!(dbg_ctx.debugger_state, DebuggerState::HALT));
+        assert_eq!(emulator.soc.peripheral.gpu.current_line, 144);
+    }
+
+    #[test]
+    fn test_stack_underflow_warning() {
+        // PUSH BC forever
+        let boot_rom = [0xC5; 0x100];
+
+        let mut emulator = create_emulator(&boot_rom);
+        let mut dbg_ctx = DebugCtx::new();
+        emulator.limiter.unlimited = true;
+        emulator.soc.cpu.sp = 0xFFFE;
+        dbg_ctx.stack_floor = 0xFFF8;
+        dbg_ctx.stack_check_enabled = true;
+
+        dbg_ctx.cmd.push(DebuggerCommand::RUN);
+        emulator.run(&mut dbg_ctx);
+
+        // 3 pushes keep sp above the floor
+        for _ in 0..3 {
+            emulator.run(&mut dbg_ctx);
+        }
+        assert_eq!(dbg_ctx.stack_warnings_nb, 0);
+
+        // next pushes go below the floor
+        for _ in 0..10 {
+            emulator.run(&mut dbg_ctx);
+        }
+        assert_eq!(emulator.soc.cpu.sp, 0xFFE4);
+        assert_eq!(dbg_ctx.stack_warnings_nb, 1);
+    }
+
+    #[test]
+    fn test_backtrace() {
+        let mut boot_rom = [0x00; 0x100];
+        // 0x0000: CALL 0x0010
+        boot_rom[0x00] = 0xCD;
+        boot_rom[0x01] = 0x10;
+        boot_rom[0x02] = 0x00;
+        // 0x0010: PUSH BC / CALL 0x0020
+        boot_rom[0x10] = 0xC5;
+        boot_rom[0x11] = 0xCD;
+        boot_rom[0x12] = 0x20;
+        boot_rom[0x13] = 0x00;
+
+        let mut emulator = create_emulator(&boot_rom);
+        let mut dbg_ctx = DebugCtx::new();
+        emulator.limiter.unlimited = true;
+        emulator.soc.cpu.sp = 0xFFFE;
+
+        for _ in 0..3 {
+            dbg_ctx.cmd.push(DebuggerCommand::STEP(1));
+            emulator.run(&mut dbg_ctx);
+            emulator.run(&mut dbg_ctx);
+        }
+        assert_eq!(emulator.soc.cpu.pc, 0x0020);
+
+        // the pushed register is skipped
+        assert_eq!(backtrace(&emulator), vec![0x0014, 0x0003]);
+
+        // goto only moves the program counter
+        dbg_ctx.cmd.push(DebuggerCommand::GOTO(0x0080));
+        emulator.run(&mut dbg_ctx);
+        assert!(matches!(dbg_ctx.debugger_state, DebuggerState::HALT));
+        assert_eq!(emulator.soc.cpu.pc, 0x0080);
+        assert_eq!(backtrace(&emulator), vec![0x0014, 0x0003]);
+    }
+
+    #[test]
+    fn test_search() {
+        let mut emulator = create_emulator(&[0x00; 0x100]);
+        let mut dbg_ctx = DebugCtx::new();
+        emulator.limiter.unlimited = true;
+        emulator.soc.peripheral.write_byte(0xC000, 0x42);
+        emulator.soc.peripheral.write_byte(0xC001, 0x42);
+
+        // the RAM is filled with 0xFF, the written value is only found at its addresses
+        dbg_ctx.cmd.push(DebuggerCommand::SEARCH_VALUE(0x42));
+        emulator.run(&mut dbg_ctx);
+        assert_eq!(dbg_ctx.memory_search.as_ref().unwrap().candidates, [0xC000, 0xC001]);
+
+        emulator.soc.peripheral.write_byte(0xC001, 0x41);
+        dbg_ctx.cmd.push(DebuggerCommand::SEARCH_VALUE(0x41));
+        emulator.run(&mut dbg_ctx);
+        assert_eq!(dbg_ctx.memory_search.as_ref().unwrap().candidates, [0xC001]);
+
+        dbg_ctx.cmd.push(DebuggerCommand::SEARCH_RESET);
+        emulator.run(&mut dbg_ctx);
+        assert!(dbg_ctx.memory_search.is_none());
+    }
+
+    #[test]
+    fn test_dump_state() {
+        let mut emulator = create_emulator(&[0x00; 0x100]);
+        emulator.soc.cpu.pc = 0x0010;
+        emulator.soc.cpu.sp = 0xFFFE;
+        emulator.soc.cpu.registers.write_bc(0x1234);
+        emulator.soc.peripheral.nvic.master_enable(true);
+        emulator.soc.peripheral.write_byte(0xFFFF, 0x05);
+        emulator.soc.peripheral.write_byte(0xFF0F, 0x01);
+        emulator.soc.peripheral.write_byte(0xFF43, 0x42);
+        emulator.soc.peripheral.write_byte(0xFF06, 0x80);
+
+        let dump = dump_state(&emulator);
+
+        // the sections come in order, the cpu one is formatted like the debugger output
+        let sections: Vec<usize> = ["[cpu]", "[interrupts]", "[gpu]", "[timer]", "[mbc]"].iter().map(|section| dump.find(section).unwrap()).collect();
+        assert!(sections.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(dump.contains(&format_cpu_registers(&emulator)));
+
+        assert!(dump.contains("pc : 0x0010 / sp : 0xfffe"));
+        assert!(dump.contains("BC : 0x1234"));
+        // the unused bits of IF read as 1
+        assert!(dump.contains("IME : true / IE : 0x05 / IF : 0xe1"));
+        assert!(dump.contains("SCX : 0x42"));
+        assert!(dump.contains("LCDC : "));
+        assert!(dump.contains("OBP1 : "));
+        assert!(dump.contains("TMA : 0x80"));
+        assert!(dump.contains("[mbc]\nrom only\n"));
+    }
+}
@@ -1,8 +1,20 @@
 use crate::soc::Soc;
-pub use crate::soc::GameBoyKey;
+use crate::soc::peripheral::IoAccess;
+use crate::soc::peripheral::io::IoWriteLog;
+pub use crate::soc::{GameBoyKey, MemoryFill};
 use crate::cartridge::Cartridge;
-use std::time::Instant;
+use std::time::Duration;
+use std::fs::{self, File};
+use std::io::{BufWriter, Error, ErrorKind, Read, Result, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 use crate::debug::{DebugCtx, run_debug_mode};
+use crate::stats::FrameStats;
+use crate::limiter::FrameLimiter;
+use crate::palette::HostPalette;
+use crate::audio::AudioSink;
+use crate::watchdog::{Lockup, Watchdog};
+use crate::savestate::{SaveState, OLDEST_SAVE_STATE_VERSION, SAVE_STATE_VERSION, invalid_data, read_bytes, read_u8, read_u64, write_bytes, write_u8, write_u64};
 
 pub const SCREEN_HEIGHT: usize = 144;
 pub const SCREEN_WIDTH: usize = 160;
@@ -13,28 +25,72 @@ pub const ONE_SECOND_IN_CYCLES: usize = 4194304; // Main sys clock 4.194304 MHz
 pub const ONE_FRAME_IN_CYCLES: usize = 70224;
 pub const ONE_FRAME_IN_NS: usize = ONE_FRAME_IN_CYCLES * ONE_SECOND_IN_MICROS / ONE_SECOND_IN_CYCLES;
 
-#[derive(PartialEq)]
-pub enum EmulatorState {
-    GetTime,
-    RunMachine,
-    WaitNextFrame,
-    DisplayFrame,
+// print each instruction before it's run, annotated with the memory it reads or writes
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Trace {
+    MNEMONICS,
+    ANNOTATED,
 }
 
+// save states parameters
+const SAVE_STATE_MAGIC: &[u8; 5] = b"QOBOY";
+pub const NB_SAVE_STATE_SLOTS: u8 = 10;
+
+// battery backed ram is saved 2 seconds after the last write, at most every 10 seconds
+const SRAM_FLUSH_DELAY_CYCLES: u64 = 2 * ONE_SECOND_IN_CYCLES as u64;
+const SRAM_FLUSH_INTERVAL_CYCLES: u64 = 10 * ONE_SECOND_IN_CYCLES as u64;
+
 pub struct Emulator {
     // gameboy emulated hardware
     pub soc: Soc,
+    // kept to power cycle the system when the cartridge is swapped
+    boot_rom: Vec<u8>,
+    // game rom, empty for a blank cartridge, kept to power cycle the system on reset
+    rom: Vec<u8>,
+    memory_fill: Option<MemoryFill>,
+    boot_skipped: bool,
     // emulator internal parameters
-    pub state: EmulatorState,
+    // cycles run since the end of the previous frame
     pub cycles_elapsed_in_frame: usize,
-    pub frame_tick: Instant,
-    run_routine: fn(&mut Emulator, &mut DebugCtx),
+    // gpu frame count at the end of the previous frame
+    last_frame_count: u64,
+    // the last instruction run has ended a frame
+    frame_ended: bool,
+    // gpu frame count of the last fetched frame
+    fetched_frame_count: u64,
+    // frames rendered but not presented after each presented one, for slow hosts
+    pub frame_skip: u32,
+    // real time pacing, separated from the emulation
+    pub limiter: FrameLimiter,
+    // wall clock frame statistics
+    stats: FrameStats,
+    last_frame_tick: Option<Duration>,
+    // save state files are named after this path
+    state_path: PathBuf,
+    // battery backed ram save file
+    sram_path: Option<PathBuf>,
+    last_sram_flush_cycles: u64,
+    // lockup detection for unattended runs
+    watchdog: Option<Watchdog>,
+    lockup: Option<Lockup>,
+    trace: Option<Trace>,
+    // only the instructions at these addresses are traced, all of them when None
+    trace_range: Option<RangeInclusive<u16>>,
+    run_routine: fn(&mut Emulator, &mut DebugCtx) -> bool,
 }
 
 impl Emulator {
     pub fn new(boot_rom: &[u8], rom: &[u8], debug_on: bool) -> Emulator {
-        let cartridge = Cartridge::new(rom);
+        Emulator::with_cartridge(boot_rom, rom, Cartridge::new(rom), debug_on)
+    }
+
+    // run the boot rom alone, a blank cartridge is inserted
+    pub fn without_cartridge(boot_rom: &[u8], debug_on: bool) -> Emulator {
+        Emulator::with_cartridge(boot_rom, &[], Cartridge::blank(), debug_on)
+    }
 
+    fn with_cartridge(boot_rom: &[u8], rom: &[u8], cartridge: Cartridge, debug_on: bool) -> Emulator {
         let soc = Soc::new(boot_rom, cartridge);
 
         let run_routine = if debug_on {
@@ -46,60 +102,1196 @@ impl Emulator {
         Emulator {
             // gameboy emulated hardware
             soc: soc,
+            boot_rom: boot_rom.to_vec(),
+            rom: rom.to_vec(),
+            memory_fill: None,
+            boot_skipped: false,
             // emulator internal parameters
-            state: EmulatorState::GetTime,
             cycles_elapsed_in_frame: 0 as usize,
-            frame_tick: Instant::now(),
+            last_frame_count: 0,
+            frame_ended: false,
+            fetched_frame_count: 0,
+            frame_skip: 0,
+            limiter: FrameLimiter::new(Duration::from_nanos(ONE_FRAME_IN_NS as u64)),
+            stats: FrameStats::new(),
+            last_frame_tick: None,
+            state_path: PathBuf::from("qoboy"),
+            sram_path: None,
+            last_sram_flush_cycles: 0,
+            watchdog: None,
+            lockup: None,
+            trace: None,
+            trace_range: None,
             // debugger parameters
             run_routine: run_routine,
         }
     }
 
-    pub fn run(&mut self, dbg_cmd: &mut DebugCtx) {
-        (self.run_routine)(self, dbg_cmd);
+    // set the power on content of WRAM, HRAM and VRAM, shall be called before running the emulator
+    pub fn fill_memory(&mut self, fill: MemoryFill) {
+        self.memory_fill = Some(fill);
+        self.soc.fill_memory(fill);
     }
 
-    pub fn frame_ready(&self) -> bool {
-        if self.state == EmulatorState::DisplayFrame {
-            true
+    // swap the cartridge with the game rom at the given path and power cycle the system
+    // the battery backed ram of the current game is saved first, its save states and
+    // save file are then named after the new game
+    // the current game keeps running if the new rom can't be loaded
+    pub fn load_cartridge(&mut self, path: &Path) -> Result<()> {
+        let rom = fs::read(path)?;
+        let mut cartridge = Cartridge::try_new(&rom).map_err(|message| Error::new(ErrorKind::InvalidData, message))?;
+
+        // the save is read before the swap, so that the current game keeps running on failure
+        let sram_path = path.with_extension("sav");
+        let has_battery = cartridge.has_battery();
+        if has_battery {
+            read_sram(&mut cartridge, &sram_path)?;
+        }
+
+        self.flush_sram()?;
+        self.insert_cartridge(cartridge);
+        self.rom = rom;
+
+        self.state_path = path.to_path_buf();
+        if has_battery {
+            self.sram_path = Some(sram_path);
+        }
+        Ok(())
+    }
+
+    // power cycle the system with the same game, its battery backed ram is saved then restored
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn reset(&mut self) -> Result<()> {
+        self.flush_sram()?;
+        let sram_path = self.sram_path.take();
+
+        let cartridge = if self.rom.is_empty() { Cartridge::blank() } else { Cartridge::new(&self.rom) };
+        self.insert_cartridge(cartridge);
+
+        match sram_path {
+            Some(path) => self.load_sram(&path),
+            None => Ok(()),
+        }
+    }
+
+    fn insert_cartridge(&mut self, cartridge: Cartridge) {
+        self.soc.reset(&self.boot_rom, cartridge);
+        if let Some(fill) = self.memory_fill {
+            self.soc.fill_memory(fill);
+        }
+        if self.boot_skipped {
+            self.soc.skip_boot();
+        }
+
+        self.cycles_elapsed_in_frame = 0;
+        self.last_frame_count = 0;
+        self.fetched_frame_count = 0;
+        self.limiter.reset();
+        self.sram_path = None;
+        self.last_sram_flush_cycles = 0;
+        self.lockup = None;
+        if let Some(watchdog) = self.watchdog.as_mut() {
+            watchdog.reset();
+        }
+    }
+
+    // don't run the boot rom, shall be called before running the emulator
+    pub fn skip_boot(&mut self) {
+        self.boot_skipped = true;
+        self.soc.skip_boot();
+    }
+
+    // run the emulated system when the limiter allows it, true when a frame has ended
+    pub fn run(&mut self, dbg_cmd: &mut DebugCtx) -> bool {
+        (self.run_routine)(self, dbg_cmd)
+    }
+
+    // run the emulated system until the end of the frame, without waiting for the frame duration
+    // true when the gpu has completed a frame, no frame is produced while the LCD is off
+    pub fn run_frame(&mut self) -> bool {
+        let frame_count = self.soc.peripheral.gpu.frame_count;
+
+        while self.lockup.is_none() && !self.step() {}
+
+        self.soc.peripheral.gpu.frame_count != frame_count
+    }
+
+    // run a single instruction, true when it ends the frame: the gpu has entered the vblank,
+    // or one frame duration has elapsed while the LCD is off
+    pub fn step(&mut self) -> bool {
+        self.frame_ended = false;
+        self.run_cycles(1);
+
+        self.frame_ended
+    }
+
+    // run the emulated system for at least the given number of clock ticks
+    // instructions can't be split so the actual number of ticks run is returned
+    // the emulated system isn't run anymore once the watchdog detected a lockup
+    pub fn run_cycles(&mut self, cycles: u64) -> u64 {
+        let start_cycles = self.soc.cycles;
+
+        while self.lockup.is_none() && self.soc.cycles - start_cycles < cycles {
+            if let Some(trace_line) = self.trace_line() {
+                println!("{}", trace_line);
+            }
+
+            let previous_pc = self.soc.cpu.pc;
+            let step_cycles = self.soc.run();
+            self.cycles_elapsed_in_frame += step_cycles as usize;
+
+            if let Some(watchdog) = self.watchdog.as_mut() {
+                let can_exit_loop = self.soc.can_exit_loop();
+                let current_line = self.soc.peripheral.gpu.current_line;
+                self.lockup = watchdog.step(previous_pc, self.soc.cpu.pc, can_exit_loop, current_line, step_cycles);
+            }
+
+            // the cpu never recovers from an undefined opcode, with or without watchdog
+            if self.soc.cpu.is_locked() {
+                let pc = self.soc.cpu.pc;
+                self.lockup = Some(Lockup::UndefinedOpcode(pc, self.soc.peripheral.read_byte(pc)));
+            }
+
+            let frame_count = self.soc.peripheral.gpu.frame_count;
+            if frame_count != self.last_frame_count || self.cycles_elapsed_in_frame >= ONE_FRAME_IN_CYCLES {
+                self.last_frame_count = frame_count;
+                self.cycles_elapsed_in_frame = 0;
+                self.frame_ended = true;
+                self.soc.end_of_frame();
+
+                if let Some(watchdog) = self.watchdog.as_mut() {
+                    let lcd_enabled = self.soc.peripheral.gpu.lcd_display_enabled;
+                    self.lockup = self.lockup.or(watchdog.end_of_frame(lcd_enabled));
+                }
+            }
+        }
+
+        self.soc.cycles - start_cycles
+    }
+
+    // restore the battery backed ram from its save file, if the cartridge has a battery
+    // the ram is then written back to this file by flush_sram
+    pub fn load_sram(&mut self, path: &Path) -> Result<()> {
+        if !self.soc.peripheral.cartridge.has_battery() {
+            return Ok(());
+        }
+
+        self.sram_path = Some(path.to_path_buf());
+        read_sram(&mut self.soc.peripheral.cartridge, path)
+    }
+
+    // write the battery backed ram if it has been modified, returns true if the file is written
+    pub fn flush_sram(&mut self) -> Result<bool> {
+        let path = match &self.sram_path {
+            Some(path) if self.soc.peripheral.cartridge.is_ram_dirty() => path.clone(),
+            _ => return Ok(false),
+        };
+
+        // the save file is replaced at once so that a crash can't leave it half written
+        let temp_path = path.with_extension("sav.tmp");
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        self.soc.peripheral.cartridge.save_ram(&mut writer)?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&temp_path, &path)?;
+
+        self.last_sram_flush_cycles = self.soc.cycles;
+        Ok(true)
+    }
+
+    // flush the battery backed ram once the game stopped writing it for a while
+    pub fn auto_flush_sram(&mut self) -> Result<bool> {
+        let cartridge = &self.soc.peripheral.cartridge;
+        let write_done = cartridge.cycles_since_ram_write() >= SRAM_FLUSH_DELAY_CYCLES;
+        let interval_elapsed = self.soc.cycles - self.last_sram_flush_cycles >= SRAM_FLUSH_INTERVAL_CYCLES;
+
+        if cartridge.is_ram_dirty() && write_done && interval_elapsed {
+            self.flush_sram()
         } else {
-            false
+            Ok(false)
+        }
+    }
+
+    // lockups are detected only if a watchdog is set, the timeout is given in frames
+    // an undefined opcode is always reported as a lockup
+    pub fn set_watchdog(&mut self, timeout_frames: Option<u32>) {
+        self.watchdog = timeout_frames.map(Watchdog::new);
+        self.lockup = None;
+    }
+
+    pub fn lockup(&self) -> Option<Lockup> {
+        self.lockup
+    }
+
+    // print the writes to the I/O registers, all of them when the filter is empty
+    pub fn set_io_write_log(&mut self, filter: Option<Vec<u16>>) {
+        self.soc.peripheral.io_write_log = filter.map(IoWriteLog::new);
+    }
+
+    // keep the bytes sent through the link port for output_serial
+    pub fn set_serial_output(&mut self, enabled: bool) {
+        self.soc.peripheral.set_serial_output(enabled);
+    }
+
+    // write the bytes sent through the link port since the last call as text, the line breaks
+    // and tabs are kept, the other non printable bytes are written as \xNN
+    pub fn output_serial(&mut self, writer: &mut dyn Write) -> Result<()> {
+        let text: String = self.soc.peripheral.take_serial_output().unwrap_or_default().iter().map(|byte| match byte {
+            b'\n' | b'\r' | b'\t' | 0x20..=0x7E => (*byte as char).to_string(),
+            _ => format!("\\x{:02X}", byte),
+        }).collect();
+
+        writer.write_all(text.as_bytes())
+    }
+
+    pub fn set_trace(&mut self, trace: Option<Trace>) {
+        self.trace = trace;
+    }
+
+    // scope the trace to a routine, given by the addresses of its instructions
+    pub fn set_trace_range(&mut self, range: Option<RangeInclusive<u16>>) {
+        self.trace_range = range;
+    }
+
+    // trace of the next instruction, none when the trace is off, the cpu is halted
+    // or the instruction is out of the trace range
+    fn trace_line(&self) -> Option<String> {
+        let trace = self.trace?;
+        if self.soc.cpu.is_halted() || self.trace_range.as_ref().is_some_and(|range| !range.contains(&self.soc.cpu.pc)) {
+            return None;
+        }
+
+        let disassembly = self.soc.cpu.disassemble(&self.soc.peripheral);
+        Some(disassembly.trace_line(&self.soc.peripheral, trace == Trace::ANNOTATED))
+    }
+
+    // clock ticks executed since power on
+    pub fn cycles(&self) -> u64 {
+        self.soc.cycles
+    }
+
+    pub fn emulated_seconds(&self) -> f64 {
+        self.soc.cycles as f64 / ONE_SECOND_IN_CYCLES as f64
+    }
+
+    pub fn save_state(&self, writer: &mut dyn Write) -> Result<()> {
+        write_bytes(writer, SAVE_STATE_MAGIC)?;
+        write_u8(writer, SAVE_STATE_VERSION)?;
+        self.soc.save_state(writer)?;
+        write_u64(writer, self.cycles_elapsed_in_frame as u64)
+    }
+
+    // the emulator is left untouched if the state can't be loaded
+    pub fn load_state(&mut self, reader: &mut dyn Read) -> Result<()> {
+        let mut magic = [0; 5];
+        read_bytes(reader, &mut magic)?;
+        if &magic != SAVE_STATE_MAGIC {
+            return Err(invalid_data("not a save state file"));
+        }
+
+        let version = read_u8(reader)?;
+        if version > SAVE_STATE_VERSION {
+            return Err(invalid_data(&format!("save state version {} is newer than the supported ones, up to {}", version, SAVE_STATE_VERSION)));
+        }
+        if version < OLDEST_SAVE_STATE_VERSION {
+            return Err(invalid_data(&format!("save state version {} is no longer supported, from {}", version, OLDEST_SAVE_STATE_VERSION)));
+        }
+
+        let mut backup: Vec<u8> = Vec::new();
+        self.soc.save_state(&mut backup)?;
+
+        let result = self.soc.load_state_version(reader, version).and_then(|_| read_u64(reader));
+        match result {
+            Ok(cycles_elapsed_in_frame) => {
+                self.cycles_elapsed_in_frame = cycles_elapsed_in_frame as usize;
+                Ok(())
+            }
+            Err(error) => {
+                self.soc.load_state(&mut &backup[..])?;
+                Err(error)
+            }
+        }
+    }
+
+    // save state slots are stored next to the given file, ie game.gb gives game.ss0 to game.ss9
+    pub fn set_state_path(&mut self, path: &Path) {
+        self.state_path = path.to_path_buf();
+    }
+
+    fn get_state_slot_path(&self, slot: u8) -> Result<PathBuf> {
+        if slot >= NB_SAVE_STATE_SLOTS {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("save state slot {} doesn't exist", slot)));
+        }
+
+        Ok(self.state_path.with_extension(format!("ss{}", slot)))
+    }
+
+    // the state dump is written next to the save states
+    pub fn get_state_dump_path(&self) -> PathBuf {
+        self.state_path.with_extension("dump.txt")
+    }
+
+    pub fn save_state_slot(&self, slot: u8) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(self.get_state_slot_path(slot)?)?);
+        self.save_state(&mut writer)?;
+        writer.flush()
+    }
+
+    // loading an empty slot returns a NotFound error
+    pub fn load_state_slot(&mut self, slot: u8) -> Result<()> {
+        let mut data: Vec<u8> = Vec::new();
+        File::open(self.get_state_slot_path(slot)?)?.read_to_end(&mut data)?;
+        self.load_state(&mut &data[..])
+    }
+
+    pub fn stats(&self) -> &FrameStats {
+        &self.stats
+    }
+
+    // save the time elapsed since the previous displayed frame, measured with the limiter clock
+    // it's shared by the skipped frames so that the statistics follow the emulation speed
+    fn record_frame_time(&mut self) {
+        let now = self.limiter.now();
+
+        if let Some(last_frame_tick) = self.last_frame_tick {
+            self.stats.record_frame((now - last_frame_tick) / (self.frame_skip + 1));
         }
+
+        self.last_frame_tick = Some(now);
     }
 
-    pub fn get_frame_buffer(&self, pixel_index: usize) -> u8 {
-        self.soc.get_frame_buffer(pixel_index)
+    // the gpu has completed a frame since the last fetch, and the skipped frames before it
+    // the gpu renders all the frames, the skipped ones are only not presented
+    pub fn frame_ready(&self) -> bool {
+        self.soc.peripheral.gpu.frame_count.wrapping_sub(self.fetched_frame_count) > self.frame_skip as u64
+    }
+
+    // get the last frame completed by the gpu, it isn't ready anymore until the next one
+    pub fn fetch_frame(&mut self) -> &[u8] {
+        self.fetched_frame_count = self.soc.peripheral.gpu.frame_count;
+        self.record_frame_time();
+
+        &self.soc.peripheral.gpu.frame_buffer
+    }
+
+    // send the samples produced by the APU since the last call to the audio backend
+    pub fn output_audio(&mut self, sink: &mut dyn AudioSink) {
+        let samples = self.soc.peripheral.apu.take_samples();
+        sink.push_samples(&samples);
+    }
+
+    // palette each pixel of the last frame was drawn with, the background or an object one
+    pub fn get_frame_palettes(&self) -> &[u8] {
+        &self.soc.peripheral.gpu.palette_buffer
+    }
+
+    // colors the CGB boot rom would give to the inserted DMG game, if any
+    pub fn compatibility_palette(&self) -> Option<HostPalette> {
+        HostPalette::for_cartridge(&self.soc.peripheral.cartridge)
+    }
+
+    // whole background map, the screen shows the part at the viewport position
+    pub fn get_background_map(&self) -> Vec<u8> {
+        self.soc.peripheral.gpu.draw_background_map()
+    }
+
+    // RGBA copy of the screen centered in a transparent frame, for compositing
+    pub fn get_rgba_frame(&self, width: usize, height: usize) -> Vec<u8> {
+        self.soc.peripheral.gpu.draw_rgba_frame(width, height)
+    }
+
+    pub fn get_viewport_position(&self) -> (u8, u8) {
+        (self.soc.peripheral.gpu.get_scx(), self.soc.peripheral.gpu.get_scy())
     }
 
     pub fn set_key(&mut self, key: GameBoyKey, value: bool) {
         self.soc.set_key(key, value);
     }
+
+    // latch short presses of a key for at least the given number of frames
+    pub fn set_key_min_hold_frames(&mut self, key: GameBoyKey, frames: u8) {
+        self.soc.set_key_min_hold_frames(key, frames);
+    }
+
+    // auto-fire: a held key is released and pressed again every given number of frames, 0 disables it
+    pub fn set_key_turbo(&mut self, key: GameBoyKey, frames: u8) {
+        self.soc.set_key_turbo(key, frames);
+    }
 }
 
-fn run_normal_mode(emulator: &mut Emulator, _dbg_ctx: &mut DebugCtx) {
-    match emulator.state {
-        EmulatorState::GetTime => {
-            emulator.frame_tick = Instant::now();
+fn run_normal_mode(emulator: &mut Emulator, _dbg_ctx: &mut DebugCtx) -> bool {
+    if !emulator.limiter.frame_due() {
+        return false;
+    }
+
+    emulator.run_frame();
+    true
+}
 
-            emulator.state = EmulatorState::RunMachine;
+// restore the battery backed ram of a cartridge from its save file, if any
+fn read_sram(cartridge: &mut Cartridge, path: &Path) -> Result<()> {
+    match File::open(path) {
+        Ok(mut file) => {
+            let mut data: Vec<u8> = Vec::new();
+            file.read_to_end(&mut data)?;
+            cartridge.load_ram(&mut &data[..])
         }
-        EmulatorState::RunMachine => {
-            emulator.cycles_elapsed_in_frame += emulator.soc.run() as usize;
+        // nothing saved yet
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(()),
+        Err(error) => Err(error),
+    }
+}
+
+// roms shared by the tests driving an emulator
+#[cfg(test)]
+pub(crate) mod test_fixtures {
+    use super::Emulator;
+    use crate::cartridge::{CARTRIDGE_TYPE_OFFSET, CARTRIDGE_RAM_SIZE_OFFSET, CARTRIDGE_ROM_SIZE_OFFSET};
+
+    // JR -2: loop forever in the boot rom
+    pub fn looping_boot_rom() -> [u8; 0x100] {
+        let mut boot_rom = [0x00; 0x100];
+        boot_rom[0x00] = 0x18;
+        boot_rom[0x01] = 0xFE;
+        boot_rom
+    }
+
+    // 32 KB rom without mbc nor ram, blank outside of its header
+    pub fn blank_rom() -> [u8; 0x8000] {
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        rom
+    }
+
+    // JR -2 at the entry point: the game loops forever once booted
+    pub fn looping_rom() -> [u8; 0x8000] {
+        let mut rom = blank_rom();
+        rom[0x0100] = 0x18;
+        rom[0x0101] = 0xFE;
+        rom
+    }
+
+    // the cpu never leaves the boot rom, the tests drive the peripherals
+    pub fn looping_emulator() -> Emulator {
+        Emulator::new(&looping_boot_rom(), &blank_rom(), false)
+    }
+}
+
+#[cfg(test)]
+mod emulator_tests {
+    use super::*;
+    use crate::cartridge::{CARTRIDGE_CGB_FLAG_OFFSET, CARTRIDGE_TYPE_OFFSET, CARTRIDGE_RAM_SIZE_OFFSET, CARTRIDGE_HEADER_CHECKSUM_OFFSET};
+    use super::test_fixtures::{blank_rom, looping_boot_rom, looping_emulator, looping_rom};
+    use crate::soc::peripheral::bootrom::BUILTIN_BOOT_ROM;
+    use crate::soc::peripheral::gpu::{GpuMode, ObjectPriority};
+    use crate::soc::peripheral::{DMA_BLOCKED_READ, OAM_SIZE, WORKING_RAM_BEGIN, ZERO_PAGE_BEGIN};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // longest instruction: 6 machine cycles
+    const MAX_INSTRUCTION_CYCLES: u64 = 24;
+
+    // the boot rom hands the cpu over to the game at this address
+    const POST_BOOT_PC: u16 = 0x0100;
+    // the DMG boot rom takes about 2.5 seconds, with the logo scrolling
+    const BOOT_CYCLES_BUDGET: u64 = 4 * ONE_SECOND_IN_CYCLES as u64;
+
+    // the cartridge logo is checked by the boot rom against its own copy
+    const BOOT_ROM_LOGO_OFFSET: usize = 0xA8;
+    const CARTRIDGE_LOGO_OFFSET: usize = 0x104;
+    const LOGO_SIZE: usize = 48;
+    const CARTRIDGE_TITLE_OFFSET: usize = 0x134;
+
+    // a rom only cartridge with a header which passes the boot rom checks
+    fn create_bootable_rom(boot_rom: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0x00; 0x8000];
+        rom[CARTRIDGE_LOGO_OFFSET..CARTRIDGE_LOGO_OFFSET + LOGO_SIZE]
+            .copy_from_slice(&boot_rom[BOOT_ROM_LOGO_OFFSET..BOOT_ROM_LOGO_OFFSET + LOGO_SIZE]);
+
+        let checksum = rom[CARTRIDGE_TITLE_OFFSET..CARTRIDGE_HEADER_CHECKSUM_OFFSET as usize]
+            .iter()
+            .fold(0u8, |checksum, byte| checksum.wrapping_sub(*byte).wrapping_sub(1));
+        rom[CARTRIDGE_HEADER_CHECKSUM_OFFSET as usize] = checksum;
+
+        rom
+    }
+
+    // run the boot rom until it hands the cpu over to the cartridge, the number of clock ticks is returned
+    // an unimplemented opcode panics, a lockup or a boot longer than the budget is an error
+    fn run_boot_rom(boot_rom: &[u8], rom: &[u8], cycles_budget: u64) -> std::result::Result<u64, String> {
+        let mut emulator = Emulator::new(boot_rom, rom, false);
+        emulator.set_watchdog(Some(10));
 
-            if emulator.cycles_elapsed_in_frame >= ONE_FRAME_IN_CYCLES {
-                emulator.cycles_elapsed_in_frame = 0;
-                emulator.state = EmulatorState::WaitNextFrame;
+        while emulator.soc.cpu.pc != POST_BOOT_PC {
+            if let Some(lockup) = emulator.lockup() {
+                return Err(format!("the boot rom is locked up, {}", lockup));
             }
+            if emulator.cycles() >= cycles_budget {
+                return Err(format!("the boot rom didn't finish within {} cycles, pc {:#06x}", cycles_budget, emulator.soc.cpu.pc));
+            }
+
+            emulator.run_cycles(1);
+        }
+
+        Ok(emulator.cycles())
+    }
+
+    #[test]
+    fn test_run_boot_rom() {
+        // LD A, 0x01 / LDH (0x50), A at the end of the boot rom, then the cpu reaches 0x0100
+        let mut boot_rom = [0x00; 0x100];
+        boot_rom[0xFC..].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+        let rom = create_bootable_rom(&boot_rom);
+        assert!(run_boot_rom(&boot_rom, &rom, BOOT_CYCLES_BUDGET).is_ok());
+
+        // JR -2: the boot rom never finishes
+        boot_rom[0x00] = 0x18;
+        boot_rom[0x01] = 0xFE;
+        assert!(run_boot_rom(&boot_rom, &rom, BOOT_CYCLES_BUDGET).is_err());
+
+        // too long
+        let boot_rom = [0x00; 0x100];
+        assert!(run_boot_rom(&boot_rom, &rom, 0x40).is_err());
+    }
+
+    // smoke test of the fundamental instructions, needs the DMG boot rom:
+    // QOBOY_BOOT_ROM=<boot_rom_path> cargo test dmg_boot_rom -- --ignored
+    #[test]
+    #[ignore]
+    fn test_dmg_boot_rom() {
+        let path = std::env::var("QOBOY_BOOT_ROM").expect("QOBOY_BOOT_ROM shall give the path of the DMG boot rom");
+        let boot_rom = std::fs::read(&path).unwrap();
+        let rom = create_bootable_rom(&boot_rom);
+
+        match run_boot_rom(&boot_rom, &rom, BOOT_CYCLES_BUDGET) {
+            Ok(cycles) => println!("boot done in {} cycles", cycles),
+            Err(message) => panic!("{}", message),
         }
-        EmulatorState::WaitNextFrame => {
-            // check if 16,742706 ms have passed during this frame
-            if emulator.frame_tick.elapsed().as_nanos() >= ONE_FRAME_IN_NS as u128{
-                emulator.state = EmulatorState::DisplayFrame;
+    }
+
+    #[test]
+    fn test_builtin_boot_rom() {
+        // a DMG game then a CGB one, started in CGB mode with the CGB registers
+        let mut rom = looping_rom();
+
+        for cgb_flag in [0x00, 0xC0] {
+            rom[CARTRIDGE_CGB_FLAG_OFFSET as usize] = cgb_flag;
+            assert!(run_boot_rom(&BUILTIN_BOOT_ROM, &rom, BOOT_CYCLES_BUDGET).is_ok());
+
+            let mut emulator = Emulator::new(&BUILTIN_BOOT_ROM, &rom, false);
+            while emulator.soc.cpu.pc != POST_BOOT_PC {
+                emulator.run_cycles(1);
             }
+
+            // the boot rom is unmapped and the registers are left as the skipped boot sets them
+            let mut skipped_boot = Emulator::new(&BUILTIN_BOOT_ROM, &rom, false);
+            skipped_boot.skip_boot();
+            assert_eq!(emulator.soc.peripheral.read_byte(0x0000), rom[0x0000]);
+            let (registers, expected) = (&emulator.soc.cpu.registers, &skipped_boot.soc.cpu.registers);
+            let expected_af = if cgb_flag == 0x00 { 0x01B0 } else { 0x1180 };
+            assert_eq!(registers.read_af(), expected_af, "CGB flag {:#04x}", cgb_flag);
+            assert_eq!(emulator.soc.peripheral.gpu.cgb_mode, cgb_flag != 0x00);
+            assert_eq!(emulator.soc.peripheral.gpu.cgb_mode, skipped_boot.soc.peripheral.gpu.cgb_mode);
+            assert_eq!(registers.read_af(), expected.read_af());
+            assert_eq!(registers.read_bc(), expected.read_bc());
+            assert_eq!(registers.read_de(), expected.read_de());
+            assert_eq!(registers.read_hl(), expected.read_hl());
+            assert_eq!(emulator.soc.cpu.sp, skipped_boot.soc.cpu.sp);
+            for register in [0xFF02, 0xFF40, 0xFF47, 0xFF6C] {
+                assert_eq!(emulator.soc.peripheral.read_byte(register), skipped_boot.soc.peripheral.read_byte(register));
+            }
+        }
+    }
+
+    #[test]
+    fn test_cycles() {
+        let mut emulator = looping_emulator();
+        assert_eq!(emulator.cycles(), 0);
+
+        for _ in 0..60 {
+            emulator.run_frame();
         }
-        EmulatorState::DisplayFrame => {
-            emulator.state = EmulatorState::GetTime;
+
+        // a frame may overshoot by one instruction
+        let expected_cycles = 60 * ONE_FRAME_IN_CYCLES as u64;
+        assert!(emulator.cycles() >= expected_cycles);
+        assert!(emulator.cycles() < expected_cycles + 60 * MAX_INSTRUCTION_CYCLES);
+
+        // 60 frames last about one second
+        assert!((emulator.emulated_seconds() - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_cpu_speed() {
+        // instructions run in a frame, from vblank to vblank
+        let instructions_per_frame = |cpu_speed: f64| {
+            let mut emulator = looping_emulator();
+            emulator.soc.cpu_speed = cpu_speed;
+            emulator.soc.peripheral.write_byte(0xFF40, 0x80);
+            emulator.run_frame();
+
+            let mut instructions = 0;
+            while !emulator.step() {
+                instructions += 1;
+            }
+            instructions + 1
+        };
+
+        let nominal = instructions_per_frame(1.0) as f64;
+        assert!((instructions_per_frame(2.0) as f64 / nominal - 2.0).abs() < 0.01);
+        assert!((instructions_per_frame(0.5) as f64 / nominal - 0.5).abs() < 0.01);
+
+        // the emulated time follows the ppu, a frame lasts the same whatever the cpu speed
+        let mut emulator = looping_emulator();
+        emulator.soc.cpu_speed = 2.0;
+        for _ in 0..60 {
+            emulator.run_frame();
         }
+        assert!((emulator.emulated_seconds() - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_run_frame() {
+        let mut emulator = looping_emulator();
+
+        // no frame is produced while the LCD is off, the frame lasts its nominal duration
+        assert!(!emulator.run_frame());
+        assert!(!emulator.frame_ready());
+        assert!(emulator.cycles() >= ONE_FRAME_IN_CYCLES as u64);
+
+        // the frame is run until the vblank starts
+        emulator.soc.peripheral.write_byte(0xFF40, 0x80);
+        assert!(emulator.run_frame());
+        assert_eq!(emulator.soc.peripheral.gpu.frame_count, 1);
+        assert_eq!(emulator.soc.peripheral.gpu.current_line, SCREEN_HEIGHT as u8);
+        assert_eq!(emulator.soc.peripheral.gpu.mode, GpuMode::VerticalBlank);
+
+        // the next frames are complete ones, from vblank to vblank
+        let start_cycles = emulator.cycles();
+        assert!(emulator.run_frame());
+        assert_eq!(emulator.soc.peripheral.gpu.frame_count, 2);
+        assert!(emulator.cycles() - start_cycles < ONE_FRAME_IN_CYCLES as u64 + MAX_INSTRUCTION_CYCLES);
+    }
+
+    #[test]
+    fn test_frame_ready() {
+        let mut emulator = looping_emulator();
+        emulator.soc.peripheral.write_byte(0xFF40, 0x80);
+
+        // a completed frame is ready once, until it's fetched
+        emulator.run_frame();
+        assert!(emulator.frame_ready());
+        assert_eq!(emulator.fetch_frame().len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+        assert!(!emulator.frame_ready());
+
+        // the limiter paces the frames, the next one isn't run right away
+        assert!(emulator.run(&mut DebugCtx::new()));
+        assert!(emulator.frame_ready());
+        emulator.fetch_frame();
+        assert!(!emulator.run(&mut DebugCtx::new()));
+        assert!(!emulator.frame_ready());
+
+        emulator.limiter.unlimited = true;
+        assert!(emulator.run(&mut DebugCtx::new()));
+        assert!(emulator.frame_ready());
+    }
+
+    #[test]
+    fn test_frame_skip() {
+        let mut emulator = looping_emulator();
+        emulator.soc.peripheral.write_byte(0xFF40, 0x80);
+        emulator.frame_skip = 2;
+
+        // one frame out of 3 is presented
+        let mut ready_frames = 0;
+        for _ in 0..9 {
+            emulator.run_frame();
+            if emulator.frame_ready() {
+                ready_frames += 1;
+                emulator.fetch_frame();
+            }
+        }
+        assert_eq!(ready_frames, 3);
+
+        // the skipped frames are emulated all the same
+        assert_eq!(emulator.soc.peripheral.gpu.frame_count, 9);
+        let expected_cycles = 9 * ONE_FRAME_IN_CYCLES as u64;
+        assert!(emulator.cycles() >= expected_cycles - ONE_FRAME_IN_CYCLES as u64);
+        assert!(emulator.cycles() < expected_cycles + 9 * MAX_INSTRUCTION_CYCLES);
+    }
+
+    #[test]
+    fn test_virtual_clock() {
+        let mut emulator = looping_emulator();
+        emulator.soc.peripheral.write_byte(0xFF40, 0x80);
+        let time = Rc::new(Cell::new(Duration::from_nanos(0)));
+        let clock_time = Rc::clone(&time);
+        emulator.limiter.clock = Box::new(move || clock_time.get());
+        let one_frame = Duration::from_nanos(ONE_FRAME_IN_NS as u64);
+
+        // the frames are run at the injected times only
+        for frame in 0..3 {
+            time.set(one_frame * frame);
+            assert!(emulator.run(&mut DebugCtx::new()));
+            emulator.fetch_frame();
+            time.set(one_frame * (frame + 1) - Duration::from_nanos(1));
+            assert!(!emulator.run(&mut DebugCtx::new()));
+            assert!(!emulator.frame_ready());
+        }
+
+        // the frame statistics are measured with the same clock
+        assert!((emulator.stats().fps() * one_frame.as_secs_f64() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_run_cycles() {
+        let mut emulator = looping_emulator();
+
+        for budget in [1, 10, 100, 1000, ONE_FRAME_IN_CYCLES as u64 + 1] {
+            let start_cycles = emulator.cycles();
+            let cycles = emulator.run_cycles(budget);
+
+            assert!(cycles >= budget);
+            assert!(cycles < budget + MAX_INSTRUCTION_CYCLES);
+            assert_eq!(emulator.cycles() - start_cycles, cycles);
+        }
+    }
+
+    #[test]
+    fn test_serial_output() {
+        // send "Hi", 0x01 and a line break through the link port, then loop forever
+        let mut boot_rom = [0x00; 0x100];
+        let mut program = Vec::new();
+        for byte in [b'H', b'i', 0x01, b'\n'] {
+            // LD A,byte / LDH (SB),A / LD A,0x81 / LDH (SC),A
+            program.extend_from_slice(&[0x3E, byte, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02]);
+        }
+        program.extend_from_slice(&[0x18, 0xFE]);
+        boot_rom[..program.len()].copy_from_slice(&program);
+
+        let rom = blank_rom();
+
+        // nothing is written while the output is disabled
+        let mut emulator = Emulator::new(&boot_rom, &rom, false);
+        emulator.run_frame();
+        let mut output = Vec::new();
+        emulator.output_serial(&mut output).unwrap();
+        assert!(output.is_empty());
+
+        // the non printable bytes are escaped, the bytes are written once
+        let mut emulator = Emulator::new(&boot_rom, &rom, false);
+        emulator.set_serial_output(true);
+        emulator.run_frame();
+        emulator.output_serial(&mut output).unwrap();
+        emulator.output_serial(&mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "Hi\\x01\n");
+
+        // the output stays enabled after a reset
+        emulator.reset().unwrap();
+        assert_eq!(emulator.soc.peripheral.take_serial_output(), Some(Vec::new()));
+    }
+
+    #[test]
+    fn test_trace_range() {
+        let rom = blank_rom();
+        // NOP all over the boot rom
+        let mut emulator = Emulator::new(&[0x00; 0x100], &rom, false);
+        assert_eq!(emulator.trace_line(), None);
+
+        emulator.set_trace(Some(Trace::MNEMONICS));
+        emulator.set_trace_range(Some(0x0002..=0x0003));
+
+        let mut traced = Vec::new();
+        for _ in 0..6 {
+            if let Some(trace_line) = emulator.trace_line() {
+                traced.push(trace_line);
+            }
+            emulator.step();
+        }
+        assert_eq!(emulator.soc.cpu.pc, 0x0006);
+
+        // only the instructions inside the range are traced
+        assert_eq!(traced.len(), 2);
+        assert!(traced[0].starts_with("0x0002:"));
+        assert!(traced[1].starts_with("0x0003:"));
+    }
+
+    #[test]
+    fn test_save_state_slots() {
+        let mut emulator = looping_emulator();
+        let state_dir = std::env::temp_dir().join(format!("qoboy_test_save_state_slots_{}", std::process::id()));
+        std::fs::create_dir_all(&state_dir).unwrap();
+        emulator.set_state_path(&state_dir.join("game.gb"));
+
+        // slot 3 and slot 0 hold different states
+        emulator.run_frame();
+        emulator.soc.peripheral.write_byte(0xC000, 0x33);
+        emulator.save_state_slot(3).unwrap();
+        let slot_3_cycles = emulator.cycles();
+
+        emulator.run_frame();
+        emulator.soc.peripheral.write_byte(0xC000, 0x00);
+        emulator.save_state_slot(0).unwrap();
+        let slot_0_cycles = emulator.cycles();
+        assert!(state_dir.join("game.ss3").exists());
+        assert!(state_dir.join("game.ss0").exists());
+
+        emulator.run_frame();
+        emulator.load_state_slot(3).unwrap();
+        assert_eq!(emulator.cycles(), slot_3_cycles);
+        assert_eq!(emulator.soc.peripheral.read_byte(0xC000), 0x33);
+
+        emulator.load_state_slot(0).unwrap();
+        assert_eq!(emulator.cycles(), slot_0_cycles);
+        assert_eq!(emulator.soc.peripheral.read_byte(0xC000), 0x00);
+
+        // empty and unknown slots are reported without modifying the emulator
+        assert_eq!(emulator.load_state_slot(5).unwrap_err().kind(), ErrorKind::NotFound);
+        assert_eq!(emulator.load_state_slot(NB_SAVE_STATE_SLOTS).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(emulator.cycles(), slot_0_cycles);
+
+        std::fs::remove_dir_all(&state_dir).unwrap();
     }
-}
 
+    #[test]
+    fn test_load_invalid_state() {
+        let mut emulator = looping_emulator();
+        emulator.run_frame();
+
+        let mut state: Vec<u8> = Vec::new();
+        emulator.save_state(&mut state).unwrap();
+        let cycles = emulator.cycles();
+        emulator.run_frame();
+
+        // a truncated state is rejected and the current state is kept
+        let truncated_state = &state[..state.len() / 2];
+        assert!(emulator.load_state(&mut &truncated_state[..]).is_err());
+        assert_eq!(emulator.cycles(), 2 * cycles);
+
+        let mut bad_magic = state.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(emulator.load_state(&mut &bad_magic[..]).unwrap_err().kind(), ErrorKind::InvalidData);
+
+        // the format version follows the magic
+        let mut newer_version = state.clone();
+        newer_version[SAVE_STATE_MAGIC.len()] = SAVE_STATE_VERSION + 1;
+        let error = emulator.load_state(&mut &newer_version[..]).unwrap_err();
+        assert_eq!(error.to_string(), format!("save state version {} is newer than the supported ones, up to {}", SAVE_STATE_VERSION + 1, SAVE_STATE_VERSION));
+        assert_eq!(emulator.cycles(), 2 * cycles);
+
+        emulator.load_state(&mut &state[..]).unwrap();
+        assert_eq!(emulator.cycles(), cycles);
+    }
+
+    #[test]
+    fn test_load_state_migration() {
+        // the state is saved on the 4th line of the vblank
+        let mut emulator = looping_emulator();
+        emulator.soc.peripheral.write_byte(0xFF40, 0x80);
+        emulator.run_frame();
+        emulator.run_cycles(3 * 456 + 100);
+        assert_eq!(emulator.soc.peripheral.gpu.current_line, 147);
+
+        // only the gpu layout has changed since the version 1
+        let mut state: Vec<u8> = Vec::new();
+        emulator.save_state(&mut state).unwrap();
+        let mut gpu_state: Vec<u8> = Vec::new();
+        emulator.soc.peripheral.gpu.save_state(&mut gpu_state).unwrap();
+        let mut v1_gpu_state: Vec<u8> = Vec::new();
+        emulator.soc.peripheral.gpu.save_state_version(&mut v1_gpu_state, 1).unwrap();
+        assert!(v1_gpu_state.len() < gpu_state.len());
+
+        let gpu_offset = state.windows(gpu_state.len()).position(|window| window == &gpu_state[..]).unwrap();
+        let mut v1_state = state.clone();
+        v1_state.splice(gpu_offset..gpu_offset + gpu_state.len(), v1_gpu_state);
+        v1_state[SAVE_STATE_MAGIC.len()] = 1;
+
+        // the migrated state gets the DMG mode and priority and runs like the saved one
+        let mut migrated = looping_emulator();
+        migrated.soc.peripheral.gpu.object_priority = ObjectPriority::OamIndex;
+        migrated.soc.peripheral.gpu.cgb_mode = true;
+        migrated.load_state(&mut &v1_state[..]).unwrap();
+        assert_eq!(migrated.soc.peripheral.gpu.object_priority, ObjectPriority::XCoordinate);
+        assert!(!migrated.soc.peripheral.gpu.cgb_mode);
+        assert_eq!(migrated.soc.peripheral.gpu.current_line, 147);
+        assert_eq!(migrated.cycles(), emulator.cycles());
+
+        emulator.run_frame();
+        migrated.run_frame();
+        assert_eq!(migrated.lockup(), None);
+        assert_eq!(migrated.cycles(), emulator.cycles());
+        assert_eq!(migrated.soc.cpu.pc, emulator.soc.cpu.pc);
+        assert!(migrated.soc.peripheral.gpu.frame_buffer[..] == emulator.soc.peripheral.gpu.frame_buffer[..]);
+
+        // a truncated version 1 state is still rejected
+        assert!(migrated.load_state(&mut &v1_state[..v1_state.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_watchdog() {
+        // the boot rom loops forever with interrupts disabled
+        let mut emulator = looping_emulator();
+        emulator.set_watchdog(Some(2));
+
+        for _ in 0..10 {
+            emulator.run_frame();
+        }
+
+        assert_eq!(emulator.lockup(), Some(Lockup::SelfLoop(0x0000)));
+
+        // the emulated system is stopped after the lockup
+        let cycles = emulator.cycles();
+        assert!(cycles < 3 * ONE_FRAME_IN_CYCLES as u64);
+        assert_eq!(emulator.run_cycles(ONE_FRAME_IN_CYCLES as u64), 0);
+        assert_eq!(emulator.cycles(), cycles);
+
+        // no lockup detection without watchdog
+        let mut emulator = looping_emulator();
+        for _ in 0..10 {
+            emulator.run_frame();
+        }
+        assert_eq!(emulator.lockup(), None);
+    }
+
+    #[test]
+    fn test_watchdog_lcd_off() {
+        // NOP then JR back to it, the cpu keeps running with the LCD off
+        let mut boot_rom = [0x00; 0x100];
+        boot_rom[0x01] = 0x18;
+        boot_rom[0x02] = 0xFD;
+
+        let mut emulator = Emulator::without_cartridge(&boot_rom, false);
+        emulator.set_watchdog(Some(2));
+        assert!(!emulator.soc.peripheral.gpu.lcd_display_enabled);
+
+        for _ in 0..10 {
+            emulator.run_frame();
+        }
+        assert_eq!(emulator.lockup(), None);
+    }
+
+    #[test]
+    fn test_undefined_opcode() {
+        // NOP then the undefined opcode 0xD3
+        let mut boot_rom = [0x00; 0x100];
+        boot_rom[0x01] = 0xD3;
+
+        // the lockup is reported without watchdog and the emulated system is stopped
+        let mut emulator = Emulator::without_cartridge(&boot_rom, false);
+        emulator.run_frame();
+        assert_eq!(emulator.lockup(), Some(Lockup::UndefinedOpcode(0x0001, 0xD3)));
+        assert_eq!(emulator.lockup().unwrap().to_string(), "cpu hung by the undefined opcode 0xd3 at 0x0001");
+        assert_eq!(emulator.run_cycles(ONE_FRAME_IN_CYCLES as u64), 0);
+
+        // the lenient cpu runs it as a NOP, the setting survives a reset
+        let mut emulator = Emulator::without_cartridge(&boot_rom, false);
+        emulator.soc.cpu.lenient_opcodes = true;
+        emulator.reset().unwrap();
+        emulator.run_frame();
+        assert_eq!(emulator.lockup(), None);
+        assert!(emulator.soc.cpu.pc > 0x0001);
+    }
+
+    #[test]
+    fn test_oam_dma_routine() {
+        // LD SP, 0xDFFE / LD A, 0xC0 / CALL 0xFF80 / JR -2, the stack is in WRAM
+        let mut rom = blank_rom();
+        rom[0x0100..0x010A].copy_from_slice(&[0x31, 0xFE, 0xDF, 0x3E, 0xC0, 0xCD, 0x80, 0xFF, 0x18, 0xFE]);
+
+        let mut emulator = Emulator::new(&[0x00; 0x100], &rom, false);
+        emulator.skip_boot();
+
+        // the usual routine copied in HRAM: LDH (0x46), A / LD A, 40 / DEC A / JR NZ, -3 / RET
+        for (offset, data) in [0xE0, 0x46, 0x3E, 0x28, 0x3D, 0x20, 0xFD, 0xC9].iter().enumerate() {
+            emulator.soc.peripheral.write_byte(ZERO_PAGE_BEGIN + offset as u16, *data);
+        }
+        for offset in 0..OAM_SIZE {
+            emulator.soc.peripheral.write_byte(WORKING_RAM_BEGIN + offset, offset as u8);
+        }
+
+        // the source is hidden from the cpu while the wait loop runs in HRAM
+        while emulator.soc.cpu.pc != ZERO_PAGE_BEGIN + 0x02 {
+            emulator.step();
+        }
+        assert_eq!(emulator.soc.peripheral.read_byte(WORKING_RAM_BEGIN + 1), DMA_BLOCKED_READ);
+
+        // the routine returns to the game once the OAM is copied
+        while emulator.soc.cpu.pc != 0x0108 {
+            assert!(emulator.cycles() < ONE_FRAME_IN_CYCLES as u64, "the dma routine didn't return, pc {:#06x}", emulator.soc.cpu.pc);
+            emulator.step();
+        }
+        for offset in 0..OAM_SIZE {
+            assert_eq!(emulator.soc.peripheral.gpu.read_oam(offset as usize), offset as u8);
+        }
+        assert_eq!(emulator.soc.peripheral.read_byte(WORKING_RAM_BEGIN + 1), 0x01);
+    }
+
+    #[test]
+    fn test_without_cartridge() {
+        // LD A, 0x42 / LD (0xC000), A / JR -2
+        let mut boot_rom = [0x00; 0x100];
+        boot_rom[..7].copy_from_slice(&[0x3E, 0x42, 0xEA, 0x00, 0xC0, 0x18, 0xFE]);
+
+        let mut emulator = Emulator::without_cartridge(&boot_rom, false);
+        emulator.run_frame();
+
+        assert_eq!(emulator.soc.cpu.registers.a, 0x42);
+        assert_eq!(emulator.soc.peripheral.read_byte(0xC000), 0x42);
+        assert_eq!(emulator.soc.cpu.pc, 0x0005);
+
+        // the cartridge space is blank
+        assert_eq!(emulator.soc.peripheral.read_byte(0x0150), 0xFF);
+        assert_eq!(emulator.soc.peripheral.read_byte(0x4000), 0xFF);
+    }
+
+    #[test]
+    fn test_auto_flush_sram() {
+        let boot_rom = looping_boot_rom();
+
+        // mbc3 with battery and 8 KB of ram
+        let mut rom = blank_rom();
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x13;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x02;
+
+        let sram_dir = std::env::temp_dir().join(format!("qoboy_test_auto_flush_sram_{}", std::process::id()));
+        std::fs::create_dir_all(&sram_dir).unwrap();
+        let sram_path = sram_dir.join("game.sav");
+
+        let mut emulator = Emulator::new(&boot_rom, &rom, false);
+        emulator.load_sram(&sram_path).unwrap();
+
+        // clean ram isn't written
+        emulator.run_cycles(SRAM_FLUSH_INTERVAL_CYCLES);
+        assert_eq!(emulator.auto_flush_sram().unwrap(), false);
+        assert!(!sram_path.exists());
+
+        // the ram is written once the game stopped writing it
+        emulator.soc.peripheral.write_byte(0x0000, 0x0A);
+        emulator.soc.peripheral.write_byte(0xA000, 0x42);
+        assert_eq!(emulator.auto_flush_sram().unwrap(), false);
+        emulator.run_cycles(SRAM_FLUSH_DELAY_CYCLES);
+        assert_eq!(emulator.auto_flush_sram().unwrap(), true);
+        assert!(!emulator.soc.peripheral.cartridge.is_ram_dirty());
+
+        // at most once per interval
+        emulator.soc.peripheral.write_byte(0xA001, 0x43);
+        emulator.run_cycles(SRAM_FLUSH_DELAY_CYCLES);
+        assert_eq!(emulator.auto_flush_sram().unwrap(), false);
+        emulator.run_cycles(SRAM_FLUSH_INTERVAL_CYCLES);
+        assert_eq!(emulator.auto_flush_sram().unwrap(), true);
+
+        // the saved ram is restored at start up
+        let mut emulator = Emulator::new(&boot_rom, &rom, false);
+        emulator.load_sram(&sram_path).unwrap();
+        emulator.soc.peripheral.write_byte(0x0000, 0x0A);
+        assert_eq!(emulator.soc.peripheral.read_byte(0xA000), 0x42);
+        assert_eq!(emulator.soc.peripheral.read_byte(0xA001), 0x43);
+
+        std::fs::remove_dir_all(&sram_dir).unwrap();
+    }
+
+    #[test]
+    fn test_skip_boot() {
+        let mut emulator = looping_emulator();
+        emulator.skip_boot();
+
+        assert_eq!(emulator.soc.cpu.pc, 0x0100);
+        assert_eq!(emulator.soc.cpu.sp, 0xFFFE);
+        assert_eq!(emulator.soc.cpu.registers.read_af(), 0x01B0);
+        assert_eq!(emulator.soc.cpu.registers.read_hl(), 0x014D);
+
+        // the boot rom isn't mapped anymore
+        assert_eq!(emulator.soc.peripheral.read_byte(0x0000), 0xFF);
+        assert_eq!(emulator.soc.peripheral.read_byte(0xFF40), 0x91);
+    }
+
+    #[test]
+    fn test_skip_boot_hardware_mode() {
+        let boot_rom = looping_boot_rom();
+        let mut rom = blank_rom();
+
+        rom[CARTRIDGE_CGB_FLAG_OFFSET as usize] = 0x00;
+        let mut emulator = Emulator::new(&boot_rom, &rom, false);
+        emulator.skip_boot();
+        assert_eq!(emulator.soc.cpu.registers.a, 0x01);
+
+        // the CGB games are run in CGB mode and detect it
+        rom[CARTRIDGE_CGB_FLAG_OFFSET as usize] = 0x80;
+        let mut emulator = Emulator::new(&boot_rom, &rom, false);
+        emulator.skip_boot();
+        assert_eq!(emulator.soc.cpu.registers.a, 0x11);
+    }
+
+    #[test]
+    fn test_load_cartridge() {
+        let boot_rom = looping_boot_rom();
+        let mut first_rom = blank_rom();
+        first_rom[0x0150] = 0x11;
+
+        let mut second_rom = first_rom;
+        second_rom[0x0150] = 0x22;
+
+        // unknown mbc type
+        let mut invalid_rom = first_rom;
+        invalid_rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x55;
+
+        // MBC3 with a battery backed 8 KB ram, its save is truncated
+        let mut battery_rom = first_rom;
+        battery_rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x13;
+        battery_rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x02;
+        battery_rom[0x0150] = 0x33;
+
+        let rom_dir = std::env::temp_dir().join(format!("qoboy_test_load_cartridge_{}", std::process::id()));
+        std::fs::create_dir_all(&rom_dir).unwrap();
+        std::fs::write(rom_dir.join("second.gb"), &second_rom[..]).unwrap();
+        std::fs::write(rom_dir.join("invalid.gb"), &invalid_rom[..]).unwrap();
+        std::fs::write(rom_dir.join("battery.gb"), &battery_rom[..]).unwrap();
+        std::fs::write(rom_dir.join("battery.sav"), [0x00; 0x10]).unwrap();
+
+        let mut emulator = Emulator::new(&boot_rom, &first_rom, false);
+        emulator.run_frame();
+        emulator.soc.peripheral.write_byte(0xC000, 0x42);
+        assert_eq!(emulator.soc.peripheral.read_byte(0x0150), 0x11);
+
+        // the system is power cycled with the new cartridge
+        emulator.load_cartridge(&rom_dir.join("second.gb")).unwrap();
+        assert_eq!(emulator.soc.peripheral.read_byte(0x0150), 0x22);
+        assert_eq!(emulator.soc.peripheral.read_byte(0xC000), 0xFF);
+        assert_eq!(emulator.soc.cpu.pc, 0x0000);
+        assert_eq!(emulator.cycles(), 0);
+
+        // the current game keeps running when the rom or its save can't be loaded
+        emulator.run_frame();
+        assert!(emulator.load_cartridge(&rom_dir.join("invalid.gb")).is_err());
+        assert!(emulator.load_cartridge(&rom_dir.join("missing.gb")).is_err());
+        assert!(emulator.load_cartridge(&rom_dir.join("battery.gb")).is_err());
+        assert_eq!(emulator.soc.peripheral.read_byte(0x0150), 0x22);
+        assert_eq!(emulator.cycles(), ONE_FRAME_IN_CYCLES as u64);
+        assert_eq!(emulator.state_path, rom_dir.join("second.gb"));
+        assert_eq!(emulator.sram_path, None);
+
+        // the save is restored along with the game
+        let mut sram = vec![0x00; 0x2000];
+        sram[0x10] = 0x42;
+        std::fs::write(rom_dir.join("battery.sav"), &sram).unwrap();
+        emulator.load_cartridge(&rom_dir.join("battery.gb")).unwrap();
+        assert_eq!(emulator.soc.peripheral.read_byte(0x0150), 0x33);
+        assert_eq!(emulator.sram_path, Some(rom_dir.join("battery.sav")));
+        emulator.soc.peripheral.write_byte(0x0000, 0x0A);
+        assert_eq!(emulator.soc.peripheral.read_byte(0xA010), 0x42);
+
+        // back to the game without battery
+        emulator.load_cartridge(&rom_dir.join("second.gb")).unwrap();
+
+        // a reset power cycles the system with the loaded game
+        emulator.soc.peripheral.write_byte(0xC000, 0x42);
+        emulator.reset().unwrap();
+        assert_eq!(emulator.soc.peripheral.read_byte(0x0150), 0x22);
+        assert_eq!(emulator.soc.peripheral.read_byte(0xC000), 0xFF);
+        assert_eq!(emulator.cycles(), 0);
+
+        std::fs::remove_dir_all(&rom_dir).unwrap();
+    }
+}
@@ -0,0 +1,109 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Result, Write};
+use std::path::{Path, PathBuf};
+
+// one minute at 60 frames per second, about 4MB of images per second
+pub const DEFAULT_MAX_RECORDED_FRAMES: u32 = 3600;
+
+// write host pixels as a binary PPM image, the alpha is dropped
+pub fn write_ppm(writer: &mut dyn Write, pixels: &[u32], width: usize, height: usize) -> Result<()> {
+    write!(writer, "P6\n{} {}\n255\n", width, height)?;
+
+    let rgb: Vec<u8> = pixels.iter().flat_map(|pixel| [(pixel >> 16) as u8, (pixel >> 8) as u8, *pixel as u8]).collect();
+    writer.write_all(&rgb)
+}
+
+// numbered images of the presented frames, ffmpeg makes a video of them with:
+// ffmpeg -framerate 60 -i frame_%06d.ppm video.mp4
+pub struct FrameRecorder {
+    dir: PathBuf,
+    // the recording stops after this number of frames so that it doesn't fill the disk
+    pub max_frames: u32,
+    recorded_frames: u32,
+}
+
+impl FrameRecorder {
+    // the directory is created if needed
+    pub fn new(dir: &Path, max_frames: u32) -> Result<FrameRecorder> {
+        fs::create_dir_all(dir)?;
+
+        Ok(FrameRecorder {
+            dir: dir.to_path_buf(),
+            max_frames,
+            recorded_frames: 0,
+        })
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.recorded_frames >= self.max_frames
+    }
+
+    pub fn frame_path(&self, frame: u32) -> PathBuf {
+        self.dir.join(format!("frame_{:06}.ppm", frame))
+    }
+
+    // write the next image, nothing is written once the record is full
+    pub fn record(&mut self, pixels: &[u32], width: usize, height: usize) -> Result<()> {
+        if self.is_full() {
+            return Ok(());
+        }
+
+        let mut writer = BufWriter::new(File::create(self.frame_path(self.recorded_frames))?);
+        write_ppm(&mut writer, pixels, width, height)?;
+        writer.flush()?;
+
+        self.recorded_frames += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod frame_record_tests {
+    use super::*;
+    use crate::emulator::{SCREEN_HEIGHT, SCREEN_WIDTH};
+    use crate::emulator::test_fixtures::looping_emulator;
+    use crate::palette::HostPalette;
+    use crate::renderer::HostColors;
+    use crate::soc::peripheral::IoAccess;
+
+    #[test]
+    fn test_write_ppm() {
+        let mut image = Vec::new();
+        write_ppm(&mut image, &[0xFF123456, 0xFFABCDEF], 2, 1).unwrap();
+        assert_eq!(image, b"P6\n2 1\n255\n\x12\x34\x56\xAB\xCD\xEF");
+    }
+
+    #[test]
+    fn test_frame_recorder() {
+        let mut emulator = looping_emulator();
+        emulator.soc.peripheral.write_byte(0xFF40, 0x80);
+
+        // the frames are recorded with the colors of the palette file
+        let mut colors = HostColors::new();
+        colors.palette = Some(HostPalette::parse("255 0 0\n0 255 0\n0 0 255\n0 0 0").unwrap());
+
+        let record_dir = std::env::temp_dir().join(format!("qoboy_test_frame_recorder_{}", std::process::id()));
+        let mut recorder = FrameRecorder::new(&record_dir, 3).unwrap();
+
+        // only the first 3 frames are written
+        for _ in 0..5 {
+            emulator.run_frame();
+            let frame = emulator.fetch_frame().to_vec();
+            let pixels = colors.convert(&frame, emulator.get_frame_palettes());
+            recorder.record(&pixels, SCREEN_WIDTH, SCREEN_HEIGHT).unwrap();
+        }
+        assert!(recorder.is_full());
+
+        let header = format!("P6\n{} {}\n255\n", SCREEN_WIDTH, SCREEN_HEIGHT);
+        for frame in 0..3 {
+            let image = fs::read(recorder.frame_path(frame)).unwrap();
+            assert_eq!(image.len(), header.len() + SCREEN_WIDTH * SCREEN_HEIGHT * 3);
+            assert!(image.starts_with(header.as_bytes()));
+            // the blank screen is drawn with the lightest color of the palette
+            assert_eq!(image[header.len()..header.len() + 3], [255, 0, 0]);
+        }
+        assert!(!recorder.frame_path(3).exists());
+
+        fs::remove_dir_all(&record_dir).unwrap();
+    }
+}
@@ -0,0 +1,221 @@
+use gilrs::{Button, Event, EventType, Gilrs};
+
+use crate::emulator::GameBoyKey;
+use crate::input_script::KEY_NAMES;
+
+// gamepad buttons which can be mapped with --gamepad-map
+const BUTTON_NAMES: [(&str, Button); 19] = [
+    ("south", Button::South),
+    ("east", Button::East),
+    ("north", Button::North),
+    ("west", Button::West),
+    ("c", Button::C),
+    ("z", Button::Z),
+    ("left-trigger", Button::LeftTrigger),
+    ("left-trigger2", Button::LeftTrigger2),
+    ("right-trigger", Button::RightTrigger),
+    ("right-trigger2", Button::RightTrigger2),
+    ("select", Button::Select),
+    ("start", Button::Start),
+    ("mode", Button::Mode),
+    ("left-thumb", Button::LeftThumb),
+    ("right-thumb", Button::RightThumb),
+    ("dpad-up", Button::DPadUp),
+    ("dpad-down", Button::DPadDown),
+    ("dpad-left", Button::DPadLeft),
+    ("dpad-right", Button::DPadRight),
+];
+
+// mapping of the gamepad buttons to the gameboy joypad
+pub struct GamepadMapping {
+    pub a: Button,
+    pub b: Button,
+    pub start: Button,
+    pub select: Button,
+    pub up: Button,
+    pub down: Button,
+    pub left: Button,
+    pub right: Button,
+}
+
+impl GamepadMapping {
+    // standard layout: the south / east face buttons are used as B / A
+    // just like on the original console
+    pub fn new() -> GamepadMapping {
+        GamepadMapping {
+            a: Button::East,
+            b: Button::South,
+            start: Button::Start,
+            select: Button::Select,
+            up: Button::DPadUp,
+            down: Button::DPadDown,
+            left: Button::DPadLeft,
+            right: Button::DPadRight,
+        }
+    }
+
+    // gameboy buttons with their gamepad button, ie a:north,b:west, the other ones keep the
+    // standard layout
+    pub fn parse(settings: &str) -> Result<GamepadMapping, String> {
+        let mut mapping = GamepadMapping::new();
+
+        for setting in settings.split(',') {
+            let (key_name, button_name) = setting.split_once(':').ok_or(format!("got {}", setting))?;
+            let (_, key) = KEY_NAMES.iter().find(|(name, _)| name.eq_ignore_ascii_case(key_name))
+                .ok_or(format!("unknown gameboy button {}", key_name))?;
+            let (_, button) = BUTTON_NAMES.iter().find(|(name, _)| name.eq_ignore_ascii_case(button_name))
+                .ok_or(format!("unknown gamepad button {}", button_name))?;
+            *mapping.button_mut(*key) = *button;
+        }
+
+        Ok(mapping)
+    }
+
+    pub fn to_gameboy_key(&self, button: Button) -> Option<GameBoyKey> {
+        if button == self.a {
+            Some(GameBoyKey::A)
+        } else if button == self.b {
+            Some(GameBoyKey::B)
+        } else if button == self.start {
+            Some(GameBoyKey::START)
+        } else if button == self.select {
+            Some(GameBoyKey::SELECT)
+        } else if button == self.up {
+            Some(GameBoyKey::UP)
+        } else if button == self.down {
+            Some(GameBoyKey::DOWN)
+        } else if button == self.left {
+            Some(GameBoyKey::LEFT)
+        } else if button == self.right {
+            Some(GameBoyKey::RIGHT)
+        } else {
+            None
+        }
+    }
+
+    fn to_button(&self, key: GameBoyKey) -> Button {
+        match key {
+            GameBoyKey::A => self.a,
+            GameBoyKey::B => self.b,
+            GameBoyKey::START => self.start,
+            GameBoyKey::SELECT => self.select,
+            GameBoyKey::UP => self.up,
+            GameBoyKey::DOWN => self.down,
+            GameBoyKey::LEFT => self.left,
+            GameBoyKey::RIGHT => self.right,
+        }
+    }
+
+    fn button_mut(&mut self, key: GameBoyKey) -> &mut Button {
+        match key {
+            GameBoyKey::A => &mut self.a,
+            GameBoyKey::B => &mut self.b,
+            GameBoyKey::START => &mut self.start,
+            GameBoyKey::SELECT => &mut self.select,
+            GameBoyKey::UP => &mut self.up,
+            GameBoyKey::DOWN => &mut self.down,
+            GameBoyKey::LEFT => &mut self.left,
+            GameBoyKey::RIGHT => &mut self.right,
+        }
+    }
+}
+
+pub struct Gamepad {
+    gilrs: Option<Gilrs>,
+    mapping: GamepadMapping,
+}
+
+impl Gamepad {
+    pub fn new(mapping: GamepadMapping) -> Gamepad {
+        // the emulator keeps running with the keyboard only if no gamepad backend is available
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(message) => {
+                println!("Gamepad support disabled: {}", message);
+                None
+            }
+        };
+
+        Gamepad {
+            gilrs: gilrs,
+            mapping: mapping,
+        }
+    }
+
+    // process pending events, this shall be called once per frame
+    pub fn poll(&mut self) {
+        if let Some(gilrs) = &mut self.gilrs {
+            while let Some(Event { id, event, .. }) = gilrs.next_event() {
+                match event {
+                    EventType::Connected => println!("Gamepad connected: {}", gilrs.gamepad(id).name()),
+                    EventType::Disconnected => println!("Gamepad disconnected: {}", gilrs.gamepad(id).name()),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    pub fn is_pressed(&self, key: GameBoyKey) -> bool {
+        match &self.gilrs {
+            // disconnected gamepads are not listed so hot plugging is handled here
+            Some(gilrs) => {
+                let button = self.mapping.to_button(key);
+                gilrs.gamepads().any(|(_, gamepad)| gamepad.is_pressed(button))
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod gamepad_tests {
+    use super::*;
+
+    #[test]
+    fn test_default_mapping() {
+        let mapping = GamepadMapping::new();
+
+        assert_eq!(mapping.to_gameboy_key(Button::East), Some(GameBoyKey::A));
+        assert_eq!(mapping.to_gameboy_key(Button::South), Some(GameBoyKey::B));
+        assert_eq!(mapping.to_gameboy_key(Button::Start), Some(GameBoyKey::START));
+        assert_eq!(mapping.to_gameboy_key(Button::Select), Some(GameBoyKey::SELECT));
+        assert_eq!(mapping.to_gameboy_key(Button::DPadUp), Some(GameBoyKey::UP));
+        assert_eq!(mapping.to_gameboy_key(Button::DPadDown), Some(GameBoyKey::DOWN));
+        assert_eq!(mapping.to_gameboy_key(Button::DPadLeft), Some(GameBoyKey::LEFT));
+        assert_eq!(mapping.to_gameboy_key(Button::DPadRight), Some(GameBoyKey::RIGHT));
+        assert_eq!(mapping.to_gameboy_key(Button::North), None);
+    }
+
+    #[test]
+    fn test_custom_mapping() {
+        let mut mapping = GamepadMapping::new();
+        mapping.a = Button::North;
+        mapping.b = Button::West;
+
+        assert_eq!(mapping.to_gameboy_key(Button::North), Some(GameBoyKey::A));
+        assert_eq!(mapping.to_gameboy_key(Button::West), Some(GameBoyKey::B));
+        assert_eq!(mapping.to_gameboy_key(Button::East), None);
+        assert_eq!(mapping.to_button(GameBoyKey::A), Button::North);
+    }
+
+    #[test]
+    fn test_parse() {
+        let mapping = GamepadMapping::parse("a:north,B:West,start:dpad-up").unwrap();
+
+        assert_eq!(mapping.to_button(GameBoyKey::A), Button::North);
+        assert_eq!(mapping.to_button(GameBoyKey::B), Button::West);
+        assert_eq!(mapping.to_button(GameBoyKey::START), Button::DPadUp);
+        // the other buttons keep the standard layout
+        assert_eq!(mapping.to_button(GameBoyKey::SELECT), Button::Select);
+        assert_eq!(mapping.to_button(GameBoyKey::UP), Button::DPadUp);
+        assert_eq!(mapping.to_gameboy_key(Button::East), None);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(GamepadMapping::parse("a").is_err());
+        assert!(GamepadMapping::parse("a:").is_err());
+        assert!(GamepadMapping::parse("x:north").is_err());
+        assert!(GamepadMapping::parse("a:north,b:pedal").is_err());
+    }
+}
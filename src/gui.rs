@@ -0,0 +1,288 @@
+use egui::epaint::{ClippedPrimitive, ImageDelta, Mesh, Primitive};
+use egui::{Color32, Event, Modifiers, PointerButton, Pos2, RawInput, Rect, TextureId};
+use minifb::{MouseButton, MouseMode, Window};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::letterbox::letterbox;
+use crate::rom_menu::list_roms;
+
+// height in pixels of the control bar, drawn above the screen
+pub const CONTROL_BAR_HEIGHT: usize = 24;
+// the bar is refreshed at the screen rate while no frame is presented, ie when paused
+const REFRESH_PERIOD: Duration = Duration::from_millis(16);
+
+// actions asked through the control bar, run by the host loop
+#[allow(non_camel_case_types)]
+#[derive(Clone, PartialEq, Debug)]
+pub enum ControlAction {
+    RESET,
+    SAVE_STATE,
+    LOAD_STATE,
+    LOAD_ROM(PathBuf),
+}
+
+struct Texture {
+    size: [usize; 2],
+    pixels: Vec<Color32>,
+}
+
+impl Texture {
+    // nearest texel of normalized coordinates
+    fn sample(&self, uv: Pos2) -> Color32 {
+        let x = ((uv.x * self.size[0] as f32) as usize).min(self.size[0] - 1);
+        let y = ((uv.y * self.size[1] as f32) as usize).min(self.size[1] - 1);
+        self.pixels[y * self.size[0] + x]
+    }
+
+    fn update(&mut self, delta: &ImageDelta) {
+        let egui::ImageData::Color(image) = &delta.image;
+
+        match delta.pos {
+            // patch of the texture, ie new glyphs in the font atlas
+            Some([x, y]) => {
+                for (row, pixels) in image.pixels.chunks(image.size[0]).enumerate() {
+                    let start = (y + row) * self.size[0] + x;
+                    self.pixels[start..start + pixels.len()].copy_from_slice(pixels);
+                }
+            }
+            None => {
+                self.size = image.size;
+                self.pixels = image.pixels.clone();
+            }
+        }
+    }
+}
+
+// egui bar with the pause, reset, save and load state buttons and a rom picker
+// it's drawn in software over the window buffer so the minifb rendering is kept
+pub struct ControlBar {
+    ctx: egui::Context,
+    textures: HashMap<TextureId, Texture>,
+    start: Instant,
+    mouse_down: bool,
+    // the emulation isn't run while paused
+    pub paused: bool,
+    // the rom picker lists the roms of this directory
+    rom_dir: PathBuf,
+    actions: Vec<ControlAction>,
+    // window content, the bar above the last frame letterboxed in the screen area
+    width: usize,
+    height: usize,
+    buffer: Vec<u32>,
+    screen: Vec<u32>,
+    last_refresh: Option<Instant>,
+}
+
+impl ControlBar {
+    // the screen is the window area below the bar
+    pub fn new(screen_width: usize, screen_height: usize, rom_dir: &Path) -> ControlBar {
+        let height = screen_height + CONTROL_BAR_HEIGHT;
+
+        ControlBar {
+            ctx: egui::Context::default(),
+            textures: HashMap::new(),
+            start: Instant::now(),
+            mouse_down: false,
+            paused: false,
+            rom_dir: rom_dir.to_path_buf(),
+            actions: Vec::new(),
+            width: screen_width,
+            height,
+            buffer: vec![0; screen_width * height],
+            screen: vec![0; screen_width * screen_height],
+            last_refresh: None,
+        }
+    }
+
+    pub fn window_size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    // actions asked since the last call
+    pub fn take_actions(&mut self) -> Vec<ControlAction> {
+        std::mem::take(&mut self.actions)
+    }
+
+    // display a frame of host pixels below the bar
+    pub fn present(&mut self, window: &mut Window, frame: &[u32], width: usize, height: usize, integer_scale: bool) {
+        self.screen = letterbox(frame, width, height, self.width, self.height - CONTROL_BAR_HEIGHT, integer_scale);
+        self.update_window(window);
+    }
+
+    // redraw the last frame so that the bar stays usable while no frame is presented
+    pub fn refresh(&mut self, window: &mut Window) {
+        match self.last_refresh {
+            Some(last_refresh) if last_refresh.elapsed() < REFRESH_PERIOD => window.update(),
+            _ => self.update_window(window),
+        }
+    }
+
+    fn update_window(&mut self, window: &mut Window) {
+        let pointer = window.get_mouse_pos(MouseMode::Discard);
+        let mouse_down = window.get_mouse_down(MouseButton::Left);
+
+        self.draw(pointer, mouse_down);
+        window.update_with_buffer(&self.buffer, self.width, self.height).unwrap();
+        self.last_refresh = Some(Instant::now());
+    }
+
+    // compose the window buffer, the pointer position is given in window pixels
+    fn draw(&mut self, pointer: Option<(f32, f32)>, mouse_down: bool) {
+        let bar_size = self.width * CONTROL_BAR_HEIGHT;
+        self.buffer[..bar_size].fill(0);
+        self.buffer[bar_size..].copy_from_slice(&self.screen);
+
+        let primitives = self.run_ui(pointer, mouse_down);
+        for primitive in primitives.iter() {
+            if let Primitive::Mesh(mesh) = &primitive.primitive {
+                self.draw_mesh(mesh, primitive.clip_rect);
+            }
+        }
+    }
+
+    fn run_ui(&mut self, pointer: Option<(f32, f32)>, mouse_down: bool) -> Vec<ClippedPrimitive> {
+        let mut events = Vec::new();
+        match pointer {
+            Some((x, y)) => {
+                let pos = Pos2::new(x, y);
+                events.push(Event::PointerMoved(pos));
+                if mouse_down != self.mouse_down {
+                    events.push(Event::PointerButton { pos, button: PointerButton::Primary, pressed: mouse_down, modifiers: Modifiers::default() });
+                }
+            }
+            None => events.push(Event::PointerGone),
+        }
+        self.mouse_down = mouse_down;
+
+        let input = RawInput {
+            screen_rect: Some(Rect::from_min_size(Pos2::ZERO, egui::vec2(self.width as f32, self.height as f32))),
+            time: Some(self.start.elapsed().as_secs_f64()),
+            events,
+            ..Default::default()
+        };
+
+        let ctx = self.ctx.clone();
+        let output = ctx.run(input, |ctx| {
+            egui::TopBottomPanel::top("control_bar").exact_height(CONTROL_BAR_HEIGHT as f32).show(ctx, |ui| {
+                ui.horizontal_centered(|ui| self.draw_buttons(ui));
+            });
+        });
+
+        for (id, delta) in output.textures_delta.set.iter() {
+            self.textures.entry(*id).or_insert(Texture { size: [0, 0], pixels: Vec::new() }).update(delta);
+        }
+        let primitives = ctx.tessellate(output.shapes, output.pixels_per_point);
+        for id in output.textures_delta.free.iter() {
+            self.textures.remove(id);
+        }
+
+        primitives
+    }
+
+    fn draw_buttons(&mut self, ui: &mut egui::Ui) {
+        if ui.button(if self.paused { "Resume" } else { "Pause" }).clicked() {
+            self.toggle_pause();
+        }
+        if ui.button("Reset").clicked() {
+            self.actions.push(ControlAction::RESET);
+        }
+        if ui.button("Save state").clicked() {
+            self.actions.push(ControlAction::SAVE_STATE);
+        }
+        if ui.button("Load state").clicked() {
+            self.actions.push(ControlAction::LOAD_STATE);
+        }
+
+        ui.menu_button("Load ROM", |ui| {
+            let roms = list_roms(&self.rom_dir);
+            if roms.is_empty() {
+                ui.label("no rom found");
+            }
+
+            for rom in roms {
+                let name = rom.file_name().unwrap_or_default().to_string_lossy().to_string();
+                if ui.button(name).clicked() {
+                    self.actions.push(ControlAction::LOAD_ROM(rom));
+                    ui.close();
+                }
+            }
+        });
+    }
+
+    // fill the triangles of the mesh, the colors are premultiplied by their alpha
+    fn draw_mesh(&mut self, mesh: &Mesh, clip_rect: Rect) {
+        let texture = match self.textures.get(&mesh.texture_id) {
+            Some(texture) => texture,
+            None => return,
+        };
+
+        let clip_rect = clip_rect.intersect(Rect::from_min_size(Pos2::ZERO, egui::vec2(self.width as f32, self.height as f32)));
+        let edge = |a: Pos2, b: Pos2, p: Pos2| (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x);
+
+        for triangle in mesh.indices.chunks_exact(3) {
+            let (a, b, c) = (&mesh.vertices[triangle[0] as usize], &mesh.vertices[triangle[1] as usize], &mesh.vertices[triangle[2] as usize]);
+            let area = edge(a.pos, b.pos, c.pos);
+            if area == 0.0 {
+                continue;
+            }
+
+            let min_x = a.pos.x.min(b.pos.x).min(c.pos.x).max(clip_rect.min.x).floor().max(0.0) as usize;
+            let max_x = a.pos.x.max(b.pos.x).max(c.pos.x).min(clip_rect.max.x).ceil().max(0.0) as usize;
+            let min_y = a.pos.y.min(b.pos.y).min(c.pos.y).max(clip_rect.min.y).floor().max(0.0) as usize;
+            let max_y = a.pos.y.max(b.pos.y).max(c.pos.y).min(clip_rect.max.y).ceil().max(0.0) as usize;
+
+            for y in min_y..max_y {
+                for x in min_x..max_x {
+                    // barycentric weights of the pixel center, all positive inside the triangle
+                    let p = Pos2::new(x as f32 + 0.5, y as f32 + 0.5);
+                    let (wa, wb, wc) = (edge(b.pos, c.pos, p) / area, edge(c.pos, a.pos, p) / area, edge(a.pos, b.pos, p) / area);
+                    if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+                        continue;
+                    }
+
+                    let uv = Pos2::new(a.uv.x * wa + b.uv.x * wb + c.uv.x * wc, a.uv.y * wa + b.uv.y * wb + c.uv.y * wc);
+                    let texel = texture.sample(uv);
+                    let channel = |index: usize| {
+                        let color = a.color[index] as f32 * wa + b.color[index] as f32 * wb + c.color[index] as f32 * wc;
+                        color * texel[index] as f32 / 255.0
+                    };
+
+                    let pixel = &mut self.buffer[y * self.width + x];
+                    let alpha = channel(3) / 255.0;
+                    let blend = |shift: u32, color: f32| ((color + ((*pixel >> shift) & 0xFF) as f32 * (1.0 - alpha)).min(255.0) as u32) << shift;
+                    *pixel = blend(16, channel(0)) | blend(8, channel(1)) | blend(0, channel(2));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod gui_tests {
+    use super::*;
+
+    #[test]
+    fn test_control_bar() {
+        let mut control_bar = ControlBar::new(480, 432, Path::new("."));
+        assert_eq!(control_bar.window_size(), (480, 432 + CONTROL_BAR_HEIGHT));
+
+        control_bar.toggle_pause();
+        assert!(control_bar.paused);
+        control_bar.toggle_pause();
+        assert!(!control_bar.paused);
+
+        // the bar is drawn over the top of the window, the frame below it
+        control_bar.screen = letterbox(&[0x00FFFFFF; 160 * 144], 160, 144, 480, 432, true);
+        control_bar.draw(None, false);
+        assert!(control_bar.buffer[..480 * CONTROL_BAR_HEIGHT].iter().any(|pixel| *pixel != 0));
+        assert!(control_bar.buffer[480 * CONTROL_BAR_HEIGHT..].iter().all(|pixel| *pixel == 0x00FFFFFF));
+        assert!(control_bar.take_actions().is_empty());
+    }
+}
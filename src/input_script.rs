@@ -0,0 +1,125 @@
+use crate::emulator::GameBoyKey;
+
+// Input scripts give the keys held from a frame on, one line per change, frames are decimal:
+//   <frame> [key ...]                   hold the keys from this frame, the other ones are released
+// keys are up, down, left, right, a, b, start and select, frames are counted from the power on
+// empty lines and lines starting with # are ignored
+pub const KEY_NAMES: [(&str, GameBoyKey); 8] = [
+    ("up", GameBoyKey::UP),
+    ("down", GameBoyKey::DOWN),
+    ("left", GameBoyKey::LEFT),
+    ("right", GameBoyKey::RIGHT),
+    ("a", GameBoyKey::A),
+    ("b", GameBoyKey::B),
+    ("start", GameBoyKey::START),
+    ("select", GameBoyKey::SELECT),
+];
+
+pub struct InputScript {
+    // frames in increasing order with the keys held from them
+    changes: Vec<(u32, Vec<GameBoyKey>)>,
+    next_change: usize,
+    held_keys: Vec<GameBoyKey>,
+}
+
+impl InputScript {
+    pub fn parse(source: &str) -> Result<InputScript, String> {
+        let mut changes: Vec<(u32, Vec<GameBoyKey>)> = Vec::new();
+
+        for (index, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error_message = |message: String| format!("line {}: {}", index + 1, message);
+            let mut words = line.split_whitespace();
+
+            let frame_word = words.next().unwrap_or_default();
+            let frame: u32 = frame_word.parse().map_err(|_| error_message(format!("invalid frame {}", frame_word)))?;
+            if changes.last().is_some_and(|(previous_frame, _)| *previous_frame >= frame) {
+                return Err(error_message(format!("frame {} isn't after the previous one", frame)));
+            }
+
+            let keys = words
+                .map(|word| {
+                    KEY_NAMES
+                        .iter()
+                        .find(|(name, _)| word.eq_ignore_ascii_case(name))
+                        .map(|(_, key)| *key)
+                        .ok_or(error_message(format!("unknown key {}", word)))
+                })
+                .collect::<Result<Vec<GameBoyKey>, String>>()?;
+
+            changes.push((frame, keys));
+        }
+
+        Ok(InputScript {
+            changes,
+            next_change: 0,
+            held_keys: Vec::new(),
+        })
+    }
+
+    // shall be called before running each frame, with the number of frames run so far
+    pub fn advance(&mut self, frame: u32) {
+        while let Some((change_frame, keys)) = self.changes.get(self.next_change) {
+            if *change_frame > frame {
+                break;
+            }
+
+            self.held_keys = keys.clone();
+            self.next_change += 1;
+        }
+    }
+
+    pub fn is_held(&self, key: GameBoyKey) -> bool {
+        self.held_keys.contains(&key)
+    }
+}
+
+#[cfg(test)]
+mod input_script_tests {
+    use super::*;
+    use crate::emulator::Emulator;
+    use crate::emulator::test_fixtures::looping_rom;
+    use crate::soc::peripheral::IoAccess;
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(InputScript::parse("# title screen\n\n0\n5 start\n6 A right").is_ok());
+        assert_eq!(InputScript::parse("5 start\nfive a").err(), Some(String::from("line 2: invalid frame five")));
+        assert_eq!(InputScript::parse("5 start\n5 a").err(), Some(String::from("line 2: frame 5 isn't after the previous one")));
+        assert_eq!(InputScript::parse("5 start turbo").err(), Some(String::from("line 1: unknown key turbo")));
+    }
+
+    #[test]
+    fn test_input_script() {
+        // the game loops forever, the joypad is read by the test
+        let rom = looping_rom();
+
+        let mut emulator = Emulator::new(&[0x00; 0x100], &rom, false);
+        emulator.skip_boot();
+
+        // start is pressed at frame 5 and released at frame 8
+        let mut script = InputScript::parse("5 start\n8").unwrap();
+        let mut start_frames = Vec::new();
+
+        for frame in 0..10 {
+            script.advance(frame);
+            for (_, key) in KEY_NAMES {
+                emulator.set_key(key, script.is_held(key));
+            }
+
+            // select the action buttons, start is bit 3 and is low when pressed
+            emulator.soc.peripheral.write_byte(0xFF00, 0x10);
+            if emulator.soc.peripheral.read_byte(0xFF00) & 0x08 == 0 {
+                start_frames.push(frame);
+            }
+
+            emulator.run_frame();
+        }
+
+        assert_eq!(start_frames, [5, 6, 7]);
+    }
+}
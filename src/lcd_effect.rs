@@ -0,0 +1,91 @@
+use crate::emulator::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// green tint of the DMG LCD, from the darkest to the lightest shade
+const LCD_DARK_COLOR: (u32, u32, u32) = (0x0F, 0x38, 0x0F);
+const LCD_LIGHT_COLOR: (u32, u32, u32) = (0x9B, 0xBC, 0x0F);
+
+// mimic the slow response of the non backlit DMG LCD, each displayed frame
+// is blended with the previous displayed one so that moving pixels leave a trail
+// only the host frame is modified, the emulated system isn't affected
+pub struct LcdEffect {
+    previous_frame: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
+    has_previous_frame: bool,
+}
+
+impl LcdEffect {
+    pub fn new() -> LcdEffect {
+        LcdEffect {
+            previous_frame: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            has_previous_frame: false,
+        }
+    }
+
+    // forget the previous frame, ie when the effect is enabled again
+    pub fn reset(&mut self) {
+        self.has_previous_frame = false;
+    }
+
+    pub fn apply(&mut self, frame: &mut [u8]) {
+        if self.has_previous_frame {
+            for (shade, previous_shade) in frame.iter_mut().zip(self.previous_frame.iter()) {
+                *shade = ((*shade as u16 + *previous_shade as u16) / 2) as u8;
+            }
+        }
+
+        self.previous_frame.copy_from_slice(frame);
+        self.has_previous_frame = true;
+    }
+}
+
+// convert a grayscale shade into a 0x00RRGGBB green LCD color
+pub fn green_tint(shade: u8) -> u32 {
+    let blend = |dark: u32, light: u32| dark + (light - dark) * shade as u32 / 255;
+
+    let r = blend(LCD_DARK_COLOR.0, LCD_LIGHT_COLOR.0);
+    let g = blend(LCD_DARK_COLOR.1, LCD_LIGHT_COLOR.1);
+    let b = blend(LCD_DARK_COLOR.2, LCD_LIGHT_COLOR.2);
+
+    (r << 16) | (g << 8) | b
+}
+
+#[cfg(test)]
+mod lcd_effect_tests {
+    use super::*;
+
+    #[test]
+    fn test_ghosting() {
+        let mut lcd_effect = LcdEffect::new();
+        let mut frame = [0; SCREEN_WIDTH * SCREEN_HEIGHT];
+
+        // the first frame is displayed as is
+        frame[0] = 255;
+        frame[1] = 96;
+        lcd_effect.apply(&mut frame);
+        assert_eq!(frame[0], 255);
+        assert_eq!(frame[1], 96);
+
+        // a pixel which changes gets an intermediate value
+        frame[0] = 0;
+        frame[1] = 96;
+        lcd_effect.apply(&mut frame);
+        assert_eq!(frame[0], 127);
+        assert_eq!(frame[1], 96);
+
+        // then fades to its new value
+        frame[0] = 0;
+        lcd_effect.apply(&mut frame);
+        assert_eq!(frame[0], 63);
+
+        // no blending after a reset
+        lcd_effect.reset();
+        frame[0] = 255;
+        lcd_effect.apply(&mut frame);
+        assert_eq!(frame[0], 255);
+    }
+
+    #[test]
+    fn test_green_tint() {
+        assert_eq!(green_tint(0), 0x0F380F);
+        assert_eq!(green_tint(255), 0x9BBC0F);
+    }
+}
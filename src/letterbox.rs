@@ -0,0 +1,86 @@
+// bars around the frame when its aspect ratio doesn't match the window one
+pub const BAR_COLOR: u32 = 0xFF000000;
+
+// part of the window showing the frame
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Viewport {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+// largest frame size fitting the window with the aspect ratio of the frame, centered in the window
+// integer scales keep all the pixels the same size, a fractional scale is used anyway when the
+// window is smaller than the frame. An empty viewport is returned for an empty window
+pub fn fit_frame(window_width: usize, window_height: usize, frame_width: usize, frame_height: usize, integer_scale: bool) -> Viewport {
+    let integer_factor = (window_width / frame_width).min(window_height / frame_height);
+
+    let (width, height) = if integer_scale && integer_factor > 0 {
+        (frame_width * integer_factor, frame_height * integer_factor)
+    } else if window_width * frame_height <= window_height * frame_width {
+        // the width is the limiting side
+        (window_width, window_width * frame_height / frame_width)
+    } else {
+        (window_height * frame_width / frame_height, window_height)
+    };
+
+    Viewport {
+        x: (window_width - width) / 2,
+        y: (window_height - height) / 2,
+        width,
+        height,
+    }
+}
+
+// scale the frame of host pixels to the window with the nearest pixels, the frame is centered
+// between black bars so that it's never stretched when the window is resized
+pub fn letterbox(frame: &[u32], frame_width: usize, frame_height: usize, window_width: usize, window_height: usize, integer_scale: bool) -> Vec<u32> {
+    let viewport = fit_frame(window_width, window_height, frame_width, frame_height, integer_scale);
+    let mut buffer = vec![BAR_COLOR; window_width * window_height];
+
+    for y in 0..viewport.height {
+        let frame_y = y * frame_height / viewport.height;
+        let line = (viewport.y + y) * window_width + viewport.x;
+
+        for (x, pixel) in buffer[line..line + viewport.width].iter_mut().enumerate() {
+            *pixel = frame[frame_y * frame_width + x * frame_width / viewport.width];
+        }
+    }
+
+    buffer
+}
+
+#[cfg(test)]
+mod letterbox_tests {
+    use super::*;
+    use crate::emulator::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    #[test]
+    fn test_fit_frame() {
+        // the default window is 3 times the screen
+        assert_eq!(fit_frame(480, 432, SCREEN_WIDTH, SCREEN_HEIGHT, true), Viewport { x: 0, y: 0, width: 480, height: 432 });
+
+        // a wide window gets bars on the sides
+        assert_eq!(fit_frame(1000, 432, SCREEN_WIDTH, SCREEN_HEIGHT, false), Viewport { x: 260, y: 0, width: 480, height: 432 });
+        // a tall window gets bars on the top and bottom, the integer scale leaves larger ones
+        assert_eq!(fit_frame(400, 1000, SCREEN_WIDTH, SCREEN_HEIGHT, false), Viewport { x: 0, y: 320, width: 400, height: 360 });
+        assert_eq!(fit_frame(400, 1000, SCREEN_WIDTH, SCREEN_HEIGHT, true), Viewport { x: 40, y: 356, width: 320, height: 288 });
+
+        // a window smaller than the screen shrinks it, an empty window shows nothing
+        assert_eq!(fit_frame(80, 80, SCREEN_WIDTH, SCREEN_HEIGHT, true), Viewport { x: 0, y: 4, width: 80, height: 72 });
+        assert_eq!(fit_frame(0, 0, SCREEN_WIDTH, SCREEN_HEIGHT, true), Viewport { x: 0, y: 0, width: 0, height: 0 });
+    }
+
+    #[test]
+    fn test_letterbox() {
+        // 2x1 frame in a 8x6 window: scaled 4 times, one bar line above and below
+        let buffer = letterbox(&[0xFFFFFFFF, 0xFF808080], 2, 1, 8, 6, true);
+        assert_eq!(buffer[..8], [BAR_COLOR; 8]);
+        assert_eq!(buffer[8..16], [0xFFFFFFFF, 0xFFFFFFFF, 0xFFFFFFFF, 0xFFFFFFFF, 0xFF808080, 0xFF808080, 0xFF808080, 0xFF808080]);
+        assert!(buffer[16..40].chunks(8).all(|line| *line == buffer[8..16]));
+        assert_eq!(buffer[40..], [BAR_COLOR; 8]);
+
+        assert!(letterbox(&[0xFFFFFFFF; 4], 2, 2, 0, 0, false).is_empty());
+    }
+}
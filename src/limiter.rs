@@ -0,0 +1,148 @@
+use std::time::{Duration, Instant};
+
+// late frames are caught up by running at most 4 frames back to back
+pub const DEFAULT_MAX_CATCH_UP_FRAMES: u32 = 4;
+
+// source of the current time, as the duration elapsed since an arbitrary origin, a virtual
+// clock can be used in place of the real one so that the frames are paced deterministically
+pub type Clock = Box<dyn Fn() -> Duration>;
+
+pub fn real_clock() -> Clock {
+    let origin = Instant::now();
+    Box::new(move || origin.elapsed())
+}
+
+// paces the emulation to the real hardware frame rate, independently of the emulation itself
+pub struct FrameLimiter {
+    period: Duration,
+    // time source, the real clock by default, shall be set before running the emulator
+    pub clock: Clock,
+    // the next frame can't be started before this time
+    next_frame_tick: Option<Duration>,
+    // late frames are run back to back to keep the average frame rate, at most this number of
+    // them: once the host is further behind, ie after a stall, the schedule restarts from the
+    // current time so that the backlog doesn't slow down the host even more
+    pub max_catch_up_frames: u32,
+    // turbo, the frames are run as fast as possible
+    pub unlimited: bool,
+}
+
+impl FrameLimiter {
+    pub fn new(period: Duration) -> FrameLimiter {
+        FrameLimiter {
+            period,
+            clock: real_clock(),
+            next_frame_tick: None,
+            max_catch_up_frames: DEFAULT_MAX_CATCH_UP_FRAMES,
+            unlimited: false,
+        }
+    }
+
+    pub fn now(&self) -> Duration {
+        (self.clock)()
+    }
+
+    // check if a new frame can be started, the following one is then due one period later
+    pub fn frame_due(&mut self) -> bool {
+        let now = self.now();
+
+        match self.next_frame_tick {
+            Some(next_frame_tick) if !self.unlimited && now < next_frame_tick => false,
+            // a late frame keeps the schedule, the next ones are run right away until it's caught up
+            Some(next_frame_tick) if !self.unlimited && now - next_frame_tick < self.period * self.max_catch_up_frames => {
+                self.next_frame_tick = Some(next_frame_tick + self.period);
+                true
+            }
+            _ => {
+                self.next_frame_tick = Some(now + self.period);
+                true
+            }
+        }
+    }
+
+    // the next frame is started right away
+    pub fn reset(&mut self) {
+        self.next_frame_tick = None;
+    }
+}
+
+#[cfg(test)]
+mod limiter_tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_frame_due() {
+        let mut limiter = FrameLimiter::new(Duration::from_secs(60));
+
+        // the first frame starts right away, the next one waits for the period
+        assert!(limiter.frame_due());
+        assert!(!limiter.frame_due());
+
+        limiter.reset();
+        assert!(limiter.frame_due());
+
+        limiter.unlimited = true;
+        assert!(limiter.frame_due());
+        assert!(limiter.frame_due());
+    }
+
+    #[test]
+    fn test_virtual_clock() {
+        let time = Rc::new(Cell::new(Duration::from_millis(0)));
+        let mut limiter = FrameLimiter::new(Duration::from_millis(16));
+        let clock_time = Rc::clone(&time);
+        limiter.clock = Box::new(move || clock_time.get());
+
+        // frames are due exactly one period apart, whatever the real time
+        assert!(limiter.frame_due());
+        time.set(Duration::from_millis(15));
+        assert!(!limiter.frame_due());
+        time.set(Duration::from_millis(16));
+        assert!(limiter.frame_due());
+
+        // a late frame keeps the schedule, the next one is due a period after the expected time
+        time.set(Duration::from_millis(40));
+        assert!(limiter.frame_due());
+        time.set(Duration::from_millis(47));
+        assert!(!limiter.frame_due());
+        time.set(Duration::from_millis(48));
+        assert!(limiter.frame_due());
+
+        // without catch up, a late frame pushes the next one a period after it
+        limiter.max_catch_up_frames = 0;
+        time.set(Duration::from_millis(70));
+        assert!(limiter.frame_due());
+        time.set(Duration::from_millis(85));
+        assert!(!limiter.frame_due());
+        time.set(Duration::from_millis(86));
+        assert!(limiter.frame_due());
+    }
+
+    #[test]
+    fn test_max_catch_up() {
+        let time = Rc::new(Cell::new(Duration::from_millis(0)));
+        let mut limiter = FrameLimiter::new(Duration::from_millis(16));
+        let clock_time = Rc::clone(&time);
+        limiter.clock = Box::new(move || clock_time.get());
+        let frames_due = |limiter: &mut FrameLimiter| (0..100).take_while(|_| limiter.frame_due()).count();
+
+        // 2 frames late: the missed frames are run back to back
+        assert!(limiter.frame_due());
+        time.set(Duration::from_millis(48));
+        assert_eq!(frames_due(&mut limiter), 3);
+
+        // a host stall of a second: a single frame is run and the schedule restarts from now
+        time.set(Duration::from_millis(1048));
+        assert_eq!(frames_due(&mut limiter), 1);
+        time.set(Duration::from_millis(1063));
+        assert!(!limiter.frame_due());
+        time.set(Duration::from_millis(1064));
+        assert!(limiter.frame_due());
+
+        // right below the stall threshold, the maximum number of frames is run back to back
+        time.set(Duration::from_millis(1080 + 16 * DEFAULT_MAX_CATCH_UP_FRAMES as u64 - 1));
+        assert_eq!(frames_due(&mut limiter), DEFAULT_MAX_CATCH_UP_FRAMES as usize);
+    }
+}
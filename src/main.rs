@@ -2,144 +2,1289 @@ mod emulator;
 mod soc;
 mod debug;
 mod cartridge;
+mod stats;
+mod limiter;
+mod overlay;
+mod color;
+mod savestate;
+mod script;
+mod watchdog;
+mod lcd_effect;
+mod ascii;
+mod snapshot;
+mod memory_search;
+mod map_view;
+mod tile_grid;
+mod letterbox;
+mod palette;
+mod renderer;
+mod audio;
+mod wav;
+mod frame_record;
+mod rom_menu;
+mod input_script;
+#[cfg(test)]
+mod screenshot;
+#[cfg(feature = "gamepad")]
+mod gamepad;
+#[cfg(feature = "gui")]
+mod gui;
 
-use minifb::{Key, Window, WindowOptions};
-use std::{fs::File, io::Read, env};
+use minifb::{Key, KeyRepeat, Window, WindowOptions};
+use std::{fs, fs::File, io::BufWriter, io::Read, io::Write, io::ErrorKind, env};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::sync::{Arc, Mutex};
 
-use crate::emulator::{Emulator, SCREEN_HEIGHT, SCREEN_WIDTH};
-use crate::debug::{DebugCtx, debug_cli, debug_vram};
+use crate::emulator::{Emulator, GameBoyKey, MemoryFill, Trace, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::cartridge::describe_header;
+use crate::debug::{DebugCtx, debug_cli, debug_vram, dump_state};
+use crate::soc::peripheral::gpu::{BACKGROUND_MAP_SIZE, SGB_FRAME_HEIGHT, SGB_FRAME_WIDTH, PixelColor, PpuAccuracy};
+use crate::overlay::Notifications;
+use crate::color::ColorCorrection;
+use crate::lcd_effect::LcdEffect;
+use crate::ascii::{DEFAULT_ASCII_WIDTH, frame_to_ascii};
+use crate::map_view::draw_map_view;
+use crate::palette::HostPalette;
+use crate::renderer::{Renderer, WindowRenderer};
+use crate::soc::peripheral::apu::SAMPLE_RATE;
+use crate::wav::WavWriter;
+use crate::frame_record::{FrameRecorder, DEFAULT_MAX_RECORDED_FRAMES};
+use crate::rom_menu::RomMenu;
+use crate::input_script::{InputScript, KEY_NAMES};
+use crate::limiter::DEFAULT_MAX_CATCH_UP_FRAMES;
+use crate::soc::peripheral::io::io_register_address;
+use crate::soc::peripheral::bootrom::BUILTIN_BOOT_ROM;
+use crate::soc::{MIN_CPU_SPEED, MAX_CPU_SPEED};
+#[cfg(feature = "gamepad")]
+use crate::gamepad::{Gamepad, GamepadMapping};
+#[cfg(feature = "gui")]
+use crate::gui::{ControlAction, ControlBar};
 
 // Window parameters
 const SCALE_FACTOR: usize = 3;
 const WINDOW_DIMENSIONS: [usize; 2] = [(SCREEN_WIDTH * SCALE_FACTOR), (SCREEN_HEIGHT * SCALE_FACTOR)];
 
+// The boot rom locks up when no game is inserted, either on the logo check or after the boot
+const BOOT_ROM_ONLY_WATCHDOG_FRAMES: u32 = 60;
+
+// Frame statistics are refreshed twice per second
+const STATS_REFRESH_FRAMES: u32 = 30;
+
+// F7 cycles the frame skip from 0 to this number of frames
+const MAX_FRAME_SKIP: u32 = 3;
+
+// keys free to write the state dump, selected with --dump-key
+const DUMP_KEYS: [(&str, Key); 4] = [("F9", Key::F9), ("F10", Key::F10), ("F11", Key::F11), ("F12", Key::F12)];
+
+// --palette value selecting the colors of the CGB boot rom instead of a palette file
+const AUTO_PALETTE: &str = "auto";
+
+// --boot value selecting the boot rom bundled with the emulator instead of a boot rom file
+const BUILTIN_BOOT_ROM_NAME: &str = "builtin";
+
+// Keyboard mapping
+const KEYBOARD_MAPPING: [(Key, GameBoyKey); 8] = [
+    (Key::Up, GameBoyKey::UP),
+    (Key::Down, GameBoyKey::DOWN),
+    (Key::Left, GameBoyKey::LEFT),
+    (Key::Right, GameBoyKey::RIGHT),
+    (Key::A, GameBoyKey::A),
+    (Key::S, GameBoyKey::B),
+    (Key::Enter, GameBoyKey::START),
+    (Key::Space, GameBoyKey::SELECT),
+];
+
+// auto-fire of the A and B buttons, toggled by these keys
+const TURBO_KEYS: [(Key, GameBoyKey, &str); 2] = [(Key::Q, GameBoyKey::A, "A"), (Key::W, GameBoyKey::B, "B")];
+const DEFAULT_TURBO_FRAMES: u8 = 2;
+
+// Save state slots are selected with the number keys
+const SAVE_STATE_SLOT_KEYS: [Key; 10] = [
+    Key::Key0, Key::Key1, Key::Key2, Key::Key3, Key::Key4,
+    Key::Key5, Key::Key6, Key::Key7, Key::Key8, Key::Key9,
+];
+
+// command line options
+struct Options {
+    // the built-in boot rom is run without path
+    boot_rom_path: Option<String>,
+    game_rom_path: Option<String>,
+    rom_dir: Option<String>,
+    debug_mode: bool,
+    // minimum hold frames of each button, indexed by the key
+    hold_frames: [u8; KEY_NAMES.len()],
+    // auto-fire of the A and B buttons at start
+    turbo: [bool; 2],
+    turbo_frames: u8,
+    memory_fill: Option<MemoryFill>,
+    watchdog_frames: Option<u32>,
+    color_correction: Option<ColorCorrection>,
+    lcd_effect: bool,
+    ascii_width: Option<usize>,
+    io_write_log: Option<Vec<u16>>,
+    serial_stdout: bool,
+    skip_boot: bool,
+    rgba_output_path: Option<String>,
+    audio_record_path: Option<String>,
+    frame_record_dir: Option<String>,
+    max_recorded_frames: u32,
+    palette_path: Option<String>,
+    // colors of the 4 shades, shared by all the palettes
+    shades: Option<HostPalette>,
+    input_script_path: Option<String>,
+    trace: Option<Trace>,
+    trace_range: Option<RangeInclusive<u16>>,
+    frame_skip: u32,
+    max_catch_up_frames: u32,
+    ppu_accuracy: PpuAccuracy,
+    cpu_speed: f64,
+    lenient_opcodes: bool,
+    dump_key: Key,
+    #[cfg(feature = "gamepad")]
+    gamepad_mapping: GamepadMapping,
+}
+
 fn main() {
-    // get arguments from the command line   
-    let (boot_rom_path, game_rom_path, debug_mode) = parse_args();
+    // get arguments from the command line
+    let options = match parse_args(env::args()) {
+        Ok(Command::Run(options)) => options,
+        Ok(Command::Help) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Ok(Command::Version) => {
+            println!("qoboy {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Ok(Command::Info(path)) => match rom_info(&path) {
+            Ok(info) => {
+                println!("{}", info);
+                return;
+            }
+            Err(error) => {
+                eprintln!("error: {}", error);
+                process::exit(1);
+            }
+        },
+        Err(message) => {
+            eprintln!("error: {}\n\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+
+    println!("boot_rom: {}", options.boot_rom_path.as_deref().unwrap_or(BUILTIN_BOOT_ROM_NAME));
+    match (&options.game_rom_path, &options.rom_dir) {
+        (Some(path), _) => println!("game_rom: {}", path),
+        (None, Some(dir)) => println!("game_rom: picked in the roms of {}", dir),
+        (None, None) => println!("game_rom: none, run the boot rom only"),
+    }
 
-    let mut file = File::open(boot_rom_path).unwrap();
-    let mut bin_data = [0xFF as u8; 256];
-    if let Err(message) = file.read_exact(&mut bin_data) {
-        panic!("Cannot read file with error message: {}", message);
+    // check the rom files before loading them
+    for path in options.boot_rom_path.iter().chain(options.game_rom_path.iter()) {
+        if let Err(message) = validate_rom_path(path) {
+            eprintln!("error: {}", message);
+            process::exit(1);
+        }
     }
 
-    let mut rom_file = File::open(game_rom_path).unwrap();
-    let rom_len = rom_file.metadata().unwrap().len();
-    let mut rom_data = vec![0xFF as u8; rom_len as usize];
-    if let Err(message) = rom_file.read_exact(&mut rom_data) {
-        panic!("Cannot read file with error message: {}", message);
+    // the game is picked in a menu once the window is open
+    let rom_menu = options.rom_dir.as_ref().map(|dir| {
+        let menu = RomMenu::new(Path::new(dir));
+        if menu.roms.is_empty() {
+            eprintln!("error: no rom found in {}", dir);
+            process::exit(1);
+        }
+        menu
+    });
+
+    let mut bin_data = BUILTIN_BOOT_ROM;
+    if let Some(path) = &options.boot_rom_path {
+        let mut file = File::open(path).unwrap();
+        if let Err(message) = file.read_exact(&mut bin_data) {
+            panic!("Cannot read file with error message: {}", message);
+        }
     }
 
+    let rom_data = options.game_rom_path.as_ref().map(|path| {
+        let mut rom_file = File::open(path).unwrap();
+        let rom_len = rom_file.metadata().unwrap().len();
+        let mut rom_data = vec![0xFFu8; rom_len as usize];
+        if let Err(message) = rom_file.read_exact(&mut rom_data) {
+            panic!("Cannot read file with error message: {}", message);
+        }
+        rom_data
+    });
+
     // launch the debugger cli
     let dbg_ctx = Arc::new(Mutex::new(DebugCtx::new()));
-    if debug_mode {
+    if options.debug_mode {
         debug_cli(&dbg_ctx);
         debug_vram(&dbg_ctx);
     }
 
     // create the emulated system
-    let mut emulator = Emulator::new(&bin_data, &rom_data, debug_mode);
+    let mut emulator = match &rom_data {
+        Some(rom_data) => Emulator::new(&bin_data, rom_data, options.debug_mode),
+        None => Emulator::without_cartridge(&bin_data, options.debug_mode),
+    };
 
-    // run the emulator
-    let mut buffer = [0; SCREEN_HEIGHT * SCREEN_WIDTH];
+    // latch short key presses so that quick taps are not missed
+    for (_, key) in KEYBOARD_MAPPING {
+        emulator.set_key_min_hold_frames(key, options.hold_frames[key as usize]);
+    }
 
-    let mut window = Window::new(
-        "Qoboy",
+    // auto-fire of the A and B buttons
+    let mut turbo = options.turbo;
+    for ((_, key, _), enabled) in TURBO_KEYS.iter().zip(turbo.iter()) {
+        emulator.set_key_turbo(*key, if *enabled { options.turbo_frames } else { 0 });
+    }
+
+    // save states are stored next to the game rom
+    if let Some(path) = options.game_rom_path.as_ref().or(options.boot_rom_path.as_ref()) {
+        emulator.set_state_path(Path::new(path));
+    }
+
+    // battery backed ram is saved next to the game rom
+    if let Some(path) = &options.game_rom_path {
+        if let Err(error) = emulator.load_sram(&Path::new(path).with_extension("sav")) {
+            eprintln!("error: cannot load the save file: {}", error);
+            process::exit(1);
+        }
+    }
+
+    // mimic a given power on state of the RAMs
+    if let Some(memory_fill) = options.memory_fill {
+        emulator.fill_memory(memory_fill);
+    }
+
+    // start the game without running the boot rom
+    if options.skip_boot {
+        emulator.skip_boot();
+    }
+
+    // stop unattended runs of locked up games, or the boot rom when it's run alone
+    let mut boot_rom_only = options.game_rom_path.is_none();
+    if boot_rom_only {
+        emulator.set_watchdog(Some(options.watchdog_frames.unwrap_or(BOOT_ROM_ONLY_WATCHDOG_FRAMES)));
+    } else {
+        emulator.set_watchdog(options.watchdog_frames);
+    }
+    let mut boot_rom_stopped = false;
+
+    // draw each pixel at its dot for the games changing the registers while a line is drawn
+    emulator.soc.peripheral.gpu.accuracy = options.ppu_accuracy;
+    emulator.soc.cpu_speed = options.cpu_speed;
+    emulator.soc.cpu.lenient_opcodes = options.lenient_opcodes;
+
+    // slow hosts can present only some of the frames, the emulation isn't affected
+    emulator.frame_skip = options.frame_skip;
+    // late frames are caught up, a host stall restarts the frame schedule
+    emulator.limiter.max_catch_up_frames = options.max_catch_up_frames;
+
+    // reverse engineering help, off by default since it slows down the emulation
+    emulator.set_io_write_log(options.io_write_log);
+    emulator.set_serial_output(options.serial_stdout);
+    emulator.set_trace(options.trace);
+    emulator.set_trace_range(options.trace_range);
+
+    // raw RGBA frames for another program to composite, usually through a named pipe
+    let mut rgba_output = options.rgba_output_path.as_ref().map(|path| {
+        File::create(path).unwrap_or_else(|error| {
+            eprintln!("error: cannot open the RGBA output {}: {}", path, error);
+            process::exit(1);
+        })
+    });
+
+    // the sound is recorded for the whole run, the file is completed on exit
+    let mut audio_record = options.audio_record_path.as_ref().map(|path| {
+        eprintln!("warning: --record-audio is experimental, the sound channels aren't generated yet so the record is silent");
+        WavWriter::create(Path::new(path), SAMPLE_RATE as u32).unwrap_or_else(|error| {
+            eprintln!("error: cannot open the audio record {}: {}", path, error);
+            process::exit(1);
+        })
+    });
+
+    // numbered images of the presented frames, to be assembled in a video
+    let max_recorded_frames = options.max_recorded_frames;
+    let mut frame_record = options.frame_record_dir.as_ref().map(|dir| {
+        FrameRecorder::new(Path::new(dir), max_recorded_frames).unwrap_or_else(|error| {
+            eprintln!("error: cannot open the frame record {}: {}", dir, error);
+            process::exit(1);
+        })
+    });
+
+    // custom colors of the background and object palettes, or the colors the CGB boot rom
+    // selects for the game, or the colors of the 4 shades given on the command line
+    let auto_palette = options.palette_path.as_deref() == Some(AUTO_PALETTE);
+    let host_palette = match &options.palette_path {
+        Some(_) if auto_palette => emulator.compatibility_palette(),
+        Some(path) => Some(fs::read_to_string(path).map_err(|error| error.to_string()).and_then(|source| HostPalette::parse(&source)).unwrap_or_else(|message| {
+            eprintln!("error: cannot load the palette {}: {}", path, message);
+            process::exit(1);
+        })),
+        None => options.shades,
+    };
+
+    // scripted key presses to reproduce a sequence, held along with the keyboard ones
+    let mut input_script = options.input_script_path.as_ref().map(|path| {
+        fs::read_to_string(path).map_err(|error| error.to_string()).and_then(|source| InputScript::parse(&source)).unwrap_or_else(|message| {
+            eprintln!("error: cannot load the input script {}: {}", path, message);
+            process::exit(1);
+        })
+    });
+    let mut emulated_frames: u32 = 0;
+
+    // gamepads are polled along with the keyboard
+    #[cfg(feature = "gamepad")]
+    let mut gamepad = Gamepad::new(options.gamepad_mapping);
+
+    // the control bar is drawn above the screen, its rom picker lists the roms of the menu
+    // directory or the roms next to the game
+    #[cfg(feature = "gui")]
+    let control_bar = ControlBar::new(
         WINDOW_DIMENSIONS[0],
         WINDOW_DIMENSIONS[1],
-        WindowOptions::default(),
+        options.rom_dir.as_ref().map(Path::new)
+            .or(options.game_rom_path.as_ref().and_then(|path| Path::new(path).parent()))
+            .filter(|dir| !dir.as_os_str().is_empty()).unwrap_or_else(|| Path::new(".")),
+    );
+    #[cfg(feature = "gui")]
+    let window_dimensions = control_bar.window_size();
+    #[cfg(not(feature = "gui"))]
+    let window_dimensions = (WINDOW_DIMENSIONS[0], WINDOW_DIMENSIONS[1]);
+
+    // run the emulator
+    let window = Window::new(
+        "Qoboy",
+        window_dimensions.0,
+        window_dimensions.1,
+        // the control bar has a fixed size, the frame is letterboxed in a resized window otherwise
+        WindowOptions {
+            resize: cfg!(not(feature = "gui")),
+            ..WindowOptions::default()
+        },
     )
     .unwrap();
 
-    while window.is_open() && !window.is_key_down(Key::Escape) {
-        // get key from the keyboard
-        if window.is_key_down(Key::Up) {
-            emulator.set_key(soc::GameBoyKey::UP, true);
-        } else {
-            emulator.set_key(soc::GameBoyKey::UP, false);
+    // the frames are displayed in the window through the renderer
+    #[cfg(feature = "gui")]
+    let mut renderer = WindowRenderer::new(window, control_bar);
+    #[cfg(not(feature = "gui"))]
+    let mut renderer = WindowRenderer::new(window);
+    renderer.colors.palette = host_palette;
+    renderer.colors.correction = options.color_correction;
+    renderer.colors.green_tint = options.lcd_effect;
+
+    let mut frame = [0; SCREEN_HEIGHT * SCREEN_WIDTH];
+    let mut show_stats = false;
+    let mut stats_text = String::new();
+    let mut frame_counter: u32 = 0;
+    let mut notifications = Notifications::new();
+    let mut state_slot: u8 = 0;
+    let mut lcd_effect = LcdEffect::new();
+    let mut show_background_map = false;
+
+    // pick the game in the menu, it's loaded by the cartridge swap of the first frame
+    if let Some(mut menu) = rom_menu {
+        // the keys held when the window opens, ie the enter key of the command, aren't presses
+        let mut previous_keys = [true; KEYBOARD_MAPPING.len()];
+        let mut picked_rom: Option<PathBuf> = None;
+
+        while picked_rom.is_none() {
+            if !renderer.window.is_open() || renderer.window.is_key_down(Key::Escape) {
+                finish_audio_record(audio_record);
+                return;
+            }
+
+            #[cfg(feature = "gamepad")]
+            gamepad.poll();
+
+            for (index, (keyboard_key, gameboy_key)) in KEYBOARD_MAPPING.iter().enumerate() {
+                #[allow(unused_mut)]
+                let mut pressed = renderer.window.is_key_down(*keyboard_key);
+
+                #[cfg(feature = "gamepad")]
+                { pressed |= gamepad.is_pressed(*gameboy_key); }
+
+                if pressed && !previous_keys[index] && picked_rom.is_none() {
+                    picked_rom = menu.press(*gameboy_key);
+                }
+                previous_keys[index] = pressed;
+            }
+
+            menu.draw(&mut frame);
+            renderer.present(&frame);
         }
 
-        if window.is_key_down(Key::Down) {
-            emulator.set_key(soc::GameBoyKey::DOWN, true);
-        } else {
-            emulator.set_key(soc::GameBoyKey::DOWN, false);
+        dbg_ctx.lock().unwrap().cartridge_path = picked_rom;
+    }
+
+    while renderer.window.is_open() && !renderer.window.is_key_down(Key::Escape) {
+        // get keys from the keyboard and the gamepad
+        #[cfg(feature = "gamepad")]
+        gamepad.poll();
+
+        if let Some(script) = input_script.as_mut() {
+            script.advance(emulated_frames);
         }
 
-        if window.is_key_down(Key::Left) {
-            emulator.set_key(soc::GameBoyKey::LEFT, true);
-        } else {
-            emulator.set_key(soc::GameBoyKey::LEFT, false);
+        for (keyboard_key, gameboy_key) in KEYBOARD_MAPPING {
+            let mut pressed = renderer.window.is_key_down(keyboard_key);
+
+            #[cfg(feature = "gamepad")]
+            { pressed |= gamepad.is_pressed(gameboy_key); }
+
+            pressed |= input_script.as_ref().is_some_and(|script| script.is_held(gameboy_key));
+
+            emulator.set_key(gameboy_key, pressed);
         }
 
-        if window.is_key_down(Key::Right) {
-            emulator.set_key(soc::GameBoyKey::RIGHT, true);
-        } else {
-            emulator.set_key(soc::GameBoyKey::RIGHT, false);
+        // run the emulator for a frame when the limiter allows it
+        #[cfg(feature = "gui")]
+        let frame_ended = !renderer.control_bar.paused && emulator.run(&mut dbg_ctx.lock().unwrap());
+        #[cfg(not(feature = "gui"))]
+        let frame_ended = emulator.run(&mut dbg_ctx.lock().unwrap());
+
+        if frame_ended {
+            emulated_frames += 1;
         }
 
-        if window.is_key_down(Key::A) {
-            emulator.set_key(soc::GameBoyKey::A, true);
-        } else {
-            emulator.set_key(soc::GameBoyKey::A, false);
+        if let Some(wav) = audio_record.as_mut() {
+            emulator.output_audio(wav);
         }
 
-        if window.is_key_down(Key::S) {
-            emulator.set_key(soc::GameBoyKey::B, true);
-        } else {
-            emulator.set_key(soc::GameBoyKey::B, false);
+        // the test roms print their diagnostics through the link port
+        if options.serial_stdout {
+            let mut stdout = std::io::stdout();
+            if let Err(error) = emulator.output_serial(&mut stdout).and_then(|_| stdout.flush()) {
+                eprintln!("error: cannot print the serial output: {}", error);
+            }
         }
 
-        if window.is_key_down(Key::Enter) {
-            emulator.set_key(soc::GameBoyKey::START, true);
-        } else {
-            emulator.set_key(soc::GameBoyKey::START, false);
+        if let Some(lockup) = emulator.lockup() {
+            if !boot_rom_only {
+                eprintln!("error: the game is locked up, {}", lockup);
+                let _ = emulator.flush_sram();
+                finish_audio_record(audio_record);
+                process::exit(3);
+            }
+
+            // keep displaying the last frame rendered by the boot rom
+            if !boot_rom_stopped {
+                boot_rom_stopped = true;
+                println!("the boot rom has stopped, {}", lockup);
+                notifications.push("BOOT ROM STOPPED");
+            }
         }
 
-        if window.is_key_down(Key::Space) {
-            emulator.set_key(soc::GameBoyKey::SELECT, true);
-        } else {
-            emulator.set_key(soc::GameBoyKey::SELECT, false);
+        // run the actions asked through the control bar
+        #[cfg(feature = "gui")]
+        for action in renderer.control_bar.take_actions() {
+            match action {
+                ControlAction::RESET => match emulator.reset() {
+                    Ok(()) => {
+                        boot_rom_stopped = false;
+                        notifications.push("RESET");
+                    }
+                    Err(error) => {
+                        println!("Cannot reset the emulator: {}", error);
+                        notifications.push("RESET FAILED");
+                    }
+                },
+                ControlAction::SAVE_STATE => save_state(&emulator, state_slot, &mut notifications),
+                ControlAction::LOAD_STATE => load_state(&mut emulator, state_slot, &mut notifications),
+                ControlAction::LOAD_ROM(path) => dbg_ctx.lock().unwrap().cartridge_path = Some(path),
+            }
         }
 
-        // run emulator until a new frame is ready
-        emulator.run(&mut *dbg_ctx.lock().unwrap());
+        // swap the cartridge when asked by the debugger or the control bar
+        let cartridge_path = dbg_ctx.lock().unwrap().cartridge_path.take();
+        if let Some(path) = cartridge_path {
+            if load_rom(&mut emulator, &path, &mut notifications) {
+                if auto_palette {
+                    renderer.colors.palette = emulator.compatibility_palette();
+                }
+                if boot_rom_only {
+                    boot_rom_only = false;
+                    boot_rom_stopped = false;
+                    emulator.set_watchdog(options.watchdog_frames);
+                }
+            }
+        }
+
+        // dump the system state to the file given to the debugger
+        let dump_path = dbg_ctx.lock().unwrap().dump_path.take();
+        if let Some(path) = dump_path {
+            write_state_dump(&emulator, &path, &mut notifications);
+        }
 
+        // save the battery backed ram periodically so that a crash doesn't lose the progress, the
+        // games also write it with the LCD off
+        if frame_ended {
+            if let Err(error) = emulator.auto_flush_sram() {
+                println!("Cannot save the battery backed ram: {}", error);
+            }
+        }
+
+        let mut window_updated = false;
         if emulator.frame_ready() {
             // copy the current frame from gpu frame buffer
-            for i in 0..SCREEN_HEIGHT * SCREEN_WIDTH {
-                buffer[i] =  255 << 24
-                            | (emulator.get_frame_buffer(i) as u32) << 16
-                            | (emulator.get_frame_buffer(i) as u32) << 8
-                            | (emulator.get_frame_buffer(i) as u32) << 0;
+            // the overlay is drawn in this copy so the emulated frame is never modified
+            frame.copy_from_slice(emulator.fetch_frame());
+
+            // print the emulated frame in the terminal
+            if let Some(width) = options.ascii_width {
+                println!("{}", frame_to_ascii(&frame, width));
+            }
+
+            if let Some(output) = rgba_output.as_mut() {
+                if let Err(error) = output.write_all(&emulator.get_rgba_frame(SGB_FRAME_WIDTH, SGB_FRAME_HEIGHT)) {
+                    println!("Cannot write the RGBA frame, output stopped: {}", error);
+                    rgba_output = None;
+                }
+            }
+
+            // the overlay is drawn after the effect to stay readable
+            if renderer.colors.green_tint {
+                lcd_effect.apply(&mut frame);
+            }
+
+            // the frames are recorded with the display colors, without the overlay
+            if let Some(recorder) = frame_record.as_mut() {
+                if let Err(error) = recorder.record(&renderer.colors.convert(&frame, emulator.get_frame_palettes()), SCREEN_WIDTH, SCREEN_HEIGHT) {
+                    println!("Cannot write the frame image, record stopped: {}", error);
+                    frame_record = None;
+                } else if recorder.is_full() {
+                    println!("the frame record is full, {} frames written", recorder.max_frames);
+                    frame_record = None;
+                }
+            }
+
+            frame_counter += 1;
+            if frame_counter >= STATS_REFRESH_FRAMES {
+                frame_counter = 0;
+                let stats = emulator.stats();
+                stats_text = format!("{:.1} FPS {:.0}%", stats.fps(), stats.speed());
+            }
+
+            if show_stats {
+                overlay::draw_text(&mut frame, &stats_text, 0, 0, u8::from(PixelColor::WHITE), Some(u8::from(PixelColor::BLACK)));
+            }
+
+            notifications.draw(&mut frame);
+            notifications.end_of_frame();
+
+            renderer.frame_palettes.copy_from_slice(emulator.get_frame_palettes());
+            renderer.viewport_position = emulator.get_viewport_position();
+            if show_background_map {
+                let (scx, scy) = renderer.viewport_position;
+                let mut map_view = draw_map_view(&emulator.get_background_map(), scx, scy);
+                renderer.present_pixels(&mut map_view, BACKGROUND_MAP_SIZE, BACKGROUND_MAP_SIZE);
+            } else {
+                // display the frame rendered by the gpu
+                renderer.present(&frame);
+            }
+            window_updated = true;
+        } else if frame_ended {
+            // no frame while the LCD is off or the frame is skipped, the last one stays displayed
+            // but the inputs are still read
+            renderer.update();
+            window_updated = true;
+        } else {
+            // keep the control bar usable while the emulation is paused
+            #[cfg(feature = "gui")]
+            if renderer.control_bar.paused {
+                renderer.update();
+                window_updated = true;
             }
-            // display the frame rendered by the gpu
-            window.update_with_buffer(&buffer, SCREEN_WIDTH, SCREEN_HEIGHT).unwrap();
         }
+
+        // the key presses are only reported until the next window update, the hotkeys are read
+        // after each one whether a frame has been presented or not
+        if !window_updated {
+            continue;
+        }
+
+        // display the frame statistics in the top left corner
+        if renderer.window.is_key_pressed(Key::F1, KeyRepeat::No) {
+            show_stats = !show_stats;
+            notifications.push(if show_stats { "STATS ON" } else { "STATS OFF" });
+        }
+
+        // blend with the previous frame and tint in green like the DMG LCD
+        if renderer.window.is_key_pressed(Key::F2, KeyRepeat::No) {
+            renderer.colors.green_tint = !renderer.colors.green_tint;
+            lcd_effect.reset();
+            notifications.push(if renderer.colors.green_tint { "LCD EFFECT ON" } else { "LCD EFFECT OFF" });
+        }
+
+        // display the whole background map instead of the screen, for debugging
+        if renderer.window.is_key_pressed(Key::F3, KeyRepeat::No) {
+            show_background_map = !show_background_map;
+            notifications.push(if show_background_map { "BG MAP ON" } else { "BG MAP OFF" });
+        }
+
+        // draw the 8x8 tile boundaries over the screen or the background map
+        if renderer.window.is_key_pressed(Key::F4, KeyRepeat::No) {
+            renderer.tile_grid = !renderer.tile_grid;
+            notifications.push(if renderer.tile_grid { "TILE GRID ON" } else { "TILE GRID OFF" });
+        }
+
+        // scale the frame by a whole factor only, so that all the pixels have the same size
+        if renderer.window.is_key_pressed(Key::F6, KeyRepeat::No) {
+            renderer.integer_scale = !renderer.integer_scale;
+            notifications.push(if renderer.integer_scale { "INTEGER SCALE ON" } else { "INTEGER SCALE OFF" });
+        }
+
+        // toggle the auto-fire of the A and B buttons
+        for ((host_key, key, name), enabled) in TURBO_KEYS.iter().zip(turbo.iter_mut()) {
+            if renderer.window.is_key_pressed(*host_key, KeyRepeat::No) {
+                *enabled = !*enabled;
+                emulator.set_key_turbo(*key, if *enabled { options.turbo_frames } else { 0 });
+                notifications.push(&format!("TURBO {} {}", name, if *enabled { "ON" } else { "OFF" }));
+            }
+        }
+
+        // present only one frame out of frame_skip + 1, for slow hosts
+        if renderer.window.is_key_pressed(Key::F7, KeyRepeat::No) {
+            emulator.frame_skip = (emulator.frame_skip + 1) % (MAX_FRAME_SKIP + 1);
+            notifications.push(&format!("FRAME SKIP {}", emulator.frame_skip));
+        }
+
+        // select, save and load the save state slots
+        for (slot, key) in SAVE_STATE_SLOT_KEYS.iter().enumerate() {
+            if renderer.window.is_key_pressed(*key, KeyRepeat::No) {
+                state_slot = slot as u8;
+                notifications.push(&format!("SLOT {}", state_slot));
+            }
+        }
+
+        if renderer.window.is_key_pressed(Key::F5, KeyRepeat::No) {
+            save_state(&emulator, state_slot, &mut notifications);
+        }
+
+        if renderer.window.is_key_pressed(Key::F8, KeyRepeat::No) {
+            load_state(&mut emulator, state_slot, &mut notifications);
+        }
+
+        // dump the system state next to the save states, for the bug reports
+        if renderer.window.is_key_pressed(options.dump_key, KeyRepeat::No) {
+            write_state_dump(&emulator, &emulator.get_state_dump_path(), &mut notifications);
+        }
+    }
+
+    finish_audio_record(audio_record);
+
+    // save the last modifications of the battery backed ram
+    if let Err(error) = emulator.flush_sram() {
+        eprintln!("error: cannot save the battery backed ram: {}", error);
+        process::exit(1);
+    }
+}
+
+// complete the header of the WAV file, the write errors of the recording are reported here
+fn finish_audio_record(audio_record: Option<WavWriter<BufWriter<File>>>) {
+    if let Some(Err(error)) = audio_record.map(WavWriter::finish) {
+        eprintln!("error: cannot write the audio record: {}", error);
+    }
+}
+
+// decoded header of the rom file, printed by --info
+fn rom_info(path: &str) -> Result<String, String> {
+    let rom = fs::read(path).map_err(|error| format!("cannot read {}: {}", path, error))?;
+    describe_header(&rom).map(|header| format!("file : {} ({} bytes)\n{}", path, rom.len(), header))
+}
+
+// swap the cartridge with the rom at the path, the save file of the current game is written first
+// the current game keeps running when the file isn't a valid rom, the failure is only notified
+fn load_rom(emulator: &mut Emulator, path: &Path, notifications: &mut Notifications) -> bool {
+    match emulator.load_cartridge(path) {
+        Ok(()) => {
+            println!("cartridge loaded: {}", path.display());
+            notifications.push("CARTRIDGE LOADED");
+            true
+        }
+        Err(error) => {
+            println!("Cannot load the cartridge {}: {}", path.display(), error);
+            notifications.push("LOAD FAILED");
+            false
+        }
+    }
+}
+
+// save the emulator state in the slot, the result is notified on screen
+fn save_state(emulator: &Emulator, slot: u8, notifications: &mut Notifications) {
+    match emulator.save_state_slot(slot) {
+        Ok(()) => notifications.push(&format!("SAVED SLOT {}", slot)),
+        Err(error) => {
+            println!("Cannot save state in slot {}: {}", slot, error);
+            notifications.push(&format!("SAVE SLOT {} FAILED", slot));
+        }
+    }
+}
+
+// load the emulator state from the slot, the result is notified on screen
+fn load_state(emulator: &mut Emulator, slot: u8, notifications: &mut Notifications) {
+    match emulator.load_state_slot(slot) {
+        Ok(()) => notifications.push(&format!("LOADED SLOT {}", slot)),
+        Err(error) if error.kind() == ErrorKind::NotFound => notifications.push(&format!("SLOT {} EMPTY", slot)),
+        Err(error) => {
+            println!("Cannot load state from slot {}: {}", slot, error);
+            notifications.push(&format!("LOAD SLOT {} FAILED", slot));
+        }
+    }
+}
+
+// write the human readable dump of the system state, the result is notified on screen
+fn write_state_dump(emulator: &Emulator, path: &Path, notifications: &mut Notifications) {
+    match fs::write(path, dump_state(emulator)) {
+        Ok(()) => {
+            println!("state dumped to {}", path.display());
+            notifications.push("STATE DUMPED");
+        }
+        Err(error) => {
+            println!("Cannot dump the state to {}: {}", path.display(), error);
+            notifications.push("DUMP FAILED");
+        }
+    }
+}
+
+const USAGE: &str = "Usage: qoboy <boot_rom_path> [game_rom_path] [options]
+       qoboy --boot=builtin [game_rom_path] [options]
+       qoboy --info <rom_path>
+
+Without game rom, the boot rom is run alone with a blank cartridge.
+
+Options:
+    --boot=<boot_rom>         builtin to run the boot rom bundled with the emulator, or the boot rom path
+    --rom-dir=<dir>           pick the game in a menu listing the roms of the directory
+    --debug                   start the emulator halted with the debugger cli
+    --skip-boot               start the game directly, with the registers set as the boot rom leaves them
+    --hold-frames=<frames>    keep short key presses down for at least this number of frames, for all the
+                              buttons or per button, ie a:3,b:2
+    --turbo=<buttons>         start with the auto-fire of the buttons, a, b or a,b, toggled with Q and W
+    --turbo-rate=<frames>     the held buttons are pressed and released every this number of frames, 2 by default
+    --ram-fill=<fill>         power on RAM content: zero, ones or random:<seed>
+    --color-correction=<mode> convert colors like CGB ones: raw or gambatte for the LCD look
+    --palette=<path>          colors of the background and object palettes, one \"R G B\" line per color,
+                              or auto to select them from the game title like the CGB boot rom
+    --shades=<colors>         colors of the 4 shades from the lightest, comma separated AARRGGBB values
+    --input-script=<path>     hold the keys of the script lines \"<frame> [key ...]\" from their frame on
+    --ppu=<accuracy>          fast draws each line at once, accurate draws each pixel at its dot
+    --cpu-speed=<multiplier>  run the cpu faster or slower than the ppu and the timers, from 0.25 to 4
+    --lenient-opcodes         run the undefined opcodes as NOP, they lock up the cpu with an error by default
+    --frame-skip=<frames>     present one frame out of frames + 1, the skipped ones are still emulated
+    --max-catch-up=<frames>   run at most this number of late frames back to back, 4 by default
+    --dump-key=<key>          key writing the system state dump next to the save states: F9 (default) to F12
+    --gamepad-map=<buttons>   gamepad buttons of the gameboy buttons, ie a:north,b:west, needs the gamepad feature
+    --lcd-effect              start with the DMG LCD ghosting and green tint, toggled with F2
+    --ascii[=<width>]         print each frame as ascii art, 80 characters wide by default
+    --log-io[=<registers>]    print the I/O registers writes, only the given comma separated ones, ie NR12,FF40
+    --serial-stdout           print the bytes sent through the link port as text, like the test roms results
+    --trace[=annotated]       print each instruction run, annotated with the memory address and value it accesses
+    --trace-range=<range>     trace only the instructions at the addresses of the range, ie 0x150-0x200
+    --rgba-output=<path>      write each frame as 256x224 RGBA pixels, the screen centered in a transparent border
+    --record-audio=<path>     experimental, write the emulated audio to a 16 bits stereo WAV file until the
+                              emulator exits, the record is silent until the sound channels are generated
+    --record-frames=<dir>     write each presented frame as a numbered PPM image in the directory, for a video
+    --record-limit=<frames>   stop the frame record after this number of frames, 3600 by default
+    --watchdog=<frames>       exit with an error when the game is locked up for this number of frames
+    --info <rom_path>         print the decoded cartridge header of the rom and exit
+    --help                    print this help
+    --version                 print the version";
+
+// what the command line asks for
+enum Command {
+    Run(Box<Options>),
+    Help,
+    Version,
+    Info(String),
+}
+
+// the first argument is the program name
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut boot = None;
+    // boot and game rom paths
+    let mut rom_paths = Vec::new();
+    let mut rom_dir = None;
+    let mut debug_mode = false;
+    let mut hold_frames = [0; KEY_NAMES.len()];
+    let mut turbo = [false; 2];
+    let mut turbo_frames = DEFAULT_TURBO_FRAMES;
+    let mut memory_fill = None;
+    let mut watchdog_frames = None;
+    let mut color_correction = None;
+    let mut lcd_effect = false;
+    let mut ascii_width = None;
+    let mut io_write_log = None;
+    let mut serial_stdout = false;
+    let mut skip_boot = false;
+    let mut rgba_output_path = None;
+    let mut audio_record_path = None;
+    let mut frame_record_dir = None;
+    let mut max_recorded_frames = DEFAULT_MAX_RECORDED_FRAMES;
+    let mut palette_path = None;
+    let mut shades = None;
+    let mut input_script_path = None;
+    let mut trace = None;
+    let mut trace_range = None;
+    let mut frame_skip = 0;
+    let mut max_catch_up_frames = DEFAULT_MAX_CATCH_UP_FRAMES;
+    let mut ppu_accuracy = PpuAccuracy::Fast;
+    let mut cpu_speed = 1.0;
+    let mut lenient_opcodes = false;
+    let mut dump_key = Key::F9;
+    #[cfg(feature = "gamepad")]
+    let mut gamepad_mapping = GamepadMapping::new();
+
+    let mut args = args.skip(1);
+    while let Some(argument) = args.next() {
+        if argument == "--help" || argument == "-h" {
+            return Ok(Command::Help);
+        } else if argument == "--version" || argument == "-V" {
+            return Ok(Command::Version);
+        } else if argument == "--info" {
+            return args.next().map(Command::Info).ok_or_else(|| String::from("--info expects a rom path"));
+        } else if argument == "--debug" {
+            debug_mode = true;
+        } else if let Some(boot_rom) = argument.strip_prefix("--boot=") {
+            boot = Some(boot_rom.to_string());
+        } else if let Some(dir) = argument.strip_prefix("--rom-dir=") {
+            rom_dir = Some(dir.to_string());
+        } else if argument == "--skip-boot" {
+            skip_boot = true;
+        } else if let Some(settings) = argument.strip_prefix("--hold-frames=") {
+            // a number of frames for all the buttons, or per button, ie 2,a:3
+            for setting in settings.split(',') {
+                let error = || format!("--hold-frames expects a number of frames or buttons with theirs, ie a:3,b:2, got {}", setting);
+                match setting.split_once(':') {
+                    Some((button, frames)) => {
+                        let (_, key) = KEY_NAMES.iter().find(|(name, _)| name.eq_ignore_ascii_case(button)).ok_or_else(error)?;
+                        hold_frames[*key as usize] = frames.parse().map_err(|_| error())?;
+                    }
+                    None => hold_frames = [setting.parse().map_err(|_| error())?; KEY_NAMES.len()],
+                }
+            }
+        } else if let Some(buttons) = argument.strip_prefix("--turbo=") {
+            for button in buttons.split(',') {
+                let index = TURBO_KEYS.iter().position(|(_, _, name)| name.eq_ignore_ascii_case(button))
+                    .ok_or(format!("--turbo expects the A and B buttons, ie a,b, got {}", button))?;
+                turbo[index] = true;
+            }
+        } else if let Some(frames) = argument.strip_prefix("--turbo-rate=") {
+            turbo_frames = frames.parse().ok().filter(|frames| *frames > 0)
+                .ok_or(format!("--turbo-rate expects a number of frames, got {}", frames))?;
+        } else if let Some(fill) = argument.strip_prefix("--ram-fill=") {
+            memory_fill = Some(parse_memory_fill(fill)?);
+        } else if let Some(mode) = argument.strip_prefix("--color-correction=") {
+            color_correction = Some(match mode {
+                "raw" => ColorCorrection::Raw,
+                "gambatte" => ColorCorrection::Gambatte,
+                _ => return Err(format!("--color-correction expects raw or gambatte, got {}", mode)),
+            });
+        } else if argument == "--lcd-effect" {
+            lcd_effect = true;
+        } else if argument == "--ascii" {
+            ascii_width = Some(DEFAULT_ASCII_WIDTH);
+        } else if let Some(width) = argument.strip_prefix("--ascii=") {
+            ascii_width = Some(width.parse().map_err(|_| format!("--ascii expects a number of characters, got {}", width))?);
+        } else if argument == "--log-io" {
+            io_write_log = Some(Vec::new());
+        } else if let Some(registers) = argument.strip_prefix("--log-io=") {
+            io_write_log = Some(parse_io_registers(registers)?);
+        } else if argument == "--serial-stdout" {
+            serial_stdout = true;
+        } else if argument == "--trace" {
+            trace = Some(Trace::MNEMONICS);
+        } else if argument == "--trace=annotated" {
+            trace = Some(Trace::ANNOTATED);
+        } else if let Some(range) = argument.strip_prefix("--trace-range=") {
+            trace_range = Some(parse_address_range(range)?);
+        } else if let Some(path) = argument.strip_prefix("--rgba-output=") {
+            rgba_output_path = Some(path.to_string());
+        } else if let Some(path) = argument.strip_prefix("--record-audio=") {
+            audio_record_path = Some(path.to_string());
+        } else if let Some(dir) = argument.strip_prefix("--record-frames=") {
+            frame_record_dir = Some(dir.to_string());
+        } else if let Some(frames) = argument.strip_prefix("--record-limit=") {
+            max_recorded_frames = frames.parse().map_err(|_| format!("--record-limit expects a number of frames, got {}", frames))?;
+        } else if let Some(path) = argument.strip_prefix("--palette=") {
+            palette_path = Some(path.to_string());
+        } else if let Some(colors) = argument.strip_prefix("--shades=") {
+            shades = Some(HostPalette::parse_shades(colors).map_err(|message| format!("--shades expects 4 comma separated colors, {}", message))?);
+        } else if let Some(path) = argument.strip_prefix("--input-script=") {
+            input_script_path = Some(path.to_string());
+        } else if let Some(accuracy) = argument.strip_prefix("--ppu=") {
+            ppu_accuracy = match accuracy {
+                "fast" => PpuAccuracy::Fast,
+                "accurate" => PpuAccuracy::Accurate,
+                _ => return Err(format!("--ppu expects fast or accurate, got {}", accuracy)),
+            };
+        } else if let Some(speed) = argument.strip_prefix("--cpu-speed=") {
+            cpu_speed = speed.parse().ok().filter(|speed| (MIN_CPU_SPEED..=MAX_CPU_SPEED).contains(speed))
+                .ok_or(format!("--cpu-speed expects a multiplier from {} to {}, got {}", MIN_CPU_SPEED, MAX_CPU_SPEED, speed))?;
+        } else if argument == "--lenient-opcodes" {
+            lenient_opcodes = true;
+        } else if let Some(frames) = argument.strip_prefix("--frame-skip=") {
+            frame_skip = frames.parse().map_err(|_| format!("--frame-skip expects a number of frames, got {}", frames))?;
+        } else if let Some(frames) = argument.strip_prefix("--max-catch-up=") {
+            max_catch_up_frames = frames.parse().map_err(|_| format!("--max-catch-up expects a number of frames, got {}", frames))?;
+        } else if let Some(key) = argument.strip_prefix("--dump-key=") {
+            dump_key = DUMP_KEYS.iter().find(|(name, _)| name.eq_ignore_ascii_case(key)).map(|(_, key)| *key)
+                .ok_or(format!("--dump-key expects F9, F10, F11 or F12, got {}", key))?;
+        } else if let Some(buttons) = argument.strip_prefix("--gamepad-map=") {
+            #[cfg(feature = "gamepad")]
+            {
+                gamepad_mapping = GamepadMapping::parse(buttons)
+                    .map_err(|message| format!("--gamepad-map expects gameboy buttons with their gamepad button, ie a:north,b:west, {}", message))?;
+            }
+            #[cfg(not(feature = "gamepad"))]
+            return Err(format!("--gamepad-map needs the gamepad feature, got {}", buttons));
+        } else if let Some(frames) = argument.strip_prefix("--watchdog=") {
+            watchdog_frames = Some(frames.parse().map_err(|_| format!("--watchdog expects a number of frames, got {}", frames))?);
+        } else if argument.starts_with("--") {
+            return Err(format!("unknown option {}", argument));
+        } else {
+            rom_paths.push(argument);
+        }
+    }
+
+    // --boot replaces the boot rom path argument
+    let mut rom_paths = rom_paths.into_iter();
+    let boot_rom_path = match boot {
+        Some(boot_rom) if boot_rom == BUILTIN_BOOT_ROM_NAME => None,
+        Some(path) => Some(path),
+        None => Some(rom_paths.next().ok_or("missing boot rom path")?),
+    };
+    let game_rom_path = rom_paths.next();
+    if let Some(argument) = rom_paths.next() {
+        return Err(format!("unexpected argument {}", argument));
+    }
+
+    // the game is picked in the menu
+    if rom_dir.is_some() && game_rom_path.is_some() {
+        return Err(String::from("--rom-dir replaces the game rom path"));
+    }
+
+    // both give the colors of the shades
+    if shades.is_some() && palette_path.is_some() {
+        return Err(String::from("--shades and --palette can't be used together"));
+    }
+
+    // the range alone traces the mnemonics
+    if trace_range.is_some() && trace.is_none() {
+        trace = Some(Trace::MNEMONICS);
+    }
+
+    Ok(Command::Run(Box::new(Options {
+        boot_rom_path,
+        game_rom_path,
+        rom_dir,
+        debug_mode,
+        hold_frames,
+        turbo,
+        turbo_frames,
+        memory_fill,
+        watchdog_frames,
+        color_correction,
+        lcd_effect,
+        ascii_width,
+        io_write_log,
+        serial_stdout,
+        skip_boot,
+        rgba_output_path,
+        audio_record_path,
+        frame_record_dir,
+        max_recorded_frames,
+        palette_path,
+        shades,
+        input_script_path,
+        trace,
+        trace_range,
+        frame_skip,
+        max_catch_up_frames,
+        ppu_accuracy,
+        cpu_speed,
+        lenient_opcodes,
+        dump_key,
+        #[cfg(feature = "gamepad")]
+        gamepad_mapping,
+    })))
+}
+
+// the file must exist and be readable
+fn validate_rom_path(path: &str) -> Result<(), String> {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Err(format!("file not found: {}", path)),
+        Err(error) => return Err(format!("cannot access {}: {}", path, error)),
+    };
+
+    if !metadata.is_file() {
+        return Err(format!("not a file: {}", path));
+    }
+
+    File::open(path).map(|_| ()).map_err(|error| format!("cannot read {}: {}", path, error))
+}
+
+fn parse_io_registers(registers: &str) -> Result<Vec<u16>, String> {
+    registers
+        .split(',')
+        .map(|register| io_register_address(register).ok_or(format!("--log-io expects I/O register names or addresses, got {}", register)))
+        .collect()
+}
+
+// first and last addresses in hexadecimal, ie 0x150-0x200
+fn parse_address_range(range: &str) -> Result<RangeInclusive<u16>, String> {
+    let error = || format!("--trace-range expects <first>-<last> hexadecimal addresses, got {}", range);
+    let parse_address = |address: &str| u16::from_str_radix(address.trim_start_matches("0x"), 16).map_err(|_| error());
+
+    let (first, last) = range.split_once('-').ok_or_else(error)?;
+    let (first, last) = (parse_address(first)?, parse_address(last)?);
+    if first > last {
+        return Err(error());
+    }
+
+    Ok(first..=last)
+}
+
+fn parse_memory_fill(fill: &str) -> Result<MemoryFill, String> {
+    match fill {
+        "zero" => Ok(MemoryFill::Zero),
+        "ones" => Ok(MemoryFill::Ones),
+        _ => match fill.strip_prefix("random:") {
+            Some(seed) => seed.parse().map(MemoryFill::Random).map_err(|_| format!("--ram-fill=random expects a numeric seed, got {}", seed)),
+            None => Err(format!("--ram-fill expects zero, ones or random:<seed>, got {}", fill)),
+        },
     }
 }
 
-fn parse_args() -> (String, String, bool) {
-    let mut boot_rom_path = String::new();
-    let mut game_rom_path = String::new();
-    let mut debug_opt = false;
+#[cfg(test)]
+mod main_tests {
+    use super::*;
+    use crate::cartridge::{CARTRIDGE_RAM_SIZE_OFFSET, CARTRIDGE_ROM_SIZE_OFFSET, CARTRIDGE_TYPE_OFFSET};
+    use crate::soc::peripheral::IoAccess;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_help_and_version() {
+        assert!(matches!(parse(&["qoboy", "--help"]), Ok(Command::Help)));
+        assert!(matches!(parse(&["qoboy", "boot.bin", "game.gb", "--help"]), Ok(Command::Help)));
+        assert!(matches!(parse(&["qoboy", "--version"]), Ok(Command::Version)));
+        assert!(matches!(parse(&["qoboy", "--info", "game.gb"]), Ok(Command::Info(path)) if path == "game.gb"));
+    }
 
-    for (index, argument) in env::args().enumerate() {
-        match index {
-            1 => {
-                boot_rom_path = argument.clone();
-                println!("boot_rom: {}", boot_rom_path);
+    #[test]
+    fn test_parse_options() {
+        match parse(&["qoboy", "boot.bin", "game.gb", "--debug", "--hold-frames=3", "--turbo=b", "--turbo-rate=4", "--ram-fill=random:42", "--watchdog=60", "--color-correction=gambatte", "--lcd-effect", "--ascii=40", "--log-io=NR12,ff40", "--serial-stdout", "--skip-boot", "--rgba-output=frames.rgba", "--record-audio=music.wav", "--record-frames=frames", "--record-limit=600", "--palette=dmg.pal", "--input-script=intro.txt", "--trace=annotated", "--trace-range=0x150-1FF", "--frame-skip=2", "--max-catch-up=1", "--ppu=accurate", "--cpu-speed=1.5", "--lenient-opcodes", "--dump-key=f10"]) {
+            Ok(Command::Run(options)) => {
+                assert_eq!(options.boot_rom_path.as_deref(), Some("boot.bin"));
+                assert_eq!(options.game_rom_path, Some(String::from("game.gb")));
+                assert!(options.debug_mode);
+                assert_eq!(options.hold_frames, [3; 8]);
+                assert_eq!(options.turbo, [false, true]);
+                assert_eq!(options.turbo_frames, 4);
+                assert!(matches!(options.memory_fill, Some(MemoryFill::Random(42))));
+                assert_eq!(options.watchdog_frames, Some(60));
+                assert_eq!(options.color_correction, Some(ColorCorrection::Gambatte));
+                assert!(options.lcd_effect);
+                assert_eq!(options.ascii_width, Some(40));
+                assert_eq!(options.io_write_log, Some(vec![0xFF12, 0xFF40]));
+                assert!(options.serial_stdout);
+                assert!(options.skip_boot);
+                assert_eq!(options.rgba_output_path, Some(String::from("frames.rgba")));
+                assert_eq!(options.audio_record_path, Some(String::from("music.wav")));
+                assert_eq!(options.frame_record_dir, Some(String::from("frames")));
+                assert_eq!(options.max_recorded_frames, 600);
+                assert_eq!(options.palette_path, Some(String::from("dmg.pal")));
+                assert_eq!(options.input_script_path, Some(String::from("intro.txt")));
+                assert_eq!(options.frame_skip, 2);
+                assert_eq!(options.max_catch_up_frames, 1);
+                assert_eq!(options.ppu_accuracy, PpuAccuracy::Accurate);
+                assert_eq!(options.cpu_speed, 1.5);
+                assert!(options.lenient_opcodes);
+                assert_eq!(options.dump_key, Key::F10);
+                assert_eq!(options.trace, Some(Trace::ANNOTATED));
+                assert_eq!(options.trace_range, Some(0x0150..=0x01FF));
             }
-            2 => {
-                game_rom_path = argument.clone();
-                println!("game_rom: {}", game_rom_path);
+            _ => panic!("options should be parsed"),
+        }
+
+        // the range alone traces the mnemonics
+        match parse(&["qoboy", "boot.bin", "game.gb", "--trace-range=0-ff"]) {
+            Ok(Command::Run(options)) => assert_eq!(options.trace, Some(Trace::MNEMONICS)),
+            _ => panic!("options should be parsed"),
+        }
+
+        // the colors of the shades are shared by all the palettes
+        match parse(&["qoboy", "boot.bin", "game.gb", "--shades=FFE0F8D0,FF88C070,FF346856,FF081820"]) {
+            Ok(Command::Run(options)) => assert_eq!(options.shades, Some(HostPalette::parse("224 248 208\n136 192 112\n52 104 86\n8 24 32").unwrap())),
+            _ => panic!("options should be parsed"),
+        }
+    }
+
+    #[test]
+    fn test_validate_rom_path() {
+        let existing_path = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+        let missing_path = concat!(env!("CARGO_MANIFEST_DIR"), "/missing_rom.gb");
+
+        assert!(validate_rom_path(existing_path).is_ok());
+        assert_eq!(validate_rom_path(missing_path), Err(format!("file not found: {}", missing_path)));
+        assert!(validate_rom_path(env!("CARGO_MANIFEST_DIR")).is_err());
+    }
+
+    #[test]
+    fn test_parse_boot_rom_only() {
+        match parse(&["qoboy", "boot.bin", "--debug"]) {
+            Ok(Command::Run(options)) => {
+                assert_eq!(options.boot_rom_path.as_deref(), Some("boot.bin"));
+                assert_eq!(options.game_rom_path, None);
+                assert_eq!(options.ascii_width, None);
             }
-            3 => if argument.eq("--debug") {
-                    debug_opt = true;
+            _ => panic!("options should be parsed"),
+        }
+
+        // the game is picked in the menu
+        match parse(&["qoboy", "boot.bin", "--rom-dir=roms"]) {
+            Ok(Command::Run(options)) => {
+                assert_eq!(options.game_rom_path, None);
+                assert_eq!(options.rom_dir, Some(String::from("roms")));
             }
-            _ => {} // nothing to do
+            _ => panic!("options should be parsed"),
         }
     }
 
-    (boot_rom_path, game_rom_path, debug_opt)
-}
\ No newline at end of file
+    #[test]
+    fn test_parse_boot() {
+        match parse(&["qoboy", "--boot=builtin", "game.gb"]) {
+            Ok(Command::Run(options)) => {
+                assert_eq!(options.boot_rom_path, None);
+                assert_eq!(options.game_rom_path.as_deref(), Some("game.gb"));
+            }
+            _ => panic!("options should be parsed"),
+        }
+
+        // the option replaces the boot rom path argument
+        match parse(&["qoboy", "game.gb", "--boot=boot.bin"]) {
+            Ok(Command::Run(options)) => {
+                assert_eq!(options.boot_rom_path.as_deref(), Some("boot.bin"));
+                assert_eq!(options.game_rom_path.as_deref(), Some("game.gb"));
+            }
+            _ => panic!("options should be parsed"),
+        }
+    }
+
+    #[test]
+    fn test_parse_hold_frames() {
+        // the buttons keep the frames given for all of them, unless given their own
+        match parse(&["qoboy", "boot.bin", "game.gb", "--hold-frames=2,a:3,B:0"]) {
+            Ok(Command::Run(options)) => {
+                let frames = |key: GameBoyKey| options.hold_frames[key as usize];
+                assert_eq!(frames(GameBoyKey::A), 3);
+                assert_eq!(frames(GameBoyKey::B), 0);
+                assert_eq!(frames(GameBoyKey::START), 2);
+                assert_eq!(frames(GameBoyKey::UP), 2);
+            }
+            _ => panic!("options should be parsed"),
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse(&["qoboy"]).is_err());
+        assert!(parse(&["qoboy", "--boot=builtin", "game.gb", "other.gb"]).is_err());
+        assert!(parse(&["qoboy", "boot.bin", "game.gb", "other.gb"]).is_err());
+        assert!(parse(&["qoboy", "boot.bin", "game.gb", "--unknown"]).is_err());
+        // the gamepad buttons are only known with the gamepad feature
+        #[cfg(feature = "gamepad")]
+        assert!(parse(&["qoboy", "boot.bin", "game.gb", "--gamepad-map=a:pedal"]).is_err());
+        #[cfg(not(feature = "gamepad"))]
+        assert!(parse(&["qoboy", "boot.bin", "game.gb", "--gamepad-map=a:north"]).is_err());
+        assert!(parse(&["qoboy", "boot.bin", "game.gb", "--shades=FFFFFFFF,FFAAAAAA,FF555555"]).is_err());
+        assert!(parse(&["qoboy", "boot.bin", "game.gb", "--shades=FFFFFFFF,FFAAAAAA,FF555555,FF000000", "--palette=dmg.pal"]).is_err());
+        assert!(parse(&["qoboy", "boot.bin", "game.gb", "--hold-frames=many"]).is_err());
+        assert!(parse(&["qoboy", "boot.bin", "game.gb", "--hold-frames=x:3"]).is_err());
+        assert!(parse(&["qoboy", "boot.bin", "game.gb", "--hold-frames=a:"]).is_err());
+        assert!(parse(&["qoboy", "boot.bin", "game.gb", "--ram-fill=full"]).is_err());
+        assert!(parse(&["qoboy", "boot.bin", "game.gb", "--turbo=a,start"]).is_err());
+        assert!(parse(&["qoboy", "boot.bin", "game.gb", "--turbo-rate=0"]).is_err());
+        assert!(parse(&["qoboy", "boot.bin", "game.gb", "--watchdog=-1"]).is_err());
+        assert!(parse(&["qoboy", "boot.bin", "game.gb", "--color-correction=vivid"]).is_err());
+        assert!(parse(&["qoboy", "boot.bin", "game.gb", "--ascii=wide"]).is_err());
+        assert!(parse(&["qoboy", "boot.bin", "game.gb", "--frame-skip=all"]).is_err());
+        assert!(parse(&["qoboy", "boot.bin", "game.gb", "--max-catch-up=-1"]).is_err());
+        assert!(parse(&["qoboy", "boot.bin", "game.gb", "--ppu=exact"]).is_err());
+        assert!(parse(&["qoboy", "boot.bin", "game.gb", "--cpu-speed=fast"]).is_err());
+        assert!(parse(&["qoboy", "boot.bin", "game.gb", "--cpu-speed=8"]).is_err());
+        assert!(parse(&["qoboy", "boot.bin", "game.gb", "--dump-key=F5"]).is_err());
+        assert!(parse(&["qoboy", "boot.bin", "game.gb", "--trace-range=0x200"]).is_err());
+        assert!(parse(&["qoboy", "boot.bin", "game.gb", "--trace-range=0x200-0x150"]).is_err());
+        assert!(parse(&["qoboy", "boot.bin", "game.gb", "--log-io=NR12,C000"]).is_err());
+        assert!(parse(&["qoboy", "boot.bin", "game.gb", "--rom-dir=roms"]).is_err());
+        assert!(parse(&["qoboy", "--info"]).is_err());
+    }
+
+    #[test]
+    fn test_rom_info() {
+        let mut rom = [0x00; 0x8000];
+        rom[0x0134..0x0138].copy_from_slice(b"GAME");
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x01;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x02;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+
+        let rom_dir = std::env::temp_dir().join(format!("qoboy_test_rom_info_{}", process::id()));
+        fs::create_dir_all(&rom_dir).unwrap();
+        let rom_path = rom_dir.join("game.gb");
+        fs::write(&rom_path, &rom[..]).unwrap();
+        fs::write(rom_dir.join("header.gb"), &rom[..0x100]).unwrap();
+
+        let info = rom_info(rom_path.to_str().unwrap()).unwrap();
+        let fields: Vec<&str> = info.lines().collect();
+        assert_eq!(fields[0], format!("file : {} (32768 bytes)", rom_path.display()));
+        assert_eq!(fields[1], "title : GAME");
+        assert_eq!(fields[2], "cgb : no (0x00)");
+        assert_eq!(fields[4], "cartridge type : MBC_1 (0x01)");
+        assert_eq!(fields[5], "rom size : SIZE_128_KB, truncated to 32768 bytes");
+        assert_eq!(fields[6], "ram size : NO_RAM");
+        assert_eq!(fields[7], "region : japan");
+        assert_eq!(fields[9], "header checksum : 0x00, invalid");
+
+        // files without a header are reported
+        assert!(rom_info(rom_dir.join("header.gb").to_str().unwrap()).is_err());
+        assert!(rom_info(rom_dir.join("missing.gb").to_str().unwrap()).is_err());
+
+        fs::remove_dir_all(&rom_dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_rom() {
+        let mut rom = [0x00; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        rom[0x0150] = 0x42;
+
+        let rom_dir = std::env::temp_dir().join(format!("qoboy_test_load_rom_{}", process::id()));
+        fs::create_dir_all(&rom_dir).unwrap();
+        fs::write(rom_dir.join("game.gb"), &rom[..]).unwrap();
+        fs::write(rom_dir.join("notes.txt"), "not a rom").unwrap();
+
+        let mut emulator = Emulator::without_cartridge(&[0x00; 0x100], false);
+        let mut notifications = Notifications::new();
+
+        // the current game keeps running when the file isn't a rom
+        assert!(!load_rom(&mut emulator, &rom_dir.join("notes.txt"), &mut notifications));
+        assert!(!load_rom(&mut emulator, &rom_dir.join("missing.gb"), &mut notifications));
+        assert_eq!(emulator.soc.peripheral.read_byte(0x0150), 0xFF);
+
+        assert!(load_rom(&mut emulator, &rom_dir.join("game.gb"), &mut notifications));
+        assert_eq!(emulator.soc.peripheral.read_byte(0x0150), 0x42);
+
+        fs::remove_dir_all(&rom_dir).unwrap();
+    }
+}
@@ -0,0 +1,55 @@
+use crate::soc::peripheral::gpu::{BACKGROUND_MAP_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// the viewport border is drawn in red so it can't be confused with a shade
+pub const VIEWPORT_COLOR: u32 = 0xFFFF0000;
+
+// convert the 256x256 background map into host pixels and outline the 160x144 part
+// shown on the screen, the viewport wraps around the map edges like the scrolling does
+pub fn draw_map_view(map: &[u8], scx: u8, scy: u8) -> Vec<u32> {
+    let mut view: Vec<u32> = map
+        .iter()
+        .map(|shade| 255 << 24 | (*shade as u32) << 16 | (*shade as u32) << 8 | (*shade as u32))
+        .collect();
+
+    let mut mark = |x: usize, y: usize| {
+        let x = (scx as usize + x) % BACKGROUND_MAP_SIZE;
+        let y = (scy as usize + y) % BACKGROUND_MAP_SIZE;
+        view[y * BACKGROUND_MAP_SIZE + x] = VIEWPORT_COLOR;
+    };
+
+    for x in 0..SCREEN_WIDTH {
+        mark(x, 0);
+        mark(x, SCREEN_HEIGHT - 1);
+    }
+    for y in 0..SCREEN_HEIGHT {
+        mark(0, y);
+        mark(SCREEN_WIDTH - 1, y);
+    }
+
+    view
+}
+
+#[cfg(test)]
+mod map_view_tests {
+    use super::*;
+
+    #[test]
+    fn test_draw_map_view() {
+        let map = [0xFF; BACKGROUND_MAP_SIZE * BACKGROUND_MAP_SIZE];
+        let view = draw_map_view(&map, 0x10, 0x20);
+        let pixel = |x: usize, y: usize| view[y * BACKGROUND_MAP_SIZE + x];
+
+        assert_eq!(view.len(), 256 * 256);
+
+        // the viewport corners are marked, its inside is the map
+        assert_eq!(pixel(0x10, 0x20), VIEWPORT_COLOR);
+        assert_eq!(pixel(0x10 + 159, 0x20 + 143), VIEWPORT_COLOR);
+        assert_eq!(pixel(0x11, 0x21), 0xFFFFFFFF);
+        assert_eq!(pixel(0x00, 0x00), 0xFFFFFFFF);
+
+        // the viewport wraps around the map
+        let view = draw_map_view(&map, 200, 0);
+        assert_eq!(view[143 * BACKGROUND_MAP_SIZE + (200 + 159) % 256], VIEWPORT_COLOR);
+        assert_eq!(view.iter().filter(|pixel| **pixel == VIEWPORT_COLOR).count(), 2 * 160 + 2 * 142);
+    }
+}
@@ -0,0 +1,61 @@
+use crate::soc::peripheral::{IoAccess, WORKING_RAM_BEGIN, WORKING_RAM_END, ZERO_PAGE_BEGIN, ZERO_PAGE_END};
+
+// the game variables are stored in WRAM and HRAM
+const SEARCH_REGIONS: [(u16, u16); 2] = [
+    (WORKING_RAM_BEGIN, WORKING_RAM_END),
+    (ZERO_PAGE_BEGIN, ZERO_PAGE_END),
+];
+
+// cheat finder: the first search lists the addresses holding a value, the next ones keep the
+// addresses holding the new value once it has changed in the game
+pub struct MemorySearch {
+    pub candidates: Vec<u16>,
+}
+
+impl MemorySearch {
+    pub fn new<T: IoAccess>(memory: &T, value: u8) -> MemorySearch {
+        MemorySearch {
+            candidates: SEARCH_REGIONS
+                .iter()
+                .flat_map(|(begin, end)| *begin..=*end)
+                .filter(|address| memory.peek_byte(*address) == value)
+                .collect(),
+        }
+    }
+
+    pub fn narrow<T: IoAccess>(&mut self, memory: &T, value: u8) {
+        self.candidates.retain(|address| memory.peek_byte(*address) == value);
+    }
+}
+
+#[cfg(test)]
+mod memory_search_tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+    use crate::emulator::test_fixtures::blank_rom;
+    use crate::soc::peripheral::Bus;
+
+    #[test]
+    fn test_memory_search() {
+        let rom = blank_rom();
+        let mut bus = Bus::new(Cartridge::new(&rom));
+
+        // 3 lives are found in WRAM and HRAM, not in VRAM
+        for address in [0xC010, 0xC200, 0xDFFF, 0xFF80, 0x8000] {
+            bus.write_byte(address, 0x03);
+        }
+        let mut search = MemorySearch::new(&bus, 0x03);
+        assert_eq!(search.candidates, [0xC010, 0xC200, 0xDFFF, 0xFF80]);
+
+        // a life is lost, only the addresses which followed are kept
+        bus.write_byte(0xC200, 0x02);
+        bus.write_byte(0xFF80, 0x02);
+        bus.write_byte(0xC300, 0x02);
+        search.narrow(&bus, 0x02);
+        assert_eq!(search.candidates, [0xC200, 0xFF80]);
+
+        bus.write_byte(0xC200, 0x01);
+        search.narrow(&bus, 0x01);
+        assert_eq!(search.candidates, [0xC200]);
+    }
+}
@@ -0,0 +1,263 @@
+use std::collections::VecDeque;
+
+use crate::emulator::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::soc::peripheral::gpu::PixelColor;
+
+// glyphs are 5x7 pixels drawn in 8x8 cells, one byte per row, MSB is the left pixel
+pub const GLYPH_SIZE: usize = 8;
+
+// lower case letters are drawn in upper case, unknown characters as '?'
+const FONT: [(char, [u8; GLYPH_SIZE]); 50] = [
+    (' ', [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('0', [0x38, 0x44, 0x4C, 0x54, 0x64, 0x44, 0x38, 0x00]),
+    ('1', [0x10, 0x30, 0x10, 0x10, 0x10, 0x10, 0x38, 0x00]),
+    ('2', [0x38, 0x44, 0x04, 0x08, 0x10, 0x20, 0x7C, 0x00]),
+    ('3', [0x7C, 0x08, 0x10, 0x08, 0x04, 0x44, 0x38, 0x00]),
+    ('4', [0x08, 0x18, 0x28, 0x48, 0x7C, 0x08, 0x08, 0x00]),
+    ('5', [0x7C, 0x40, 0x78, 0x04, 0x04, 0x44, 0x38, 0x00]),
+    ('6', [0x18, 0x20, 0x40, 0x78, 0x44, 0x44, 0x38, 0x00]),
+    ('7', [0x7C, 0x04, 0x08, 0x10, 0x20, 0x20, 0x20, 0x00]),
+    ('8', [0x38, 0x44, 0x44, 0x38, 0x44, 0x44, 0x38, 0x00]),
+    ('9', [0x38, 0x44, 0x44, 0x3C, 0x04, 0x08, 0x30, 0x00]),
+    ('A', [0x38, 0x44, 0x44, 0x7C, 0x44, 0x44, 0x44, 0x00]),
+    ('B', [0x78, 0x44, 0x44, 0x78, 0x44, 0x44, 0x78, 0x00]),
+    ('C', [0x38, 0x44, 0x40, 0x40, 0x40, 0x44, 0x38, 0x00]),
+    ('D', [0x78, 0x44, 0x44, 0x44, 0x44, 0x44, 0x78, 0x00]),
+    ('E', [0x7C, 0x40, 0x40, 0x78, 0x40, 0x40, 0x7C, 0x00]),
+    ('F', [0x7C, 0x40, 0x40, 0x78, 0x40, 0x40, 0x40, 0x00]),
+    ('G', [0x38, 0x44, 0x40, 0x5C, 0x44, 0x44, 0x3C, 0x00]),
+    ('H', [0x44, 0x44, 0x44, 0x7C, 0x44, 0x44, 0x44, 0x00]),
+    ('I', [0x38, 0x10, 0x10, 0x10, 0x10, 0x10, 0x38, 0x00]),
+    ('J', [0x1C, 0x08, 0x08, 0x08, 0x08, 0x48, 0x30, 0x00]),
+    ('K', [0x44, 0x48, 0x50, 0x60, 0x50, 0x48, 0x44, 0x00]),
+    ('L', [0x40, 0x40, 0x40, 0x40, 0x40, 0x40, 0x7C, 0x00]),
+    ('M', [0x44, 0x6C, 0x54, 0x54, 0x44, 0x44, 0x44, 0x00]),
+    ('N', [0x44, 0x44, 0x64, 0x54, 0x4C, 0x44, 0x44, 0x00]),
+    ('O', [0x38, 0x44, 0x44, 0x44, 0x44, 0x44, 0x38, 0x00]),
+    ('P', [0x78, 0x44, 0x44, 0x78, 0x40, 0x40, 0x40, 0x00]),
+    ('Q', [0x38, 0x44, 0x44, 0x44, 0x54, 0x48, 0x34, 0x00]),
+    ('R', [0x78, 0x44, 0x44, 0x78, 0x50, 0x48, 0x44, 0x00]),
+    ('S', [0x3C, 0x40, 0x40, 0x38, 0x04, 0x04, 0x78, 0x00]),
+    ('T', [0x7C, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x00]),
+    ('U', [0x44, 0x44, 0x44, 0x44, 0x44, 0x44, 0x38, 0x00]),
+    ('V', [0x44, 0x44, 0x44, 0x44, 0x44, 0x28, 0x10, 0x00]),
+    ('W', [0x44, 0x44, 0x44, 0x54, 0x54, 0x54, 0x28, 0x00]),
+    ('X', [0x44, 0x44, 0x28, 0x10, 0x28, 0x44, 0x44, 0x00]),
+    ('Y', [0x44, 0x44, 0x28, 0x10, 0x10, 0x10, 0x10, 0x00]),
+    ('Z', [0x7C, 0x04, 0x08, 0x10, 0x20, 0x40, 0x7C, 0x00]),
+    ('.', [0x00, 0x00, 0x00, 0x00, 0x00, 0x30, 0x30, 0x00]),
+    (',', [0x00, 0x00, 0x00, 0x00, 0x30, 0x10, 0x20, 0x00]),
+    (':', [0x00, 0x30, 0x30, 0x00, 0x30, 0x30, 0x00, 0x00]),
+    ('-', [0x00, 0x00, 0x00, 0x7C, 0x00, 0x00, 0x00, 0x00]),
+    ('+', [0x00, 0x10, 0x10, 0x7C, 0x10, 0x10, 0x00, 0x00]),
+    ('/', [0x00, 0x04, 0x08, 0x10, 0x20, 0x40, 0x00, 0x00]),
+    ('%', [0x60, 0x64, 0x08, 0x10, 0x20, 0x4C, 0x0C, 0x00]),
+    ('!', [0x10, 0x10, 0x10, 0x10, 0x10, 0x00, 0x10, 0x00]),
+    ('?', [0x38, 0x44, 0x04, 0x08, 0x10, 0x00, 0x10, 0x00]),
+    ('(', [0x08, 0x10, 0x20, 0x20, 0x20, 0x10, 0x08, 0x00]),
+    (')', [0x20, 0x10, 0x08, 0x08, 0x08, 0x10, 0x20, 0x00]),
+    ('=', [0x00, 0x00, 0x7C, 0x00, 0x7C, 0x00, 0x00, 0x00]),
+    ('_', [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x7C, 0x00]),
+];
+
+fn get_glyph(character: char) -> [u8; GLYPH_SIZE] {
+    let character = character.to_ascii_uppercase();
+
+    match FONT.iter().find(|(glyph_char, _)| *glyph_char == character) {
+        Some((_, glyph)) => *glyph,
+        None => get_glyph('?'),
+    }
+}
+
+// draw a text in a frame of SCREEN_WIDTH x SCREEN_HEIGHT shades, (x, y) being its top left corner
+// pixels out of the frame are clipped, the glyph cells are filled with the background shade if any
+pub fn draw_text(frame: &mut [u8], text: &str, x: i32, y: i32, shade: u8, background: Option<u8>) {
+    for (char_index, character) in text.chars().enumerate() {
+        let glyph = get_glyph(character);
+        let glyph_x = x + (char_index * GLYPH_SIZE) as i32;
+
+        for (row, row_data) in glyph.iter().enumerate() {
+            let pixel_y = y + row as i32;
+            if pixel_y < 0 || pixel_y >= SCREEN_HEIGHT as i32 {
+                continue;
+            }
+
+            for column in 0..GLYPH_SIZE {
+                let pixel_x = glyph_x + column as i32;
+                if pixel_x < 0 || pixel_x >= SCREEN_WIDTH as i32 {
+                    continue;
+                }
+
+                let pixel_index = pixel_y as usize * SCREEN_WIDTH + pixel_x as usize;
+                if (row_data >> (7 - column)) & 0x01 != 0 {
+                    frame[pixel_index] = shade;
+                } else if let Some(background) = background {
+                    frame[pixel_index] = background;
+                }
+            }
+        }
+    }
+}
+
+// notifications are displayed for about 2 seconds, fading out during the last half second
+pub const NOTIFICATION_TTL_FRAMES: u32 = 120;
+const NOTIFICATION_FADE_FRAMES: u32 = 30;
+const NB_NOTIFICATIONS_MAX: usize = 4;
+
+pub struct Notification {
+    pub text: String,
+    // remaining frames to display
+    pub ttl: u32,
+}
+
+pub struct Notifications {
+    messages: VecDeque<Notification>,
+}
+
+impl Notifications {
+    pub fn new() -> Notifications {
+        Notifications {
+            messages: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, text: &str) {
+        // the oldest message is dropped when too many are displayed
+        if self.messages.len() == NB_NOTIFICATIONS_MAX {
+            self.messages.pop_front();
+        }
+
+        self.messages.push_back(Notification {
+            text: text.to_string(),
+            ttl: NOTIFICATION_TTL_FRAMES,
+        });
+    }
+
+    // shall be called once per displayed frame
+    pub fn end_of_frame(&mut self) {
+        for message in self.messages.iter_mut() {
+            message.ttl = message.ttl.saturating_sub(1);
+        }
+
+        self.messages.retain(|message| message.ttl > 0);
+    }
+
+    // draw the messages in the bottom left corner, the newest one being at the bottom
+    pub fn draw(&self, frame: &mut [u8]) {
+        for (line, message) in self.messages.iter().rev().enumerate() {
+            let shade = if message.ttl > NOTIFICATION_FADE_FRAMES {
+                PixelColor::WHITE
+            } else if message.ttl > NOTIFICATION_FADE_FRAMES / 2 {
+                PixelColor::LIGHT_GRAY
+            } else {
+                PixelColor::DARK_GRAY
+            };
+
+            let y = SCREEN_HEIGHT as i32 - ((line + 1) * GLYPH_SIZE) as i32;
+            draw_text(frame, &message.text, 0, y, u8::from(shade), Some(u8::from(PixelColor::BLACK)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod overlay_tests {
+    use super::*;
+
+    const BLACK: u8 = 0;
+    const WHITE: u8 = 255;
+    const GRAY: u8 = 96;
+
+    #[test]
+    fn test_draw_char() {
+        let mut frame = [WHITE; SCREEN_WIDTH * SCREEN_HEIGHT];
+
+        draw_text(&mut frame, "A", 10, 20, BLACK, None);
+
+        // top of the A
+        assert_eq!(frame[20 * SCREEN_WIDTH + 11], WHITE);
+        assert_eq!(frame[20 * SCREEN_WIDTH + 12], BLACK);
+        assert_eq!(frame[20 * SCREEN_WIDTH + 14], BLACK);
+        assert_eq!(frame[20 * SCREEN_WIDTH + 15], WHITE);
+        // middle bar of the A
+        for pixel_x in 11..16 {
+            assert_eq!(frame[23 * SCREEN_WIDTH + pixel_x], BLACK);
+        }
+        // the A is made of 18 pixels
+        let nb_pixels = frame.iter().filter(|shade| **shade == BLACK).count();
+        assert_eq!(nb_pixels, 18);
+    }
+
+    #[test]
+    fn test_draw_background() {
+        let mut frame = [WHITE; SCREEN_WIDTH * SCREEN_HEIGHT];
+
+        draw_text(&mut frame, "a ", 0, 0, BLACK, Some(GRAY));
+
+        // lower case letters use the upper case glyphs
+        assert_eq!(frame[2], BLACK);
+        // the whole cells are filled
+        assert_eq!(frame[0], GRAY);
+        assert_eq!(frame[7 * SCREEN_WIDTH + 15], GRAY);
+        assert_eq!(frame[16], WHITE);
+        assert_eq!(frame[8 * SCREEN_WIDTH], WHITE);
+    }
+
+    #[test]
+    fn test_clipping() {
+        let mut frame = [WHITE; SCREEN_WIDTH * SCREEN_HEIGHT];
+
+        // nothing is drawn out of the frame and nothing wraps to the next line
+        draw_text(&mut frame, "AAA", SCREEN_WIDTH as i32 - 4, -3, BLACK, Some(GRAY));
+        draw_text(&mut frame, "A", -4, SCREEN_HEIGHT as i32 - 2, BLACK, Some(GRAY));
+
+        assert_eq!(frame[SCREEN_WIDTH - 5], WHITE);
+        assert_eq!(frame[SCREEN_WIDTH - 3], BLACK);
+        assert_eq!(frame[SCREEN_WIDTH], WHITE);
+        assert_eq!(frame[(SCREEN_HEIGHT - 2) * SCREEN_WIDTH], BLACK);
+        assert_eq!(frame[(SCREEN_HEIGHT - 2) * SCREEN_WIDTH + 4], WHITE);
+    }
+
+    #[test]
+    fn test_notifications() {
+        let mut notifications = Notifications::new();
+
+        notifications.push("SAVED");
+        let message = notifications.messages.front().unwrap();
+        assert_eq!(message.text, "SAVED");
+        assert_eq!(message.ttl, NOTIFICATION_TTL_FRAMES);
+
+        // messages are queued and expire on their own
+        notifications.end_of_frame();
+        notifications.push("LOADED");
+        assert_eq!(notifications.messages.len(), 2);
+
+        for _ in 0..NOTIFICATION_TTL_FRAMES - 1 {
+            notifications.end_of_frame();
+        }
+        let message = notifications.messages.front().unwrap();
+        assert_eq!(notifications.messages.len(), 1);
+        assert_eq!(message.text, "LOADED");
+        assert_eq!(message.ttl, 1);
+
+        notifications.end_of_frame();
+        assert_eq!(notifications.messages.len(), 0);
+    }
+
+    #[test]
+    fn test_notifications_fading() {
+        let mut notifications = Notifications::new();
+        let mut frame = [BLACK; SCREEN_WIDTH * SCREEN_HEIGHT];
+        // first pixel of the top of the A
+        let pixel_index = (SCREEN_HEIGHT - GLYPH_SIZE) * SCREEN_WIDTH + 2;
+
+        notifications.push("A");
+        notifications.draw(&mut frame);
+        assert_eq!(frame[pixel_index], WHITE);
+
+        for _ in 0..NOTIFICATION_TTL_FRAMES - 1 {
+            notifications.end_of_frame();
+        }
+        notifications.draw(&mut frame);
+        assert_eq!(frame[pixel_index], GRAY);
+    }
+}
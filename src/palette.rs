@@ -0,0 +1,321 @@
+use std::convert::TryFrom;
+
+use crate::cartridge::{Cartridge, HardwareMode};
+use crate::color::{rgb555_to_rgb888, ColorCorrection};
+use crate::soc::peripheral::gpu::{PixelColor, PALETTE_OBJ_0, PALETTE_OBJ_1};
+
+const NB_COLORS_PER_PALETTE: usize = 4;
+const NB_PALETTES: usize = 3;
+
+// RGB555 colors of the CGB boot rom compatibility palettes, 4 colors per palette
+const COMPATIBILITY_COLORS: [u16; 120] = [
+    0x7FFF, 0x32BF, 0x00D0, 0x0000,
+    0x639F, 0x4279, 0x15B0, 0x04CB,
+    0x7FFF, 0x6E31, 0x454A, 0x0000,
+    0x7FFF, 0x1BEF, 0x0200, 0x0000,
+    0x7FFF, 0x421F, 0x1CF2, 0x0000,
+    0x7FFF, 0x5294, 0x294A, 0x0000,
+    0x7FFF, 0x03FF, 0x012F, 0x0000,
+    0x7FFF, 0x03EF, 0x01D6, 0x0000,
+    0x7FFF, 0x42B5, 0x3DC8, 0x0000,
+    0x7E74, 0x03FF, 0x0180, 0x0000,
+    0x67FF, 0x77AC, 0x1A13, 0x2D6B,
+    0x7ED6, 0x4BFF, 0x2175, 0x0000,
+    0x53FF, 0x4A5F, 0x7E52, 0x0000,
+    0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0,
+    0x03ED, 0x7FFF, 0x255F, 0x0000,
+    0x036A, 0x021F, 0x03FF, 0x7FFF,
+    0x7FFF, 0x01DF, 0x0112, 0x0000,
+    0x231F, 0x035F, 0x00F2, 0x0009,
+    0x7FFF, 0x03EA, 0x011F, 0x0000,
+    0x299F, 0x001A, 0x000C, 0x0000,
+    0x7FFF, 0x027F, 0x001F, 0x0000,
+    0x7FFF, 0x03E0, 0x0206, 0x0120,
+    0x7FFF, 0x7EEB, 0x001F, 0x7C00,
+    0x7FFF, 0x3FFF, 0x7E00, 0x001F,
+    0x7FFF, 0x03FF, 0x001F, 0x0000,
+    0x03FF, 0x001F, 0x000C, 0x0000,
+    0x7FFF, 0x033F, 0x0193, 0x0000,
+    0x0000, 0x4200, 0x037F, 0x7FFF,
+    0x7FFF, 0x7E8C, 0x7C00, 0x0000,
+    0x7FFF, 0x1BEF, 0x6180, 0x0000,
+];
+
+// offsets in the colors of the OBP0, OBP1 and background palettes of each combination, a few
+// ones start in the middle of a palette like in the boot rom table
+const PALETTE_COMBINATIONS: [(usize, usize, usize); 51] = [
+    (16, 16, 116), (72, 72, 72), (80, 80, 80), (96, 96, 96), (36, 36, 36), (0, 0, 0),
+    (108, 108, 108), (20, 20, 20), (48, 48, 48), (104, 104, 104), (64, 32, 32), (16, 112, 112),
+    (16, 8, 8), (12, 16, 16), (16, 116, 116), (112, 16, 112), (8, 68, 8), (64, 64, 32),
+    (16, 16, 28), (16, 16, 72), (16, 16, 80), (76, 76, 36), (15, 15, 44), (68, 68, 8),
+    (16, 16, 8), (16, 16, 12), (112, 112, 0), (12, 12, 0), (0, 0, 4), (72, 88, 72),
+    (80, 88, 80), (96, 88, 96), (64, 88, 32), (68, 16, 52), (111, 0, 56), (111, 16, 60),
+    (76, 91, 36), (64, 112, 40), (16, 92, 112), (68, 88, 8), (16, 0, 8), (16, 112, 12),
+    (112, 12, 0), (12, 112, 16), (84, 112, 16), (12, 112, 0), (100, 12, 112), (0, 112, 32),
+    (16, 12, 112), (112, 12, 24), (16, 112, 116),
+];
+
+// sums of the title bytes of the games licensed by Nintendo, the first one is the default entry
+// selected for the other games
+const TITLE_CHECKSUMS: [u8; 94] = [
+    0x00, 0x88, 0x16, 0x36, 0xD1, 0xDB, 0xF2, 0x3C, 0x8C, 0x92, 0x3D, 0x5C, 0x58, 0xC9, 0x3E, 0x70,
+    0x1D, 0x59, 0x69, 0x19, 0x35, 0xA8, 0x14, 0xAA, 0x75, 0x95, 0x99, 0x34, 0x6F, 0x15, 0xFF, 0x97,
+    0x4B, 0x90, 0x17, 0x10, 0x39, 0xF7, 0xF6, 0xA2, 0x49, 0x4E, 0x43, 0x68, 0xE0, 0x8B, 0xF0, 0xCE,
+    0x0C, 0x29, 0xE8, 0xB7, 0x86, 0x9A, 0x52, 0x01, 0x9D, 0x71, 0x9C, 0xBD, 0x5D, 0x6D, 0x67, 0x3F,
+    0x6B, 0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4, 0xB3,
+    0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4, 0xB3,
+];
+
+// the checksums from this entry are shared by several games, told apart by the 4th letter of
+// their title
+const DUPLICATE_CHECKSUMS_START: usize = 65;
+const FOURTH_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+// combination of palettes selected by each title checksum
+const CHECKSUM_COMBINATIONS: [usize; 94] = [
+    0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37, 30, 44,
+    21, 32, 31, 20, 5, 33, 13, 14, 5, 29, 5, 18, 9, 3, 2, 26,
+    25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34,
+    5, 42, 6, 5, 33, 25, 42, 42, 40, 2, 16, 25, 42, 42, 5, 0,
+    39, 36, 22, 25, 6, 32, 12, 36, 11, 39, 18, 39, 24, 31, 50, 17,
+    46, 6, 27, 0, 47, 41, 41, 0, 0, 19, 34, 23, 18, 29,
+];
+
+// host colors of the background and of the two object palettes, loaded from a palette file
+// which holds one "R G B" triple per line, from the lightest to the darkest color:
+// 4 colors are shared by all the palettes, 12 colors are the background, OBP0 and OBP1 ones
+// empty lines and lines starting with '#' are ignored
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct HostPalette {
+    // 0x00RRGGBB colors indexed by palette then by color index
+    colors: [[u32; NB_COLORS_PER_PALETTE]; NB_PALETTES],
+}
+
+impl HostPalette {
+    pub fn parse(source: &str) -> Result<HostPalette, String> {
+        let mut colors = Vec::new();
+
+        for (line_index, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let components: Vec<&str> = line.split_whitespace().collect();
+            if components.len() != 3 {
+                return Err(format!("line {}: expected 3 components R G B, got {}", line_index + 1, line));
+            }
+
+            let mut color = 0;
+            for component in components {
+                let value: u8 = component.parse()
+                    .map_err(|_| format!("line {}: invalid component {}, expected 0 to 255", line_index + 1, component))?;
+                color = (color << 8) | value as u32;
+            }
+            colors.push(color);
+        }
+
+        let mut palette = HostPalette { colors: [[0; NB_COLORS_PER_PALETTE]; NB_PALETTES] };
+        match colors.len() {
+            NB_COLORS_PER_PALETTE => {
+                for palette_colors in palette.colors.iter_mut() {
+                    palette_colors.copy_from_slice(&colors);
+                }
+            }
+            count if count == NB_COLORS_PER_PALETTE * NB_PALETTES => {
+                for (palette_colors, file_colors) in palette.colors.iter_mut().zip(colors.chunks(NB_COLORS_PER_PALETTE)) {
+                    palette_colors.copy_from_slice(file_colors);
+                }
+            }
+            count => return Err(format!("expected 4 or 12 colors, got {}", count)),
+        }
+
+        Ok(palette)
+    }
+
+    // the 4 shades shared by all the palettes, from the lightest to the darkest, given as comma
+    // separated AARRGGBB or RRGGBB hexadecimal colors. The alpha is ignored, the screen is opaque
+    pub fn parse_shades(shades: &str) -> Result<HostPalette, String> {
+        let colors = shades.split(',')
+            .map(|color| {
+                let color = color.trim();
+                match color.len() {
+                    6 | 8 => u32::from_str_radix(color, 16).map(|color| color & 0xFFFFFF).map_err(|_| format!("invalid color {}", color)),
+                    _ => Err(format!("invalid color {}, expected AARRGGBB or RRGGBB", color)),
+                }
+            })
+            .collect::<Result<Vec<u32>, String>>()?;
+
+        if colors.len() != NB_COLORS_PER_PALETTE {
+            return Err(format!("expected 4 colors, got {}", colors.len()));
+        }
+
+        let mut palette = HostPalette { colors: [[0; NB_COLORS_PER_PALETTE]; NB_PALETTES] };
+        for palette_colors in palette.colors.iter_mut() {
+            palette_colors.copy_from_slice(&colors);
+        }
+
+        Ok(palette)
+    }
+
+    // colors given by the CGB boot rom to a DMG game, selected with the sum of the title bytes
+    // for the games licensed by Nintendo, the other games get the default colors. None is
+    // returned for the CGB games, they set their own colors
+    pub fn for_cartridge(cartridge: &Cartridge) -> Option<HostPalette> {
+        if cartridge.hardware_mode() != HardwareMode::DMG {
+            return None;
+        }
+
+        let title = cartridge.title();
+        let checksum = title.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+
+        let entry = if cartridge.is_licensed_by_nintendo() {
+            TITLE_CHECKSUMS.iter().enumerate()
+                .position(|(index, title_checksum)| {
+                    *title_checksum == checksum
+                        && (index < DUPLICATE_CHECKSUMS_START || FOURTH_LETTERS[index - DUPLICATE_CHECKSUMS_START] == title[3])
+                })
+                .unwrap_or(0)
+        } else {
+            0
+        };
+
+        let (obp0, obp1, background) = PALETTE_COMBINATIONS[CHECKSUM_COMBINATIONS[entry]];
+        let mut colors = [[0; NB_COLORS_PER_PALETTE]; NB_PALETTES];
+        for (palette_colors, offset) in colors.iter_mut().zip([background, obp0, obp1]) {
+            for (color, rgb555) in palette_colors.iter_mut().zip(&COMPATIBILITY_COLORS[offset..offset + NB_COLORS_PER_PALETTE]) {
+                *color = rgb555_to_rgb888(*rgb555, ColorCorrection::Raw);
+            }
+        }
+
+        Some(HostPalette { colors })
+    }
+
+    // host color of a shade drawn with the given gpu palette, the shades which aren't one of the
+    // 4 gray levels, ie blended by the overlay, are mapped to the closest one
+    pub fn color(&self, palette: u8, shade: u8) -> u32 {
+        let color_index = match PixelColor::try_from(shade) {
+            Ok(color) => color.to_index(),
+            Err(shade) if shade >= 224 => PixelColor::WHITE.to_index(),
+            Err(shade) if shade >= 144 => PixelColor::LIGHT_GRAY.to_index(),
+            Err(shade) if shade >= 48 => PixelColor::DARK_GRAY.to_index(),
+            Err(_) => PixelColor::BLACK.to_index(),
+        };
+
+        // everything else is drawn with the background palette
+        let palette_index = match palette {
+            PALETTE_OBJ_0 => 1,
+            PALETTE_OBJ_1 => 2,
+            _ => 0,
+        };
+
+        self.colors[palette_index][color_index as usize]
+    }
+}
+
+#[cfg(test)]
+mod palette_tests {
+    use super::*;
+    use crate::cartridge::{CARTRIDGE_OLD_LICENSEE_OFFSET, CARTRIDGE_RAM_SIZE_OFFSET, CARTRIDGE_ROM_SIZE_OFFSET, CARTRIDGE_TITLE_OFFSET, CARTRIDGE_TYPE_OFFSET};
+    use crate::soc::peripheral::gpu::PALETTE_BG;
+
+    const PALETTE_FILE: &str = "# background
+224 248 208
+136 192 112
+52 104 86
+8 24 32
+
+# OBP0
+255 255 255
+255 0 0
+0 255 0
+0 0 255
+# OBP1
+255 255 0
+0 255 255
+255 0 255
+0 0 0
+";
+
+    #[test]
+    fn test_parse_palette() {
+        let palette = HostPalette::parse(PALETTE_FILE).unwrap();
+
+        assert_eq!(palette.color(PALETTE_BG, u8::from(PixelColor::WHITE)), 0xE0F8D0);
+        assert_eq!(palette.color(PALETTE_BG, u8::from(PixelColor::BLACK)), 0x081820);
+        assert_eq!(palette.color(PALETTE_OBJ_0, u8::from(PixelColor::LIGHT_GRAY)), 0xFF0000);
+        assert_eq!(palette.color(PALETTE_OBJ_1, u8::from(PixelColor::DARK_GRAY)), 0xFF00FF);
+        // a blended shade is mapped to the closest gray level
+        assert_eq!(palette.color(PALETTE_OBJ_0, 100), 0x00FF00);
+
+        // 4 colors are shared by all the palettes
+        let palette = HostPalette::parse("255 255 255\n170 170 170\n85 85 85\n0 0 0").unwrap();
+        assert_eq!(palette.color(PALETTE_OBJ_1, u8::from(PixelColor::LIGHT_GRAY)), 0xAAAAAA);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(HostPalette::parse("255 255\n"), Err(String::from("line 1: expected 3 components R G B, got 255 255")));
+        assert_eq!(HostPalette::parse("# comment\n255 256 0\n"), Err(String::from("line 2: invalid component 256, expected 0 to 255")));
+        assert_eq!(HostPalette::parse("255 255 255\n"), Err(String::from("expected 4 or 12 colors, got 1")));
+    }
+
+    #[test]
+    fn test_parse_shades() {
+        let palette = HostPalette::parse_shades("FFE0F8D0,ff88c070, 346856,FF081820").unwrap();
+
+        // each shade gets its color in all the palettes
+        for gpu_palette in [PALETTE_BG, PALETTE_OBJ_0, PALETTE_OBJ_1] {
+            assert_eq!(palette.color(gpu_palette, u8::from(PixelColor::WHITE)), 0xE0F8D0);
+            assert_eq!(palette.color(gpu_palette, u8::from(PixelColor::LIGHT_GRAY)), 0x88C070);
+            assert_eq!(palette.color(gpu_palette, u8::from(PixelColor::DARK_GRAY)), 0x346856);
+            assert_eq!(palette.color(gpu_palette, u8::from(PixelColor::BLACK)), 0x081820);
+        }
+
+        assert_eq!(HostPalette::parse_shades("FFFFFFFF,FFAAAAAA,FF555555"), Err(String::from("expected 4 colors, got 3")));
+        assert_eq!(HostPalette::parse_shades("FFFFFFFF,FFAAAAAA,FF555555,FF000000,FF000000"), Err(String::from("expected 4 colors, got 5")));
+        assert_eq!(HostPalette::parse_shades("FFFFFFFF,FFAAAAAA,FF55555G,FF000000"), Err(String::from("invalid color FF55555G")));
+        assert_eq!(HostPalette::parse_shades("FFFFFFFF,FFAAAAAA,FFF,FF000000"), Err(String::from("invalid color FFF, expected AARRGGBB or RRGGBB")));
+    }
+
+    fn create_cartridge(title: &str, licensee: u8) -> Cartridge {
+        let mut rom = [0x00; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_OLD_LICENSEE_OFFSET as usize] = licensee;
+        rom[CARTRIDGE_TITLE_OFFSET as usize..CARTRIDGE_TITLE_OFFSET as usize + title.len()].copy_from_slice(title.as_bytes());
+
+        Cartridge::new(&rom)
+    }
+
+    #[test]
+    fn test_compatibility_palette() {
+        // the title checksum of POKEMON RED is 0x14
+        let palette = HostPalette::for_cartridge(&create_cartridge("POKEMON RED", 0x01)).unwrap();
+        assert_eq!(palette.color(PALETTE_BG, u8::from(PixelColor::LIGHT_GRAY)), 0xFF8484);
+        assert_eq!(palette.color(PALETTE_OBJ_0, u8::from(PixelColor::LIGHT_GRAY)), 0x7BFF31);
+
+        // the 4th letter tells apart the games with the same checksum
+        let palette = HostPalette::for_cartridge(&create_cartridge("POKEMON BLUE", 0x01)).unwrap();
+        assert_eq!(palette.color(PALETTE_BG, u8::from(PixelColor::DARK_GRAY)), 0x0000FF);
+        assert_eq!(palette.color(PALETTE_OBJ_0, u8::from(PixelColor::LIGHT_GRAY)), 0xFF8484);
+        let palette = HostPalette::for_cartridge(&create_cartridge("VEGAS STAKES", 0x01)).unwrap();
+        assert_eq!(palette.color(PALETTE_BG, u8::from(PixelColor::LIGHT_GRAY)), 0x7BFF31);
+        assert_eq!(palette.color(PALETTE_OBJ_1, u8::from(PixelColor::DARK_GRAY)), 0x0000FF);
+
+        // a few combinations start in the middle of a palette, like the SUPER MARIOLAND objects
+        let palette = HostPalette::for_cartridge(&create_cartridge("SUPER MARIOLAND", 0x01)).unwrap();
+        assert_eq!(palette.color(PALETTE_OBJ_0, u8::from(PixelColor::WHITE)), 0x000000);
+        assert_eq!(palette.color(PALETTE_OBJ_0, u8::from(PixelColor::LIGHT_GRAY)), 0xFFFFFF);
+        assert_eq!(palette.color(PALETTE_BG, u8::from(PixelColor::LIGHT_GRAY)), 0xFFFF94);
+
+        // unknown and third party games get the default colors
+        for palette in [HostPalette::for_cartridge(&create_cartridge("UNKNOWN", 0x01)), HostPalette::for_cartridge(&create_cartridge("POKEMON RED", 0x08))] {
+            let palette = palette.unwrap();
+            assert_eq!(palette.color(PALETTE_BG, u8::from(PixelColor::LIGHT_GRAY)), 0x7BFF31);
+            assert_eq!(palette.color(PALETTE_BG, u8::from(PixelColor::DARK_GRAY)), 0x0063C6);
+            assert_eq!(palette.color(PALETTE_OBJ_1, u8::from(PixelColor::LIGHT_GRAY)), 0xFF8484);
+        }
+    }
+}
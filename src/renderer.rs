@@ -0,0 +1,170 @@
+use minifb::Window;
+
+use crate::color::{ColorCorrection, rgb555_to_rgb888, shade_to_rgb555};
+use crate::emulator::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::lcd_effect::green_tint;
+#[cfg(not(feature = "gui"))]
+use crate::letterbox::letterbox;
+use crate::palette::HostPalette;
+use crate::tile_grid::draw_tile_grid;
+#[cfg(feature = "gui")]
+use crate::gui::ControlBar;
+
+// frontend displaying the emulated frames, the emulation loop doesn't depend on the windowing
+// library so that other frontends can be added, ie SDL2, a headless one or WASM
+pub trait Renderer {
+    // display a SCREEN_WIDTH x SCREEN_HEIGHT frame of shades, as fetched from the emulator
+    fn present(&mut self, frame: &[u8]);
+}
+
+// conversion of the shades to host colors, shared by the frontends and the frame record
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct HostColors {
+    pub palette: Option<HostPalette>,
+    pub correction: Option<ColorCorrection>,
+    // the shades are tinted in green like the DMG LCD, over the other color settings
+    pub green_tint: bool,
+}
+
+impl HostColors {
+    pub fn new() -> HostColors {
+        HostColors {
+            palette: None,
+            correction: None,
+            green_tint: false,
+        }
+    }
+
+    // ARGB color of a shade drawn with the given background or object palette
+    pub fn color(&self, palette_index: u8, shade: u8) -> u32 {
+        255 << 24 | match (self.palette, self.correction) {
+            _ if self.green_tint => green_tint(shade),
+            // colors of the palette file, following the palette each pixel was drawn with
+            (Some(palette), _) => palette.color(palette_index, shade),
+            // shades are converted like CGB colors
+            (None, Some(correction)) => rgb555_to_rgb888(shade_to_rgb555(shade), correction),
+            (None, None) => (shade as u32) << 16 | (shade as u32) << 8 | shade as u32,
+        }
+    }
+
+    // host pixels of a frame of shades, along with the palette each pixel was drawn with
+    pub fn convert(&self, frame: &[u8], frame_palettes: &[u8]) -> Vec<u32> {
+        frame.iter().zip(frame_palettes.iter()).map(|(shade, palette_index)| self.color(*palette_index, *shade)).collect()
+    }
+}
+
+// minifb window, the shades are converted to host colors and the frame is letterboxed in the
+// window, below the control bar with the gui feature
+pub struct WindowRenderer {
+    pub window: Window,
+    #[cfg(feature = "gui")]
+    pub control_bar: ControlBar,
+    // palette each pixel was drawn with, set before presenting a frame with a host palette
+    pub frame_palettes: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
+    pub colors: HostColors,
+    // 8x8 tile boundaries drawn over the frame, aligned on the background scrolled by SCX / SCY
+    pub tile_grid: bool,
+    pub viewport_position: (u8, u8),
+    // the frame is scaled by a whole factor only
+    pub integer_scale: bool,
+}
+
+impl WindowRenderer {
+    pub fn new(window: Window, #[cfg(feature = "gui")] control_bar: ControlBar) -> WindowRenderer {
+        WindowRenderer {
+            window,
+            #[cfg(feature = "gui")]
+            control_bar,
+            frame_palettes: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            colors: HostColors::new(),
+            tile_grid: false,
+            viewport_position: (0, 0),
+            integer_scale: false,
+        }
+    }
+
+    // display host pixels of any size, ie the background map view whose tiles aren't scrolled
+    pub fn present_pixels(&mut self, pixels: &mut [u32], width: usize, height: usize) {
+        if self.tile_grid {
+            draw_tile_grid(pixels, width, 0, 0);
+        }
+
+        self.display(pixels, width, height);
+    }
+
+    fn display(&mut self, pixels: &mut [u32], width: usize, height: usize) {
+        #[cfg(feature = "gui")]
+        self.control_bar.present(&mut self.window, pixels, width, height, self.integer_scale);
+
+        #[cfg(not(feature = "gui"))]
+        {
+            let (window_width, window_height) = self.window.get_size();
+            let buffer = letterbox(pixels, width, height, window_width, window_height, self.integer_scale);
+
+            if buffer.is_empty() {
+                // minimized window, the inputs are still read
+                self.window.update();
+            } else {
+                self.window.update_with_buffer(&buffer, window_width, window_height).unwrap();
+            }
+        }
+    }
+
+    // read the inputs while no frame is presented, the last one stays displayed
+    pub fn update(&mut self) {
+        #[cfg(feature = "gui")]
+        self.control_bar.refresh(&mut self.window);
+        #[cfg(not(feature = "gui"))]
+        self.window.update();
+    }
+}
+
+impl Renderer for WindowRenderer {
+    fn present(&mut self, frame: &[u8]) {
+        let mut buffer = self.colors.convert(frame, &self.frame_palettes);
+        if self.tile_grid {
+            let (scx, scy) = self.viewport_position;
+            draw_tile_grid(&mut buffer, SCREEN_WIDTH, scx, scy);
+        }
+        self.display(&mut buffer, SCREEN_WIDTH, SCREEN_HEIGHT);
+    }
+}
+
+#[cfg(test)]
+mod renderer_tests {
+    use super::*;
+    use crate::emulator::test_fixtures::looping_emulator;
+    use crate::soc::peripheral::IoAccess;
+
+    // headless renderer keeping a copy of the presented frames
+    struct RecordingRenderer {
+        frames: Vec<Vec<u8>>,
+    }
+
+    impl Renderer for RecordingRenderer {
+        fn present(&mut self, frame: &[u8]) {
+            self.frames.push(frame.to_vec());
+        }
+    }
+
+    #[test]
+    fn test_recording_renderer() {
+        let mut emulator = looping_emulator();
+        emulator.soc.peripheral.write_byte(0xFF40, 0x80);
+        emulator.frame_skip = 1;
+        let mut renderer = RecordingRenderer { frames: Vec::new() };
+
+        // the frames are presented through the trait, the skipped ones aren't
+        for _ in 0..4 {
+            emulator.run_frame();
+            if emulator.frame_ready() {
+                let renderer: &mut dyn Renderer = &mut renderer;
+                renderer.present(emulator.fetch_frame());
+            }
+        }
+
+        assert_eq!(renderer.frames.len(), 2);
+        assert!(renderer.frames.iter().all(|frame| frame.len() == SCREEN_WIDTH * SCREEN_HEIGHT));
+        assert_eq!(renderer.frames[1], emulator.soc.peripheral.gpu.frame_buffer.to_vec());
+    }
+}
@@ -0,0 +1,135 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::emulator::{GameBoyKey, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::overlay::{GLYPH_SIZE, draw_text};
+use crate::soc::peripheral::gpu::PixelColor;
+
+// files listed as game roms
+const ROM_EXTENSIONS: [&str; 2] = ["gb", "gbc"];
+
+// the title and an empty line are drawn above the roms
+const MENU_TITLE: &str = "SELECT A GAME";
+const FIRST_ROM_LINE: usize = 2;
+const NB_VISIBLE_ROMS: usize = SCREEN_HEIGHT / GLYPH_SIZE - FIRST_ROM_LINE;
+const MAX_NAME_LENGTH: usize = SCREEN_WIDTH / GLYPH_SIZE;
+
+// game roms of a directory, sorted by name whatever the case
+pub fn list_roms(dir: &Path) -> Vec<PathBuf> {
+    let mut roms: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file())
+            .filter(|path| path.extension().is_some_and(|extension| ROM_EXTENSIONS.iter().any(|rom_extension| extension.eq_ignore_ascii_case(rom_extension))))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    roms.sort_by_key(|path| path.file_name().unwrap_or_default().to_string_lossy().to_lowercase());
+    roms
+}
+
+// text menu listing the roms of a directory, drawn with the overlay font
+// the selection is moved with the d-pad, A or START picks the selected rom
+pub struct RomMenu {
+    pub roms: Vec<PathBuf>,
+    pub selected: usize,
+}
+
+impl RomMenu {
+    pub fn new(dir: &Path) -> RomMenu {
+        RomMenu {
+            roms: list_roms(dir),
+            selected: 0,
+        }
+    }
+
+    // the selection wraps around the list, the picked rom is returned
+    pub fn press(&mut self, key: GameBoyKey) -> Option<PathBuf> {
+        if self.roms.is_empty() {
+            return None;
+        }
+
+        match key {
+            GameBoyKey::UP => self.selected = (self.selected + self.roms.len() - 1) % self.roms.len(),
+            GameBoyKey::DOWN => self.selected = (self.selected + 1) % self.roms.len(),
+            GameBoyKey::A | GameBoyKey::START => return Some(self.roms[self.selected].clone()),
+            _ => {}
+        }
+
+        None
+    }
+
+    // draw the page of the selected rom, which is drawn in reverse
+    pub fn draw(&self, frame: &mut [u8]) {
+        let (white, black) = (u8::from(PixelColor::WHITE), u8::from(PixelColor::BLACK));
+        frame.fill(white);
+        draw_text(frame, MENU_TITLE, 0, 0, black, None);
+
+        let first_rom = self.selected / NB_VISIBLE_ROMS * NB_VISIBLE_ROMS;
+        for (line, (index, rom)) in self.roms.iter().enumerate().skip(first_rom).take(NB_VISIBLE_ROMS).enumerate() {
+            let name: String = rom.file_stem().unwrap_or_default().to_string_lossy().chars().take(MAX_NAME_LENGTH).collect();
+            let y = ((FIRST_ROM_LINE + line) * GLYPH_SIZE) as i32;
+
+            if index == self.selected {
+                draw_text(frame, &format!("{:<width$}", name, width = MAX_NAME_LENGTH), 0, y, white, Some(black));
+            } else {
+                draw_text(frame, &name, 0, y, black, None);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod rom_menu_tests {
+    use super::*;
+
+    #[test]
+    fn test_list_roms() {
+        let rom_dir = std::env::temp_dir().join(format!("qoboy_test_list_roms_{}", std::process::id()));
+        fs::create_dir_all(rom_dir.join("saves.gb")).unwrap();
+        for name in ["tetris.gb", "Pokemon.GBC", "zelda.gbc", "alleyway.gb", "tetris.sav", "readme.txt"] {
+            fs::write(rom_dir.join(name), []).unwrap();
+        }
+
+        // only the rom files are listed, sorted whatever the case of their name
+        let names: Vec<String> = list_roms(&rom_dir).iter().map(|rom| rom.file_name().unwrap().to_string_lossy().to_string()).collect();
+        assert_eq!(names, ["alleyway.gb", "Pokemon.GBC", "tetris.gb", "zelda.gbc"]);
+        assert!(list_roms(&rom_dir.join("missing")).is_empty());
+
+        fs::remove_dir_all(&rom_dir).unwrap();
+    }
+
+    #[test]
+    fn test_rom_menu() {
+        let mut menu = RomMenu {
+            roms: (0..20).map(|index| PathBuf::from(format!("game{:02}.gb", index))).collect(),
+            selected: 0,
+        };
+
+        // the selection wraps around the list
+        assert_eq!(menu.press(GameBoyKey::UP), None);
+        assert_eq!(menu.selected, 19);
+        assert_eq!(menu.press(GameBoyKey::DOWN), None);
+        assert_eq!(menu.press(GameBoyKey::DOWN), None);
+        assert_eq!(menu.selected, 1);
+        assert_eq!(menu.press(GameBoyKey::B), None);
+        assert_eq!(menu.press(GameBoyKey::START), Some(PathBuf::from("game01.gb")));
+
+        // the selected rom is drawn in reverse on the line of its page
+        let (white, black) = (u8::from(PixelColor::WHITE), u8::from(PixelColor::BLACK));
+        let mut frame = [0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        menu.draw(&mut frame);
+        assert_eq!(frame[(FIRST_ROM_LINE + 1) * GLYPH_SIZE * SCREEN_WIDTH], black);
+        assert_eq!(frame[FIRST_ROM_LINE * GLYPH_SIZE * SCREEN_WIDTH], white);
+
+        menu.selected = 17;
+        menu.draw(&mut frame);
+        assert_eq!(frame[(FIRST_ROM_LINE + 1) * GLYPH_SIZE * SCREEN_WIDTH], black);
+        assert!(frame[(FIRST_ROM_LINE + 4) * GLYPH_SIZE * SCREEN_WIDTH..].iter().all(|shade| *shade == white));
+
+        // nothing is picked in an empty directory
+        let mut menu = RomMenu { roms: Vec::new(), selected: 0 };
+        assert_eq!(menu.press(GameBoyKey::A), None);
+    }
+}
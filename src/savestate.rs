@@ -0,0 +1,111 @@
+use std::io::{Error, ErrorKind, Read, Result, Write};
+
+// save state format versions, the states of the older ones are migrated while they're loaded
+//   1: first format
+//   2: the gpu saves the OPRI object priority, the CGB mode and the second VRAM bank
+//   3: the gpu counts the vblank cycles per line, LY runs from 144 to 153
+pub const SAVE_STATE_VERSION: u8 = 3;
+pub const OLDEST_SAVE_STATE_VERSION: u8 = 1;
+
+// every component of the emulated system saves and restores its own state
+// fields are written one after the other, in the same order they are read back
+pub trait SaveState {
+    fn save_state(&self, writer: &mut dyn Write) -> Result<()>;
+
+    fn load_state(&mut self, reader: &mut dyn Read) -> Result<()>;
+
+    // load a state written with an older format version, the components whose layout has
+    // changed since then read the older one and give a value to the missing fields
+    fn load_state_version(&mut self, reader: &mut dyn Read, _version: u8) -> Result<()> {
+        self.load_state(reader)
+    }
+}
+
+pub fn write_u8(writer: &mut dyn Write, value: u8) -> Result<()> {
+    writer.write_all(&[value])
+}
+
+pub fn write_bool(writer: &mut dyn Write, value: bool) -> Result<()> {
+    write_u8(writer, value as u8)
+}
+
+pub fn write_u16(writer: &mut dyn Write, value: u16) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+pub fn write_u64(writer: &mut dyn Write, value: u64) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+pub fn write_bytes(writer: &mut dyn Write, data: &[u8]) -> Result<()> {
+    writer.write_all(data)
+}
+
+pub fn read_u8(reader: &mut dyn Read) -> Result<u8> {
+    let mut data = [0; 1];
+    reader.read_exact(&mut data)?;
+    Ok(data[0])
+}
+
+pub fn read_bool(reader: &mut dyn Read) -> Result<bool> {
+    match read_u8(reader)? {
+        0 => Ok(false),
+        1 => Ok(true),
+        value => Err(invalid_data(&format!("invalid boolean value {}", value))),
+    }
+}
+
+pub fn read_u16(reader: &mut dyn Read) -> Result<u16> {
+    let mut data = [0; 2];
+    reader.read_exact(&mut data)?;
+    Ok(u16::from_le_bytes(data))
+}
+
+pub fn read_u64(reader: &mut dyn Read) -> Result<u64> {
+    let mut data = [0; 8];
+    reader.read_exact(&mut data)?;
+    Ok(u64::from_le_bytes(data))
+}
+
+// the destination length gives the number of bytes to read
+pub fn read_bytes(reader: &mut dyn Read, data: &mut [u8]) -> Result<()> {
+    reader.read_exact(data)
+}
+
+pub fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod savestate_tests {
+    use super::*;
+
+    #[test]
+    fn test_read_write() {
+        let mut data: Vec<u8> = Vec::new();
+        write_u8(&mut data, 0xAB).unwrap();
+        write_bool(&mut data, true).unwrap();
+        write_u16(&mut data, 0x1234).unwrap();
+        write_u64(&mut data, 0x0123_4567_89AB_CDEF).unwrap();
+        write_bytes(&mut data, &[0x55, 0xAA]).unwrap();
+
+        let mut reader = &data[..];
+        let mut bytes = [0; 2];
+        assert_eq!(read_u8(&mut reader).unwrap(), 0xAB);
+        assert_eq!(read_bool(&mut reader).unwrap(), true);
+        assert_eq!(read_u16(&mut reader).unwrap(), 0x1234);
+        assert_eq!(read_u64(&mut reader).unwrap(), 0x0123_4567_89AB_CDEF);
+        read_bytes(&mut reader, &mut bytes).unwrap();
+        assert_eq!(bytes, [0x55, 0xAA]);
+
+        // nothing left to read
+        assert_eq!(read_u8(&mut reader).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_invalid_bool() {
+        let data = [0x02];
+
+        assert_eq!(read_bool(&mut &data[..]).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
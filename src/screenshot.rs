@@ -0,0 +1,140 @@
+use std::fs;
+use std::path::Path;
+
+use crate::emulator::{Emulator, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::frame_record::write_ppm;
+use crate::renderer::HostColors;
+
+// the boot rom isn't run, the cpu starts the game with the registers set as the boot rom leaves them
+const NO_BOOT_ROM: [u8; 0x100] = [0x00; 0x100];
+
+// the watchdog stops a locked up game well before the frame is reached
+const SCREENSHOT_WATCHDOG_FRAMES: u32 = 600;
+
+// host pixels of the screen once the rom has run the given number of frames, with the default DMG
+// colors. The run is deterministic: no boot rom, the default memory content and no key pressed
+// a frame while the LCD is off lasts one frame duration
+pub fn screenshot_at_frame(rom: &[u8], frame: u32) -> Result<Vec<u32>, String> {
+    let mut emulator = Emulator::new(&NO_BOOT_ROM, rom, false);
+    emulator.skip_boot();
+    emulator.set_watchdog(Some(SCREENSHOT_WATCHDOG_FRAMES));
+
+    for _ in 0..frame {
+        emulator.run_frame();
+        if let Some(lockup) = emulator.lockup() {
+            return Err(format!("the game is locked up before frame {}, {}", frame, lockup));
+        }
+    }
+
+    let frame_buffer = emulator.fetch_frame().to_vec();
+    Ok(HostColors::new().convert(&frame_buffer, emulator.get_frame_palettes()))
+}
+
+pub fn write_screenshot(path: &Path, pixels: &[u32]) -> Result<(), String> {
+    let mut image = Vec::new();
+    write_ppm(&mut image, pixels, SCREEN_WIDTH, SCREEN_HEIGHT).map_err(|error| error.to_string())?;
+    fs::write(path, image).map_err(|error| format!("cannot write {}: {}", path.display(), error))
+}
+
+// read a screen sized PPM image written by write_screenshot
+pub fn read_screenshot(path: &Path) -> Result<Vec<u32>, String> {
+    let image = fs::read(path).map_err(|error| format!("cannot read {}: {}", path.display(), error))?;
+
+    let header = format!("P6\n{} {}\n255\n", SCREEN_WIDTH, SCREEN_HEIGHT);
+    match image.strip_prefix(header.as_bytes()) {
+        Some(rgb) if rgb.len() == SCREEN_WIDTH * SCREEN_HEIGHT * 3 => Ok(rgb
+            .chunks(3)
+            .map(|pixel| 255 << 24 | (pixel[0] as u32) << 16 | (pixel[1] as u32) << 8 | pixel[2] as u32)
+            .collect()),
+        _ => Err(format!("{} isn't a {}x{} PPM image", path.display(), SCREEN_WIDTH, SCREEN_HEIGHT)),
+    }
+}
+
+// the pixels match when none of their color components differ by more than the tolerance
+// the first differing pixel and the number of differing pixels are reported
+pub fn compare_images(expected: &[u32], actual: &[u32], tolerance: u8) -> Result<(), String> {
+    if expected.len() != actual.len() {
+        return Err(format!("{} pixels expected, got {}", expected.len(), actual.len()));
+    }
+
+    let component_diff = |expected: u32, actual: u32, shift: u32| ((expected >> shift) as u8).abs_diff((actual >> shift) as u8);
+    let mut differences = expected
+        .iter()
+        .zip(actual.iter())
+        .enumerate()
+        .filter(|(_, (expected, actual))| [16, 8, 0].iter().any(|shift| component_diff(**expected, **actual, *shift) > tolerance));
+
+    match differences.next() {
+        None => Ok(()),
+        Some((index, (expected_pixel, actual_pixel))) => Err(format!(
+            "{} pixels differ, first at ({}, {}): expected {:06X}, got {:06X}",
+            differences.count() + 1,
+            index % SCREEN_WIDTH,
+            index / SCREEN_WIDTH,
+            expected_pixel & 0xFFFFFF,
+            actual_pixel & 0xFFFFFF
+        )),
+    }
+}
+
+#[cfg(test)]
+mod screenshot_tests {
+    use super::*;
+    use crate::emulator::test_fixtures::looping_rom;
+
+    // golden image frame, the game has drawn its first screen by then
+    const GOLDEN_FRAME: u32 = 300;
+
+    #[test]
+    fn test_screenshot_at_frame() {
+        // the game loops forever on the screen left by the boot
+        let rom = looping_rom();
+
+        // the capture is deterministic
+        let screenshot = screenshot_at_frame(&rom, 10).unwrap();
+        assert_eq!(screenshot.len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+        assert_eq!(screenshot_at_frame(&rom, 10).unwrap(), screenshot);
+
+        let screenshot_dir = std::env::temp_dir().join(format!("qoboy_test_screenshot_{}", std::process::id()));
+        fs::create_dir_all(&screenshot_dir).unwrap();
+        let baseline_path = screenshot_dir.join("baseline.ppm");
+        write_screenshot(&baseline_path, &screenshot).unwrap();
+        assert!(compare_images(&read_screenshot(&baseline_path).unwrap(), &screenshot, 0).is_ok());
+
+        // a small color change is accepted within the tolerance
+        let baseline = vec![0xFF808080; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let mut changed = baseline.clone();
+        changed[SCREEN_WIDTH + 2] = 0xFF808083;
+        changed[2 * SCREEN_WIDTH] = 0xFF7D8080;
+        assert!(compare_images(&baseline, &changed, 3).is_ok());
+        assert_eq!(compare_images(&baseline, &changed, 2), Err(String::from("2 pixels differ, first at (2, 1): expected 808080, got 808083")));
+        assert!(compare_images(&baseline, &changed[1..], 255).is_err());
+
+        fs::write(&baseline_path, b"P6\n1 1\n255\n\x00\x00\x00").unwrap();
+        assert!(read_screenshot(&baseline_path).is_err());
+
+        fs::remove_dir_all(&screenshot_dir).unwrap();
+    }
+
+    // golden image of a game, the baseline is written by the first run:
+    // QOBOY_GOLDEN_ROM=<game_rom_path> QOBOY_GOLDEN_IMAGE=<ppm_path> cargo test golden_image -- --ignored
+    #[test]
+    #[ignore]
+    fn test_golden_image() {
+        let rom_path = std::env::var("QOBOY_GOLDEN_ROM").expect("QOBOY_GOLDEN_ROM shall give the path of the game rom");
+        let image_path = std::env::var("QOBOY_GOLDEN_IMAGE").expect("QOBOY_GOLDEN_IMAGE shall give the path of the baseline image");
+        let image_path = Path::new(&image_path);
+
+        let rom = fs::read(&rom_path).unwrap();
+        let screenshot = screenshot_at_frame(&rom, GOLDEN_FRAME).unwrap();
+
+        if image_path.exists() {
+            if let Err(message) = compare_images(&read_screenshot(image_path).unwrap(), &screenshot, 0) {
+                panic!("frame {} of {} doesn't match {}: {}", GOLDEN_FRAME, rom_path, image_path.display(), message);
+            }
+        } else {
+            write_screenshot(image_path, &screenshot).unwrap();
+            println!("baseline written to {}", image_path.display());
+        }
+    }
+}
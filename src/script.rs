@@ -0,0 +1,263 @@
+use crate::emulator::Emulator;
+use crate::soc::peripheral::IoAccess;
+
+// Debugger scripts are made of one command per line, addresses and values are hexadecimal:
+//   read <addr>                         print the byte at addr
+//   write <addr> <value>                write value at addr
+//   log <addr> [label]                  print the byte at addr with a label
+//   on <pc> [if <addr> <op> <value>] <command>
+//                                       run command each time the cpu reaches pc, op is ==, !=, < or >
+//   break                               halt the debugger, only allowed after on
+// empty lines and lines starting with # are ignored
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Lower,
+    Greater,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Condition {
+    address: u16,
+    operator: Operator,
+    value: u8,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Command {
+    Read(u16),
+    Write(u16, u8),
+    Log(u16, String),
+    Break,
+}
+
+struct Hook {
+    pc: u16,
+    condition: Option<Condition>,
+    command: Command,
+}
+
+pub struct Script {
+    // top level commands, run once
+    commands: Vec<Command>,
+    // commands run when the cpu reaches a given address
+    hooks: Vec<Hook>,
+    previous_pc: Option<u16>,
+}
+
+impl Script {
+    pub fn parse(source: &str) -> Result<Script, String> {
+        let mut script = Script {
+            commands: Vec::new(),
+            hooks: Vec::new(),
+            previous_pc: None,
+        };
+
+        for (index, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let error_message = |message: String| format!("line {}: {}", index + 1, message);
+
+            if words[0] == "on" {
+                script.hooks.push(parse_hook(&words[1..]).map_err(error_message)?);
+            } else {
+                match parse_command(&words).map_err(error_message)? {
+                    Command::Break => return Err(error_message(String::from("break can only be used in a hook"))),
+                    command => script.commands.push(command),
+                }
+            }
+        }
+
+        Ok(script)
+    }
+
+    // run the top level commands, the value of the last read is returned
+    pub fn run(&mut self, emulator: &mut Emulator) -> Option<u8> {
+        let mut value = None;
+
+        for command in self.commands.drain(..) {
+            if let Some(read_value) = execute(&command, emulator) {
+                value = Some(read_value);
+            }
+        }
+
+        value
+    }
+
+    // shall be called after each cpu step, returns true if a hook asks to halt the debugger
+    pub fn run_hooks(&mut self, emulator: &mut Emulator) -> bool {
+        let pc = emulator.soc.cpu.pc;
+
+        // hooks are run once when the cpu enters the address, not while it's halted on it
+        if self.previous_pc == Some(pc) {
+            return false;
+        }
+        self.previous_pc = Some(pc);
+
+        let mut halt = false;
+        for hook in self.hooks.iter().filter(|hook| hook.pc == pc) {
+            let condition_met = match hook.condition {
+                Some(condition) => condition.is_met(emulator.soc.peripheral.peek_byte(condition.address)),
+                None => true,
+            };
+
+            if condition_met {
+                if hook.command == Command::Break {
+                    println!("script: break at {:#06x}", pc);
+                    halt = true;
+                } else {
+                    execute(&hook.command, emulator);
+                }
+            }
+        }
+
+        halt
+    }
+}
+
+impl Condition {
+    fn is_met(&self, data: u8) -> bool {
+        match self.operator {
+            Operator::Equal => data == self.value,
+            Operator::NotEqual => data != self.value,
+            Operator::Lower => data < self.value,
+            Operator::Greater => data > self.value,
+        }
+    }
+}
+
+fn execute(command: &Command, emulator: &mut Emulator) -> Option<u8> {
+    match command {
+        Command::Read(address) => {
+            let data = emulator.soc.peripheral.peek_byte(*address);
+            println!("script: [{:#06x}] = {:#04x}", address, data);
+            Some(data)
+        }
+        Command::Write(address, data) => {
+            emulator.soc.peripheral.write_byte(*address, *data);
+            None
+        }
+        Command::Log(address, label) => {
+            println!("script: {} = {:#04x}", label, emulator.soc.peripheral.peek_byte(*address));
+            None
+        }
+        Command::Break => None,
+    }
+}
+
+fn parse_hook(words: &[&str]) -> Result<Hook, String> {
+    let pc = parse_address(words.first())?;
+
+    if words.get(1) == Some(&"if") {
+        let condition = Condition {
+            address: parse_address(words.get(2))?,
+            operator: match words.get(3) {
+                Some(&"==") => Operator::Equal,
+                Some(&"!=") => Operator::NotEqual,
+                Some(&"<") => Operator::Lower,
+                Some(&">") => Operator::Greater,
+                _ => return Err(String::from("expected ==, !=, < or > in the condition")),
+            },
+            value: parse_value(words.get(4))?,
+        };
+
+        Ok(Hook { pc, condition: Some(condition), command: parse_command(&words[5..])? })
+    } else {
+        Ok(Hook { pc, condition: None, command: parse_command(&words[1..])? })
+    }
+}
+
+fn parse_command(words: &[&str]) -> Result<Command, String> {
+    match words.first() {
+        Some(&"read") => Ok(Command::Read(parse_address(words.get(1))?)),
+        Some(&"write") => Ok(Command::Write(parse_address(words.get(1))?, parse_value(words.get(2))?)),
+        Some(&"log") => {
+            let address = parse_address(words.get(1))?;
+            let label = if words.len() > 2 { words[2..].join(" ") } else { format!("[{:#06x}]", address) };
+            Ok(Command::Log(address, label))
+        }
+        Some(&"break") => Ok(Command::Break),
+        Some(command) => Err(format!("unknown command {}", command)),
+        None => Err(String::from("missing command")),
+    }
+}
+
+fn parse_address(word: Option<&&str>) -> Result<u16, String> {
+    match word {
+        Some(word) => u16::from_str_radix(word, 16).map_err(|_| format!("invalid address {}", word)),
+        None => Err(String::from("missing address")),
+    }
+}
+
+fn parse_value(word: Option<&&str>) -> Result<u8, String> {
+    match word {
+        Some(word) => u8::from_str_radix(word, 16).map_err(|_| format!("invalid value {}", word)),
+        None => Err(String::from("missing value")),
+    }
+}
+
+#[cfg(test)]
+mod script_tests {
+    use super::*;
+    use crate::emulator::test_fixtures::blank_rom;
+
+    fn create_emulator() -> Emulator {
+        // INC A then JR -3: loop forever in the boot rom
+        let mut boot_rom = [0x00; 0x100];
+        boot_rom[0x00] = 0x3C;
+        boot_rom[0x01] = 0x18;
+        boot_rom[0x02] = 0xFD;
+
+        let rom = blank_rom();
+
+        Emulator::new(&boot_rom, &rom, true)
+    }
+
+    #[test]
+    fn test_read_memory() {
+        let mut emulator = create_emulator();
+        emulator.soc.peripheral.write_byte(0xC010, 0x5A);
+
+        let mut script = Script::parse("# read a variable\nread C010\n").unwrap();
+        assert_eq!(script.run(&mut emulator), Some(0x5A));
+
+        // top level commands are run once
+        assert_eq!(script.run(&mut emulator), None);
+
+        let mut script = Script::parse("write C020 A5\nread C020").unwrap();
+        assert_eq!(script.run(&mut emulator), Some(0xA5));
+    }
+
+    #[test]
+    fn test_hooks() {
+        let mut emulator = create_emulator();
+        let mut script = Script::parse("on 0001 write C000 42\non 0000 if C000 == 42 break").unwrap();
+
+        // the first hook doesn't halt, the second one isn't met yet
+        emulator.soc.run();
+        assert_eq!(emulator.soc.cpu.pc, 0x0001);
+        assert_eq!(script.run_hooks(&mut emulator), false);
+        assert_eq!(emulator.soc.peripheral.read_byte(0xC000), 0x42);
+
+        // the condition is met when the loop comes back to 0x0000
+        emulator.soc.run();
+        assert_eq!(emulator.soc.cpu.pc, 0x0000);
+        assert_eq!(script.run_hooks(&mut emulator), true);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Script::parse("read").is_err());
+        assert!(Script::parse("read XYZ").is_err());
+        assert!(Script::parse("write C000 100").is_err());
+        assert!(Script::parse("break").is_err());
+        assert!(Script::parse("on 0100 if C000 = 01 break").is_err());
+        assert_eq!(Script::parse("read C000\njump 0100").err(), Some(String::from("line 2: unknown command jump")));
+    }
+}
@@ -0,0 +1,80 @@
+use crate::soc::peripheral::{IoAccess, VRAM_BEGIN, VRAM_END, EXTERNAL_RAM_BEGIN, EXTERNAL_RAM_END, WORKING_RAM_BEGIN,
+    WORKING_RAM_END, OAM_BEGIN, OAM_END, ZERO_PAGE_BEGIN, ZERO_PAGE_END};
+
+// writable memories, the I/O registers are left out since some of them change on their own
+const SNAPSHOT_REGIONS: [(u16, u16); 5] = [
+    (VRAM_BEGIN, VRAM_END),
+    (EXTERNAL_RAM_BEGIN, EXTERNAL_RAM_END),
+    (WORKING_RAM_BEGIN, WORKING_RAM_END),
+    (OAM_BEGIN, OAM_END),
+    (ZERO_PAGE_BEGIN, ZERO_PAGE_END),
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MemoryChange {
+    pub address: u16,
+    pub old: u8,
+    pub new: u8,
+}
+
+// copy of the writable memories, used to find what a game modified between two points
+pub struct MemorySnapshot {
+    // content of each region, in the SNAPSHOT_REGIONS order
+    regions: Vec<Vec<u8>>,
+}
+
+impl MemorySnapshot {
+    pub fn capture<T: IoAccess>(memory: &T) -> MemorySnapshot {
+        MemorySnapshot {
+            regions: SNAPSHOT_REGIONS
+                .iter()
+                .map(|(begin, end)| (*begin..=*end).map(|address| memory.peek_byte(address)).collect())
+                .collect(),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.regions.iter().map(|region| region.len()).sum()
+    }
+
+    // list the bytes which differ between the snapshot and the current memory
+    pub fn diff<T: IoAccess>(&self, memory: &T) -> Vec<MemoryChange> {
+        let mut changes = Vec::new();
+
+        for ((begin, end), region) in SNAPSHOT_REGIONS.iter().zip(self.regions.iter()) {
+            for (address, old) in (*begin..=*end).zip(region.iter()) {
+                let new = memory.peek_byte(address);
+                if new != *old {
+                    changes.push(MemoryChange { address, old: *old, new });
+                }
+            }
+        }
+
+        changes
+    }
+}
+
+#[cfg(test)]
+mod snapshot_tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+    use crate::emulator::test_fixtures::blank_rom;
+    use crate::soc::peripheral::Bus;
+
+    #[test]
+    fn test_snapshot_diff() {
+        let rom = blank_rom();
+        let mut bus = Bus::new(Cartridge::new(&rom));
+
+        let snapshot = MemorySnapshot::capture(&bus);
+        assert_eq!(snapshot.size(), 0x2000 + 0x2000 + 0x2000 + 0xA0 + 0x7F);
+        assert!(snapshot.diff(&bus).is_empty());
+
+        bus.write_byte(0xC123, 0x03);
+        assert_eq!(snapshot.diff(&bus), vec![MemoryChange { address: 0xC123, old: 0xFF, new: 0x03 }]);
+
+        // writing back the same value isn't a change
+        bus.write_byte(0xFF90, 0xFF);
+        assert_eq!(snapshot.diff(&bus).len(), 1);
+    }
+}
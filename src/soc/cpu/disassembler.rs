@@ -0,0 +1,288 @@
+use crate::soc::cpu::Cpu;
+use crate::soc::peripheral::IoAccess;
+
+const HIGH_PAGE: u16 = 0xFF00;
+
+// operand names, indexed by the fields of the opcode
+const R8: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const HL_INDIRECT: u8 = 6;
+const R16: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const R16_STACK: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const CONDITIONS: [&str; 4] = ["NZ", "Z", "NC", "C"];
+const ALU: [&str; 8] = ["ADD A,", "ADC A,", "SUB ", "SBC A,", "AND ", "XOR ", "OR ", "CP "];
+const ROTATIONS: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+const ACCUMULATOR_OPERATIONS: [&str; 8] = ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"];
+
+// memory accessed by an instruction, resolved with the current registers
+#[derive(Debug, PartialEq)]
+enum MemoryAccess {
+    // a register is loaded from memory, the address is already shown by the mnemonic
+    Load(&'static str, u16),
+    // a register is loaded from an address computed from registers
+    LoadIndirect(&'static str, u16),
+    Store(u16, u8),
+    Store16(u16, u16),
+    // the byte is used as an operand or modified in place
+    Operand(u16),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Disassembly {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub text: String,
+    access: Option<MemoryAccess>,
+}
+
+impl Disassembly {
+    // memory address and value read or written by the instruction, the memory is peeked
+    // before the instruction is run so the values are the ones it's about to use
+    pub fn annotation<T: IoAccess>(&self, memory: &T) -> Option<String> {
+        self.access.as_ref().map(|access| match *access {
+            MemoryAccess::Load(register, address) => format!("{}<-0x{:02X}", register, memory.read_byte(address)),
+            MemoryAccess::LoadIndirect(register, address) => format!("{}<-(0x{:04X})=0x{:02X}", register, address, memory.read_byte(address)),
+            MemoryAccess::Store(address, data) => format!("(0x{:04X})<-0x{:02X}", address, data),
+            MemoryAccess::Store16(address, data) => format!("(0x{:04X})<-0x{:04X}", address, data),
+            MemoryAccess::Operand(address) => format!("(0x{:04X})=0x{:02X}", address, memory.read_byte(address)),
+        })
+    }
+
+    // address, raw bytes and mnemonic, followed by the annotation when asked
+    pub fn trace_line<T: IoAccess>(&self, memory: &T, annotated: bool) -> String {
+        let bytes: Vec<String> = self.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        let line = format!("0x{:04X}: {:<8}  {}", self.address, bytes.join(" "), self.text);
+
+        match self.annotation(memory) {
+            Some(annotation) if annotated => format!("{}  ; {}", line, annotation),
+            _ => line,
+        }
+    }
+}
+
+// decode the instruction at pc without running it
+pub fn disassemble<T: IoAccess>(cpu: &Cpu, memory: &T, pc: u16) -> Disassembly {
+    let opcode = memory.read_byte(pc);
+    let d8 = memory.read_byte(pc.wrapping_add(1));
+    let d16 = (memory.read_byte(pc.wrapping_add(2)) as u16) << 8 | d8 as u16;
+
+    let registers = &cpu.registers;
+    let hl = registers.read_hl();
+    let r8_value = |index: u8| match index {
+        0 => registers.b,
+        1 => registers.c,
+        2 => registers.d,
+        3 => registers.e,
+        4 => registers.h,
+        5 => registers.l,
+        _ => registers.a,
+    };
+
+    let x = opcode >> 6;
+    let y = (opcode >> 3) & 0x07;
+    let z = opcode & 0x07;
+    let p = (y >> 1) as usize;
+    let q = y & 0x01;
+
+    let (text, size, access) = match (x, z) {
+        (0, 0) => match y {
+            0 => (String::from("NOP"), 1, None),
+            1 => (format!("LD (0x{:04X}),SP", d16), 3, Some(MemoryAccess::Store16(d16, cpu.sp))),
+            2 => (String::from("STOP"), 2, None),
+            3 => (format!("JR 0x{:04X}", relative_target(pc, d8)), 2, None),
+            _ => (format!("JR {},0x{:04X}", CONDITIONS[(y - 4) as usize], relative_target(pc, d8)), 2, None),
+        },
+        (0, 1) if q == 0 => (format!("LD {},0x{:04X}", R16[p], d16), 3, None),
+        (0, 1) => (format!("ADD HL,{}", R16[p]), 1, None),
+        (0, 2) => {
+            let (operand, address) = match p {
+                0 => ("(BC)", registers.read_bc()),
+                1 => ("(DE)", registers.read_de()),
+                2 => ("(HL+)", hl),
+                _ => ("(HL-)", hl),
+            };
+
+            if q == 0 {
+                (format!("LD {},A", operand), 1, Some(MemoryAccess::Store(address, registers.a)))
+            } else {
+                (format!("LD A,{}", operand), 1, Some(MemoryAccess::LoadIndirect("A", address)))
+            }
+        }
+        (0, 3) if q == 0 => (format!("INC {}", R16[p]), 1, None),
+        (0, 3) => (format!("DEC {}", R16[p]), 1, None),
+        (0, 4) => (format!("INC {}", R8[y as usize]), 1, hl_operand(y, hl)),
+        (0, 5) => (format!("DEC {}", R8[y as usize]), 1, hl_operand(y, hl)),
+        (0, 6) => {
+            let access = if y == HL_INDIRECT { Some(MemoryAccess::Store(hl, d8)) } else { None };
+            (format!("LD {},0x{:02X}", R8[y as usize], d8), 2, access)
+        }
+        (0, _) => (String::from(ACCUMULATOR_OPERATIONS[y as usize]), 1, None),
+        (1, HL_INDIRECT) if y == HL_INDIRECT => (String::from("HALT"), 1, None),
+        (1, _) => {
+            let access = if y == HL_INDIRECT {
+                Some(MemoryAccess::Store(hl, r8_value(z)))
+            } else if z == HL_INDIRECT {
+                Some(MemoryAccess::LoadIndirect(R8[y as usize], hl))
+            } else {
+                None
+            };
+            (format!("LD {},{}", R8[y as usize], R8[z as usize]), 1, access)
+        }
+        (2, _) => (format!("{}{}", ALU[y as usize], R8[z as usize]), 1, hl_operand(z, hl)),
+        (_, 0) => match y {
+            0..=3 => (format!("RET {}", CONDITIONS[y as usize]), 1, None),
+            4 => (format!("LDH (0x{:02X}),A", d8), 2, Some(MemoryAccess::Store(HIGH_PAGE | d8 as u16, registers.a))),
+            5 => (format!("ADD SP,{}", d8 as i8), 2, None),
+            6 => (format!("LDH A,(0x{:02X})", d8), 2, Some(MemoryAccess::LoadIndirect("A", HIGH_PAGE | d8 as u16))),
+            _ => (format!("LD HL,SP{:+}", d8 as i8), 2, None),
+        },
+        (_, 1) if q == 0 => (format!("POP {}", R16_STACK[p]), 1, None),
+        (_, 1) => match p {
+            0 => (String::from("RET"), 1, None),
+            1 => (String::from("RETI"), 1, None),
+            2 => (String::from("JP HL"), 1, None),
+            _ => (String::from("LD SP,HL"), 1, None),
+        },
+        (_, 2) => match y {
+            0..=3 => (format!("JP {},0x{:04X}", CONDITIONS[y as usize], d16), 3, None),
+            4 => (String::from("LD (C),A"), 1, Some(MemoryAccess::Store(HIGH_PAGE | registers.c as u16, registers.a))),
+            5 => (format!("LD (0x{:04X}),A", d16), 3, Some(MemoryAccess::Store(d16, registers.a))),
+            6 => (String::from("LD A,(C)"), 1, Some(MemoryAccess::LoadIndirect("A", HIGH_PAGE | registers.c as u16))),
+            _ => (format!("LD A,(0x{:04X})", d16), 3, Some(MemoryAccess::Load("A", d16))),
+        },
+        (_, 3) => match y {
+            0 => (format!("JP 0x{:04X}", d16), 3, None),
+            1 => disassemble_long(d8, hl),
+            6 => (String::from("DI"), 1, None),
+            7 => (String::from("EI"), 1, None),
+            _ => (format!("DB 0x{:02X}", opcode), 1, None),
+        },
+        (_, 4) if y < 4 => (format!("CALL {},0x{:04X}", CONDITIONS[y as usize], d16), 3, None),
+        (_, 5) if q == 0 => (format!("PUSH {}", R16_STACK[p]), 1, None),
+        (_, 5) if p == 0 => (format!("CALL 0x{:04X}", d16), 3, None),
+        (_, 6) => (format!("{}0x{:02X}", ALU[y as usize], d8), 2, None),
+        (_, 7) => (format!("RST 0x{:02X}", y * 8), 1, None),
+        // no instruction for this opcode
+        _ => (format!("DB 0x{:02X}", opcode), 1, None),
+    };
+
+    Disassembly {
+        address: pc,
+        bytes: (0..size).map(|offset| memory.read_byte(pc.wrapping_add(offset))).collect(),
+        text,
+        access,
+    }
+}
+
+// instructions following the 0xCB prefix
+fn disassemble_long(long_opcode: u8, hl: u16) -> (String, u16, Option<MemoryAccess>) {
+    let x = long_opcode >> 6;
+    let y = (long_opcode >> 3) & 0x07;
+    let z = long_opcode & 0x07;
+
+    let text = match x {
+        0 => format!("{} {}", ROTATIONS[y as usize], R8[z as usize]),
+        1 => format!("BIT {},{}", y, R8[z as usize]),
+        2 => format!("RES {},{}", y, R8[z as usize]),
+        _ => format!("SET {},{}", y, R8[z as usize]),
+    };
+
+    (text, 2, hl_operand(z, hl))
+}
+
+fn hl_operand(index: u8, hl: u16) -> Option<MemoryAccess> {
+    if index == HL_INDIRECT {
+        Some(MemoryAccess::Operand(hl))
+    } else {
+        None
+    }
+}
+
+// the offset is relative to the next instruction
+fn relative_target(pc: u16, offset: u8) -> u16 {
+    pc.wrapping_add(2).wrapping_add(offset as i8 as u16)
+}
+
+#[cfg(test)]
+mod disassembler_tests {
+    use super::*;
+
+    // flat 64KB memory
+    struct Memory {
+        data: Vec<u8>,
+    }
+
+    impl IoAccess for Memory {
+        fn read_byte(&self, address: u16) -> u8 {
+            self.data[address as usize]
+        }
+
+        fn write_byte(&mut self, address: u16, data: u8) {
+            self.data[address as usize] = data;
+        }
+    }
+
+    fn create_memory(program: &[u8]) -> Memory {
+        let mut memory = Memory { data: vec![0x00; 0x10000] };
+        memory.data[0x0150..0x0150 + program.len()].copy_from_slice(program);
+        memory
+    }
+
+    #[test]
+    fn test_load_from_memory_annotation() {
+        // LD A,(0xC000)
+        let mut memory = create_memory(&[0xFA, 0x00, 0xC0]);
+        memory.write_byte(0xC000, 0x3C);
+        let cpu = Cpu::new();
+
+        let disassembly = disassemble(&cpu, &memory, 0x0150);
+        assert_eq!(disassembly.text, "LD A,(0xC000)");
+        assert_eq!(disassembly.bytes, vec![0xFA, 0x00, 0xC0]);
+        assert_eq!(disassembly.annotation(&memory), Some(String::from("A<-0x3C")));
+        assert_eq!(disassembly.trace_line(&memory, true), "0x0150: FA 00 C0  LD A,(0xC000)  ; A<-0x3C");
+        assert_eq!(disassembly.trace_line(&memory, false), "0x0150: FA 00 C0  LD A,(0xC000)");
+    }
+
+    #[test]
+    fn test_indirect_annotations() {
+        // LD B,(HL) / LD (HL+),A / LDH (0x40),A / INC (HL)
+        let mut memory = create_memory(&[0x46, 0x22, 0xE0, 0x40, 0x34]);
+        memory.write_byte(0xC123, 0x99);
+        let mut cpu = Cpu::new();
+        cpu.registers.write_hl(0xC123);
+        cpu.registers.a = 0x91;
+
+        let annotations: Vec<(String, Option<String>)> = [0x0150, 0x0151, 0x0152, 0x0154]
+            .iter()
+            .map(|pc| {
+                let disassembly = disassemble(&cpu, &memory, *pc);
+                let annotation = disassembly.annotation(&memory);
+                (disassembly.text, annotation)
+            })
+            .collect();
+
+        assert_eq!(annotations, vec![
+            (String::from("LD B,(HL)"), Some(String::from("B<-(0xC123)=0x99"))),
+            (String::from("LD (HL+),A"), Some(String::from("(0xC123)<-0x91"))),
+            (String::from("LDH (0x40),A"), Some(String::from("(0xFF40)<-0x91"))),
+            (String::from("INC (HL)"), Some(String::from("(0xC123)=0x99"))),
+        ]);
+    }
+
+    #[test]
+    fn test_disassemble() {
+        // JR NZ,-2 / CALL 0x1234 / BIT 7,H / XOR 0x0F / RST 0x38 / illegal opcode
+        let memory = create_memory(&[0x20, 0xFE, 0xCD, 0x34, 0x12, 0xCB, 0x7C, 0xEE, 0x0F, 0xFF, 0xD3]);
+        let cpu = Cpu::new();
+
+        let mut pc = 0x0150;
+        let mut texts = Vec::new();
+        while pc < 0x0150 + 11 {
+            let disassembly = disassemble(&cpu, &memory, pc);
+            assert_eq!(disassembly.annotation(&memory), None);
+            pc += disassembly.bytes.len() as u16;
+            texts.push(disassembly.text);
+        }
+
+        assert_eq!(texts, vec!["JR NZ,0x0150", "CALL 0x1234", "BIT 7,H", "XOR 0x0F", "RST 0x38", "DB 0xD3"]);
+    }
+}
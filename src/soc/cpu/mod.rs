@@ -1,14 +1,20 @@
 mod instruction;
 mod register;
+pub mod disassembler;
 
 use instruction::{
     ArithmeticTarget, BitTarget, Direction, IncDecTarget, Instruction, JumpTarget, Load16Target,
     PopPushTarget, RamTarget, ResetTarget, SPTarget, U16Target,
 };
 use register::Registers;
+use disassembler::{Disassembly, disassemble};
+
+use std::io::{Read, Result, Write};
 
 use crate::soc::peripheral::{IoAccess, Interrupt, VBLANK_VECTOR, LCDSTAT_VECTOR, TIMER_VECTOR};
 use crate::soc::peripheral::nvic::InterruptSources;
+use crate::cartridge::HardwareMode;
+use crate::savestate::{SaveState, invalid_data, read_bool, read_u8, read_u16, write_bool, write_u8, write_u16};
 
 const RUN_0_CYCLE: u8 = 0;
 const RUN_1_CYCLE: u8 = 1;
@@ -50,7 +56,7 @@ macro_rules! arithmetic_instruction {
             ArithmeticTarget::L => (run_instruction_in_register!(l => a, $self.$instruction), RUN_1_CYCLE),
             ArithmeticTarget::HL => ({
                 let address = $self.registers.read_hl();
-                let value = $peripheral.read(address);
+                let value = $peripheral.read_byte(address);
                 let new_value = $self.$instruction(value);
                 $self.registers.a = new_value;
                 // compute next PC value
@@ -59,7 +65,7 @@ macro_rules! arithmetic_instruction {
             }, RUN_2_CYCLES),
             ArithmeticTarget::D8 => ({
                 let address = $self.pc.wrapping_add(1);
-                let value = $peripheral.read(address);
+                let value = $peripheral.read_byte(address);
                 let new_value = $self.$instruction(value);
                 $self.registers.a = new_value;
                 // compute next PC value
@@ -98,9 +104,9 @@ macro_rules! inc_dec_instruction {
             IncDecTarget::L => (run_instruction_in_register!(l => l, $self.$instruction), RUN_1_CYCLE),
             IncDecTarget::HL => ({
                 let address = $self.registers.read_hl();
-                let value = $peripheral.read(address);
+                let value = $peripheral.read_byte(address);
                 let new_value = $self.$instruction(value);
-                $peripheral.write(address, new_value);
+                $peripheral.write_byte(address, new_value);
                 // compute next PC value
                 // modulo operation to avoid overflowing effects
                 $self.pc.wrapping_add(1)
@@ -147,7 +153,7 @@ macro_rules! load_input_register {
             ArithmeticTarget::L => (load_in_register!(l => $main_register, $self), RUN_1_CYCLE),
             ArithmeticTarget::HL => ({
                 let address = $self.registers.read_hl();
-                let value = $peripheral.read(address);
+                let value = $peripheral.read_byte(address);
                 $self.registers.$main_register = value;
                 // compute next PC value
                 // modulo operation to avoid overflowing effects
@@ -155,7 +161,7 @@ macro_rules! load_input_register {
             }, RUN_2_CYCLES),
             ArithmeticTarget::D8 => ({
                 let address = $self.pc.wrapping_add(1);
-                let value = $peripheral.read(address);
+                let value = $peripheral.read_byte(address);
                 $self.registers.$main_register = value;
                 // compute next PC value
                 // modulo operation to avoid overflowing effects
@@ -169,7 +175,7 @@ macro_rules! load_in_memory {
     ($input_register: ident, $self:ident, $peripheral:expr) => {{
         let address = $self.registers.read_hl();
         let value = $self.registers.$input_register;
-        $peripheral.write(address, value);
+        $peripheral.write_byte(address, value);
         // compute next PC value
         // modulo operation to avoid overflowing effects
         $self.pc.wrapping_add(1)
@@ -189,9 +195,9 @@ macro_rules! load_reg_in_memory {
             ArithmeticTarget::HL => (0, RUN_0_CYCLE),
             ArithmeticTarget::D8 => ({
                 let value_address = $self.pc.wrapping_add(1);
-                let value = $peripheral.read(value_address);
+                let value = $peripheral.read_byte(value_address);
                 let mem_address = $self.registers.read_hl();
-                $peripheral.write(mem_address, value);
+                $peripheral.write_byte(mem_address, value);
                 // compute next PC value
                 // modulo operation to avoid overflowing effects
                 $self.pc.wrapping_add(2)
@@ -205,7 +211,7 @@ macro_rules! load_indirect {
         match $register {
             Load16Target::BC => {
                 let address = $self.registers.read_bc();
-                let value = $peripheral.read(address);
+                let value = $peripheral.read_byte(address);
                 $self.registers.a = value;
                 // compute next PC value
                 // modulo operation to avoid overflowing effects
@@ -213,7 +219,7 @@ macro_rules! load_indirect {
             }
             Load16Target::DE => {
                 let address = $self.registers.read_de();
-                let value = $peripheral.read(address);
+                let value = $peripheral.read_byte(address);
                 $self.registers.a = value;
                 // compute next PC value
                 // modulo operation to avoid overflowing effects
@@ -221,7 +227,7 @@ macro_rules! load_indirect {
             }
             Load16Target::HL_plus => {
                 let address = $self.registers.read_hl();
-                let value = $peripheral.read(address);
+                let value = $peripheral.read_byte(address);
                 $self.registers.a = value;
                 let new_address = address.wrapping_add(1);
                 $self.registers.write_hl(new_address);
@@ -231,7 +237,7 @@ macro_rules! load_indirect {
             }
             Load16Target::HL_minus => {
                 let address = $self.registers.read_hl();
-                let value = $peripheral.read(address);
+                let value = $peripheral.read_byte(address);
                 $self.registers.a = value;
                 let new_address = address.wrapping_sub(1);
                 $self.registers.write_hl(new_address);
@@ -249,7 +255,7 @@ macro_rules! store_indirect {
             Load16Target::BC => {
                 let value = $self.registers.a;
                 let address = $self.registers.read_bc();
-                $peripheral.write(address, value);
+                $peripheral.write_byte(address, value);
                 // compute next PC value
                 // modulo operation to avoid overflowing effects
                 $self.pc.wrapping_add(1)
@@ -257,7 +263,7 @@ macro_rules! store_indirect {
             Load16Target::DE => {
                 let value = $self.registers.a;
                 let address = $self.registers.read_de();
-                $peripheral.write(address, value);
+                $peripheral.write_byte(address, value);
                 // compute next PC value
                 // modulo operation to avoid overflowing effects
                 $self.pc.wrapping_add(1)
@@ -265,7 +271,7 @@ macro_rules! store_indirect {
             Load16Target::HL_plus => {
                 let value = $self.registers.a;
                 let address = $self.registers.read_hl();
-                $peripheral.write(address, value);
+                $peripheral.write_byte(address, value);
                 let new_address = address.wrapping_add(1);
                 $self.registers.write_hl(new_address);
                 // compute next PC value
@@ -275,7 +281,7 @@ macro_rules! store_indirect {
             Load16Target::HL_minus => {
                 let value = $self.registers.a;
                 let address = $self.registers.read_hl();
-                $peripheral.write(address, value);
+                $peripheral.write_byte(address, value);
                 let new_address = address.wrapping_sub(1);
                 $self.registers.write_hl(new_address);
                 // compute next PC value
@@ -292,8 +298,8 @@ macro_rules! load_immediate {
             U16Target::BC => {
                 let low_address = $self.pc.wrapping_add(1);
                 let high_address = $self.pc.wrapping_add(2);
-                let low_byte = $peripheral.read(low_address);
-                let high_byte = $peripheral.read(high_address);
+                let low_byte = $peripheral.read_byte(low_address);
+                let high_byte = $peripheral.read_byte(high_address);
                 let value = (low_byte as u16) + ((high_byte as u16) << 8);
                 $self.registers.write_bc(value);
                 // compute next PC value
@@ -303,8 +309,8 @@ macro_rules! load_immediate {
             U16Target::DE => {
                 let low_address = $self.pc.wrapping_add(1);
                 let high_address = $self.pc.wrapping_add(2);
-                let low_byte = $peripheral.read(low_address);
-                let high_byte = $peripheral.read(high_address);
+                let low_byte = $peripheral.read_byte(low_address);
+                let high_byte = $peripheral.read_byte(high_address);
                 let value = (low_byte as u16) + ((high_byte as u16) << 8);
                 $self.registers.write_de(value);
                 // compute next PC value
@@ -314,8 +320,8 @@ macro_rules! load_immediate {
             U16Target::HL => {
                 let low_address = $self.pc.wrapping_add(1);
                 let high_address = $self.pc.wrapping_add(2);
-                let low_byte = $peripheral.read(low_address);
-                let high_byte = $peripheral.read(high_address);
+                let low_byte = $peripheral.read_byte(low_address);
+                let high_byte = $peripheral.read_byte(high_address);
                 let value = (low_byte as u16) + ((high_byte as u16) << 8);
                 $self.registers.write_hl(value);
                 // compute next PC value
@@ -325,8 +331,8 @@ macro_rules! load_immediate {
             U16Target::SP => {
                 let low_address = $self.pc.wrapping_add(1);
                 let high_address = $self.pc.wrapping_add(2);
-                let low_byte = $peripheral.read(low_address);
-                let high_byte = $peripheral.read(high_address);
+                let low_byte = $peripheral.read_byte(low_address);
+                let high_byte = $peripheral.read_byte(high_address);
                 let value = (low_byte as u16) + ((high_byte as u16) << 8);
                 $self.sp = value;
                 // compute next PC value
@@ -483,13 +489,6 @@ macro_rules! reset {
     }};
 }
 
-macro_rules! interrupt_enable {
-    ($enable: ident, $self:ident, $peripheral:expr) => {{
-        $peripheral.master_enable($enable);
-        $self.pc.wrapping_add(1)
-    }};
-}
-
 macro_rules! rotate_register {
     ($register: ident, $self:ident.$instruction:ident, $direction: ident, $zero:ident) => {{
         // update flag register
@@ -540,11 +539,11 @@ macro_rules! rotate_from_register {
                 $self.registers.f.half_carry = false;
                 // get data from memory
                 let address = $self.registers.read_hl();
-                let value = $peripheral.read(address);
+                let value = $peripheral.read_byte(address);
                 // rotate value
                 let new_value = $self.$instruction(value, $direction, true);
                 // save value in memory
-                $peripheral.write(address, new_value);
+                $peripheral.write_byte(address, new_value);
                 // return next pc
                 ($self.pc.wrapping_add(2), RUN_4_CYCLES)
             }
@@ -586,11 +585,11 @@ macro_rules! shift {
             IncDecTarget::HL => {
                 // get data from memory
                 let address = $self.registers.read_hl();
-                let value = $peripheral.read(address);
+                let value = $peripheral.read_byte(address);
                 // rotate value
                 let new_value = $self.$instruction(value);
                 // save value in memory
-                $peripheral.write(address, new_value);
+                $peripheral.write_byte(address, new_value);
                 // return next pc
                 ($self.pc.wrapping_add(2), RUN_4_CYCLES)
             }
@@ -611,7 +610,7 @@ macro_rules! long_inst_from_reg {
             IncDecTarget::HL => ({
                 // get data from memory
                 let address = $self.registers.read_hl();
-                let value = $peripheral.read(address);
+                let value = $peripheral.read_byte(address);
                 // complement value
                 $self.$instruction($bit, value);
                 // return next pc
@@ -667,11 +666,11 @@ macro_rules! long_inst_from_reg {
             IncDecTarget::HL => {
                 // get data from memory
                 let address = $self.registers.read_hl();
-                let value = $peripheral.read(address);
+                let value = $peripheral.read_byte(address);
                 // run instruction on value
                 let new_value = $self.$instruction($enable, $bit, value);
                 // save new value in memory
-                $peripheral.write(address, new_value);
+                $peripheral.write_byte(address, new_value);
                 // return next pc
                 ($self.pc.wrapping_add(2), RUN_4_CYCLES)
             }
@@ -708,11 +707,14 @@ macro_rules! long_inst {
 }
 
 #[derive(PartialEq)]
+#[allow(non_camel_case_types)]
 pub enum CpuMode {
     RUN,
     INTERRUPT,
     STOP,
     HALT,
+    // an undefined opcode hangs the cpu until the power off, the interrupts can't wake it up
+    LOCKED_UP,
 }
 
 pub enum CarryOp {
@@ -725,6 +727,11 @@ pub struct Cpu {
     pub pc: u16,
     pub sp: u16,
     mode: CpuMode,
+    // interrupts are enabled after the instruction following EI
+    ei_delay: bool,
+    // run the undefined opcodes as NOP instead of locking up, host setting which isn't part of
+    // the save states
+    pub lenient_opcodes: bool,
 }
 
 impl Cpu {
@@ -734,12 +741,49 @@ impl Cpu {
             pc: 0x0000,
             sp: 0x0000,
             mode: CpuMode::RUN,
+            ei_delay: false,
+            lenient_opcodes: false,
+        }
+    }
+
+    // registers values when the boot rom hands over to the game
+    // games check A to detect the CGB: 0x01 on DMG, 0x11 on CGB
+    pub fn skip_boot(&mut self, hardware_mode: HardwareMode) {
+        match hardware_mode {
+            HardwareMode::DMG => {
+                self.registers.write_af(0x01B0);
+                self.registers.write_bc(0x0013);
+                self.registers.write_de(0x00D8);
+                self.registers.write_hl(0x014D);
+            }
+            HardwareMode::CGB => {
+                self.registers.write_af(0x1180);
+                self.registers.write_bc(0x0000);
+                self.registers.write_de(0xFF56);
+                self.registers.write_hl(0x000D);
+            }
         }
+        self.sp = 0xFFFE;
+        self.pc = 0x0100;
+    }
+
+    // the instruction at pc, for the trace and the debugger
+    pub fn disassemble<T: IoAccess>(&self, peripheral: &T) -> Disassembly {
+        disassemble(self, peripheral, self.pc)
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.mode == CpuMode::HALT
+    }
+
+    // the opcode at pc is undefined and has locked up the cpu
+    pub fn is_locked(&self) -> bool {
+        self.mode == CpuMode::LOCKED_UP
     }
 
     fn decode<T: IoAccess>(&mut self, instruction_byte: u8, peripheral: &mut T) -> Option<Instruction> {
         if Instruction::is_long_instruction(instruction_byte) {
-            let long_instruction_byte = peripheral.read(self.pc.wrapping_add(1));
+            let long_instruction_byte = peripheral.read_byte(self.pc.wrapping_add(1));
             Instruction::from_long_byte(long_instruction_byte)
         } else {
             Instruction::from_byte(instruction_byte)
@@ -748,7 +792,9 @@ impl Cpu {
 
     pub fn run<T: IoAccess + Interrupt>(&mut self, peripheral: &mut T) -> u8 {
         // catch interrupt as soon as possible
-        if peripheral.is_an_interrupt_to_run() {
+        let mut wake_from_halt = false;
+        if self.mode != CpuMode::LOCKED_UP && peripheral.is_an_interrupt_to_run() {
+            wake_from_halt = self.mode == CpuMode::HALT;
             self.mode = CpuMode::INTERRUPT;
         }
     
@@ -756,18 +802,31 @@ impl Cpu {
         match self.mode {
     
             CpuMode::RUN => {
+                // an EI executed just before takes effect after this instruction
+                let enable_interrupts = self.ei_delay;
+
                 // fetch instruction
-                let instruction_byte = peripheral.read(self.pc);
+                let instruction_byte = peripheral.read_byte(self.pc);
                 // decode instruction
                 let (next_pc, cpu_cycles) = if let Some(instruction) = self.decode(instruction_byte, peripheral) {
                     // execute instruction
                     self.execute(instruction, peripheral)
+                } else if self.lenient_opcodes {
+                    (self.pc.wrapping_add(1), RUN_1_CYCLE)
                 } else {
-                    panic!("Unknown instruction found for 0x{:x}", instruction_byte);
+                    // pc stays on the undefined opcode
+                    self.mode = CpuMode::LOCKED_UP;
+                    (self.pc, RUN_1_CYCLE)
                 };
 
                 // update PC value & cycles value
                 self.pc = next_pc;
+
+                // a DI executed meanwhile cancels the EI
+                if enable_interrupts && self.ei_delay {
+                    self.ei_delay = false;
+                    peripheral.master_enable(true);
+                }
     
                 // run the peripheral subsystem
                 cpu_cycles
@@ -783,9 +842,10 @@ impl Cpu {
                     // jump to interrupt routine
                     self.jump_to_interrupt_routine(interrupt_source, peripheral);
                     // 2 NOP (2 cycles) + PUSH (2 cycles) + set PC (1 cycle)
+                    // exiting HALT mode takes one more cycle
 
                     // run the peripheral subsystem
-                    RUN_5_CYCLES
+                    if wake_from_halt { RUN_6_CYCLES } else { RUN_5_CYCLES }
                 } else {
                     panic!("An interrupt has been triggered but no interrupt source has been found !")
                 }
@@ -806,6 +866,11 @@ impl Cpu {
                 // all system is stopped
                 RUN_0_CYCLE
             }
+
+            CpuMode::LOCKED_UP => {
+                // the peripherals keep running
+                RUN_1_CYCLE
+            }
         }
     }
 
@@ -862,8 +927,8 @@ impl Cpu {
             Instruction::PUSH(target) => (push!(target, self, peripheral), RUN_4_CYCLES),
 
             // Interrupt instructions
-            Instruction::DI => (interrupt_enable!(false, self, peripheral), RUN_1_CYCLE),
-            Instruction::EI => (interrupt_enable!(true, self, peripheral), RUN_1_CYCLE),
+            Instruction::DI => (self.disable_interrupts(peripheral), RUN_1_CYCLE),
+            Instruction::EI => (self.enable_interrupts(), RUN_1_CYCLE),
 
             // Control instructions
             Instruction::NOP => (self.pc.wrapping_add(1), RUN_1_CYCLE),
@@ -1033,22 +1098,22 @@ impl Cpu {
     fn load_sp<T: IoAccess>(&mut self, target: SPTarget, peripheral: &mut T) -> (u16, u8) {
         match target {
             SPTarget::FROM_SP => ({
-                let low_byte_address = peripheral.read(self.pc.wrapping_add(1)) as u16;
-                let high_byte_address = peripheral.read(self.pc.wrapping_add(2)) as u16;
-                let address = low_byte_address + (high_byte_address << 8);
+                let low_byte_address = peripheral.read_byte(self.pc.wrapping_add(1)) as u16;
+                let high_byte_address = peripheral.read_byte(self.pc.wrapping_add(2)) as u16;
+                let address = (high_byte_address << 8) | low_byte_address;
 
                 // save Stack Pointer lower byte
                 let mut data = (self.sp & 0x00FF) as u8;
-                peripheral.write(address, data);
+                peripheral.write_byte(address, data);
                 // save Stack Pointer higher byte
                 data = ((self.sp & 0xFF00) >> 8) as u8;
-                peripheral.write(address + 1, data);
+                peripheral.write_byte(address.wrapping_add(1), data);
 
                 // return next program counter value
                 self.pc.wrapping_add(3)
             }, RUN_5_CYCLES),
             SPTarget::TO_HL => ({
-                let immediate = peripheral.read(self.pc.wrapping_add(1)) as i8 as u16;
+                let immediate = peripheral.read_byte(self.pc.wrapping_add(1)) as i8 as u16;
                 let stack_addr = self.sp.wrapping_add(immediate);
                 self.registers.write_hl(stack_addr);
 
@@ -1077,15 +1142,15 @@ impl Cpu {
                 // get address from instruction
                 let base_ram_address = 0xFF00;
                 let immediate_address = self.pc.wrapping_add(1);
-                let ram_offset = peripheral.read(immediate_address) as u16;
+                let ram_offset = peripheral.read_byte(immediate_address) as u16;
 
                 if load {
                     // read data from ram memory & load it in register a
-                    self.registers.a = peripheral.read(base_ram_address + ram_offset);
+                    self.registers.a = peripheral.read_byte(base_ram_address + ram_offset);
                 } else {
                     // read data from register A & store it in RAM
                     peripheral
-                        .write(base_ram_address + ram_offset, self.registers.a);
+                        .write_byte(base_ram_address + ram_offset, self.registers.a);
                 }
 
                 // return next program counter value
@@ -1098,11 +1163,11 @@ impl Cpu {
 
                 if load {
                     // read data from ram memory & load it in register a
-                    self.registers.a = peripheral.read(base_ram_address + ram_offset);
+                    self.registers.a = peripheral.read_byte(base_ram_address + ram_offset);
                 } else {
                     // read data from register A & store it in RAM
                     peripheral
-                        .write(base_ram_address + ram_offset, self.registers.a);
+                        .write_byte(base_ram_address + ram_offset, self.registers.a);
                 }
 
                 // return next program counter value
@@ -1110,16 +1175,16 @@ impl Cpu {
             }, RUN_2_CYCLES),
             RamTarget::TwoBytesAddress => ({
                 // get address from instruction
-                let low_byte_address = peripheral.read(self.pc.wrapping_add(1)) as u16;
-                let high_byte_address = peripheral.read(self.pc.wrapping_add(2)) as u16;
+                let low_byte_address = peripheral.read_byte(self.pc.wrapping_add(1)) as u16;
+                let high_byte_address = peripheral.read_byte(self.pc.wrapping_add(2)) as u16;
                 let address = low_byte_address + (high_byte_address << 8);
 
                 if load {
                     // read data from ram memory & load it in register a
-                    self.registers.a = peripheral.read(address);
+                    self.registers.a = peripheral.read_byte(address);
                 } else {
                     // read data from register A & store it in RAM
-                    peripheral.write(address, self.registers.a);
+                    peripheral.write_byte(address, self.registers.a);
                 }
 
                 // return next program counter value
@@ -1131,7 +1196,7 @@ impl Cpu {
     fn jump_relative<T: IoAccess>(&mut self, flag: bool, peripheral: &mut T) -> (u16, u8) {
         // get the immediate from memory
         let immediate_address = self.pc.wrapping_add(1);
-        let immediate = peripheral.read(immediate_address) as i8 as u16;
+        let immediate = peripheral.read_byte(immediate_address) as i8 as u16;
 
         // do the jump following the flag value
         if flag {
@@ -1144,8 +1209,8 @@ impl Cpu {
 
     fn jump_immediate<T: IoAccess>(&mut self, flag: bool, peripheral: &mut T) -> (u16, u8) {
         // get the immediate from memory
-        let low_immediate = peripheral.read(self.pc.wrapping_add(1)) as u16;
-        let high_immediate = peripheral.read(self.pc.wrapping_add(2)) as u16;
+        let low_immediate = peripheral.read_byte(self.pc.wrapping_add(1)) as u16;
+        let high_immediate = peripheral.read_byte(self.pc.wrapping_add(2)) as u16;
         let immediate = (high_immediate << 8) | low_immediate;
 
         // do the jump following the flag value
@@ -1168,8 +1233,8 @@ impl Cpu {
         // update stack pointer
         self.sp = self.sp.wrapping_add(2);
         // read data from RAM memory
-        let low_byte = peripheral.read(low_stack_address) as u16;
-        let high_byte = peripheral.read(high_stack_address) as u16;
+        let low_byte = peripheral.read_byte(low_stack_address) as u16;
+        let high_byte = peripheral.read_byte(high_stack_address) as u16;
         low_byte | (high_byte << 8)
     }
 
@@ -1181,14 +1246,14 @@ impl Cpu {
         let high_stack_address = self.sp.wrapping_sub(1);
         let low_stack_address = self.sp.wrapping_sub(2);
         // save data in memory
-        peripheral.write(high_stack_address, high_byte);
-        peripheral.write(low_stack_address, low_byte);
+        peripheral.write_byte(high_stack_address, high_byte);
+        peripheral.write_byte(low_stack_address, low_byte);
         // update stack pointer
         self.sp = self.sp.wrapping_sub(2);
     }
 
     fn add_sp<T: IoAccess>(&mut self, peripheral: &mut T) -> u16 {
-        let immediate = peripheral.read(self.pc.wrapping_add(1)) as i8 as u16;
+        let immediate = peripheral.read_byte(self.pc.wrapping_add(1)) as i8 as u16;
         let result = self.sp.wrapping_add(immediate);
 
         // update flags
@@ -1210,7 +1275,21 @@ impl Cpu {
         addr_to_reset as u16
     }
 
+    fn disable_interrupts<T: Interrupt>(&mut self, peripheral: &mut T) -> u16 {
+        self.ei_delay = false;
+        peripheral.master_enable(false);
+        self.pc.wrapping_add(1)
+    }
+
+    fn enable_interrupts(&mut self) -> u16 {
+        // the master enable is set by the run loop once the next instruction is executed
+        self.ei_delay = true;
+        self.pc.wrapping_add(1)
+    }
+
     fn reti<T: IoAccess + Interrupt>(&mut self, peripheral: &mut T) -> u16 {
+        // unlike EI, interrupts are enabled immediately
+        self.ei_delay = false;
         peripheral.master_enable(true);
         self.pop(peripheral)
     }
@@ -1221,8 +1300,8 @@ impl Cpu {
             // save the return address on the stack
             self.push(self.pc.wrapping_add(3), peripheral);
             // get the call address
-            let low_byte_address = peripheral.read(self.pc.wrapping_add(1)) as u16;
-            let high_byte_address = peripheral.read(self.pc.wrapping_add(2)) as u16;
+            let low_byte_address = peripheral.read_byte(self.pc.wrapping_add(1)) as u16;
+            let high_byte_address = peripheral.read_byte(self.pc.wrapping_add(2)) as u16;
             let call_address = low_byte_address | (high_byte_address << 8);
             // return the call address
             (call_address, RUN_6_CYCLES)
@@ -1457,6 +1536,38 @@ impl Cpu {
     }
 }
 
+impl SaveState for Cpu {
+    fn save_state(&self, writer: &mut dyn Write) -> Result<()> {
+        self.registers.save_state(writer)?;
+        write_u16(writer, self.pc)?;
+        write_u16(writer, self.sp)?;
+        write_u8(writer, match self.mode {
+            CpuMode::RUN => 0,
+            CpuMode::INTERRUPT => 1,
+            CpuMode::STOP => 2,
+            CpuMode::HALT => 3,
+            CpuMode::LOCKED_UP => 4,
+        })?;
+        write_bool(writer, self.ei_delay)
+    }
+
+    fn load_state(&mut self, reader: &mut dyn Read) -> Result<()> {
+        self.registers.load_state(reader)?;
+        self.pc = read_u16(reader)?;
+        self.sp = read_u16(reader)?;
+        self.mode = match read_u8(reader)? {
+            0 => CpuMode::RUN,
+            1 => CpuMode::INTERRUPT,
+            2 => CpuMode::STOP,
+            3 => CpuMode::HALT,
+            4 => CpuMode::LOCKED_UP,
+            mode => return Err(invalid_data(&format!("invalid cpu mode {}", mode))),
+        };
+        self.ei_delay = read_bool(reader)?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod cpu_tests {
     use super::*;
@@ -1469,7 +1580,7 @@ mod cpu_tests {
         IncDecTarget, JumpTarget, Load16Target, PopPushTarget, ResetTarget, SPTarget, U16Target,
     };
     use crate::cartridge::{Cartridge, CARTRIDGE_TYPE_OFFSET, CARTRIDGE_RAM_SIZE_OFFSET, CARTRIDGE_ROM_SIZE_OFFSET};
-    use crate::soc::peripheral::Peripheral;
+    use crate::soc::peripheral::Bus;
 
     #[test]
     fn test_add_registers() {
@@ -1478,7 +1589,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         cpu.registers.write_bc(0xAABB);
         cpu.execute(ADD(B), &mut peripheral);
         assert_eq!(cpu.registers.read_af(), 0xAA00);
@@ -1491,11 +1602,11 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         let address = 0xC000;
         let data = 0xAA;
 
-        peripheral.write(address, data);
+        peripheral.write_byte(address, data);
         cpu.registers.write_hl(address);
         cpu.execute(ADD(HL), &mut peripheral);
         assert_eq!(cpu.registers.read_af(), 0xAA00);
@@ -1509,7 +1620,7 @@ mod cpu_tests {
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
         rom[0x0001 as usize] = 0x23;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
 
         cpu.execute(ADD(D8), &mut peripheral);
         assert_eq!(cpu.registers.read_af(), 0x2300);
@@ -1522,7 +1633,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         cpu.registers.write_bc(0x2200);
         cpu.registers.write_hl(0x0125);
         cpu.execute(ADD16(U16Target::BC), &mut peripheral);
@@ -1550,7 +1661,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         cpu.registers.write_af(0x0110);
         cpu.registers.write_bc(0xAABB);
@@ -1570,11 +1681,11 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         let address = 0xC000;
         let data = 0xAA;
 
-        peripheral.write(address, data);
+        peripheral.write_byte(address, data);
         cpu.registers.write_hl(address);
         cpu.execute(ADDC(HL), &mut peripheral);
         assert_eq!(cpu.registers.read_af(), 0xAA00);
@@ -1587,11 +1698,11 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         let address = 0xC001;
         let data = 0x23;
 
-        peripheral.write(address, data);
+        peripheral.write_byte(address, data);
         cpu.registers.write_af(0x0110);
         cpu.pc = 0xC000;
         cpu.execute(ADDC(D8), &mut peripheral);
@@ -1605,7 +1716,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         cpu.registers.write_bc(0xAABB);
         cpu.registers.write_af(0xFF00);
         cpu.execute(SUB(C), &mut peripheral);
@@ -1619,7 +1730,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         cpu.registers.write_bc(0xAABB);
         cpu.registers.write_af(0xFF10);
         cpu.execute(SBC(C), &mut peripheral);
@@ -1633,7 +1744,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         cpu.registers.write_bc(0xAABB);
         cpu.registers.write_af(0xAA00);
         cpu.execute(AND(B), &mut peripheral);
@@ -1647,7 +1758,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         cpu.registers.write_bc(0x0022);
         cpu.registers.write_af(0x2100);
         cpu.execute(XOR(C), &mut peripheral);
@@ -1661,7 +1772,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         cpu.registers.write_bc(0x0022);
         cpu.registers.write_af(0x2100);
         cpu.execute(OR(C), &mut peripheral);
@@ -1675,7 +1786,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         cpu.registers.write_bc(0x0022);
         cpu.registers.write_af(0x2200);
@@ -1688,6 +1799,141 @@ mod cpu_tests {
         assert_eq!(cpu.registers.read_af(), 0x2270);
     }
 
+    #[test]
+    fn test_immediate_alu() {
+        let mut cpu = Cpu::new();
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
+
+        // opcode, A, carry flag, d8, expected A, expected flags ZNHC
+        let reference = [
+            // ADD A,d8: the carry isn't added
+            (0xC6, 0x3A, true, 0xC6, 0x00, 0xB0),
+            (0xC6, 0x3C, true, 0x12, 0x4E, 0x00),
+            (0xC6, 0x0F, false, 0x01, 0x10, 0x20),
+            // ADC A,d8
+            (0xCE, 0xE1, true, 0x0F, 0xF1, 0x20),
+            (0xCE, 0xE1, true, 0x1E, 0x00, 0xB0),
+            (0xCE, 0x00, true, 0xFF, 0x00, 0xB0),
+            (0xCE, 0xE1, false, 0x0F, 0xF0, 0x20),
+            // SUB d8
+            (0xD6, 0x3E, true, 0x3E, 0x00, 0xC0),
+            (0xD6, 0x3E, false, 0x0F, 0x2F, 0x60),
+            (0xD6, 0x3E, false, 0x40, 0xFE, 0x50),
+            // SBC A,d8
+            (0xDE, 0x3B, true, 0x2A, 0x10, 0x40),
+            (0xDE, 0x3B, true, 0x3A, 0x00, 0xC0),
+            (0xDE, 0x3B, true, 0x4F, 0xEB, 0x70),
+            (0xDE, 0x00, false, 0x00, 0x00, 0xC0),
+            // AND d8 sets H and clears C
+            (0xE6, 0x5A, true, 0x3F, 0x1A, 0x20),
+            (0xE6, 0x5A, false, 0xA5, 0x00, 0xA0),
+            // XOR d8 clears H and C
+            (0xEE, 0xFF, true, 0xFF, 0x00, 0x80),
+            (0xEE, 0xFF, false, 0x0F, 0xF0, 0x00),
+            // OR d8 clears H and C
+            (0xF6, 0x5A, true, 0x0F, 0x5F, 0x00),
+            (0xF6, 0x00, false, 0x00, 0x00, 0x80),
+            // CP d8 sets the flags of SUB but A is kept
+            (0xFE, 0x3C, false, 0x2F, 0x3C, 0x60),
+            (0xFE, 0x3C, true, 0x3C, 0x3C, 0xC0),
+            (0xFE, 0x3C, false, 0x40, 0x3C, 0x50),
+        ];
+
+        for (opcode, a, carry, data, expected_a, expected_flags) in reference {
+            peripheral.write_byte(0xC000, opcode);
+            peripheral.write_byte(0xC001, data);
+            cpu.pc = 0xC000;
+            // the other flags are set to check that they're all written
+            cpu.registers.write_af(((a as u16) << 8) | if carry { 0xF0 } else { 0xE0 });
+
+            assert_eq!(cpu.run(&mut peripheral), RUN_2_CYCLES);
+            assert_eq!(cpu.pc, 0xC002);
+            assert_eq!(cpu.registers.read_af(), ((expected_a as u16) << 8) | expected_flags,
+                       "opcode {:#04x} with A={:#04x}, carry={}, d8={:#04x}", opcode, a, carry, data);
+        }
+    }
+
+    #[test]
+    fn test_hl_and_high_page_loads() {
+        let mut cpu = Cpu::new();
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
+
+        // opcode, HL before and after the instruction, the HL variants don't use the immediate byte
+        let hl_loads = [
+            // LD (HL+),A / LD (HL-),A
+            (0x22, 0xC100, 0xC101),
+            (0x32, 0xC100, 0xC0FF),
+            // LD A,(HL+) / LD A,(HL-), HL wraps around
+            (0x2A, 0xFFFF, 0x0000),
+            (0x3A, 0xC200, 0xC1FF),
+        ];
+
+        for flags in [0x00, 0xF0] {
+            for (opcode, hl, expected_hl) in hl_loads {
+                peripheral.write_byte(0xC000, opcode);
+                peripheral.write_byte(hl, 0x5A);
+                cpu.pc = 0xC000;
+                cpu.registers.write_hl(hl);
+                cpu.registers.write_af(if opcode & 0x08 == 0 { 0x3C00 } else { 0x0000 } | flags);
+
+                assert_eq!(cpu.run(&mut peripheral), RUN_2_CYCLES);
+                assert_eq!(cpu.pc, 0xC001);
+                assert_eq!(cpu.registers.read_hl(), expected_hl, "opcode {:#04x}", opcode);
+                // the stores write A at the address before the update, the loads read from it
+                if opcode & 0x08 == 0 {
+                    assert_eq!(peripheral.read_byte(hl), 0x3C, "opcode {:#04x}", opcode);
+                    assert_eq!(cpu.registers.read_af(), 0x3C00 | flags, "opcode {:#04x}", opcode);
+                } else {
+                    assert_eq!(cpu.registers.read_af(), 0x5A00 | flags, "opcode {:#04x}", opcode);
+                }
+            }
+
+            // LDH (a8),A / LDH A,(a8): the address is 0xFF00 + a8
+            peripheral.write_byte(0xC000, 0xE0);
+            peripheral.write_byte(0xC001, 0x85);
+            cpu.pc = 0xC000;
+            cpu.registers.write_af(0x7700 | flags);
+            assert_eq!(cpu.run(&mut peripheral), RUN_3_CYCLES);
+            assert_eq!(cpu.pc, 0xC002);
+            assert_eq!(peripheral.read_byte(0xFF85), 0x77);
+            assert_eq!(cpu.registers.read_af(), 0x7700 | flags);
+
+            peripheral.write_byte(0xC000, 0xF0);
+            peripheral.write_byte(0xC001, 0x86);
+            peripheral.write_byte(0xFF86, 0x99);
+            cpu.pc = 0xC000;
+            assert_eq!(cpu.run(&mut peripheral), RUN_3_CYCLES);
+            assert_eq!(cpu.pc, 0xC002);
+            assert_eq!(cpu.registers.read_af(), 0x9900 | flags);
+
+            // LD (C),A / LD A,(C): the address is 0xFF00 + C
+            peripheral.write_byte(0xC000, 0xE2);
+            cpu.pc = 0xC000;
+            cpu.registers.c = 0x90;
+            cpu.registers.write_af(0x4400 | flags);
+            assert_eq!(cpu.run(&mut peripheral), RUN_2_CYCLES);
+            assert_eq!(cpu.pc, 0xC001);
+            assert_eq!(peripheral.read_byte(0xFF90), 0x44);
+            assert_eq!(cpu.registers.read_af(), 0x4400 | flags);
+
+            peripheral.write_byte(0xC000, 0xF2);
+            peripheral.write_byte(0xFF91, 0x12);
+            cpu.pc = 0xC000;
+            cpu.registers.c = 0x91;
+            assert_eq!(cpu.run(&mut peripheral), RUN_2_CYCLES);
+            assert_eq!(cpu.pc, 0xC001);
+            assert_eq!(cpu.registers.read_af(), 0x1200 | flags);
+        }
+    }
+
     #[test]
     fn test_inc_registers() {
         let mut cpu = Cpu::new();
@@ -1695,7 +1941,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         cpu.registers.write_bc(0x2200);
         cpu.execute(INC(IncDecTarget::B), &mut peripheral);
@@ -1707,10 +1953,10 @@ mod cpu_tests {
 
         let address = 0xc000;
         let data = 0xAA;
-        peripheral.write(address, data);
+        peripheral.write_byte(address, data);
         cpu.registers.write_hl(address);
         cpu.execute(INC(IncDecTarget::HL), &mut peripheral);
-        assert_eq!(peripheral.read(address), 0xAB);
+        assert_eq!(peripheral.read_byte(address), 0xAB);
     }
 
     #[test]
@@ -1720,7 +1966,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         cpu.registers.write_bc(0x2200);
         cpu.execute(INC16(U16Target::BC), &mut peripheral);
@@ -1746,7 +1992,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         cpu.registers.write_bc(0x2200);
         cpu.execute(DEC(IncDecTarget::B), &mut peripheral);
@@ -1758,10 +2004,45 @@ mod cpu_tests {
 
         let address = 0xc000;
         let data = 0xAA;
-        peripheral.write(address, data);
+        peripheral.write_byte(address, data);
         cpu.registers.write_hl(address);
         cpu.execute(DEC(IncDecTarget::HL), &mut peripheral);
-        assert_eq!(peripheral.read(address), 0xA9);
+        assert_eq!(peripheral.read_byte(address), 0xA9);
+    }
+
+    #[test]
+    fn test_inc_dec_hl_memory() {
+        let mut cpu = Cpu::new();
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
+
+        let address = 0xC000;
+        cpu.registers.write_hl(address);
+
+        // read, modify and write back takes 3 machine cycles (12 clock ticks)
+        peripheral.write_byte(address, 0xFF);
+        cpu.registers.f.carry = true;
+        let (_, cycles) = cpu.execute(INC(IncDecTarget::HL), &mut peripheral);
+        assert_eq!(peripheral.read_byte(address), 0x00);
+        assert_eq!(cycles, RUN_3_CYCLES);
+        assert_eq!(cpu.registers.f.zero, true);
+        assert_eq!(cpu.registers.f.substraction, false);
+        assert_eq!(cpu.registers.f.half_carry, true);
+        // carry is never modified
+        assert_eq!(cpu.registers.f.carry, true);
+
+        peripheral.write_byte(address, 0x10);
+        cpu.registers.f.carry = false;
+        let (_, cycles) = cpu.execute(DEC(IncDecTarget::HL), &mut peripheral);
+        assert_eq!(peripheral.read_byte(address), 0x0F);
+        assert_eq!(cycles, RUN_3_CYCLES);
+        assert_eq!(cpu.registers.f.zero, false);
+        assert_eq!(cpu.registers.f.substraction, true);
+        assert_eq!(cpu.registers.f.half_carry, true);
+        assert_eq!(cpu.registers.f.carry, false);
     }
 
     #[test]
@@ -1771,7 +2052,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         cpu.registers.write_bc(0x2200);
         cpu.execute(DEC16(U16Target::BC), &mut peripheral);
@@ -1797,7 +2078,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         cpu.registers.write_de(0x0057);
         cpu.execute(LOAD(IncDecTarget::B, E), &mut peripheral);
@@ -1809,7 +2090,7 @@ mod cpu_tests {
 
         let mut mem_address = 0x0001 + 0xC000;
         let mut data = 0x23;
-        peripheral.write(mem_address, data);
+        peripheral.write_byte(mem_address, data);
         cpu.pc = 0xC000;
         cpu.execute(LOAD(IncDecTarget::A, D8), &mut peripheral);
         assert_eq!(cpu.registers.read_af(), 0x2300);
@@ -1817,17 +2098,17 @@ mod cpu_tests {
         mem_address = 0x0010 + 0xC000;
         cpu.registers.write_hl(mem_address);
         cpu.execute(LOAD(IncDecTarget::HL, D8), &mut peripheral);
-        assert_eq!(peripheral.read(mem_address), 0x23);
+        assert_eq!(peripheral.read_byte(mem_address), 0x23);
 
         mem_address = 0x002A + 0xC000;
         cpu.registers.write_hl(mem_address);
         cpu.registers.write_de(0xD500);
         cpu.execute(LOAD(IncDecTarget::HL, D), &mut peripheral);
-        assert_eq!(peripheral.read(mem_address), 0xD5);
+        assert_eq!(peripheral.read_byte(mem_address), 0xD5);
 
         mem_address = 0x00C8 + 0xC000;
         data = 0x5F;
-        peripheral.write(mem_address, data);
+        peripheral.write_byte(mem_address, data);
         cpu.registers.write_hl(mem_address);
         cpu.execute(LOAD(IncDecTarget::A, HL), &mut peripheral);
         assert_eq!(cpu.registers.read_af(), 0x5F00);
@@ -1844,17 +2125,17 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         let mem_address = 0xC000;
         let mut data = 0x56;
-        peripheral.write(mem_address, data);
+        peripheral.write_byte(mem_address, data);
         cpu.registers.write_bc(mem_address);
         cpu.execute(LOAD_INDIRECT(Load16Target::BC), &mut peripheral);
         assert_eq!(cpu.registers.read_af(), 0x5600);
 
         data = 0xC6;
-        peripheral.write(mem_address, data);
+        peripheral.write_byte(mem_address, data);
         cpu.registers.write_hl(mem_address);
         cpu.execute(LOAD_INDIRECT(Load16Target::HL_plus), &mut peripheral);
         assert_eq!(cpu.registers.read_af(), 0xC600);
@@ -1868,13 +2149,13 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         let low_data = 0x4C;
         let high_data = 0xB7;
         let value = ((high_data as u16) << 8) + low_data as u16;
-        peripheral.write(0x0001 + 0xC000, low_data);
-        peripheral.write(0x0002 + 0xC000, high_data);
+        peripheral.write_byte(0x0001 + 0xC000, low_data);
+        peripheral.write_byte(0x0002 + 0xC000, high_data);
         cpu.pc = 0xC000;
         cpu.execute(LOAD_IMMEDIATE(U16Target::DE), &mut peripheral);
         assert_eq!(cpu.registers.read_de(), value);
@@ -1890,7 +2171,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         let mem_address = 0xC000;
         let mut data = 0x5600;
@@ -1898,13 +2179,13 @@ mod cpu_tests {
         cpu.registers.write_de(mem_address);
         cpu.pc = 0xC000;
         cpu.execute(STORE_INDIRECT(Load16Target::DE), &mut peripheral);
-        assert_eq!(peripheral.read(mem_address), 0x56);
+        assert_eq!(peripheral.read_byte(mem_address), 0x56);
 
         data = 0xC600;
         cpu.registers.write_af(data);
         cpu.registers.write_hl(mem_address);
         cpu.execute(STORE_INDIRECT(Load16Target::HL_minus), &mut peripheral);
-        assert_eq!(peripheral.read(mem_address), 0xC6);
+        assert_eq!(peripheral.read_byte(mem_address), 0xC6);
         assert_eq!(cpu.registers.read_hl(), mem_address - 1);
     }
 
@@ -1915,7 +2196,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         // first, fill memory with program
         let base_address: u16 = 0x0000;
@@ -1926,22 +2207,22 @@ mod cpu_tests {
         ];
         let mut index = 0;
         for data in program {
-            peripheral.write(base_address + index, data);
+            peripheral.write_byte(base_address + index, data);
             index += 1;
         }
 
         // run CPU to do the jump
         cpu.run(&mut peripheral);
         assert_eq!(
-            peripheral.read(cpu.pc),
-            peripheral.read(base_address + (jump as u16) + 2)
+            peripheral.read_byte(cpu.pc),
+            peripheral.read_byte(base_address + (jump as u16) + 2)
         );
 
         // reset CPU and run it with the flag, we don't do the jump
         cpu.registers.f.zero = true;
         cpu.pc = base_address;
         cpu.run(&mut peripheral);
-        assert_eq!(peripheral.read(cpu.pc), peripheral.read(base_address + 2));
+        assert_eq!(peripheral.read_byte(cpu.pc), peripheral.read_byte(base_address + 2));
     }
 
     #[test]
@@ -1951,7 +2232,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         // first, fill memory with program
         let base_address: u16 = 0x0000;
@@ -1962,7 +2243,7 @@ mod cpu_tests {
         ];
         let mut index = 0;
         for data in program {
-            peripheral.write(base_address + index, data);
+            peripheral.write_byte(base_address + index, data);
             index += 1;
         }
 
@@ -1970,15 +2251,15 @@ mod cpu_tests {
         cpu.registers.f.carry = true;
         cpu.run(&mut peripheral);
         assert_eq!(
-            peripheral.read(cpu.pc),
-            peripheral.read(base_address + (jump as u16) + 2)
+            peripheral.read_byte(cpu.pc),
+            peripheral.read_byte(base_address + (jump as u16) + 2)
         );
 
         // reset CPU and run it with the flag, we don't do the jump
         cpu.registers.f.carry = false;
         cpu.pc = base_address;
         cpu.run(&mut peripheral);
-        assert_eq!(peripheral.read(cpu.pc), peripheral.read(base_address + 2));
+        assert_eq!(peripheral.read_byte(cpu.pc), peripheral.read_byte(base_address + 2));
     }
 
     #[test]
@@ -1988,7 +2269,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         // first, fill memory with program
         let base_address: u16 = 0x0000;
@@ -1999,15 +2280,15 @@ mod cpu_tests {
         ];
         let mut index = 0;
         for data in program {
-            peripheral.write(base_address + index, data);
+            peripheral.write_byte(base_address + index, data);
             index += 1;
         }
 
         // run CPU to do the jump
         cpu.run(&mut peripheral);
         assert_eq!(
-            peripheral.read(cpu.pc),
-            peripheral.read(base_address + (jump as u16) + 2)
+            peripheral.read_byte(cpu.pc),
+            peripheral.read_byte(base_address + (jump as u16) + 2)
         );
     }
 
@@ -2018,7 +2299,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         // first, fill memory with program
         let base_address: u16 = 0x0000;
@@ -2029,7 +2310,7 @@ mod cpu_tests {
         ];
         let mut index = 0;
         for data in program {
-            peripheral.write(base_address + index, data);
+            peripheral.write_byte(base_address + index, data);
             index += 1;
         }
 
@@ -2037,15 +2318,53 @@ mod cpu_tests {
         cpu.registers.f.zero = true;
         cpu.run(&mut peripheral);
         assert_eq!(
-            peripheral.read(cpu.pc),
-            peripheral.read(base_address + (jump as u16))
+            peripheral.read_byte(cpu.pc),
+            peripheral.read_byte(base_address + (jump as u16))
         );
 
         // reset CPU and run it with the flag, we don't do the jump
         cpu.registers.f.zero = false;
         cpu.pc = base_address;
         cpu.run(&mut peripheral);
-        assert_eq!(peripheral.read(cpu.pc), peripheral.read(base_address + 3));
+        assert_eq!(peripheral.read_byte(cpu.pc), peripheral.read_byte(base_address + 3));
+    }
+
+    #[test]
+    fn test_immediate_decoding() {
+        let mut cpu = Cpu::new();
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
+
+        // JR -2 jumps on itself
+        peripheral.write_byte(0xC100, 0x18);
+        peripheral.write_byte(0xC101, 0xFE);
+        cpu.pc = 0xC100;
+        assert_eq!(cpu.run(&mut peripheral), RUN_3_CYCLES);
+        assert_eq!(cpu.pc, 0xC100);
+        assert_eq!(cpu.run(&mut peripheral), RUN_3_CYCLES);
+        assert_eq!(cpu.pc, 0xC100);
+
+        // JP 0x1234, the immediate is little endian
+        peripheral.write_byte(0xC110, 0xC3);
+        peripheral.write_byte(0xC111, 0x34);
+        peripheral.write_byte(0xC112, 0x12);
+        cpu.pc = 0xC110;
+        assert_eq!(cpu.run(&mut peripheral), RUN_4_CYCLES);
+        assert_eq!(cpu.pc, 0x1234);
+
+        // LD (0xC000),SP stores the low byte first
+        peripheral.write_byte(0xC120, 0x08);
+        peripheral.write_byte(0xC121, 0x00);
+        peripheral.write_byte(0xC122, 0xC0);
+        cpu.pc = 0xC120;
+        cpu.sp = 0xBEEF;
+        assert_eq!(cpu.run(&mut peripheral), RUN_5_CYCLES);
+        assert_eq!(cpu.pc, 0xC123);
+        assert_eq!(peripheral.read_byte(0xC000), 0xEF);
+        assert_eq!(peripheral.read_byte(0xC001), 0xBE);
     }
 
     #[test]
@@ -2055,14 +2374,14 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         // first, fill memory with program
         let jump_inst: u8 = 0xE9;
         let program: [u8; 8] = [jump_inst, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88];
         let mut index = 0;
         for data in program {
-            peripheral.write(index, data);
+            peripheral.write_byte(index, data);
             index += 1;
         }
 
@@ -2070,7 +2389,7 @@ mod cpu_tests {
         cpu.registers.write_hl(jump);
         // run CPU to do the jump
         cpu.run(&mut peripheral);
-        assert_eq!(peripheral.read(cpu.pc), peripheral.read(jump));
+        assert_eq!(peripheral.read_byte(cpu.pc), peripheral.read_byte(jump));
     }
 
     #[test]
@@ -2080,7 +2399,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         let data: u16 = 0xA7D8;
         cpu.registers.write_hl(data);
@@ -2095,7 +2414,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         cpu.sp = 0x0010;
         let offset: u8 = 0x02;
@@ -2105,7 +2424,7 @@ mod cpu_tests {
         let program: [u8; 2] = [jump_inst, offset];
         let mut index = 0;
         for data in program {
-            peripheral.write(index + 0xC000, data);
+            peripheral.write_byte(index + 0xC000, data);
             index += 1;
         }
 
@@ -2121,7 +2440,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         // first, fill memory with program
         let base_address = 0xC000;
@@ -2132,7 +2451,7 @@ mod cpu_tests {
         let program: [u8; 3] = [jump_inst, low_address, high_address];
         let mut index = 0;
         for data in program {
-            peripheral.write(index + base_address, data);
+            peripheral.write_byte(index + base_address, data);
             index += 1;
         }
 
@@ -2140,10 +2459,10 @@ mod cpu_tests {
         cpu.pc = base_address;
         cpu.sp = 0x57A8;
         cpu.run(&mut peripheral);
-        assert_eq!((cpu.sp & 0x00FF) as u8, peripheral.read(address));
+        assert_eq!((cpu.sp & 0x00FF) as u8, peripheral.read_byte(address));
         assert_eq!(
             ((cpu.sp & 0xFF00) >> 8) as u8,
-            peripheral.read(address + 1)
+            peripheral.read_byte(address + 1)
         );
     }
 
@@ -2154,12 +2473,12 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         // initialize RAM memory
         let ram_data_address = 0xFFA5;
         let data = 0xF8;
-        peripheral.write(ram_data_address, data);
+        peripheral.write_byte(ram_data_address, data);
 
         // initialize ROM memory
         let base_program_address = 0xC000;
@@ -2167,14 +2486,14 @@ mod cpu_tests {
         let program: [u8; 2] = [jump_inst, (ram_data_address & 0x00FF) as u8];
         let mut index = 0;
         for data in program {
-            peripheral.write(index + base_program_address, data);
+            peripheral.write_byte(index + base_program_address, data);
             index += 1;
         }
 
         // set cpu and run it
         cpu.pc = base_program_address;
         cpu.run(&mut peripheral);
-        assert_eq!(cpu.registers.a, peripheral.read(ram_data_address));
+        assert_eq!(cpu.registers.a, peripheral.read_byte(ram_data_address));
     }
 
     #[test]
@@ -2184,12 +2503,12 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         // initialize RAM memory
         let ram_data_address = 0xFFA5;
         let data = 0xF8;
-        peripheral.write(ram_data_address, data);
+        peripheral.write_byte(ram_data_address, data);
 
         // initialize ROM memory
         let base_program_address = 0xC000;
@@ -2201,14 +2520,14 @@ mod cpu_tests {
         ];
         let mut index = 0;
         for data in program {
-            peripheral.write(index + base_program_address, data);
+            peripheral.write_byte(index + base_program_address, data);
             index += 1;
         }
 
         // set cpu and run it
         cpu.pc = base_program_address;
         cpu.run(&mut peripheral);
-        assert_eq!(cpu.registers.a, peripheral.read(ram_data_address));
+        assert_eq!(cpu.registers.a, peripheral.read_byte(ram_data_address));
     }
 
     #[test]
@@ -2218,12 +2537,12 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         // initialize RAM memory
         let ram_data_address = 0xFFA5;
         let data = 0xF8;
-        peripheral.write(ram_data_address, data);
+        peripheral.write_byte(ram_data_address, data);
 
         // initialize ROM memory
         let base_program_address = 0xC000;
@@ -2231,7 +2550,7 @@ mod cpu_tests {
         let program: [u8; 1] = [jump_inst];
         let mut index = 0;
         for data in program {
-            peripheral.write(index + base_program_address, data);
+            peripheral.write_byte(index + base_program_address, data);
             index += 1;
         }
 
@@ -2239,7 +2558,7 @@ mod cpu_tests {
         cpu.pc = base_program_address;
         cpu.registers.c = (ram_data_address & 0x00FF) as u8;
         cpu.run(&mut peripheral);
-        assert_eq!(cpu.registers.a, peripheral.read(ram_data_address));
+        assert_eq!(cpu.registers.a, peripheral.read_byte(ram_data_address));
     }
 
     #[test]
@@ -2249,7 +2568,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         // initialize RAM memory
         let ram_data_address = 0xFFA5;
@@ -2261,7 +2580,7 @@ mod cpu_tests {
         let program: [u8; 2] = [jump_inst, (ram_data_address & 0x00FF) as u8];
         let mut index = 0;
         for data in program {
-            peripheral.write(index + base_program_address, data);
+            peripheral.write_byte(index + base_program_address, data);
             index += 1;
         }
 
@@ -2269,7 +2588,7 @@ mod cpu_tests {
         cpu.pc = base_program_address;
         cpu.registers.a = data;
         cpu.run(&mut peripheral);
-        assert_eq!(cpu.registers.a, peripheral.read(ram_data_address));
+        assert_eq!(cpu.registers.a, peripheral.read_byte(ram_data_address));
     }
 
     #[test]
@@ -2279,7 +2598,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         // initialize RAM memory parameters
         let ram_address = 0xFFA5;
@@ -2290,11 +2609,11 @@ mod cpu_tests {
         cpu.registers.write_de(push_data);
         cpu.execute(PUSH(PopPushTarget::DE), &mut peripheral);
         assert_eq!(
-            peripheral.read(ram_address.wrapping_sub(1)),
+            peripheral.read_byte(ram_address.wrapping_sub(1)),
             ((push_data & 0xFF00) >> 8) as u8
         );
         assert_eq!(
-            peripheral.read(ram_address.wrapping_sub(2)),
+            peripheral.read_byte(ram_address.wrapping_sub(2)),
             (push_data & 0x00FF) as u8
         );
 
@@ -2310,7 +2629,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         // init parameters
         let data_to_add = 0x88;
@@ -2322,7 +2641,7 @@ mod cpu_tests {
         let program: [u8; 2] = [inst, data_to_add as u8];
         let mut index = 0;
         for data in program {
-            peripheral.write(index + base_program_address, data);
+            peripheral.write_byte(index + base_program_address, data);
             index += 1;
         }
 
@@ -2343,7 +2662,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         // initialize RAM memory parameters
         let ram_address = 0xFFA5;
@@ -2364,7 +2683,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         // test push instruction
         cpu.sp = 0xFFAF;
@@ -2379,12 +2698,15 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
+        // EI only requests the master enable, which is done by the run loop
         cpu.execute(EI, &mut peripheral);
-        assert_eq!(peripheral.nvic.interrupt_master_enable, true);
+        assert_eq!(cpu.ei_delay, true);
+        assert_eq!(peripheral.nvic.interrupt_master_enable, false);
 
         cpu.execute(DI, &mut peripheral);
+        assert_eq!(cpu.ei_delay, false);
         assert_eq!(peripheral.nvic.interrupt_master_enable, false);
 
         // initialize RAM memory parameters
@@ -2407,14 +2729,14 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         // first, fill memory with program
         let inst: u8 = 0xC4;
         let program: [u8; 8] = [inst, 0x00, 0x05, 0x44, 0x55, 0x66, 0x77, 0x88];
         let mut index = 0;
         for data in program {
-            peripheral.write(index + 0xc000, data);
+            peripheral.write_byte(index + 0xc000, data);
             index += 1;
         }
 
@@ -2426,6 +2748,92 @@ mod cpu_tests {
         assert_eq!(cpu.pc, 0x0500);
     }
 
+    #[test]
+    fn test_conditional_control_cycles() {
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
+
+        // opcode, zero flag, carry flag, taken, expected cycles
+        let table: [(u8, bool, bool, bool, u8); 32] = [
+            // JR cc, e8
+            (0x20, false, false, true, RUN_3_CYCLES),
+            (0x20, true, false, false, RUN_2_CYCLES),
+            (0x28, true, false, true, RUN_3_CYCLES),
+            (0x28, false, false, false, RUN_2_CYCLES),
+            (0x30, false, false, true, RUN_3_CYCLES),
+            (0x30, false, true, false, RUN_2_CYCLES),
+            (0x38, false, true, true, RUN_3_CYCLES),
+            (0x38, false, false, false, RUN_2_CYCLES),
+            // JP cc, a16
+            (0xC2, false, false, true, RUN_4_CYCLES),
+            (0xC2, true, false, false, RUN_3_CYCLES),
+            (0xCA, true, false, true, RUN_4_CYCLES),
+            (0xCA, false, false, false, RUN_3_CYCLES),
+            (0xD2, false, false, true, RUN_4_CYCLES),
+            (0xD2, false, true, false, RUN_3_CYCLES),
+            (0xDA, false, true, true, RUN_4_CYCLES),
+            (0xDA, false, false, false, RUN_3_CYCLES),
+            // CALL cc, a16
+            (0xC4, false, false, true, RUN_6_CYCLES),
+            (0xC4, true, false, false, RUN_3_CYCLES),
+            (0xCC, true, false, true, RUN_6_CYCLES),
+            (0xCC, false, false, false, RUN_3_CYCLES),
+            (0xD4, false, false, true, RUN_6_CYCLES),
+            (0xD4, false, true, false, RUN_3_CYCLES),
+            (0xDC, false, true, true, RUN_6_CYCLES),
+            (0xDC, false, false, false, RUN_3_CYCLES),
+            // RET cc
+            (0xC0, false, false, true, RUN_5_CYCLES),
+            (0xC0, true, false, false, RUN_2_CYCLES),
+            (0xC8, true, false, true, RUN_5_CYCLES),
+            (0xC8, false, false, false, RUN_2_CYCLES),
+            (0xD0, false, false, true, RUN_5_CYCLES),
+            (0xD0, false, true, false, RUN_2_CYCLES),
+            (0xD8, false, true, true, RUN_5_CYCLES),
+            (0xD8, false, false, false, RUN_2_CYCLES),
+        ];
+
+        for (opcode, zero, carry, taken, cycles) in table {
+            let mut cpu = Cpu::new();
+            cpu.registers.f.zero = zero;
+            cpu.registers.f.carry = carry;
+
+            // a RET returns to 0x0500 like the jumps and calls
+            cpu.sp = 0xFFA5;
+            cpu.push(0x0500, &mut peripheral);
+
+            // JR jumps 0x10 bytes after its operand
+            let (operand, target, length) = match opcode & 0xC7 {
+                0x00 => (0x10, 0xC012, 2),
+                0xC0 => (0x00, 0x0500, 1),
+                _ => (0x00, 0x0500, 3),
+            };
+            peripheral.write_byte(0xC000, opcode);
+            peripheral.write_byte(0xC001, operand);
+            peripheral.write_byte(0xC002, 0x05);
+
+            cpu.pc = 0xC000;
+            assert_eq!(cpu.run(&mut peripheral), cycles, "opcode 0x{:02X} taken {}", opcode, taken);
+            if taken {
+                assert_eq!(cpu.pc, target, "opcode 0x{:02X}", opcode);
+            } else {
+                assert_eq!(cpu.pc, 0xC000 + length, "opcode 0x{:02X}", opcode);
+            }
+        }
+
+        // unconditional RET
+        let mut cpu = Cpu::new();
+        cpu.sp = 0xFFA5;
+        cpu.push(0x0500, &mut peripheral);
+        peripheral.write_byte(0xC000, 0xC9);
+        cpu.pc = 0xC000;
+        assert_eq!(cpu.run(&mut peripheral), RUN_4_CYCLES);
+        assert_eq!(cpu.pc, 0x0500);
+    }
+
     #[test]
     fn test_nop_stop_halt() {
         let mut cpu = Cpu::new();
@@ -2433,7 +2841,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         // first, fill memory with program
         let nop_inst: u8 = 0x00;
@@ -2444,7 +2852,7 @@ mod cpu_tests {
         ];
         let mut index = 0;
         for data in program {
-            peripheral.write(index + 0xC000, data);
+            peripheral.write_byte(index + 0xC000, data);
             index += 1;
         }
 
@@ -2478,7 +2886,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         // init stack pointer
         cpu.sp = 0xFFA5;
@@ -2492,7 +2900,7 @@ mod cpu_tests {
         ];
         let mut index = 0;
         for data in program {
-            peripheral.write(index + 0xC000, data);
+            peripheral.write_byte(index + 0xC000, data);
             index += 1;
         }
 
@@ -2525,6 +2933,174 @@ mod cpu_tests {
         assert_eq!(cpu.pc, LCDSTAT_VECTOR);
     }
 
+    #[test]
+    fn test_undefined_opcodes() {
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
+
+        for opcode in [0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD] {
+            // NOP, undefined opcode, NOP
+            let mut cpu = Cpu::new();
+            cpu.sp = 0xFFA5;
+            peripheral.write_byte(0xC000, 0x00);
+            peripheral.write_byte(0xC001, opcode);
+            peripheral.write_byte(0xC002, 0x00);
+
+            cpu.pc = 0xC000;
+            cpu.run(&mut peripheral);
+            assert!(!cpu.is_locked());
+
+            // the cpu hangs on the opcode, an interrupt doesn't get it out
+            assert_eq!(cpu.run(&mut peripheral), RUN_1_CYCLE);
+            assert!(cpu.is_locked());
+            peripheral.nvic.master_enable(true);
+            peripheral.nvic.enable_interrupt(InterruptSources::TIMER, true);
+            peripheral.nvic.set_interrupt(InterruptSources::TIMER);
+            cpu.run(&mut peripheral);
+            assert!(cpu.is_locked());
+            assert_eq!(cpu.pc, 0xC001);
+            peripheral.nvic.set_it_flag(0x00);
+
+            // lenient running skips the opcode like a NOP
+            let mut cpu = Cpu::new();
+            cpu.lenient_opcodes = true;
+            cpu.pc = 0xC001;
+            assert_eq!(cpu.run(&mut peripheral), RUN_1_CYCLE);
+            assert!(!cpu.is_locked());
+            assert_eq!(cpu.pc, 0xC002);
+        }
+    }
+
+    #[test]
+    fn test_ei_delay() {
+        let mut cpu = Cpu::new();
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
+
+        // init stack pointer
+        cpu.sp = 0xFFA5;
+
+        // EI, NOP, NOP with a timer interrupt already pending
+        let program: [u8; 3] = [0xFB, 0x00, 0x00];
+        for (index, data) in program.iter().enumerate() {
+            peripheral.write_byte(0xC000 + index as u16, *data);
+        }
+        peripheral.nvic.enable_interrupt(InterruptSources::TIMER, true);
+        peripheral.nvic.set_interrupt(InterruptSources::TIMER);
+
+        // run EI, interrupts are not enabled yet
+        cpu.pc = 0xC000;
+        cpu.run(&mut peripheral);
+        assert_eq!(cpu.pc, 0xC001);
+        assert_eq!(peripheral.nvic.interrupt_master_enable, false);
+
+        // the instruction following EI is executed before the interrupt
+        cpu.run(&mut peripheral);
+        assert_eq!(cpu.pc, 0xC002);
+        assert_eq!(peripheral.nvic.interrupt_master_enable, true);
+
+        // then the interrupt is serviced, the return address is the second NOP
+        cpu.run(&mut peripheral);
+        assert_eq!(cpu.pc, TIMER_VECTOR);
+        assert_eq!(peripheral.read_byte(0xFFA3), 0x02);
+        assert_eq!(peripheral.read_byte(0xFFA4), 0xC0);
+
+        // EI followed by DI leaves interrupts disabled
+        peripheral.write_byte(0xC010, 0xFB);
+        peripheral.write_byte(0xC011, 0xF3);
+        peripheral.write_byte(0xC012, 0x00);
+        cpu.pc = 0xC010;
+        cpu.run(&mut peripheral);
+        cpu.run(&mut peripheral);
+        cpu.run(&mut peripheral);
+        assert_eq!(cpu.pc, 0xC013);
+        assert_eq!(peripheral.nvic.interrupt_master_enable, false);
+
+        // RETI enables interrupts immediately
+        cpu.execute(RETI, &mut peripheral);
+        assert_eq!(peripheral.nvic.interrupt_master_enable, true);
+    }
+
+    #[test]
+    fn test_interrupt_dispatch_cycles() {
+        let mut cpu = Cpu::new();
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
+
+        // init stack pointer
+        cpu.sp = 0xFFA5;
+
+        // NOP then HALT
+        peripheral.write_byte(0xC000, 0x00);
+        peripheral.write_byte(0xC001, 0x76);
+        peripheral.nvic.master_enable(true);
+        peripheral.nvic.enable_interrupt(InterruptSources::TIMER, true);
+
+        // interrupt dispatched while the cpu is running
+        cpu.pc = 0xC000;
+        peripheral.nvic.set_interrupt(InterruptSources::TIMER);
+        assert_eq!(cpu.run(&mut peripheral), RUN_5_CYCLES);
+        assert_eq!(cpu.pc, TIMER_VECTOR);
+        assert_eq!(peripheral.nvic.interrupt_master_enable, false);
+
+        // interrupt dispatched while the cpu is halted
+        cpu.pc = 0xC001;
+        peripheral.nvic.master_enable(true);
+        assert_eq!(cpu.run(&mut peripheral), RUN_1_CYCLE);
+        assert_eq!(cpu.run(&mut peripheral), RUN_1_CYCLE);
+        assert_eq!(cpu.pc, 0xC002);
+        peripheral.nvic.set_interrupt(InterruptSources::TIMER);
+        assert_eq!(cpu.run(&mut peripheral), RUN_6_CYCLES);
+        assert_eq!(cpu.pc, TIMER_VECTOR);
+    }
+
+    #[test]
+    fn test_halt_wakeup_without_ime() {
+        let mut cpu = Cpu::new();
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
+
+        // init stack pointer
+        cpu.sp = 0xFFA5;
+
+        // HALT then INC A with interrupts disabled
+        peripheral.write_byte(0xC000, 0x76);
+        peripheral.write_byte(0xC001, 0x3C);
+        peripheral.nvic.master_enable(false);
+        peripheral.nvic.enable_interrupt(InterruptSources::TIMER, true);
+
+        cpu.pc = 0xC000;
+        cpu.run(&mut peripheral);
+        assert!(cpu.is_halted());
+
+        // a requested interrupt which isn't enabled doesn't wake the cpu up
+        peripheral.nvic.set_interrupt(InterruptSources::VBLANK);
+        cpu.run(&mut peripheral);
+        assert!(cpu.is_halted());
+
+        // an enabled one wakes the cpu up without being serviced
+        peripheral.nvic.set_interrupt(InterruptSources::TIMER);
+        cpu.run(&mut peripheral);
+        assert!(!cpu.is_halted());
+        cpu.run(&mut peripheral);
+        assert_eq!(cpu.pc, 0xC002);
+        assert_eq!(cpu.registers.a, 0x01);
+        assert_eq!(cpu.sp, 0xFFA5);
+        assert!(peripheral.nvic.is_an_interrupt_pending());
+    }
+
     #[test]
     fn test_complement() {
         let mut cpu = Cpu::new();
@@ -2532,7 +3108,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         cpu.registers.a = 0x55;
         cpu.execute(Instruction::CPL, &mut peripheral);
@@ -2546,7 +3122,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         cpu.execute(Instruction::SCF, &mut peripheral);
         assert_eq!(cpu.registers.f.carry, true);
@@ -2563,7 +3139,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         cpu.registers.a = 0x0B;
         cpu.execute(Instruction::DAA, &mut peripheral);
@@ -2577,7 +3153,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         cpu.registers.a = 0xB5;
         cpu.execute(Instruction::RCA(Direction::LEFT), &mut peripheral);
@@ -2592,7 +3168,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         cpu.registers.a = 0xB5;
         cpu.registers.f.carry = true;
@@ -2601,6 +3177,45 @@ mod cpu_tests {
         assert_eq!(cpu.registers.a, 0xDA);
     }
 
+    #[test]
+    fn test_accumulator_rotate_flags() {
+        let mut cpu = Cpu::new();
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
+
+        // opcode, A and carry before, A and carry after: Z is cleared even when A ends at 0
+        let rotates: [(u8, u8, bool, u8, bool); 8] = [
+            (0x07, 0x00, false, 0x00, false), // RLCA
+            (0x0F, 0x00, false, 0x00, false), // RRCA
+            (0x17, 0x00, false, 0x00, false), // RLA
+            (0x1F, 0x00, false, 0x00, false), // RRA
+            (0x07, 0x80, false, 0x01, true),
+            (0x0F, 0x01, false, 0x80, true),
+            (0x17, 0x80, false, 0x00, true),
+            (0x1F, 0x01, false, 0x00, true),
+        ];
+
+        for (opcode, a, carry, expected_a, expected_carry) in rotates {
+            peripheral.write_byte(0xC000, opcode);
+            cpu.pc = 0xC000;
+            cpu.registers.a = a;
+            cpu.registers.f.carry = carry;
+            cpu.registers.f.zero = true;
+            cpu.registers.f.substraction = true;
+            cpu.registers.f.half_carry = true;
+
+            cpu.run(&mut peripheral);
+            assert_eq!(cpu.registers.a, expected_a, "opcode {:#04x}", opcode);
+            assert_eq!(cpu.registers.f.carry, expected_carry, "opcode {:#04x}", opcode);
+            assert!(!cpu.registers.f.zero, "opcode {:#04x}", opcode);
+            assert!(!cpu.registers.f.substraction, "opcode {:#04x}", opcode);
+            assert!(!cpu.registers.f.half_carry, "opcode {:#04x}", opcode);
+        }
+    }
+
     #[test]
     fn test_decode_long_instruction() {
         let mut cpu = Cpu::new();
@@ -2608,12 +3223,12 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         let program: [u8; 2] = [0xCB, 0x19];
         let mut index = 0;
         for data in program {
-            peripheral.write(index + 0xC000, data);
+            peripheral.write_byte(index + 0xC000, data);
             index += 1;
         }
 
@@ -2635,7 +3250,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         cpu.registers.b = 0xB5;
         cpu.execute(Instruction::RC(Direction::LEFT, IncDecTarget::B), &mut peripheral);
@@ -2650,14 +3265,14 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         let address = 0xC000;
         let data = 0xB5;
-        peripheral.write(address, data);
+        peripheral.write_byte(address, data);
         cpu.registers.write_hl(address);
         cpu.execute(Instruction::RC(Direction::LEFT, IncDecTarget::HL), &mut peripheral);
-        assert_eq!(peripheral.read(address), 0x6B);
+        assert_eq!(peripheral.read_byte(address), 0x6B);
     }
 
     #[test]
@@ -2667,7 +3282,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         cpu.registers.e = 0xB5;
         cpu.registers.f.carry = true;
@@ -2683,7 +3298,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         cpu.registers.d = 0xB5;
         cpu.execute(Instruction::SLA(IncDecTarget::D), &mut peripheral);
@@ -2698,7 +3313,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         cpu.registers.h = 0xB5;
         cpu.execute(Instruction::SRL(IncDecTarget::H), &mut peripheral);
@@ -2713,15 +3328,15 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         let address = 0xC000;
         let data = 0xB5;
-        peripheral.write(address, data);
+        peripheral.write_byte(address, data);
         cpu.registers.write_hl(address);
         cpu.pc = 0xC000;
         cpu.execute(Instruction::SRL(IncDecTarget::HL), &mut peripheral);
-        assert_eq!(peripheral.read(address), 0x5A);
+        assert_eq!(peripheral.read_byte(address), 0x5A);
     }
 
     #[test]
@@ -2731,7 +3346,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         cpu.registers.c = 0xB5;
         cpu.execute(Instruction::SRA(IncDecTarget::C), &mut peripheral);
@@ -2746,7 +3361,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         cpu.registers.l = 0xB5;
         cpu.execute(Instruction::SWAP(IncDecTarget::L), &mut peripheral);
@@ -2760,7 +3375,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         cpu.registers.h = 0xB5;
         cpu.execute(Instruction::BIT(BitTarget::BIT_1, IncDecTarget::H), &mut peripheral);
@@ -2786,7 +3401,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         cpu.registers.b = 0xB5;
         cpu.execute(Instruction::RESET_BIT(BitTarget::BIT_2, IncDecTarget::B), &mut peripheral);
@@ -2804,18 +3419,18 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         
         let address = 0xC000;
         let data = 0xB5;
-        peripheral.write(address, data);
+        peripheral.write_byte(address, data);
         cpu.registers.write_hl(address);
 
         cpu.pc = 0xC000;
         cpu.execute(Instruction::RESET_BIT(BitTarget::BIT_2, IncDecTarget::HL), &mut peripheral);
-        assert_eq!(peripheral.read(address), 0xB1);
+        assert_eq!(peripheral.read_byte(address), 0xB1);
 
         cpu.execute(Instruction::SET_BIT(BitTarget::BIT_3, IncDecTarget::HL), &mut peripheral);
-        assert_eq!(peripheral.read(address), 0xB9);
+        assert_eq!(peripheral.read_byte(address), 0xB9);
     }
 }
\ No newline at end of file
@@ -1,3 +1,7 @@
+use std::io::{Read, Result, Write};
+
+use crate::savestate::{SaveState, read_u8, write_u8};
+
 const ZERO_BIT: u8 = 7;
 const SUBSTRACTION_BIT: u8 = 6;
 const HALF_CARRY_BIT: u8 = 5;
@@ -110,6 +114,31 @@ impl Registers {
     }
 }
 
+impl SaveState for Registers {
+    fn save_state(&self, writer: &mut dyn Write) -> Result<()> {
+        write_u8(writer, self.a)?;
+        write_u8(writer, self.b)?;
+        write_u8(writer, self.c)?;
+        write_u8(writer, self.d)?;
+        write_u8(writer, self.e)?;
+        write_u8(writer, u8::from(self.f))?;
+        write_u8(writer, self.h)?;
+        write_u8(writer, self.l)
+    }
+
+    fn load_state(&mut self, reader: &mut dyn Read) -> Result<()> {
+        self.a = read_u8(reader)?;
+        self.b = read_u8(reader)?;
+        self.c = read_u8(reader)?;
+        self.d = read_u8(reader)?;
+        self.e = read_u8(reader)?;
+        self.f = FlagRegister::from(read_u8(reader)?);
+        self.h = read_u8(reader)?;
+        self.l = read_u8(reader)?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod registers_tests {
     use super::*;
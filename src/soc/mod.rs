@@ -2,41 +2,156 @@ pub mod peripheral;
 mod cpu;
 
 use cpu::Cpu;
-use peripheral::Peripheral;
+pub use peripheral::{Bus, MemoryFill};
 use crate::cartridge::Cartridge;
+use crate::savestate::{SaveState, SAVE_STATE_VERSION, read_u64, write_u64};
+
+use std::io::{Read, Result, Write};
 pub use peripheral::keypad::GameBoyKey;
 
 const CLOCK_TICK_PER_MACHINE_CYCLE: u8 = 4;
 
+// cpu speed multipliers, the peripherals are still run at the hardware speed
+pub const MIN_CPU_SPEED: f64 = 0.25;
+pub const MAX_CPU_SPEED: f64 = 4.0;
+// clock ticks of the longest instruction, the peripherals are run by steps of at most this length
+// when the cpu is slowed down
+const MAX_PERIPHERAL_STEP: u8 = 6 * CLOCK_TICK_PER_MACHINE_CYCLE;
+
 pub struct Soc {
     pub cpu: Cpu,
-    pub peripheral: Peripheral,
+    pub peripheral: Bus,
+    // clock ticks executed since power on
+    pub cycles: u64,
+    // cpu clock ticks run per peripheral clock tick, host setting which isn't part of the save states
+    // the cpu is desynchronized from the ppu and the timers, for slow homebrews or experiments
+    pub cpu_speed: f64,
+    // fraction of a peripheral clock tick left by the cpu speed
+    pending_ticks: f64,
 }
 
 impl Soc {
     pub fn new(boot_rom: &[u8], cartridge: Cartridge) -> Soc {
-        let mut peripheral = Peripheral::new(cartridge);
+        let mut peripheral = Bus::new(cartridge);
         peripheral.load_bootrom(boot_rom);
 
         Soc {
             cpu: Cpu::new(),
             peripheral: peripheral,
+            cycles: 0,
+            cpu_speed: 1.0,
+            pending_ticks: 0.0,
         }
     }
 
+    // power cycle the system with another cartridge, the host settings of the bus are kept
+    pub fn reset(&mut self, boot_rom: &[u8], cartridge: Cartridge) {
+        let mut soc = Soc::new(boot_rom, cartridge);
+        std::mem::swap(&mut soc.peripheral.keypad, &mut self.peripheral.keypad);
+        soc.peripheral.io_write_log = self.peripheral.io_write_log.take();
+        soc.peripheral.set_serial_output(self.peripheral.take_serial_output().is_some());
+        soc.peripheral.gpu.accuracy = self.peripheral.gpu.accuracy;
+        soc.cpu_speed = self.cpu_speed;
+        soc.cpu.lenient_opcodes = self.cpu.lenient_opcodes;
+
+        *self = soc;
+    }
+
+    // start the game directly, in the state the boot rom leaves the system
+    pub fn skip_boot(&mut self) {
+        let hardware_mode = self.peripheral.cartridge.hardware_mode();
+
+        self.cpu.skip_boot(hardware_mode);
+        self.peripheral.skip_boot(hardware_mode);
+    }
+
     pub fn run(&mut self) -> u8 {
-        let cycles = self.cpu.run(&mut self.peripheral) * CLOCK_TICK_PER_MACHINE_CYCLE;
+        if let Some(io_write_log) = self.peripheral.io_write_log.as_mut() {
+            io_write_log.pc = self.cpu.pc;
+        }
 
-        self.peripheral.run(cycles);
+        let cpu_cycles = self.cpu.run(&mut self.peripheral) * CLOCK_TICK_PER_MACHINE_CYCLE;
+        let cycles = self.peripheral_cycles(cpu_cycles);
+
+        let mut remaining_cycles = cycles;
+        while remaining_cycles > 0 {
+            let step_cycles = remaining_cycles.min(MAX_PERIPHERAL_STEP);
+            self.peripheral.run(step_cycles);
+            remaining_cycles -= step_cycles;
+        }
+
+        if let Some(io_write_log) = self.peripheral.io_write_log.as_mut() {
+            for io_write in io_write_log.writes.drain(..) {
+                println!("io: {}", io_write);
+            }
+        }
+
+        self.cycles += cycles as u64;
 
         cycles
     }
 
-    pub fn get_frame_buffer(&self, pixel_index: usize) -> u8 {
-        self.peripheral.gpu.frame_buffer[pixel_index]
+    // clock ticks run by the peripherals while the cpu runs the given ones, the emulated time
+    // follows the peripherals
+    fn peripheral_cycles(&mut self, cpu_cycles: u8) -> u8 {
+        if self.cpu_speed == 1.0 {
+            return cpu_cycles;
+        }
+
+        self.pending_ticks += cpu_cycles as f64 / self.cpu_speed.clamp(MIN_CPU_SPEED, MAX_CPU_SPEED);
+        let cycles = self.pending_ticks as u8;
+        self.pending_ticks -= cycles as f64;
+
+        cycles
     }
 
     pub fn set_key(&mut self, key: GameBoyKey, value: bool) {
         self.peripheral.keypad.set(key, value);
     }
-}
\ No newline at end of file
+
+    pub fn set_key_min_hold_frames(&mut self, key: GameBoyKey, frames: u8) {
+        self.peripheral.keypad.set_min_hold_frames(key, frames);
+    }
+
+    pub fn set_key_turbo(&mut self, key: GameBoyKey, frames: u8) {
+        self.peripheral.keypad.set_turbo(key, frames);
+    }
+
+    pub fn fill_memory(&mut self, fill: MemoryFill) {
+        self.peripheral.fill_memory(fill);
+    }
+
+    // an enabled interrupt can get the cpu out of its current loop, or wake it up when halted
+    pub fn can_exit_loop(&self) -> bool {
+        let interrupts_enabled = (self.peripheral.nvic.interrupt_enable & 0x1F) != 0;
+
+        if self.cpu.is_halted() {
+            interrupts_enabled
+        } else {
+            interrupts_enabled && self.peripheral.nvic.interrupt_master_enable
+        }
+    }
+
+    pub fn end_of_frame(&mut self) {
+        self.peripheral.keypad.end_of_frame();
+    }
+}
+
+impl SaveState for Soc {
+    fn save_state(&self, writer: &mut dyn Write) -> Result<()> {
+        self.cpu.save_state(writer)?;
+        self.peripheral.save_state(writer)?;
+        write_u64(writer, self.cycles)
+    }
+
+    fn load_state(&mut self, reader: &mut dyn Read) -> Result<()> {
+        self.load_state_version(reader, SAVE_STATE_VERSION)
+    }
+
+    fn load_state_version(&mut self, reader: &mut dyn Read, version: u8) -> Result<()> {
+        self.cpu.load_state(reader)?;
+        self.peripheral.load_state_version(reader, version)?;
+        self.cycles = read_u64(reader)?;
+        Ok(())
+    }
+}
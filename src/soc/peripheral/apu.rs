@@ -0,0 +1,213 @@
+use std::io::{Read, Result, Write};
+
+use crate::savestate::{SaveState, read_bool, read_bytes, read_u8, write_bool, write_bytes, write_u8};
+
+pub const APU_REGISTERS_BEGIN: u16 = 0xFF10;
+pub const APU_REGISTERS_END: u16 = 0xFF3F;
+const APU_REGISTERS_SIZE: usize = (APU_REGISTERS_END - APU_REGISTERS_BEGIN + 1) as usize;
+
+// NR52, sound on/off
+const SOUND_CONTROL_REGISTER: u16 = 0xFF26;
+const SOUND_ENABLE: u8 = 0x80;
+
+// the wave pattern RAM isn't affected by the sound on/off
+const WAVE_PATTERN_RAM_BEGIN: u16 = 0xFF30;
+
+// NRx4 registers, writing the trigger bit starts the channel
+const CHANNELS_CONTROL_REGISTERS: [u16; 4] = [0xFF14, 0xFF19, 0xFF1E, 0xFF23];
+const CHANNEL_TRIGGER: u8 = 0x80;
+
+// the DAC of a channel is on if one of these bits is set: volume and envelope
+// direction in NR12, NR22 and NR42, NR30 bit 7 for the wave channel
+const CHANNELS_DAC_REGISTERS: [(u16, u8); 4] = [(0xFF12, 0xF8), (0xFF17, 0xF8), (0xFF1A, 0x80), (0xFF21, 0xF8)];
+
+// mono output samples per second, the main clock runs at 4194304 Hz
+pub const SAMPLE_RATE: usize = 44100;
+const CLOCK_FREQUENCY: usize = 4194304;
+// samples not taken by the host are dropped past one second
+const MAX_PENDING_SAMPLES: usize = SAMPLE_RATE;
+
+// sound registers only, no sound is generated yet: the output is silent
+// they are stored so that games and test roms read back what they wrote,
+// the write only bits are masked by the I/O dispatch table
+pub struct Apu {
+    registers: [u8; APU_REGISTERS_SIZE],
+    enabled: bool,
+    // NR52 status bits, a triggered channel runs until its DAC is turned off
+    channels_on: u8,
+    // clock ticks since the last sample, scaled by the sample rate
+    sample_cycles: usize,
+    // samples produced since they were last taken, in the -1.0 to 1.0 range
+    samples: Vec<f32>,
+    // constant level output while the channels aren't generated, only changed by the tests
+    output_level: f32,
+}
+
+impl Apu {
+    pub fn new() -> Apu {
+        Apu {
+            registers: [0; APU_REGISTERS_SIZE],
+            enabled: false,
+            channels_on: 0,
+            sample_cycles: 0,
+            samples: Vec::new(),
+            output_level: 0.0,
+        }
+    }
+
+    pub fn run(&mut self, runned_cycles: u8) {
+        self.sample_cycles += runned_cycles as usize * SAMPLE_RATE;
+
+        while self.sample_cycles >= CLOCK_FREQUENCY {
+            self.sample_cycles -= CLOCK_FREQUENCY;
+            if self.samples.len() < MAX_PENDING_SAMPLES {
+                self.samples.push(self.output());
+            }
+        }
+    }
+
+    // samples produced since the last call
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    // the channels aren't generated yet, the output is silent outside of the tests
+    fn output(&self) -> f32 {
+        self.output_level
+    }
+
+    // drive a known level through the output path
+    #[cfg(test)]
+    pub fn set_output_level(&mut self, level: f32) {
+        self.output_level = level;
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            SOUND_CONTROL_REGISTER => if self.enabled { SOUND_ENABLE | self.channels_on } else { 0x00 },
+            _ => self.registers[(address - APU_REGISTERS_BEGIN) as usize],
+        }
+    }
+
+    pub fn write(&mut self, address: u16, data: u8) {
+        match address {
+            SOUND_CONTROL_REGISTER => {
+                self.enabled = (data & SOUND_ENABLE) != 0;
+
+                // turning the sound off clears all the sound registers
+                if !self.enabled {
+                    self.channels_on = 0;
+                    let registers_end = (WAVE_PATTERN_RAM_BEGIN - APU_REGISTERS_BEGIN) as usize;
+                    self.registers[..registers_end].iter_mut().for_each(|register| *register = 0);
+                }
+            }
+            WAVE_PATTERN_RAM_BEGIN..=APU_REGISTERS_END => {
+                self.registers[(address - APU_REGISTERS_BEGIN) as usize] = data;
+            }
+            // the sound registers can't be written while the sound is off
+            _ => if self.enabled {
+                self.registers[(address - APU_REGISTERS_BEGIN) as usize] = data;
+                self.update_channels_status(address, data);
+            }
+        }
+    }
+
+    fn update_channels_status(&mut self, address: u16, data: u8) {
+        let channels = CHANNELS_CONTROL_REGISTERS.iter().zip(CHANNELS_DAC_REGISTERS.iter());
+
+        for (channel, (control_register, (dac_register, dac_bits))) in channels.enumerate() {
+            let dac_on = (self.read(*dac_register) & dac_bits) != 0;
+
+            if !dac_on {
+                self.channels_on &= !(1 << channel);
+            } else if address == *control_register && (data & CHANNEL_TRIGGER) != 0 {
+                self.channels_on |= 1 << channel;
+            }
+        }
+    }
+}
+
+impl SaveState for Apu {
+    fn save_state(&self, writer: &mut dyn Write) -> Result<()> {
+        write_bytes(writer, &self.registers)?;
+        write_bool(writer, self.enabled)?;
+        write_u8(writer, self.channels_on)
+    }
+
+    fn load_state(&mut self, reader: &mut dyn Read) -> Result<()> {
+        read_bytes(reader, &mut self.registers)?;
+        self.enabled = read_bool(reader)?;
+        self.channels_on = read_u8(reader)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod apu_tests {
+    use super::*;
+
+    #[test]
+    fn test_sound_enable() {
+        let mut apu = Apu::new();
+
+        // registers are ignored while the sound is off
+        apu.write(0xFF12, 0xF3);
+        assert_eq!(apu.read(0xFF12), 0x00);
+
+        apu.write(SOUND_CONTROL_REGISTER, 0xFF);
+        assert_eq!(apu.read(SOUND_CONTROL_REGISTER), 0x80);
+        apu.write(0xFF12, 0xF3);
+        assert_eq!(apu.read(0xFF12), 0xF3);
+
+        // turning the sound off clears the registers but not the wave pattern RAM
+        apu.write(0xFF30, 0x12);
+        apu.write(SOUND_CONTROL_REGISTER, 0x00);
+        assert_eq!(apu.read(0xFF12), 0x00);
+        assert_eq!(apu.read(0xFF30), 0x12);
+    }
+
+    #[test]
+    fn test_channels_status() {
+        let mut apu = Apu::new();
+        apu.write(SOUND_CONTROL_REGISTER, 0x80);
+
+        // a channel with its DAC off can't be triggered
+        apu.write(0xFF19, 0x80);
+        assert_eq!(apu.read(SOUND_CONTROL_REGISTER), 0x80);
+
+        // channel 1 is triggered, then stopped by turning its DAC off
+        apu.write(0xFF12, 0xF3);
+        apu.write(0xFF14, 0x87);
+        assert_eq!(apu.read(SOUND_CONTROL_REGISTER), 0x81);
+        apu.write(0xFF12, 0x00);
+        assert_eq!(apu.read(SOUND_CONTROL_REGISTER), 0x80);
+
+        // channel 3 DAC is NR30 bit 7
+        apu.write(0xFF1A, 0x80);
+        apu.write(0xFF1E, 0x80);
+        assert_eq!(apu.read(SOUND_CONTROL_REGISTER), 0x84);
+
+        // turning the sound off stops every channel
+        apu.write(SOUND_CONTROL_REGISTER, 0x00);
+        apu.write(SOUND_CONTROL_REGISTER, 0x80);
+        assert_eq!(apu.read(SOUND_CONTROL_REGISTER), 0x80);
+    }
+
+    #[test]
+    fn test_samples() {
+        let mut apu = Apu::new();
+
+        // one second of clock ticks gives one second of samples
+        for _ in 0..CLOCK_FREQUENCY / 4 {
+            apu.run(4);
+        }
+        assert_eq!(apu.take_samples().len(), SAMPLE_RATE);
+        assert!(apu.take_samples().is_empty());
+
+        // the samples not taken are dropped past one second
+        for _ in 0..CLOCK_FREQUENCY / 2 {
+            apu.run(4);
+        }
+        assert_eq!(apu.take_samples().len(), MAX_PENDING_SAMPLES);
+    }
+}
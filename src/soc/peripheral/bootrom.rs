@@ -1,4 +1,80 @@
+use std::io::{Read, Result, Write};
+
 use crate::soc::peripheral::BOOT_ROM_SIZE;
+use crate::savestate::{SaveState, read_bool, write_bool};
+
+// boot rom written for the emulator, released with it under the GPL, so that games run without
+// the Nintendo one. It skips the logo: it clears VRAM, sets up the audio, the background palette
+// and the LCD, selects the CGB mode for the games flagged as CGB compatible, leaves the registers
+// as the DMG or the CGB boot rom does, then disables itself from its last 2 bytes, the cpu goes on
+// at 0x0100 in the cartridge
+const BUILTIN_BOOT_CODE: [u8; 0x59] = [
+    0x31, 0xFE, 0xFF,       // 0x00: LD SP,$FFFE
+    0xAF,                   // 0x03: XOR A
+    0x21, 0xFF, 0x9F,       // 0x04: LD HL,$9FFF
+    0x32,                   // 0x07: LD (HL-),A         clear VRAM
+    0xCB, 0x7C,             // 0x08: BIT 7,H
+    0x20, 0xFB,             // 0x0A: JR NZ,$0007
+    0x21, 0x26, 0xFF,       // 0x0C: LD HL,$FF26
+    0x0E, 0x11,             // 0x0F: LD C,$11
+    0x3E, 0x80,             // 0x11: LD A,$80
+    0x32,                   // 0x13: LD (HL-),A         NR52, the APU is on
+    0xE2,                   // 0x14: LD ($FF00+C),A     NR11
+    0x0C,                   // 0x15: INC C
+    0x3E, 0xF3,             // 0x16: LD A,$F3
+    0xE2,                   // 0x18: LD ($FF00+C),A     NR12
+    0x32,                   // 0x19: LD (HL-),A         NR51
+    0x3E, 0x77,             // 0x1A: LD A,$77
+    0x77,                   // 0x1C: LD (HL),A          NR50
+    0x3E, 0xFC,             // 0x1D: LD A,$FC
+    0xE0, 0x47,             // 0x1F: LDH ($47),A        BGP
+    0x3E, 0x91,             // 0x21: LD A,$91
+    0xE0, 0x40,             // 0x23: LDH ($40),A        LCDC, the LCD is on
+    0xFA, 0x43, 0x01,       // 0x25: LD A,($0143)       CGB flag, 0x80 or 0xC0
+    0xE6, 0xBF,             // 0x28: AND $BF
+    0xFE, 0x80,             // 0x2A: CP $80
+    0x20, 0x1A,             // 0x2C: JR NZ,$0048
+    0xE0, 0x4C,             // 0x2E: LDH ($4C),A        KEY0, CGB mode
+    0x3E, 0x7F,             // 0x30: LD A,$7F
+    0xE0, 0x02,             // 0x32: LDH ($02),A        SC
+    0xAF,                   // 0x34: XOR A
+    0xE0, 0x6C,             // 0x35: LDH ($6C),A        OPRI, OAM order
+    0x21, 0x80, 0x11,       // 0x37: LD HL,$1180        CGB registers
+    0xE5,                   // 0x3A: PUSH HL
+    0xF1,                   // 0x3B: POP AF
+    0x01, 0x00, 0x00,       // 0x3C: LD BC,$0000
+    0x11, 0x56, 0xFF,       // 0x3F: LD DE,$FF56
+    0x21, 0x0D, 0x00,       // 0x42: LD HL,$000D
+    0xC3, 0xFE, 0x00,       // 0x45: JP $00FE
+    0x21, 0xB0, 0x01,       // 0x48: LD HL,$01B0        DMG registers
+    0xE5,                   // 0x4B: PUSH HL
+    0xF1,                   // 0x4C: POP AF
+    0x01, 0x13, 0x00,       // 0x4D: LD BC,$0013
+    0x11, 0xD8, 0x00,       // 0x50: LD DE,$00D8
+    0x21, 0x4D, 0x01,       // 0x53: LD HL,$014D
+    0xC3, 0xFE, 0x00,       // 0x56: JP $00FE
+];
+
+// the boot rom is disabled by the write to 0xFF50 ending at 0x00FF
+const BUILTIN_BOOT_HAND_OFF: [u8; 2] = [
+    0xE0, 0x50,             // 0xFE: LDH ($50),A
+];
+
+pub const BUILTIN_BOOT_ROM: [u8; BOOT_ROM_SIZE as usize] = builtin_boot_rom();
+
+const fn builtin_boot_rom() -> [u8; BOOT_ROM_SIZE as usize] {
+    let mut rom = [0x00; BOOT_ROM_SIZE as usize];
+
+    let mut index = 0;
+    while index < BUILTIN_BOOT_CODE.len() {
+        rom[index] = BUILTIN_BOOT_CODE[index];
+        index += 1;
+    }
+
+    rom[BOOT_ROM_SIZE as usize - 2] = BUILTIN_BOOT_HAND_OFF[0];
+    rom[BOOT_ROM_SIZE as usize - 1] = BUILTIN_BOOT_HAND_OFF[1];
+    rom
+}
 
 pub struct BootRom {
     rom: [u8; BOOT_ROM_SIZE as usize],
@@ -32,4 +108,16 @@ impl BootRom {
     pub fn get_state(&self) -> bool {
         self.enabled
     }
-}
\ No newline at end of file
+}
+
+// the boot rom content is loaded from a file, only its mapping is saved
+impl SaveState for BootRom {
+    fn save_state(&self, writer: &mut dyn Write) -> Result<()> {
+        write_bool(writer, self.enabled)
+    }
+
+    fn load_state(&mut self, reader: &mut dyn Read) -> Result<()> {
+        self.enabled = read_bool(reader)?;
+        Ok(())
+    }
+}
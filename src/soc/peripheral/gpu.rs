@@ -1,15 +1,45 @@
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::io::{Read, Result, Write};
+
 use crate::soc::peripheral::{VRAM_SIZE, OAM_SIZE};
 use crate::soc::peripheral::nvic::{Nvic, InterruptSources};
+use crate::savestate::{SaveState, SAVE_STATE_VERSION, invalid_data, read_bool, read_bytes, read_u8, read_u16, write_bool, write_bytes, write_u8, write_u16};
 
 const HORIZONTAL_BLANK_CYCLES: u16 = 204;
-const VERTICAL_BLANK_CYCLES: u16 = 4560;
 const OAM_SCAN_CYCLES: u16 = 80;
 const DRAW_PIXEL_CYCLES: u16 = 172;
 const ONE_LINE_CYCLES: u16 = HORIZONTAL_BLANK_CYCLES + OAM_SCAN_CYCLES + DRAW_PIXEL_CYCLES;
+// the vertical blank lasts 10 lines, LY runs from 144 to 153 meanwhile
+const VERTICAL_BLANK_LINES: usize = 10;
+const LAST_LINE: u8 = (SCREEN_HEIGHT + VERTICAL_BLANK_LINES - 1) as u8;
+// the pixel fetcher reads the tile number then the 2 bytes of the tile row, 2 dots each
+const FETCH_TILE_DOTS: u16 = 6;
+const FETCH_TILE_NUMBER_DOT: u16 = 2;
+// a sprite fetch stalls the draw pixel mode for 6 dots, and up to 5 more dots while the
+// background fetcher completes the tile the leftmost sprite pixel lies in
+const SPRITE_FETCH_DOTS: u16 = 6;
+const SPRITE_FETCH_MAX_WAIT_DOTS: u16 = 5;
+// sprites from this X are off the right edge of the screen, they're never fetched
+const SPRITE_X_HIDDEN: u8 = 168;
+// the LCD enters the vertical blank once per frame of 154 lines, a ppu which doesn't within 2
+// frames has stopped advancing its modes. The watchdog only runs in debug builds
+#[cfg(debug_assertions)]
+const VBLANK_WATCHDOG_CYCLES: u32 = 2 * 154 * ONE_LINE_CYCLES as u32;
 
 pub const SCREEN_WIDTH: usize = 160;
 pub const SCREEN_HEIGHT: usize = 144;
 
+// the background is a 32x32 tiles map, the screen shows a part of it
+pub const BACKGROUND_MAP_SIZE: usize = 256;
+
+// SGB border size, a frame of this size has room for the screen and its border
+pub const SGB_FRAME_WIDTH: usize = 256;
+pub const SGB_FRAME_HEIGHT: usize = 224;
+const RGBA_BYTES_PER_PIXEL: usize = 4;
+const ALPHA_OPAQUE: u8 = 0xFF;
+
 const TILE_ROW_SIZE_IN_PIXEL: u8 = 8;
 const TILE_SIZE_IN_BYTES: u16 = 16;
 const TILE_MAP_SIZE: u8 = 32;
@@ -27,8 +57,17 @@ const SPRITE_TILE_INDEX_OFFSET: u16 = 2;
 const SPRITE_ATTRIBUTES_OFFSET: u16 = 3;
 const NB_SRITES_TO_DISPLAY_MAX: u16 = 10;
 const PIXEL_TRANSPARENT: u8 = 0x00;
+// CGB background attribute, stored in VRAM bank 1 at the address of the tile in its map
+pub const BG_ATTRIBUTE_PRIORITY: u8 = 0x80;
+
+// palette used to draw each pixel of the frame, the host may map each of them to its own colors
+pub const PALETTE_BG: u8 = 0;
+pub const PALETTE_OBJ_0: u8 = 1;
+pub const PALETTE_OBJ_1: u8 = 2;
 
 const WINDOW_X_OFFSET: u8 = 7;
+// from this WX the window is off screen
+const WINDOW_X_HIDDEN: u8 = 166;
 
 #[allow(non_camel_case_types)]
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -39,6 +78,50 @@ pub enum PixelColor {
     BLACK = 0,
 }
 
+impl PixelColor {
+    // map a 2 bits color index (0-3) to its grayscale color
+    pub fn from_index(index: u8) -> PixelColor {
+        match index & 0x03 {
+            0 => PixelColor::WHITE,
+            1 => PixelColor::LIGHT_GRAY,
+            2 => PixelColor::DARK_GRAY,
+            _ => PixelColor::BLACK,
+        }
+    }
+
+    // get the 2 bits color index (0-3) of the grayscale color
+    pub fn to_index(self) -> u8 {
+        match self {
+            PixelColor::WHITE => 0,
+            PixelColor::LIGHT_GRAY => 1,
+            PixelColor::DARK_GRAY => 2,
+            PixelColor::BLACK => 3,
+        }
+    }
+}
+
+// a PixelColor converts into its grayscale shade, not into its color index
+impl From<PixelColor> for u8 {
+    fn from(color: PixelColor) -> u8 {
+        color as u8
+    }
+}
+
+// only the 4 grayscale shades can be converted back, the byte is returned otherwise
+impl TryFrom<u8> for PixelColor {
+    type Error = u8;
+
+    fn try_from(shade: u8) -> std::result::Result<PixelColor, u8> {
+        match shade {
+            255 => Ok(PixelColor::WHITE),
+            192 => Ok(PixelColor::LIGHT_GRAY),
+            96 => Ok(PixelColor::DARK_GRAY),
+            0 => Ok(PixelColor::BLACK),
+            _ => Err(shade),
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Palette{
     pub color_0: PixelColor, 
@@ -56,18 +139,31 @@ impl Palette {
             color_3: PixelColor::BLACK,
         }
     }
+
+    // get the color mapped to a 2 bits color index (0-3)
+    pub fn get_color(&self, index: u8) -> PixelColor {
+        match index & 0x03 {
+            0 => self.color_0,
+            1 => self.color_1,
+            2 => self.color_2,
+            _ => self.color_3,
+        }
+    }
+
+    // BGP / OBP0 / OBP1 register value, 2 bits per color
+    pub fn to_byte(self) -> u8 {
+        self.color_0.to_index()
+            | (self.color_1.to_index() << 2)
+            | (self.color_2.to_index() << 4)
+            | (self.color_3.to_index() << 6)
+    }
 }
 
 macro_rules! set_palette {
     ($self:ident.$palette:ident.$palette_index:ident, $data: ident, $color_index: expr) => {{
         let value = ($data >> ($color_index * 2)) & 0x03;
 
-        match value {
-            0 => $self.$palette.$palette_index = PixelColor::WHITE,
-            1 => $self.$palette.$palette_index = PixelColor::LIGHT_GRAY,
-            2 => $self.$palette.$palette_index = PixelColor::DARK_GRAY,
-            _ => $self.$palette.$palette_index = PixelColor::BLACK,
-        }
+        $self.$palette.$palette_index = PixelColor::from_index(value);
     }};
 }
 
@@ -94,6 +190,25 @@ pub enum ObjectSize {
     OS8X16,
 }
 
+// OPRI: the overlapping sprites are drawn by OAM order on CGB, the first one on top, and by X
+// coordinate on DMG, the leftmost one on top then the first one at the same X
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ObjectPriority {
+    OamIndex,
+    XCoordinate,
+}
+
+// the fast path draws a whole line at the end of the draw pixel mode from the final register
+// values, the accurate one runs a pixel FIFO dot by dot so that the registers written during the
+// mode, like SCX or the palettes, only affect the following pixels and the length of the mode
+// follows the window. Both draw the same frames when the registers aren't modified while a line
+// is drawn, and the sprites and the fine scroll lengthen the draw pixel mode of both
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PpuAccuracy {
+    Fast,
+    Accurate,
+}
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum GpuMode {
     HorizontalBlank,
@@ -109,6 +224,13 @@ pub struct Gpu {
     // OAM is a memory area used to store sprites attributes
     // Sprites data are stored in VRAM memory $8000-8FFF
     oam: [u8; OAM_SIZE as usize],
+    // CGB second VRAM bank, only the background attributes of its tile maps are drawn
+    pub vram_bank_1: [u8; VRAM_SIZE as usize],
+    // 0xFF4F: VRAM bank mapped for the cpu, always bank 0 on DMG
+    vram_bank: u8,
+    // the boot rom has selected the CGB mode through KEY0: the background attributes are used
+    // and LCDC bit 0 becomes the background master priority instead of disabling it
+    pub cgb_mode: bool,
 
     // ****** LCD DISPLAY PARAMETERS *******
     // 0xFF40: LCD control register
@@ -152,15 +274,75 @@ pub struct Gpu {
     window_x_offset: u8,
     window_y_offset: u8,
 
+    // 0xFF6C: object priority mode, the CGB boot rom selects the OAM order for the CGB games,
+    // the DMG always draws by X coordinate
+    pub object_priority: ObjectPriority,
+
     // ****** GPU INTERNAL PARAMETERS *******
     cycles: u16,
     new_mode_flag: bool,
-    vblank_line: u16,
+    // STAT interrupt line, the sources enabled in STAT ored together
+    // the interrupt is requested on its rising edge only, so a source becoming active while
+    // another one keeps the line high doesn't request a new interrupt
+    stat_line: bool,
+    // the window has been drawn on the current line
     window_flag: bool,
+    // window line to draw, only advanced on the lines where the window is drawn
+    // so the window resumes where it stopped when it's disabled for some lines
     window_line_counter: u8,
+    // LY has matched WY in this frame, the window can be drawn from this line
+    window_y_triggered: bool,
+    // rendering path, host setting which isn't part of the save states
+    pub accuracy: PpuAccuracy,
+    // OAM addresses of the sprites of the current line found by the OAM scan, from the lowest to
+    // the highest priority
+    line_sprites: Vec<u16>,
+    // an OAM DMA transfer is running, set by the bus before running the gpu
+    pub oam_dma_active: bool,
+    // the OAM scan of the current line overlapped an OAM DMA transfer, no sprite is found
+    oam_scan_blocked: bool,
+    // length of the last draw pixel mode, the horizontal blank lasts until the end of the line
+    draw_pixel_cycles: u16,
+    // cycles run with the LCD on since the last vertical blank, checked by the watchdog
+    #[cfg(debug_assertions)]
+    cycles_since_vblank: u32,
+
+    // ****** PIXEL FIFO, ACCURATE PATH ONLY *******
+    // next pixel of the current line shifted out of the FIFO
+    line_x: u8,
+    // dots run in the draw pixel mode of the current line
+    line_dot: u16,
+    // background or window color indexes waiting to be shifted out, with their CGB priority
+    bg_fifo: VecDeque<(u8, bool)>,
+    // pixels of the first tile discarded for the fine scroll, SCX & 7
+    discarded_pixels: u8,
+    // dots left of the dummy fetch done at the start of each line
+    fetcher_stall: u16,
+    // dots spent fetching the current tile
+    fetcher_dot: u16,
+    // tiles fetched on the current line, from the left of the background or of the window
+    fetcher_x: u8,
+    // the window has replaced the background on the current line
+    fetcher_window: bool,
+    // tile being fetched: tile memory index and line in the tile map
+    fetched_tile_index: u8,
+    fetched_tile_y: u8,
+    fetched_tile_priority: bool,
+    // sprites left to fetch on the current line: screen X of their first pixel and fetch dots
+    sprite_fetches: VecDeque<(i16, u16)>,
+    // dots left of the running sprite fetch, the FIFO and the fetcher are stalled meanwhile
+    sprite_stall: u16,
 
     // ****** OUTPUT FRAME BUFFER *******
     pub frame_buffer: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
+    // frames completed since power on, incremented when entering the vblank
+    // the host presents a frame when it changes, it isn't part of the save states
+    pub frame_count: u64,
+    // background color indexes (0-3) of the current frame before palette mapping
+    // needed to resolve the priority between sprites and background
+    pub index_buffer: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
+    // palette the pixels of the frame buffer have been drawn with
+    pub palette_buffer: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
 }
 
 impl Gpu {
@@ -168,6 +350,9 @@ impl Gpu {
         Gpu {
             vram: [0xFF; VRAM_SIZE as usize],
             oam: [0xFF; OAM_SIZE as usize],
+            vram_bank_1: [0xFF; VRAM_SIZE as usize],
+            vram_bank: 0,
+            cgb_mode: false,
 
             lcd_display_enabled: false,
             window_tile_map_area: TileMapArea::X9800,
@@ -198,13 +383,40 @@ impl Gpu {
             window_x_offset: 0,
             window_y_offset: 0,
 
+            object_priority: ObjectPriority::XCoordinate,
+
             cycles: 0,
             new_mode_flag: true,
-            vblank_line: 0,
+            stat_line: false,
             window_flag: false,
             window_line_counter: 0,
+            window_y_triggered: false,
+            accuracy: PpuAccuracy::Fast,
+            line_sprites: Vec::with_capacity(NB_SRITES_TO_DISPLAY_MAX as usize),
+            oam_dma_active: false,
+            oam_scan_blocked: false,
+            draw_pixel_cycles: DRAW_PIXEL_CYCLES,
+            #[cfg(debug_assertions)]
+            cycles_since_vblank: 0,
+
+            line_x: 0,
+            line_dot: 0,
+            bg_fifo: VecDeque::with_capacity(2 * TILE_ROW_SIZE_IN_PIXEL as usize),
+            discarded_pixels: 0,
+            fetcher_stall: 0,
+            fetcher_dot: 0,
+            fetcher_x: 0,
+            fetcher_window: false,
+            fetched_tile_index: 0,
+            fetched_tile_y: 0,
+            fetched_tile_priority: false,
+            sprite_fetches: VecDeque::with_capacity(NB_SRITES_TO_DISPLAY_MAX as usize),
+            sprite_stall: 0,
 
             frame_buffer: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame_count: 0,
+            index_buffer: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            palette_buffer: [PALETTE_BG; SCREEN_WIDTH * SCREEN_HEIGHT],
         }
     }
 
@@ -212,45 +424,104 @@ impl Gpu {
         self.vram[address as usize]
     }
 
+    // bank 0 writes set up the rendering tests, the cpu writes through the mapped bank
+    #[cfg_attr(not(test), allow(dead_code))]
     pub fn write_vram(&mut self, address: u16, data: u8) { 
         self.vram[address as usize] = data;
     }
 
+    // VRAM seen by the cpu, through the bank selected by VBK
+    pub fn read_mapped_vram(&self, address: u16) -> u8 {
+        match self.vram_bank {
+            0 => self.vram[address as usize],
+            _ => self.vram_bank_1[address as usize],
+        }
+    }
+
+    pub fn write_mapped_vram(&mut self, address: u16, data: u8) {
+        match self.vram_bank {
+            0 => self.vram[address as usize] = data,
+            _ => self.vram_bank_1[address as usize] = data,
+        }
+    }
+
     pub fn read_oam(&self, address: usize) -> u8 {
         self.oam[address]
     }
 
+    // bulk loaders to set up rendering scenarios in tests, offset is relative to the VRAM start
+    #[cfg(test)]
+    pub fn load_vram(&mut self, offset: u16, data: &[u8]) {
+        let offset = offset as usize;
+        self.vram[offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    #[cfg(test)]
+    pub fn load_oam(&mut self, data: &[u8; OAM_SIZE as usize]) {
+        self.oam = *data;
+    }
+
+    // draw the 144 lines of a frame at once from the current registers, without running the
+    // modes nor the interrupts, to render a static scene. The line and window state is restored
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub(crate) fn render_frame(&mut self) {
+        let (current_line, window_line_counter, window_y_triggered) = (self.current_line, self.window_line_counter, self.window_y_triggered);
+        self.window_line_counter = 0;
+        self.window_y_triggered = false;
+
+        for line in 0..SCREEN_HEIGHT as u8 {
+            self.current_line = line;
+            self.draw_static_line();
+        }
+
+        self.current_line = current_line;
+        self.window_line_counter = window_line_counter;
+        self.window_y_triggered = window_y_triggered;
+    }
+
+    // scan the OAM and draw the current line, without running the modes
+    pub(crate) fn draw_static_line(&mut self) {
+        self.scan_oam();
+        self.draw_line();
+    }
+
     pub fn write_oam(&mut self, address: usize, data: u8) {         
         self.oam[address] = data;
     }
 
     pub fn run(&mut self, cycles: u8, nvic: &mut Nvic) {
         if self.lcd_display_enabled {
-            // update GPU cycles counter
-            self.cycles += cycles as u16;
+            // update GPU cycles counter, a stalled mode saturates it and leaves the report to the
+            // watchdog
+            self.cycles = self.cycles.saturating_add(cycles as u16);
+            #[cfg(debug_assertions)]
+            self.check_vblank_watchdog(cycles);
 
             match self.mode {
                 GpuMode::HorizontalBlank => {
-                    // handle interrupts generation
-                    if self.new_mode_flag && self.hblank_interrupt_enabled{
-                        self.new_mode_flag = false;
-                        nvic.set_interrupt(InterruptSources::STAT);
-                    }
-
-                    // we reached the end of the mode
-                    if self.cycles >= HORIZONTAL_BLANK_CYCLES {
-                        self.cycles = self.cycles % HORIZONTAL_BLANK_CYCLES;
+                    // we reached the end of the mode, the line lasts the same whatever the length of
+                    // the draw pixel mode
+                    let horizontal_blank_cycles = ONE_LINE_CYCLES - OAM_SCAN_CYCLES - self.draw_pixel_cycles;
+                    if self.cycles >= horizontal_blank_cycles {
+                        self.cycles %= horizontal_blank_cycles;
                         // we detected the end of a line
                         if self.current_line < (SCREEN_HEIGHT - 1) as u8 {
                             self.current_line += 1;
-                            if self.window_flag { self.window_line_counter += 1 }
                             // run the compare line circuitry
-                            self.compare_line(nvic);
+                            self.compare_line();
                             // reset new mode flag
                             self.new_mode_flag = true;
                             // go to next gpu mode
                             self.mode = GpuMode::OAMScan;
                         } else {
+                            // the frame is complete, the vblank starts with line 144
+                            self.current_line += 1;
+                            self.compare_line();
+                            self.frame_count = self.frame_count.wrapping_add(1);
+                            #[cfg(debug_assertions)]
+                            {
+                                self.cycles_since_vblank = 0;
+                            }
                             // reset new mode flag
                             self.new_mode_flag = true;
                             // go to next gpu mode
@@ -263,234 +534,549 @@ impl Gpu {
                     if self.new_mode_flag {
                         self.new_mode_flag = false;
                         nvic.set_interrupt(InterruptSources::VBLANK);
-
-                        if self.vblank_interrupt_enabled {
-                            nvic.set_interrupt(InterruptSources::STAT);
-                        }
                     }
 
-                    // if we reached a new line in vblank mode, run compare line circuitry
-                    if (self.cycles / ((self.vblank_line + 1) * ONE_LINE_CYCLES)) != 0 {
-                        self.vblank_line += 1;
-                        self.current_line += 1;
-                        if self.window_flag { self.window_line_counter += 1 }
-
-                        self.compare_line(nvic);
-                    }
-
-                    // we reached the end of the mode
-                    if self.cycles >= VERTICAL_BLANK_CYCLES {
-                        self.cycles = self.cycles % VERTICAL_BLANK_CYCLES;
-                        // reset the line counter to draw a new frame
-                        self.current_line = 0;
-                        self.window_line_counter = 0;
-                        // reset the vblank line counter
-                        self.vblank_line = 0;
-                        // reset new mode flag
-                        self.new_mode_flag = true;
-                        // go to next gpu mode
-                        self.mode = GpuMode::OAMScan;
+                    // we reached the end of a vblank line
+                    if self.cycles >= ONE_LINE_CYCLES {
+                        self.cycles %= ONE_LINE_CYCLES;
+                        if self.current_line < LAST_LINE {
+                            // if we reached a new line in vblank mode, run compare line circuitry
+                            self.current_line += 1;
+                            self.compare_line();
+                        } else {
+                            // reset the line counter to draw a new frame
+                            self.current_line = 0;
+                            self.compare_line();
+                            self.window_line_counter = 0;
+                            self.window_y_triggered = false;
+                            // reset new mode flag
+                            self.new_mode_flag = true;
+                            // go to next gpu mode
+                            self.mode = GpuMode::OAMScan;
+                        }
                     }
                 }
                 GpuMode::OAMScan => {
-                    // handle interrupts generation
-                    if self.new_mode_flag && self.oam_interrupt_enabled{
-                        self.new_mode_flag = false;
-                        nvic.set_interrupt(InterruptSources::STAT);
+                    // the DMA owns the OAM bus, the ppu can't read the sprite attributes
+                    if self.oam_dma_active {
+                        self.oam_scan_blocked = true;
                     }
 
                     // we reached the end of the mode
                     if self.cycles >= OAM_SCAN_CYCLES {
                         self.cycles = self.cycles % OAM_SCAN_CYCLES;
+                        // select the sprites of the line, they lengthen the draw pixel mode along
+                        // with the fine scroll
+                        self.scan_oam();
+                        self.draw_pixel_cycles = DRAW_PIXEL_CYCLES
+                            + (self.viewport_x_offset % TILE_ROW_SIZE_IN_PIXEL) as u16
+                            + self.sprite_fetch_dots().iter().map(|(_, dots)| dots).sum::<u16>();
                         // reset new mode flag
                         self.new_mode_flag = true;
+                        // the accurate path draws the line along the draw pixel mode
+                        if self.accuracy == PpuAccuracy::Accurate {
+                            self.begin_line();
+                        }
                         // go to next gpu mode
                         self.mode = GpuMode::DrawPixel;
                     }
                 }
                 GpuMode::DrawPixel => {
-                    // we reached the end of the mode
-                    if self.cycles >= DRAW_PIXEL_CYCLES {
-                        self.cycles = self.cycles % DRAW_PIXEL_CYCLES;
-                        // draw the line at the end of the draw pixel mode
-                        self.draw_line();
-                        // go to next gpu mode
-                        self.mode = GpuMode::HorizontalBlank;
+                    match self.accuracy {
+                        PpuAccuracy::Fast => {
+                            // we reached the end of the mode, its length is known from the OAM scan
+                            if self.cycles >= self.draw_pixel_cycles {
+                                self.cycles %= self.draw_pixel_cycles;
+                                // draw the line at the end of the draw pixel mode
+                                self.draw_line();
+                                // go to next gpu mode
+                                self.mode = GpuMode::HorizontalBlank;
+                            }
+                        }
+                        PpuAccuracy::Accurate => {
+                            while self.line_dot < self.cycles && (self.line_x as usize) < SCREEN_WIDTH {
+                                self.run_dot();
+                            }
+
+                            // the mode ends once the last pixel of the line is shifted out
+                            if self.line_x as usize == SCREEN_WIDTH {
+                                self.cycles -= self.line_dot;
+                                self.end_line();
+                                self.draw_pixel_cycles = self.line_dot;
+                                // go to next gpu mode
+                                self.mode = GpuMode::HorizontalBlank;
+                            }
+                        }
                     }
                 }
             }
         }
+
+        self.update_stat_line(nvic);
+    }
+
+    // a regression stopping the mode machine would freeze the screen silently, panic with the
+    // ppu state instead
+    #[cfg(debug_assertions)]
+    fn check_vblank_watchdog(&mut self, cycles: u8) {
+        self.cycles_since_vblank += cycles as u32;
+        if self.cycles_since_vblank > VBLANK_WATCHDOG_CYCLES {
+            panic!(
+                "ppu stall: no vblank in {} cycles, mode {:?}, LY {}, mode cycles {}, draw pixel cycles {}, line x {}, line dot {}",
+                self.cycles_since_vblank, self.mode, self.current_line, self.cycles, self.draw_pixel_cycles, self.line_x, self.line_dot
+            );
+        }
+    }
+
+    // the STAT interrupt is requested when the line rises, it stays low while the LCD is off
+    fn update_stat_line(&mut self, nvic: &mut Nvic) {
+        let stat_line = self.lcd_display_enabled && (
+            (self.line_compare_it_enable && self.line_compare_state)
+            || match self.mode {
+                GpuMode::HorizontalBlank => self.hblank_interrupt_enabled,
+                GpuMode::VerticalBlank => self.vblank_interrupt_enabled,
+                GpuMode::OAMScan => self.oam_interrupt_enabled,
+                GpuMode::DrawPixel => false,
+            });
+
+        if stat_line && !self.stat_line {
+            nvic.set_interrupt(InterruptSources::STAT);
+        }
+        self.stat_line = stat_line;
     }
 
 
+    // draw the whole line at once, from the register values at the end of the draw pixel mode
     fn draw_line(&mut self) {
-        let mut bg_line = [0x00; SCREEN_WIDTH as usize];
-        let pixel_y_index = self.current_line;
-
-        if self.background_display_enabled  {
-            for pixel_x_index in 0..SCREEN_WIDTH {
-                // check if we display the background or the window
-                let (tile_map_area, y_offset, x_offset) = 
-                    if self.window_display_enabled 
-                    && self.window_y_offset <= self.current_line
-                    && self.window_x_offset.wrapping_sub(WINDOW_X_OFFSET) <= pixel_x_index as u8 {
-                        self.window_flag = true;
-                        // window display mode
-                        (self.window_tile_map_area,
-                        self.window_line_counter,
-                        (pixel_x_index as u8).wrapping_sub(self.window_x_offset.wrapping_sub(WINDOW_X_OFFSET)))
-                    } else {
-                        self.window_flag = false;
-                        // background display mode
-                        (self.background_tile_map_area,
-                        pixel_y_index.wrapping_add(self.viewport_y_offset),
-                        (pixel_x_index as u8).wrapping_add(self.viewport_x_offset))
-                    };
-
-                // compute the tile index in tile map
-                let tile_map_y_index = (y_offset / TILE_ROW_SIZE_IN_PIXEL) as u16;
-                let tile_map_x_index = (x_offset / TILE_ROW_SIZE_IN_PIXEL) as u16;
-                let tile_map_index = tile_map_y_index * (TILE_MAP_SIZE as u16) + tile_map_x_index;
-
-                // get the tile memory address from the tile map
-                let tile_mem_index = self.read_vram((tile_map_area as u16) + tile_map_index);
-
-                // convert a 8 bits tile index into a 16 bits tile memory addr
-                let tile_mem_addr = (tile_mem_index as u16) * TILE_SIZE_IN_BYTES;
-
-                // get the row offset in the tile
-                let tile_row_offset = y_offset % TILE_ROW_SIZE_IN_PIXEL * BYTES_PER_TILE_ROM;
-
-                // get tile row data from vram
-                let (data_1, data_0) = self.get_bg_tile_data(tile_mem_addr, tile_row_offset as u16);
-
-                // get pixel bits from data
-                let bit_0 = data_0 >> (7 - (x_offset % TILE_ROW_SIZE_IN_PIXEL)) & 0x01;
-                let bit_1 = data_1 >> (7 - (x_offset % TILE_ROW_SIZE_IN_PIXEL)) & 0x01;
-
-                // find pixel color
-                let pixel_value = (bit_1 << 1) | bit_0;
-                let pixel_color = self.get_bg_pixel_color_from_palette(pixel_value);
-
-                // fill frame buffer
-                self.frame_buffer[(pixel_y_index as usize) * SCREEN_WIDTH + pixel_x_index] = pixel_color;
-                // save the line for sprite rendering
-                bg_line[pixel_x_index] = pixel_value;
+        self.begin_line();
+        for pixel_x_index in 0..SCREEN_WIDTH {
+            self.draw_pixel(pixel_x_index);
+        }
+        self.end_line();
+    }
+
+    // run a dot of the pixel FIFO: a pixel is shifted out while the fetcher reads the next tile
+    // the registers are read when they're used, ie SCX when a tile is fetched and the palettes
+    // when a pixel is shifted out. The FIFO is stalled while a sprite it reaches is fetched
+    fn run_dot(&mut self) {
+        self.line_dot += 1;
+
+        if self.sprite_stall == 0 {
+            while let Some((sprite_x, dots)) = self.sprite_fetches.front().copied() {
+                if sprite_x > self.line_x as i16 {
+                    break;
+                }
+                self.sprite_fetches.pop_front();
+                // the sprites aren't fetched while they're disabled
+                if self.object_display_enabled {
+                    self.sprite_stall += dots;
+                }
             }
         }
 
-        if self.object_display_enabled {
-            // sprites array wich will contain sprites address to display
-            let mut sprites: Vec<u16> = Vec::new();
-            // find 10 sprites to display on the current line
-            let mut nb_sprites_to_display = 0;
-            for sprites_idx in 0..NB_SPRITES_IN_OAM {
-                if nb_sprites_to_display < NB_SRITES_TO_DISPLAY_MAX {
-                    let sprite_addr = sprites_idx * SPRITE_ATTRIBUTES_SIZE_IN_BYTES;
-                    // get the srite first line
-                    let sprite_y_pos_start = self.read_oam((sprite_addr + SPRITE_Y_POS_OFFSET) as usize) as u16 as i16 - SPRITE_Y_OFFSET;
-                    // get the sprite last line
-                    let sprite_y_pos_end = match self.object_size {
-                        ObjectSize::OS8X8 => sprite_y_pos_start + TILE_ROW_SIZE_IN_PIXEL as i16 - 1,
-                        ObjectSize::OS8X16 => sprite_y_pos_start + TILE_ROW_SIZE_IN_PIXEL as i16 * 2 - 1,
-                    };
-                    // check if the current line hits the sprite
-                    if (self.current_line as i16 >= sprite_y_pos_start) && (self.current_line as i16 <= sprite_y_pos_end) {
-                        // add the sprite to the list
-                        sprites.push(sprite_addr);
-                        // increase sprites counter
-                        nb_sprites_to_display += 1;
-                    }
+        if self.sprite_stall > 0 {
+            self.sprite_stall -= 1;
+            return;
+        }
+
+        // the window replaces the background from WX, the fetcher restarts from its first tile
+        if !self.fetcher_window
+        && (self.background_display_enabled || self.cgb_mode)
+        && self.window_display_enabled
+        && self.window_y_triggered {
+            if let Some(window_start_x) = self.window_start_x().filter(|start_x| *start_x <= self.line_x as i16) {
+                self.fetcher_window = true;
+                self.window_flag = true;
+                // the window pixels left of the screen are shifted out without being drawn
+                self.discarded_pixels = (-window_start_x).max(0) as u8;
+                self.bg_fifo.clear();
+                self.fetcher_dot = 0;
+                self.fetcher_x = 0;
+            }
+        }
+
+        if let Some(pixel) = self.bg_fifo.pop_front() {
+            if self.discarded_pixels > 0 {
+                // the fine scroll pixels are shifted out without being drawn, one per dot
+                self.discarded_pixels -= 1;
+            } else {
+                let background = if self.background_display_enabled || self.cgb_mode { Some(pixel) } else { None };
+                self.output_pixel(self.line_x as usize, background);
+                self.line_x += 1;
+            }
+        }
+
+        if self.fetcher_stall > 0 {
+            self.fetcher_stall -= 1;
+            return;
+        }
+
+        if self.fetcher_dot < FETCH_TILE_DOTS {
+            self.fetcher_dot += 1;
+            if self.fetcher_dot == FETCH_TILE_NUMBER_DOT {
+                self.fetch_tile_number();
+            }
+        }
+
+        // the tile is pushed once there's room for it in the FIFO
+        if self.fetcher_dot == FETCH_TILE_DOTS && self.bg_fifo.len() <= TILE_ROW_SIZE_IN_PIXEL as usize {
+            self.push_tile();
+        }
+    }
+
+    fn fetch_tile_number(&mut self) {
+        let (tile_map_area, tile_map_x_index, y_offset) = if self.fetcher_window {
+            (self.window_tile_map_area, self.fetcher_x, self.window_line_counter)
+        } else {
+            (self.background_tile_map_area,
+            (self.viewport_x_offset / TILE_ROW_SIZE_IN_PIXEL).wrapping_add(self.fetcher_x),
+            self.current_line.wrapping_add(self.viewport_y_offset))
+        };
+
+        let tile_map_index = (y_offset / TILE_ROW_SIZE_IN_PIXEL) as u16 * (TILE_MAP_SIZE as u16)
+                           + (tile_map_x_index % TILE_MAP_SIZE) as u16;
+
+        self.fetched_tile_index = self.read_vram((tile_map_area as u16) + tile_map_index);
+        self.fetched_tile_y = y_offset;
+        self.fetched_tile_priority = self.get_bg_priority((tile_map_area as u16) + tile_map_index);
+    }
+
+    fn push_tile(&mut self) {
+        for pixel_x_offset in 0..TILE_ROW_SIZE_IN_PIXEL {
+            let pixel_value = self.get_bg_tile_pixel(self.fetched_tile_index, pixel_x_offset, self.fetched_tile_y);
+            self.bg_fifo.push_back((pixel_value, self.fetched_tile_priority));
+        }
+
+        self.fetcher_x = self.fetcher_x.wrapping_add(1);
+        self.fetcher_dot = 0;
+    }
+
+    fn begin_line(&mut self) {
+        // once WY is reached the window stays triggered until the end of the frame
+        if self.window_y_offset == self.current_line {
+            self.window_y_triggered = true;
+        }
+        self.window_flag = false;
+
+        // the FIFO starts empty, the first fetch is a dummy one
+        // the fine scroll delays the first pixel, which lengthens the draw pixel mode
+        self.line_x = 0;
+        self.line_dot = 0;
+        self.discarded_pixels = self.viewport_x_offset % TILE_ROW_SIZE_IN_PIXEL;
+        self.bg_fifo.clear();
+        self.fetcher_stall = FETCH_TILE_DOTS;
+        self.fetcher_dot = 0;
+        self.fetcher_x = 0;
+        self.fetcher_window = false;
+
+        // the sprites are fetched when the FIFO reaches them
+        self.sprite_fetches = self.sprite_fetch_dots().into();
+        self.sprite_stall = 0;
+    }
+
+    // mode 2: find the 10 first sprites of OAM hitting the current line
+    // none is found when the OAM was read by a DMA transfer during the scan
+    fn scan_oam(&mut self) {
+        self.line_sprites.clear();
+        for sprites_idx in 0..NB_SPRITES_IN_OAM {
+            if self.line_sprites.len() == NB_SRITES_TO_DISPLAY_MAX as usize || self.oam_scan_blocked {
+                break;
+            }
+
+            let sprite_addr = sprites_idx * SPRITE_ATTRIBUTES_SIZE_IN_BYTES;
+            // get the srite first line
+            let sprite_y_pos_start = self.read_oam((sprite_addr + SPRITE_Y_POS_OFFSET) as usize) as u16 as i16 - SPRITE_Y_OFFSET;
+            // get the sprite last line
+            let sprite_y_pos_end = match self.object_size {
+                ObjectSize::OS8X8 => sprite_y_pos_start + TILE_ROW_SIZE_IN_PIXEL as i16 - 1,
+                ObjectSize::OS8X16 => sprite_y_pos_start + TILE_ROW_SIZE_IN_PIXEL as i16 * 2 - 1,
+            };
+            // check if the current line hits the sprite
+            if (self.current_line as i16 >= sprite_y_pos_start) && (self.current_line as i16 <= sprite_y_pos_end) {
+                self.line_sprites.push(sprite_addr);
+            }
+        }
+
+        // sort objects to draw :
+        // from lower priority in first positions
+        // to higher priority in last positions
+        self.line_sprites.reverse();
+        if self.object_priority == ObjectPriority::XCoordinate {
+            // the stable sort keeps the OAM order of the sprites at the same X
+            let oam = &self.oam;
+            self.line_sprites.sort_by_key(|sprite| Reverse(oam[(sprite + SPRITE_X_POS_OFFSET) as usize]));
+        }
+    }
+
+    // screen X of the first pixel of the sprites of the line and the dots their fetch lasts,
+    // in their fetch order. The fetch waits for the background fetcher to complete the tile the
+    // first sprite pixel lies in, unless a previous sprite has already waited for this tile.
+    // The sprites off the right edge aren't fetched, the ones with X = 0 wait the longest
+    fn sprite_fetch_dots(&self) -> Vec<(i16, u16)> {
+        if !self.object_display_enabled {
+            return Vec::new();
+        }
+
+        let mut sprites_x: Vec<u8> = self.line_sprites.iter().rev()
+            .map(|sprite| self.read_oam((sprite + SPRITE_X_POS_OFFSET) as usize))
+            .filter(|sprite_x| *sprite_x < SPRITE_X_HIDDEN)
+            .collect();
+        // the sprites at the same X are fetched in OAM order
+        sprites_x.sort();
+
+        let fine_scroll = self.viewport_x_offset % TILE_ROW_SIZE_IN_PIXEL;
+        let mut waited_tiles: Vec<u8> = Vec::with_capacity(sprites_x.len());
+        sprites_x.iter().map(|sprite_x| {
+            let wait_dots = if *sprite_x == 0 {
+                SPRITE_FETCH_MAX_WAIT_DOTS
+            } else {
+                // the fetched tiles start from the fine scroll, offset by the sprite X offset
+                let fetched_x = sprite_x + fine_scroll;
+                if waited_tiles.contains(&(fetched_x / TILE_ROW_SIZE_IN_PIXEL)) {
+                    0
                 } else {
-                    // we reached the maximum sprites number to display
-                    // go out of the loop
-                    break
+                    waited_tiles.push(fetched_x / TILE_ROW_SIZE_IN_PIXEL);
+                    SPRITE_FETCH_MAX_WAIT_DOTS.saturating_sub((fetched_x % TILE_ROW_SIZE_IN_PIXEL) as u16)
                 }
+            };
+
+            (*sprite_x as i16 - SPRITE_X_OFFSET, SPRITE_FETCH_DOTS + wait_dots)
+        }).collect()
+    }
+
+    fn end_line(&mut self) {
+        if self.window_flag {
+            self.window_line_counter = self.window_line_counter.wrapping_add(1);
+        }
+        self.oam_scan_blocked = false;
+    }
+
+    // draw a pixel of the current line, from the current register values
+    fn draw_pixel(&mut self, pixel_x_index: usize) {
+        let background = if self.background_display_enabled || self.cgb_mode {
+            Some(self.get_bg_pixel(pixel_x_index))
+        } else {
+            None
+        };
+
+        self.output_pixel(pixel_x_index, background);
+    }
+
+    // color index of the background or of the window at a pixel of the current line, with the
+    // CGB priority of its tile
+    fn get_bg_pixel(&mut self, pixel_x_index: usize) -> (u8, bool) {
+        // check if we display the background or the window
+        let window_start_x = self.window_start_x().filter(|start_x| *start_x <= pixel_x_index as i16);
+        let (tile_map_area, y_offset, x_offset) = match window_start_x {
+            Some(start_x) if self.window_display_enabled && self.window_y_triggered => {
+                self.window_flag = true;
+                // window display mode
+                (self.window_tile_map_area,
+                self.window_line_counter,
+                (pixel_x_index as i16 - start_x) as u8)
             }
-            // sort objects to draw :
-            // from lower priority in first positions
-            // to higher priority in last positions
-            let mut sprites_sorted: Vec<u16> = Vec::new();
-            for sprite_idx in 0..nb_sprites_to_display {
-                sprites_sorted.push(sprites[(nb_sprites_to_display - 1 - sprite_idx) as usize]);
+            _ => {
+                // background display mode
+                (self.background_tile_map_area,
+                self.current_line.wrapping_add(self.viewport_y_offset),
+                (pixel_x_index as u8).wrapping_add(self.viewport_x_offset))
             }
-            // draw sorted sprites
+        };
+
+        // compute the tile index in tile map
+        let tile_map_y_index = (y_offset / TILE_ROW_SIZE_IN_PIXEL) as u16;
+        let tile_map_x_index = (x_offset / TILE_ROW_SIZE_IN_PIXEL) as u16;
+        let tile_map_index = tile_map_y_index * (TILE_MAP_SIZE as u16) + tile_map_x_index;
+
+        // get the tile memory address from the tile map
+        let tile_mem_index = self.read_vram((tile_map_area as u16) + tile_map_index);
+        let priority = self.get_bg_priority((tile_map_area as u16) + tile_map_index);
+
+        (self.get_bg_tile_pixel(tile_mem_index, x_offset, y_offset), priority)
+    }
+
+    // the CGB attribute of a tile map entry puts the background colors 1-3 over the sprites
+    fn get_bg_priority(&self, tile_map_address: u16) -> bool {
+        self.cgb_mode && (self.vram_bank_1[tile_map_address as usize] & BG_ATTRIBUTE_PRIORITY) != 0
+    }
+
+    // screen X of the first window pixel, negative when WX < 7 moves the window partly off the
+    // left edge of the screen. None when WX >= 166 moves it off screen
+    fn window_start_x(&self) -> Option<i16> {
+        if self.window_x_offset >= WINDOW_X_HIDDEN {
+            None
+        } else {
+            Some(self.window_x_offset as i16 - WINDOW_X_OFFSET as i16)
+        }
+    }
+
+    // write a pixel of the current line in the frame buffer, the background color index is mixed
+    // with the sprites, none when the background is disabled
+    fn output_pixel(&mut self, pixel_x_index: usize, background: Option<(u8, bool)>) {
+        let pixel_index = (self.current_line as usize) * SCREEN_WIDTH + pixel_x_index;
+
+        // a disabled background is blank and seen as transparent by the sprites
+        let (pixel_value, bg_priority) = background.unwrap_or((PIXEL_TRANSPARENT, false));
+        self.frame_buffer[pixel_index] = if background.is_some() {
+            self.get_bg_pixel_color_from_palette(pixel_value)
+        } else {
+            u8::from(PixelColor::WHITE)
+        };
+        self.palette_buffer[pixel_index] = PALETTE_BG;
+        // save the color index for sprite rendering
+        self.index_buffer[pixel_index] = pixel_value;
+
+        if self.object_display_enabled {
             // higher priority sprites are drawn in last positions
             // so it can override lower priority sprites values
-            for sprite in sprites_sorted {
-                let pixel_y_index: u8 = self.current_line;
-                // get sprite's attributes
-                let sprite_y_pos = self.read_oam((sprite + SPRITE_Y_POS_OFFSET) as usize) as u16 as i16  - SPRITE_Y_OFFSET;
-                let sprite_x_pos = self.read_oam((sprite + SPRITE_X_POS_OFFSET) as usize) as i16;
-                let sprite_tile_addr = match self.object_size {
-                    ObjectSize::OS8X8 => {
-                        self.read_oam((sprite + SPRITE_TILE_INDEX_OFFSET) as usize) as u16 * TILE_SIZE_IN_BYTES
-                    },
-                    ObjectSize::OS8X16 => {
-                        // ignore bit 0 for tile index in 8x16 object size mode
-                        (self.read_oam((sprite + SPRITE_TILE_INDEX_OFFSET) as usize) as u16 * TILE_SIZE_IN_BYTES) & 0xFFE0
-                    },
-                };
-                let sprite_attr = self.read_oam((sprite + SPRITE_ATTRIBUTES_OFFSET) as usize);
-                let sprite_bg_over = (sprite_attr & 0x80) != 0;
-                let sprite_y_flip = (sprite_attr & 0x40) != 0;
-                let sprite_x_flip = (sprite_attr & 0x20) != 0;
-                let sprite_palette_idx = (sprite_attr & 0x10) != 0;
-                let sprite_size_offset =  match self.object_size {
-                    ObjectSize::OS8X8 => 1,
-                    ObjectSize::OS8X16 => 2,
+            for sprite_idx in 0..self.line_sprites.len() {
+                let sprite = self.line_sprites[sprite_idx];
+                let (pixel_value, sprite_bg_over, sprite_palette_idx) = match self.get_sprite_pixel(sprite, pixel_x_index) {
+                    Some(pixel) => pixel,
+                    None => continue,
                 };
-                // get tile addr
-                let sprite_row_offset = (pixel_y_index as i16 - sprite_y_pos) as u16;
-                let tile_addr = if sprite_y_flip == false {
-                    sprite_tile_addr + sprite_row_offset * BYTES_PER_TILE_ROM as u16
+
+                // check if bg overlap sprites
+                let bg_pixel_value = self.index_buffer[pixel_index];
+                if self.is_object_over_background(bg_pixel_value, bg_priority, sprite_bg_over) {
+                    // find sprite pixel color
+                    self.frame_buffer[pixel_index] = self.get_object_pixel_color_from_palette(pixel_value, sprite_palette_idx);
+                    self.palette_buffer[pixel_index] = if sprite_palette_idx { PALETTE_OBJ_1 } else { PALETTE_OBJ_0 };
                 } else {
-                    let row = ((TILE_ROW_SIZE_IN_PIXEL * sprite_size_offset) as u16).wrapping_sub(1).wrapping_sub(sprite_row_offset);
-                    sprite_tile_addr + row * BYTES_PER_TILE_ROM as u16
-                };
-                // get one row of sprite data
-                let data_0 = self.read_vram(tile_addr);
-                let data_1 = self.read_vram(tile_addr + 1);
-                // draw each pixel of the sprite's row
-                for pixel_x_offset in 0..TILE_ROW_SIZE_IN_PIXEL {
-                    // get pixel bits from data
-                    let (bit_1, bit_0) = if sprite_x_flip == false {
-                        let bit_0 = (data_0 >> (7 - pixel_x_offset)) & 0x01;
-                        let bit_1 = (data_1 >> (7 - pixel_x_offset)) & 0x01;
-
-                        (bit_1, bit_0)
-                    } else {
-                        let bit_0 = (data_0 >> pixel_x_offset) & 0x01;
-                        let bit_1 = (data_1 >> pixel_x_offset) & 0x01;
-
-                        (bit_1, bit_0)
-                    };
-                    // deduce pixel value
-                    let pixel_value = (bit_1 << 1) | bit_0;
-                    // compute the x coordinate of the pixel in the frame buffer
-                    let pixel_x_index = sprite_x_pos - SPRITE_X_OFFSET + pixel_x_offset as i16;
-                    // don't draw the pixel if it's not in the viewport
-                    if pixel_x_index >= 0 
-                    && pixel_x_index < SCREEN_WIDTH as i16 
-                    && pixel_value != PIXEL_TRANSPARENT {
-                        // check if bg overlap sprites
-                        if !sprite_bg_over || bg_line[pixel_x_index as usize] == PIXEL_TRANSPARENT {
-                            // find sprite pixel color
-                            let pixel_color = self.get_object_pixel_color_from_palette(pixel_value, sprite_palette_idx);
-                            // fill frame buffer
-                            self.frame_buffer[(pixel_y_index as usize) * SCREEN_WIDTH + (pixel_x_index as usize)] = pixel_color;
-                        } else {
-                            // find bg pixel color
-                            let pixel_color = self.get_bg_pixel_color_from_palette(bg_line[pixel_x_index as usize]);
-                            // fill frame buffer
-                            self.frame_buffer[(pixel_y_index as usize) * SCREEN_WIDTH + (pixel_x_index as usize)] = pixel_color;
-                        }
-                    }
+                    // find bg pixel color
+                    self.frame_buffer[pixel_index] = self.get_bg_pixel_color_from_palette(bg_pixel_value);
+                    self.palette_buffer[pixel_index] = PALETTE_BG;
                 }
             }
         }
     }
 
+    // color index of a sprite at a pixel of the current line, with its background priority and
+    // palette flags, none when the sprite doesn't cover the pixel or is transparent there
+    fn get_sprite_pixel(&self, sprite: u16, pixel_x_index: usize) -> Option<(u8, bool, bool)> {
+        let sprite_x_pos = self.read_oam((sprite + SPRITE_X_POS_OFFSET) as usize) as i16;
+        let pixel_x_offset = pixel_x_index as i16 - (sprite_x_pos - SPRITE_X_OFFSET);
+        if pixel_x_offset < 0 || pixel_x_offset >= TILE_ROW_SIZE_IN_PIXEL as i16 {
+            return None;
+        }
+
+        // get sprite's attributes
+        let sprite_y_pos = self.read_oam((sprite + SPRITE_Y_POS_OFFSET) as usize) as u16 as i16  - SPRITE_Y_OFFSET;
+        let sprite_tile_addr = match self.object_size {
+            ObjectSize::OS8X8 => {
+                self.read_oam((sprite + SPRITE_TILE_INDEX_OFFSET) as usize) as u16 * TILE_SIZE_IN_BYTES
+            },
+            ObjectSize::OS8X16 => {
+                // ignore bit 0 for tile index in 8x16 object size mode
+                (self.read_oam((sprite + SPRITE_TILE_INDEX_OFFSET) as usize) as u16 * TILE_SIZE_IN_BYTES) & 0xFFE0
+            },
+        };
+        let sprite_attr = self.read_oam((sprite + SPRITE_ATTRIBUTES_OFFSET) as usize);
+        let sprite_bg_over = (sprite_attr & 0x80) != 0;
+        let sprite_y_flip = (sprite_attr & 0x40) != 0;
+        let sprite_x_flip = (sprite_attr & 0x20) != 0;
+        let sprite_palette_idx = (sprite_attr & 0x10) != 0;
+        let sprite_size_offset =  match self.object_size {
+            ObjectSize::OS8X8 => 1,
+            ObjectSize::OS8X16 => 2,
+        };
+        // get tile addr
+        let sprite_row_offset = (self.current_line as i16 - sprite_y_pos) as u16;
+        let tile_addr = if !sprite_y_flip {
+            sprite_tile_addr + sprite_row_offset * BYTES_PER_TILE_ROM as u16
+        } else {
+            let row = ((TILE_ROW_SIZE_IN_PIXEL * sprite_size_offset) as u16).wrapping_sub(1).wrapping_sub(sprite_row_offset);
+            sprite_tile_addr + row * BYTES_PER_TILE_ROM as u16
+        };
+        // get one row of sprite data
+        let data_0 = self.read_vram(tile_addr);
+        let data_1 = self.read_vram(tile_addr + 1);
+        // get pixel bits from data
+        let bit_shift = if !sprite_x_flip { 7 - pixel_x_offset } else { pixel_x_offset };
+        let bit_0 = (data_0 >> bit_shift) & 0x01;
+        let bit_1 = (data_1 >> bit_shift) & 0x01;
+        // deduce pixel value
+        let pixel_value = (bit_1 << 1) | bit_0;
+
+        if pixel_value == PIXEL_TRANSPARENT {
+            None
+        } else {
+            Some((pixel_value, sprite_bg_over, sprite_palette_idx))
+        }
+    }
+
+    // background color 0 is always behind the sprites, otherwise the sprite priority flag and the
+    // CGB background attribute decide. In CGB mode LCDC bit 0 clear puts all the sprites on top
+    fn is_object_over_background(&self, bg_pixel_value: u8, bg_priority: bool, object_behind_bg: bool) -> bool {
+        if bg_pixel_value == PIXEL_TRANSPARENT || (self.cgb_mode && !self.background_display_enabled) {
+            true
+        } else {
+            !object_behind_bg && !bg_priority
+        }
+    }
+
+    // color index (0-3) of a pixel in a background tile, x and y are the pixel position in the map
+    fn get_bg_tile_pixel(&self, tile_mem_index: u8, x_offset: u8, y_offset: u8) -> u8 {
+        // convert a 8 bits tile index into a 16 bits tile memory addr
+        let tile_mem_addr = (tile_mem_index as u16) * TILE_SIZE_IN_BYTES;
+
+        // get the row offset in the tile
+        let tile_row_offset = y_offset % TILE_ROW_SIZE_IN_PIXEL * BYTES_PER_TILE_ROM;
+
+        // get tile row data from vram
+        let (data_1, data_0) = self.get_bg_tile_data(tile_mem_addr, tile_row_offset as u16);
+
+        // get pixel bits from data
+        let bit_0 = data_0 >> (7 - (x_offset % TILE_ROW_SIZE_IN_PIXEL)) & 0x01;
+        let bit_1 = data_1 >> (7 - (x_offset % TILE_ROW_SIZE_IN_PIXEL)) & 0x01;
+
+        (bit_1 << 1) | bit_0
+    }
+
+    // render the whole background tile map with the background palette, for debugging
+    pub fn draw_background_map(&self) -> Vec<u8> {
+        let mut map = vec![0; BACKGROUND_MAP_SIZE * BACKGROUND_MAP_SIZE];
+
+        for (pixel_index, shade) in map.iter_mut().enumerate() {
+            let y_offset = (pixel_index / BACKGROUND_MAP_SIZE) as u8;
+            let x_offset = (pixel_index % BACKGROUND_MAP_SIZE) as u8;
+
+            let tile_map_index = (y_offset / TILE_ROW_SIZE_IN_PIXEL) as u16 * (TILE_MAP_SIZE as u16)
+                                + (x_offset / TILE_ROW_SIZE_IN_PIXEL) as u16;
+            let tile_mem_index = self.read_vram((self.background_tile_map_area as u16) + tile_map_index);
+
+            let pixel_value = self.get_bg_tile_pixel(tile_mem_index, x_offset, y_offset);
+            *shade = self.get_bg_pixel_color_from_palette(pixel_value);
+        }
+
+        map
+    }
+
+    // RGBA frame for compositing, the screen is centered in a frame of the given size
+    // the pixels around the screen, and the whole screen while the LCD is off, are transparent
+    pub fn draw_rgba_frame(&self, width: usize, height: usize) -> Vec<u8> {
+        assert!(width >= SCREEN_WIDTH && height >= SCREEN_HEIGHT, "the RGBA frame is smaller than the screen");
+
+        let mut rgba = vec![0; width * height * RGBA_BYTES_PER_PIXEL];
+        if !self.lcd_display_enabled {
+            return rgba;
+        }
+
+        let left = (width - SCREEN_WIDTH) / 2;
+        let top = (height - SCREEN_HEIGHT) / 2;
+
+        for (line, screen_line) in self.frame_buffer.chunks(SCREEN_WIDTH).enumerate() {
+            let line_start = ((top + line) * width + left) * RGBA_BYTES_PER_PIXEL;
+            let line_end = line_start + SCREEN_WIDTH * RGBA_BYTES_PER_PIXEL;
+
+            for (pixel, shade) in rgba[line_start..line_end].chunks_mut(RGBA_BYTES_PER_PIXEL).zip(screen_line) {
+                pixel.copy_from_slice(&[*shade, *shade, *shade, ALPHA_OPAQUE]);
+            }
+        }
+
+        rgba
+    }
+
     fn get_bg_tile_data(&self, tile_mem_addr: u16, tile_row_offset: u16) -> (u8, u8) {
 
         if self.background_tile_data_area {
@@ -516,48 +1102,28 @@ impl Gpu {
     }
 
     pub fn get_bg_pixel_color_from_palette(&self, pixel_value: u8) -> u8 {
-        match pixel_value {
-            0 => self.background_palette.color_0 as u8,
-            1 => self.background_palette.color_1 as u8,
-            2 => self.background_palette.color_2 as u8,
-            _ => self.background_palette.color_3 as u8,
-        }
+        u8::from(self.background_palette.get_color(pixel_value))
     }
 
     fn get_object_pixel_color_from_palette(&self, pixel_value: u8, sprite_palette_idx: bool) -> u8 {
         if sprite_palette_idx {
-            match pixel_value {
-                0 => self.object_palette_1.color_0 as u8,
-                1 => self.object_palette_1.color_1 as u8,
-                2 => self.object_palette_1.color_2 as u8,
-                _ => self.object_palette_1.color_3 as u8,
-            }
+            u8::from(self.object_palette_1.get_color(pixel_value))
         } else {
-            match pixel_value {
-                0 => self.object_palette_0.color_0 as u8,
-                1 => self.object_palette_0.color_1 as u8,
-                2 => self.object_palette_0.color_2 as u8,
-                _ => self.object_palette_0.color_3 as u8,
-            }
+            u8::from(self.object_palette_0.get_color(pixel_value))
         }
     }
 
-    fn compare_line(&mut self, nvic: &mut Nvic) {
-        if self.current_line == self.compare_line {
-            self.line_compare_state = true;
-
-            // managed interrupt
-            if self.line_compare_it_enable {
-                nvic.set_interrupt(InterruptSources::STAT);
-            }
-        } else {
-            self.line_compare_state = false;
-        }
+    // the interrupt is requested through the STAT line
+    fn compare_line(&mut self) {
+        self.line_compare_state = self.current_line == self.compare_line;
     }
 
     pub fn control_from_byte(&mut self, data: u8) {
         // bit 7
-        self.lcd_display_enabled = ((data >> 7) & 0x01) != 0;
+        let lcd_display_enabled = ((data >> 7) & 0x01) != 0;
+        if lcd_display_enabled != self.lcd_display_enabled {
+            self.switch_lcd(lcd_display_enabled);
+        }
         // bit 6
         if((data >> 6) & 0x01) != 0 {
             self.window_tile_map_area = TileMapArea::X9C00;            
@@ -622,8 +1188,26 @@ impl Gpu {
         self.hblank_interrupt_enabled = ((data >> 3) & 0x01) != 0;
     }
 
+    // turning the LCD off stops the ppu at line 0 in the horizontal blank mode
+    // turning it on starts a new frame from the OAM scan of line 0
+    fn switch_lcd(&mut self, enabled: bool) {
+        self.lcd_display_enabled = enabled;
+        self.cycles = 0;
+        #[cfg(debug_assertions)]
+        {
+            self.cycles_since_vblank = 0;
+        }
+        self.current_line = 0;
+        self.window_line_counter = 0;
+        self.window_y_triggered = false;
+        self.new_mode_flag = true;
+        self.mode = if enabled { GpuMode::OAMScan } else { GpuMode::HorizontalBlank };
+    }
+
     pub fn status_to_byte(&self) -> u8 {
+        // the mode reads 0 while the LCD is off
         let gpu_mode_bits = match self.mode {
+            _ if !self.lcd_display_enabled => 0,
             GpuMode::HorizontalBlank => 0,
             GpuMode::VerticalBlank => 1,
             GpuMode::OAMScan => 2,
@@ -636,7 +1220,7 @@ impl Gpu {
             | ((self.vblank_interrupt_enabled as u8) << 4)
             | ((self.hblank_interrupt_enabled as u8) << 3)
             | ((self.line_compare_state as u8) << 2)
-            | ((gpu_mode_bits as u8) & 0x11)
+            | ((gpu_mode_bits as u8) & 0x03)
     }
 
     pub fn get_scy(&self) -> u8 {
@@ -697,6 +1281,42 @@ impl Gpu {
         set_palette!(self.object_palette_0.color_3, data, 3);
     }
 
+    pub fn get_background_palette(&self) -> u8 {
+        self.background_palette.to_byte()
+    }
+
+    pub fn get_object_palette_0(&self) -> u8 {
+        self.object_palette_0.to_byte()
+    }
+
+    pub fn get_object_palette_1(&self) -> u8 {
+        self.object_palette_1.to_byte()
+    }
+
+    // KEY0 written by the boot rom, bit 2 set keeps the DMG compatibility mode
+    pub fn set_hardware_mode(&mut self, data: u8) {
+        self.cgb_mode = data & 0x04 == 0;
+    }
+
+    pub fn set_vram_bank(&mut self, data: u8) {
+        self.vram_bank = data & 0x01;
+    }
+
+    pub fn get_vram_bank(&self) -> u8 {
+        self.vram_bank
+    }
+
+    pub fn set_object_priority(&mut self, data: u8) {
+        self.object_priority = if data & 0x01 != 0 { ObjectPriority::XCoordinate } else { ObjectPriority::OamIndex };
+    }
+
+    pub fn get_object_priority(&self) -> u8 {
+        match self.object_priority {
+            ObjectPriority::OamIndex => 0x00,
+            ObjectPriority::XCoordinate => 0x01,
+        }
+    }
+
     pub fn set_object_palette_1(&mut self, data: u8) {
         set_palette!(self.object_palette_1.color_0, data, 0);
         set_palette!(self.object_palette_1.color_1, data, 1);
@@ -705,29 +1325,328 @@ impl Gpu {
     }
 }
 
-#[cfg(test)]
-mod gpu_tests {
-    use super::*;
+impl SaveState for Palette {
+    fn save_state(&self, writer: &mut dyn Write) -> Result<()> {
+        write_u8(writer, self.color_0.to_index())?;
+        write_u8(writer, self.color_1.to_index())?;
+        write_u8(writer, self.color_2.to_index())?;
+        write_u8(writer, self.color_3.to_index())
+    }
 
-    #[test]
-    fn test_read_write_vram() {
-        let mut gpu = Gpu::new();
-        gpu.write_vram(0x0001, 0xAA);
-        gpu.write_vram(0x0002, 0x55);
-        gpu.write_vram(0x0010, 0xAA);
-        assert_eq!(gpu.read_vram(0x0001), 0xAA);
-        assert_eq!(gpu.read_vram(0x0002), 0x55);
-        assert_eq!(gpu.read_vram(0x0010), 0xAA);
+    fn load_state(&mut self, reader: &mut dyn Read) -> Result<()> {
+        self.color_0 = PixelColor::from_index(read_u8(reader)?);
+        self.color_1 = PixelColor::from_index(read_u8(reader)?);
+        self.color_2 = PixelColor::from_index(read_u8(reader)?);
+        self.color_3 = PixelColor::from_index(read_u8(reader)?);
+        Ok(())
     }
+}
 
-    #[test]
-    fn test_draw_line() {
-        let mut gpu = Gpu::new();
+impl Gpu {
+    // the state in the layout of the given format version, the older layouts are only written
+    // to test their migration
+    pub fn save_state_version(&self, writer: &mut dyn Write, version: u8) -> Result<()> {
+        // the older versions counted the vblank cycles from its start, with LY at 143 on its
+        // first line
+        let (current_line, cycles, vblank_line) = if version < 3 && self.mode == GpuMode::VerticalBlank {
+            let vblank_line = (self.current_line - SCREEN_HEIGHT as u8) as u16;
+            (self.current_line - 1, self.cycles + vblank_line * ONE_LINE_CYCLES, vblank_line)
+        } else {
+            (self.current_line, self.cycles, 0)
+        };
 
-        // init GPU
-        gpu.background_display_enabled = true;
-        gpu.background_tile_data_area = true;
-        gpu.background_tile_map_area = TileMapArea::X9800;
+        write_bytes(writer, &self.vram)?;
+        write_bytes(writer, &self.oam)?;
+        // LCD control register holds all the display parameters
+        write_u8(writer, self.control_to_byte())?;
+        write_bool(writer, self.line_compare_it_enable)?;
+        write_bool(writer, self.oam_interrupt_enabled)?;
+        write_bool(writer, self.vblank_interrupt_enabled)?;
+        write_bool(writer, self.hblank_interrupt_enabled)?;
+        write_bool(writer, self.line_compare_state)?;
+        write_u8(writer, match self.mode {
+            GpuMode::HorizontalBlank => 0,
+            GpuMode::VerticalBlank => 1,
+            GpuMode::OAMScan => 2,
+            GpuMode::DrawPixel => 3,
+        })?;
+        write_u8(writer, self.viewport_y_offset)?;
+        write_u8(writer, self.viewport_x_offset)?;
+        write_u8(writer, current_line)?;
+        write_u8(writer, self.compare_line)?;
+        self.background_palette.save_state(writer)?;
+        self.object_palette_0.save_state(writer)?;
+        self.object_palette_1.save_state(writer)?;
+        write_u8(writer, self.window_x_offset)?;
+        write_u8(writer, self.window_y_offset)?;
+        write_u16(writer, cycles)?;
+        write_bool(writer, self.new_mode_flag)?;
+        if version < 3 {
+            write_u16(writer, vblank_line)?;
+        }
+        write_bool(writer, self.window_flag)?;
+        write_u8(writer, self.window_line_counter)?;
+        write_bool(writer, self.window_y_triggered)?;
+        write_bytes(writer, &self.frame_buffer)?;
+        write_bytes(writer, &self.index_buffer)?;
+        write_bytes(writer, &self.palette_buffer)?;
+        write_u16(writer, self.draw_pixel_cycles)?;
+        write_bool(writer, self.oam_scan_blocked)?;
+        write_bool(writer, self.stat_line)?;
+        if version >= 2 {
+            write_u8(writer, self.get_object_priority())?;
+            write_bool(writer, self.cgb_mode)?;
+            write_u8(writer, self.vram_bank)?;
+            write_bytes(writer, &self.vram_bank_1)?;
+        }
+        Ok(())
+    }
+}
+
+impl SaveState for Gpu {
+    fn save_state(&self, writer: &mut dyn Write) -> Result<()> {
+        self.save_state_version(writer, SAVE_STATE_VERSION)
+    }
+
+    fn load_state(&mut self, reader: &mut dyn Read) -> Result<()> {
+        self.load_state_version(reader, SAVE_STATE_VERSION)
+    }
+
+    fn load_state_version(&mut self, reader: &mut dyn Read, version: u8) -> Result<()> {
+        read_bytes(reader, &mut self.vram)?;
+        read_bytes(reader, &mut self.oam)?;
+        self.control_from_byte(read_u8(reader)?);
+        self.line_compare_it_enable = read_bool(reader)?;
+        self.oam_interrupt_enabled = read_bool(reader)?;
+        self.vblank_interrupt_enabled = read_bool(reader)?;
+        self.hblank_interrupt_enabled = read_bool(reader)?;
+        self.line_compare_state = read_bool(reader)?;
+        self.mode = match read_u8(reader)? {
+            0 => GpuMode::HorizontalBlank,
+            1 => GpuMode::VerticalBlank,
+            2 => GpuMode::OAMScan,
+            3 => GpuMode::DrawPixel,
+            mode => return Err(invalid_data(&format!("invalid gpu mode {}", mode))),
+        };
+        self.viewport_y_offset = read_u8(reader)?;
+        self.viewport_x_offset = read_u8(reader)?;
+        self.current_line = read_u8(reader)?;
+        self.compare_line = read_u8(reader)?;
+        self.background_palette.load_state(reader)?;
+        self.object_palette_0.load_state(reader)?;
+        self.object_palette_1.load_state(reader)?;
+        self.window_x_offset = read_u8(reader)?;
+        self.window_y_offset = read_u8(reader)?;
+        self.cycles = read_u16(reader)?;
+        self.new_mode_flag = read_bool(reader)?;
+        if version < 3 {
+            // the vblank cycles were counted from its start, with LY at 143 on its first line
+            let vblank_line = read_u16(reader)?;
+            if vblank_line as usize >= VERTICAL_BLANK_LINES || self.cycles < vblank_line * ONE_LINE_CYCLES {
+                return Err(invalid_data(&format!("invalid vblank line {} after {} cycles", vblank_line, self.cycles)));
+            }
+            if self.mode == GpuMode::VerticalBlank {
+                self.current_line = SCREEN_HEIGHT as u8 + vblank_line as u8;
+                self.cycles -= vblank_line * ONE_LINE_CYCLES;
+            }
+        }
+        self.window_flag = read_bool(reader)?;
+        self.window_line_counter = read_u8(reader)?;
+        self.window_y_triggered = read_bool(reader)?;
+        read_bytes(reader, &mut self.frame_buffer)?;
+        read_bytes(reader, &mut self.index_buffer)?;
+        read_bytes(reader, &mut self.palette_buffer)?;
+        self.draw_pixel_cycles = read_u16(reader)?;
+        self.oam_scan_blocked = read_bool(reader)?;
+        self.stat_line = read_bool(reader)?;
+        if version >= 2 {
+            self.set_object_priority(read_u8(reader)?);
+            self.cgb_mode = read_bool(reader)?;
+            self.set_vram_bank(read_u8(reader)?);
+            read_bytes(reader, &mut self.vram_bank_1)?;
+        } else {
+            // the first format predates the CGB mode, the games ran in DMG mode which draws the
+            // sprites by X coordinate and has a single VRAM bank
+            self.object_priority = ObjectPriority::XCoordinate;
+            self.cgb_mode = false;
+            self.vram_bank = 0;
+            self.vram_bank_1 = [0xFF; VRAM_SIZE as usize];
+        }
+        #[cfg(debug_assertions)]
+        {
+            self.cycles_since_vblank = 0;
+        }
+
+        // the accurate path draws the current line again from its first pixel
+        if self.mode == GpuMode::DrawPixel {
+            self.scan_oam();
+            self.begin_line();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod gpu_tests {
+    use super::*;
+
+    // compare the region of the frame buffer from its top left pixel (x, y) with the expected
+    // rows of color indexes, 0 for white to 3 for black. All the differing pixels are reported
+    #[track_caller]
+    fn assert_frame_region(gpu: &Gpu, x: usize, y: usize, expected_rows: &[&str]) {
+        let mut mismatches = Vec::new();
+        for (row, expected_row) in expected_rows.iter().enumerate() {
+            for (column, index) in expected_row.bytes().enumerate() {
+                let (pixel_x, pixel_y) = (x + column, y + row);
+                let expected = PixelColor::from_index(index - b'0');
+                let actual = gpu.frame_buffer[pixel_y * SCREEN_WIDTH + pixel_x];
+                if actual != u8::from(expected) {
+                    mismatches.push(format!("({}, {}): expected {:?}, got {:?}", pixel_x, pixel_y, expected, PixelColor::try_from(actual)));
+                }
+            }
+        }
+
+        assert!(mismatches.is_empty(), "{} pixels differ from ({}, {}):\n{}", mismatches.len(), x, y, mismatches.join("\n"));
+    }
+
+    #[test]
+    fn test_read_write_vram() {
+        let mut gpu = Gpu::new();
+        gpu.write_vram(0x0001, 0xAA);
+        gpu.write_vram(0x0002, 0x55);
+        gpu.write_vram(0x0010, 0xAA);
+        assert_eq!(gpu.read_vram(0x0001), 0xAA);
+        assert_eq!(gpu.read_vram(0x0002), 0x55);
+        assert_eq!(gpu.read_vram(0x0010), 0xAA);
+    }
+
+    #[test]
+    fn test_window_line_counter() {
+        let mut gpu = Gpu::new();
+        gpu.vram = [0x00; VRAM_SIZE as usize];
+        gpu.background_display_enabled = true;
+        gpu.background_tile_data_area = true;
+        gpu.window_tile_map_area = TileMapArea::X9C00;
+        gpu.window_x_offset = WINDOW_X_OFFSET;
+        gpu.window_y_offset = 0;
+
+        // the first window tile row is white, the second one is black
+        for row in 0..16 {
+            gpu.write_vram(0x0010 + row, 0xFF);
+        }
+        for tile in 0..32 {
+            gpu.write_vram(0x1C20 + tile, 0x01);
+        }
+
+        // the window is disabled from line 4 to 9
+        for line in 0..20 {
+            gpu.current_line = line;
+            gpu.window_display_enabled = !(4..10).contains(&line);
+            gpu.draw_static_line();
+        }
+
+        // line 10 draws the window line 4, the second tile row starts at line 14
+        let pixel = |line: usize| gpu.frame_buffer[line * SCREEN_WIDTH];
+        assert_eq!(pixel(3), u8::from(PixelColor::WHITE));
+        assert_eq!(pixel(10), u8::from(PixelColor::WHITE));
+        assert_eq!(pixel(13), u8::from(PixelColor::WHITE));
+        assert_eq!(pixel(14), u8::from(PixelColor::BLACK));
+        assert_eq!(gpu.window_line_counter, 14);
+    }
+
+    #[test]
+    fn test_window_x_edges() {
+        let mut nvic = Nvic::new();
+        let white = u8::from(PixelColor::WHITE);
+        let light_gray = u8::from(PixelColor::LIGHT_GRAY);
+        let black = u8::from(PixelColor::BLACK);
+
+        // screen X range of the first black window tile, the next ones are light gray
+        for (wx, first_tile) in [(0, 0..1), (3, 0..4), (7, 0..8), (165, 158..160), (166, 0..0), (255, 0..0)] {
+            for accuracy in [PpuAccuracy::Fast, PpuAccuracy::Accurate] {
+                let mut gpu = Gpu::new();
+                gpu.accuracy = accuracy;
+                gpu.load_vram(0, &[0x00; VRAM_SIZE as usize]);
+                gpu.load_vram(0x0010, &[0xFF; TILE_SIZE_IN_BYTES as usize]);
+                gpu.load_vram(0x0020, &[0xFF, 0x00].repeat(TILE_SIZE_IN_BYTES as usize / 2));
+                gpu.load_vram(0x1C00, &[0x02; 32]);
+                gpu.load_vram(0x1C00, &[0x01]);
+                gpu.set_background_palette(0xE4);
+                gpu.set_window_x(wx);
+                gpu.set_window_y(0);
+                gpu.control_from_byte(0xF1);
+
+                while gpu.mode != GpuMode::HorizontalBlank {
+                    gpu.run(1, &mut nvic);
+                }
+
+                let window_start = if wx >= WINDOW_X_HIDDEN { SCREEN_WIDTH } else { first_tile.start };
+                let expected: Vec<u8> = (0..SCREEN_WIDTH).map(|x| match x {
+                    _ if first_tile.contains(&x) => black,
+                    _ if x >= window_start => light_gray,
+                    _ => white,
+                }).collect();
+                assert!(gpu.frame_buffer[..SCREEN_WIDTH] == expected[..], "WX {} {:?}", wx, accuracy);
+                assert_eq!(gpu.window_flag, wx < WINDOW_X_HIDDEN);
+            }
+        }
+    }
+
+    #[test]
+    fn test_draw_rgba_frame() {
+        let mut gpu = Gpu::new();
+        gpu.frame_buffer[0] = u8::from(PixelColor::DARK_GRAY);
+
+        // nothing is displayed while the LCD is off
+        let rgba = gpu.draw_rgba_frame(SGB_FRAME_WIDTH, SGB_FRAME_HEIGHT);
+        assert_eq!(rgba.len(), 256 * 224 * 4);
+        assert!(rgba.chunks(4).all(|pixel| pixel[3] == 0x00));
+
+        gpu.lcd_display_enabled = true;
+        let rgba = gpu.draw_rgba_frame(SGB_FRAME_WIDTH, SGB_FRAME_HEIGHT);
+        let pixel = |x: usize, y: usize| &rgba[(y * SGB_FRAME_WIDTH + x) * 4..(y * SGB_FRAME_WIDTH + x + 1) * 4];
+
+        // the border around the screen is transparent
+        assert_eq!(pixel(0, 0)[3], 0x00);
+        assert_eq!(pixel(47, 40)[3], 0x00);
+        assert_eq!(pixel(208, 183)[3], 0x00);
+
+        // the screen is centered and opaque
+        assert_eq!(pixel(48, 40), [96, 96, 96, 0xFF]);
+        assert_eq!(pixel(207, 183)[3], 0xFF);
+        assert_eq!(rgba.chunks(4).filter(|pixel| pixel[3] == 0xFF).count(), SCREEN_WIDTH * SCREEN_HEIGHT);
+
+        // without border, the frame is the screen
+        assert_eq!(gpu.draw_rgba_frame(SCREEN_WIDTH, SCREEN_HEIGHT)[0..4], [96, 96, 96, 0xFF]);
+    }
+
+    #[test]
+    fn test_draw_background_map() {
+        let mut gpu = Gpu::new();
+        gpu.background_tile_data_area = true;
+        gpu.vram = [0x00; VRAM_SIZE as usize];
+
+        // tile 1 is black, drawn at the last tile of the map
+        for row in 0..16 {
+            gpu.write_vram(0x0010 + row, 0xFF);
+        }
+        gpu.write_vram(0x1800 + 32 * 32 - 1, 0x01);
+
+        let map = gpu.draw_background_map();
+        assert_eq!(map.len(), BACKGROUND_MAP_SIZE * BACKGROUND_MAP_SIZE);
+        assert_eq!(map[0], u8::from(PixelColor::WHITE));
+        assert_eq!(map[255 * BACKGROUND_MAP_SIZE + 255], u8::from(PixelColor::BLACK));
+        assert_eq!(map[247 * BACKGROUND_MAP_SIZE + 247], u8::from(PixelColor::WHITE));
+    }
+
+    #[test]
+    fn test_draw_line() {
+        let mut gpu = Gpu::new();
+
+        // init GPU
+        gpu.background_display_enabled = true;
+        gpu.background_tile_data_area = true;
+        gpu.background_tile_map_area = TileMapArea::X9800;
         gpu.current_line = 8; // first line of the second tile row
 
         // init VRAM
@@ -743,12 +1662,267 @@ mod gpu_tests {
         gpu.write_vram(0x1821, 0x21);
 
         // draw the line in the frame buffer
-        gpu.draw_line();
+        gpu.draw_static_line();
 
-        // check frame buffer
-        // line 8 * 160 = 1280 / 0x0500
-        assert_eq!(gpu.frame_buffer[0x0500], PixelColor::BLACK as u8);
-        assert_eq!(gpu.frame_buffer[0x0508], PixelColor::BLACK as u8);
+        // the first pixel of both tiles is black
+        assert_frame_region(&gpu, 0, 8, &["3000000030000000"]);
+    }
+
+    #[test]
+    fn test_draw_tile_block() {
+        let mut gpu = Gpu::new();
+        gpu.background_display_enabled = true;
+        gpu.background_tile_data_area = true;
+        gpu.background_tile_map_area = TileMapArea::X9800;
+        gpu.set_background_palette(0xE4);
+
+        // tile 1 is a diagonal of color 1, tile 2 a checkerboard of color 3
+        for row in 0..TILE_ROW_SIZE_IN_PIXEL as u16 {
+            gpu.load_vram(0x0010 + 2 * row, &[0x80 >> row, 0x00]);
+            let checkerboard = if row % 2 == 0 { 0xAA } else { 0x55 };
+            gpu.load_vram(0x0020 + 2 * row, &[checkerboard, checkerboard]);
+        }
+
+        // the tiles alternate in a 2x2 block at the top left of the background
+        gpu.load_vram(0x1800, &[0x01, 0x02]);
+        gpu.load_vram(0x1820, &[0x02, 0x01]);
+        gpu.render_frame();
+
+        assert_frame_region(&gpu, 0, 0, &[
+            "1000000030303030",
+            "0100000003030303",
+            "0010000030303030",
+            "0001000003030303",
+            "0000100030303030",
+            "0000010003030303",
+            "0000001030303030",
+            "0000000103030303",
+            "3030303010000000",
+            "0303030301000000",
+            "3030303000100000",
+            "0303030300010000",
+            "3030303000001000",
+            "0303030300000100",
+            "3030303000000010",
+            "0303030300000001",
+        ]);
+    }
+
+    #[test]
+    #[should_panic(expected = "2 pixels differ from (0, 0):\n(1, 0): expected WHITE, got Ok(BLACK)\n(0, 1): expected LIGHT_GRAY, got Ok(BLACK)")]
+    fn test_assert_frame_region() {
+        // the frame buffer is black at power on
+        let gpu = Gpu::new();
+        assert_frame_region(&gpu, 0, 0, &["30", "13"]);
+    }
+
+    #[test]
+    fn test_draw_line_bulk_load() {
+        let mut gpu = Gpu::new();
+        gpu.background_display_enabled = true;
+        gpu.background_tile_data_area = true;
+        gpu.background_tile_map_area = TileMapArea::X9800;
+        gpu.current_line = 8;
+
+        // tiles 32 and 33, then the second row of the tile map
+        gpu.load_vram(0x0200, &[0x80, 0x80]);
+        gpu.load_vram(0x0210, &[0x80, 0x80]);
+        gpu.load_vram(0x1820, &[0x20, 0x21]);
+
+        gpu.draw_static_line();
+
+        assert_eq!(gpu.frame_buffer[0x0500], u8::from(PixelColor::BLACK));
+        assert_eq!(gpu.frame_buffer[0x0508], u8::from(PixelColor::BLACK));
+    }
+
+    #[test]
+    fn test_draw_sprite() {
+        let mut gpu = Gpu::new();
+        gpu.object_display_enabled = true;
+        gpu.current_line = 8;
+
+        // a single sprite using tile 0x22 at the top left of line 8, the others are hidden
+        let mut oam = [0x00; OAM_SIZE as usize];
+        oam[0..4].copy_from_slice(&[8 + SPRITE_Y_OFFSET as u8, SPRITE_X_OFFSET as u8, 0x22, 0x00]);
+        gpu.load_oam(&oam);
+        gpu.load_vram(0x0220, &[0xC0, 0x80]);
+
+        gpu.draw_static_line();
+
+        assert_eq!(gpu.frame_buffer[0x0500], u8::from(PixelColor::BLACK));
+        assert_eq!(gpu.frame_buffer[0x0501], u8::from(PixelColor::LIGHT_GRAY));
+
+        // the host colors follow the palette of each pixel
+        assert_eq!(gpu.palette_buffer[0x0500], PALETTE_OBJ_0);
+        assert_eq!(gpu.palette_buffer[0x0502], PALETTE_BG);
+    }
+
+    #[test]
+    fn test_sprite_transparency() {
+        let mut gpu = Gpu::new();
+        gpu.load_vram(0, &[0x00; VRAM_SIZE as usize]);
+        gpu.background_display_enabled = true;
+        gpu.background_tile_data_area = true;
+        gpu.background_tile_map_area = TileMapArea::X9800;
+        gpu.object_display_enabled = true;
+        gpu.set_background_palette(0xE4);
+        gpu.set_object_palette_0(0xE4);
+        gpu.current_line = 8;
+
+        // background pixels 0-3 have color index 0, pixels 4-7 color index 2
+        gpu.load_vram(0x1820, &[0x20]);
+        gpu.load_vram(0x0200, &[0x00, 0x0F]);
+        // sprite pixels 0-1 and 4-5 have color index 1, the others color index 0
+        gpu.load_vram(0x0010, &[0xCC, 0x00]);
+        // a lower priority sprite at the same position, all pixels have color index 3
+        gpu.load_vram(0x0020, &[0xFF, 0xFF]);
+
+        let mut oam = [0x00; OAM_SIZE as usize];
+        oam[0..4].copy_from_slice(&[24, 8, 0x01, 0x00]);
+        gpu.load_oam(&oam);
+        let line = |gpu: &Gpu| -> Vec<PixelColor> {
+            gpu.frame_buffer[0x0500..0x0508].iter().map(|shade| PixelColor::try_from(*shade).unwrap()).collect()
+        };
+        use PixelColor::{BLACK, DARK_GRAY, LIGHT_GRAY, WHITE};
+
+        // a sprite without priority is drawn over any background color, its color 0 is transparent
+        gpu.draw_static_line();
+        assert_eq!(line(&gpu), [LIGHT_GRAY, LIGHT_GRAY, WHITE, WHITE, LIGHT_GRAY, LIGHT_GRAY, DARK_GRAY, DARK_GRAY]);
+
+        // a sprite behind the background is only drawn over the background color 0
+        oam[3] = 0x80;
+        gpu.load_oam(&oam);
+        gpu.draw_static_line();
+        assert_eq!(line(&gpu), [LIGHT_GRAY, LIGHT_GRAY, WHITE, WHITE, DARK_GRAY, DARK_GRAY, DARK_GRAY, DARK_GRAY]);
+
+        // the lower priority sprite shows through the transparent pixels of the first one, but
+        // it's hidden where the first one is opaque, even if the background is drawn there
+        oam[4..8].copy_from_slice(&[24, 8, 0x02, 0x00]);
+        gpu.load_oam(&oam);
+        gpu.draw_static_line();
+        assert_eq!(line(&gpu), [LIGHT_GRAY, LIGHT_GRAY, BLACK, BLACK, DARK_GRAY, DARK_GRAY, BLACK, BLACK]);
+
+        // a disabled background is white whatever its palette, and transparent to all sprites
+        oam[4..8].copy_from_slice(&[0x00; 4]);
+        gpu.load_oam(&oam);
+        gpu.set_background_palette(0x1B);
+        gpu.background_display_enabled = false;
+        gpu.draw_static_line();
+        assert_eq!(line(&gpu), [LIGHT_GRAY, LIGHT_GRAY, WHITE, WHITE, LIGHT_GRAY, LIGHT_GRAY, WHITE, WHITE]);
+    }
+
+    #[test]
+    fn test_object_priority() {
+        let mut gpu = Gpu::new();
+        gpu.object_display_enabled = true;
+        gpu.set_object_palette_0(0xE4);
+        gpu.current_line = 8;
+
+        // the first sprite in OAM is dark gray from X = 4, the second one is black from X = 0
+        gpu.load_vram(0x0010, &[0x00, 0xFF]);
+        gpu.load_vram(0x0020, &[0xFF, 0xFF]);
+        let mut oam = [0x00; OAM_SIZE as usize];
+        oam[0..4].copy_from_slice(&[8 + SPRITE_Y_OFFSET as u8, 4 + SPRITE_X_OFFSET as u8, 0x01, 0x00]);
+        oam[4..8].copy_from_slice(&[8 + SPRITE_Y_OFFSET as u8, SPRITE_X_OFFSET as u8, 0x02, 0x00]);
+        gpu.load_oam(&oam);
+        let overlap = |gpu: &Gpu| PixelColor::try_from(gpu.frame_buffer[0x0506]).unwrap();
+
+        // DMG: the leftmost sprite is on top
+        gpu.draw_static_line();
+        assert_eq!(overlap(&gpu), PixelColor::BLACK);
+
+        // CGB: the first sprite in OAM is on top
+        gpu.set_object_priority(0x00);
+        assert_eq!(gpu.get_object_priority(), 0x00);
+        gpu.draw_static_line();
+        assert_eq!(overlap(&gpu), PixelColor::DARK_GRAY);
+
+        // DMG: the first sprite in OAM is on top of the ones at the same X
+        gpu.set_object_priority(0x01);
+        oam[5] = 4 + SPRITE_X_OFFSET as u8;
+        gpu.load_oam(&oam);
+        gpu.draw_static_line();
+        assert_eq!(overlap(&gpu), PixelColor::DARK_GRAY);
+    }
+
+    #[test]
+    fn test_cgb_background_priority() {
+        let mut gpu = Gpu::new();
+
+        // CGB background with the master priority set
+        gpu.cgb_mode = true;
+        gpu.background_display_enabled = true;
+        gpu.background_tile_data_area = true;
+        gpu.background_tile_map_area = TileMapArea::X9800;
+        gpu.set_background_palette(0xE4);
+        gpu.current_line = 8;
+
+        // first pixel of tile 32 has color index 1, second one has color index 3, others 0
+        gpu.write_vram(0x0200, 0xC0);
+        gpu.write_vram(0x0201, 0x40);
+        gpu.write_vram(0x1820, 0x20);
+        // this tile has the background priority attribute
+        gpu.vram_bank_1[0x1820] = BG_ATTRIBUTE_PRIORITY;
+
+        // a sprite above the background on the same pixels, made of color index 1
+        gpu.object_display_enabled = true;
+        gpu.set_object_palette_0(0x1B);
+        gpu.write_oam(0, 24);
+        gpu.write_oam(1, 8);
+        gpu.write_oam(2, 0x01);
+        gpu.write_oam(3, 0x00);
+        gpu.write_vram(0x0010, 0xFF);
+        gpu.write_vram(0x0011, 0x00);
+
+        // background colors 1-3 are drawn over the sprite, color 0 stays behind
+        gpu.draw_static_line();
+        assert_eq!(gpu.frame_buffer[0x0500], u8::from(PixelColor::LIGHT_GRAY));
+        assert_eq!(gpu.frame_buffer[0x0501], u8::from(PixelColor::BLACK));
+        assert_eq!(gpu.frame_buffer[0x0502], u8::from(PixelColor::DARK_GRAY));
+
+        // without the master priority, the sprite wins
+        gpu.background_display_enabled = false;
+        gpu.draw_static_line();
+        assert_eq!(gpu.frame_buffer[0x0500], u8::from(PixelColor::DARK_GRAY));
+        assert_eq!(gpu.frame_buffer[0x0501], u8::from(PixelColor::DARK_GRAY));
+        // but the background is still displayed, the next tile is filled with color 3
+        assert_eq!(gpu.frame_buffer[0x0508], u8::from(PixelColor::BLACK));
+
+        // the attribute is ignored on DMG
+        gpu.cgb_mode = false;
+        gpu.background_display_enabled = true;
+        gpu.draw_static_line();
+        assert_eq!(gpu.frame_buffer[0x0500], u8::from(PixelColor::DARK_GRAY));
+        assert_eq!(gpu.frame_buffer[0x0501], u8::from(PixelColor::DARK_GRAY));
+    }
+
+    #[test]
+    fn test_index_buffer() {
+        let mut gpu = Gpu::new();
+
+        // init GPU with an inverted palette
+        gpu.background_display_enabled = true;
+        gpu.background_tile_data_area = true;
+        gpu.background_tile_map_area = TileMapArea::X9800;
+        gpu.set_background_palette(0x1B);
+        gpu.current_line = 8;
+
+        // first pixel of tile 32 has color index 1, second one has color index 3
+        gpu.write_vram(0x0200, 0xC0);
+        gpu.write_vram(0x0201, 0x40);
+        gpu.write_vram(0x1820, 0x20);
+
+        gpu.draw_static_line();
+
+        // the index buffer holds the raw pixel values
+        assert_eq!(gpu.index_buffer[0x0500], 1);
+        assert_eq!(gpu.index_buffer[0x0501], 3);
+        assert_eq!(gpu.index_buffer[0x0502], 0);
+
+        // the frame buffer holds the shades mapped by the palette
+        assert_eq!(gpu.frame_buffer[0x0500], u8::from(PixelColor::DARK_GRAY));
+        assert_eq!(gpu.frame_buffer[0x0501], u8::from(PixelColor::WHITE));
+        assert_eq!(gpu.frame_buffer[0x0502], u8::from(PixelColor::BLACK));
     }
 
     #[test]
@@ -785,18 +1959,18 @@ mod gpu_tests {
 
         // draw the line in the frame buffer
         gpu.current_line = 8; // first line of the second tile row -> line 9
-        gpu.draw_line();
+        gpu.draw_static_line();
 
         gpu.current_line = 128; // first line of the 16th tile row -> line 129
-        gpu.draw_line();
+        gpu.draw_static_line();
 
         // check frame buffer
         // line 8 * 160 = 1280 / 0x0500
-        assert_eq!(gpu.frame_buffer[0x0500], PixelColor::BLACK as u8);
-        assert_eq!(gpu.frame_buffer[0x0508], PixelColor::BLACK as u8);
+        assert_eq!(gpu.frame_buffer[0x0500], u8::from(PixelColor::BLACK));
+        assert_eq!(gpu.frame_buffer[0x0508], u8::from(PixelColor::BLACK));
         // line 128 * 160 = 20480 / 0x5000
-        assert_eq!(gpu.frame_buffer[0x5000], PixelColor::BLACK as u8);
-        assert_eq!(gpu.frame_buffer[0x5008], PixelColor::BLACK as u8);
+        assert_eq!(gpu.frame_buffer[0x5000], u8::from(PixelColor::BLACK));
+        assert_eq!(gpu.frame_buffer[0x5008], u8::from(PixelColor::BLACK));
     }
 
     #[test]
@@ -822,12 +1996,41 @@ mod gpu_tests {
         gpu.write_vram(0x1821, 0x21);
 
         // draw the line in the frame buffer
-        gpu.draw_line();
+        gpu.draw_static_line();
 
         // check frame buffer
         // line 8 * 160 = 1280 / 0x0500
-        assert_eq!(gpu.frame_buffer[0x0500], PixelColor::BLACK as u8);
-        assert_eq!(gpu.frame_buffer[0x0508], PixelColor::BLACK as u8);
+        assert_eq!(gpu.frame_buffer[0x0500], u8::from(PixelColor::BLACK));
+        assert_eq!(gpu.frame_buffer[0x0508], u8::from(PixelColor::BLACK));
+    }
+
+    #[test]
+    fn test_tile_map_area_x9c00() {
+        let mut gpu = Gpu::new();
+        gpu.vram = [0x00; VRAM_SIZE as usize];
+
+        // init GPU: background enabled, $8000 tile data, X9C00 tile map selected by LCDC bit 3
+        gpu.control_from_byte(0x19);
+        assert!(gpu.background_tile_map_area == TileMapArea::X9C00);
+        gpu.current_line = 8; // first line of the second tile row
+
+        // init VRAM
+        // tile 32 has its first pixel black, tile 0 is white
+        gpu.write_vram(0x0200, 0x80);
+        gpu.write_vram(0x0201, 0x80);
+
+        // only the X9C00 tile map points to tile 32
+        gpu.write_vram(0x1C20, 0x20);
+
+        // draw the line in the frame buffer without any scrolling
+        gpu.draw_static_line();
+        assert_eq!(gpu.frame_buffer[0x0500], u8::from(PixelColor::BLACK));
+        assert_eq!(gpu.frame_buffer[0x0501], u8::from(PixelColor::WHITE));
+
+        // the X9800 tile map is used again once LCDC bit 3 is cleared
+        gpu.control_from_byte(0x11);
+        gpu.draw_static_line();
+        assert_eq!(gpu.frame_buffer[0x0500], u8::from(PixelColor::WHITE));
     }
 
     #[test]
@@ -855,22 +2058,22 @@ mod gpu_tests {
         gpu.viewport_y_offset = 1;
         gpu.viewport_x_offset = 0;
         gpu.current_line = 7; // line 8 now corresponds to line 9
-        gpu.draw_line();
+        gpu.draw_static_line();
 
         // check frame buffer
         // line 9 * 160 = 1440 / 0x05A0
-        assert_eq!(gpu.frame_buffer[0x05A0], PixelColor::BLACK as u8);
-        assert_eq!(gpu.frame_buffer[0x05A8], PixelColor::BLACK as u8);
+        assert_eq!(gpu.frame_buffer[0x05A0], u8::from(PixelColor::BLACK));
+        assert_eq!(gpu.frame_buffer[0x05A8], u8::from(PixelColor::BLACK));
 
         // scroll on x axis and draw the line
         gpu.viewport_y_offset = 0;
         gpu.viewport_x_offset = 1;
         gpu.current_line = 8;
-        gpu.draw_line();
+        gpu.draw_static_line();
 
         // check frame buffer
         // line 8 * 160 = 1280 / 0x0500
-        assert_eq!(gpu.frame_buffer[0x0507], PixelColor::BLACK as u8);
+        assert_eq!(gpu.frame_buffer[0x0507], u8::from(PixelColor::BLACK));
     }
 
     #[test]
@@ -907,14 +2110,370 @@ mod gpu_tests {
 
         // check frame buffer
         // line 0 * 160 = 0 / 0x0000
-        assert_eq!(gpu.frame_buffer[0x0000], PixelColor::BLACK as u8);
-        assert_eq!(gpu.frame_buffer[0x0008], PixelColor::BLACK as u8);
+        assert_eq!(gpu.frame_buffer[0x0000], u8::from(PixelColor::BLACK));
+        assert_eq!(gpu.frame_buffer[0x0008], u8::from(PixelColor::BLACK));
         // line 8 * 160 = 1280 / 0x0500
-        assert_eq!(gpu.frame_buffer[0x0500], PixelColor::BLACK as u8);
-        assert_eq!(gpu.frame_buffer[0x0508], PixelColor::BLACK as u8);
+        assert_eq!(gpu.frame_buffer[0x0500], u8::from(PixelColor::BLACK));
+        assert_eq!(gpu.frame_buffer[0x0508], u8::from(PixelColor::BLACK));
         // line 128 * 160 = 20480 / 0x5000
-        assert_eq!(gpu.frame_buffer[0x5000], PixelColor::BLACK as u8);
-        assert_eq!(gpu.frame_buffer[0x5008], PixelColor::BLACK as u8);
+        assert_eq!(gpu.frame_buffer[0x5000], u8::from(PixelColor::BLACK));
+        assert_eq!(gpu.frame_buffer[0x5008], u8::from(PixelColor::BLACK));
+    }
+
+    // background, window and sprites drawn from a pseudo random VRAM and OAM
+    fn create_scene(accuracy: PpuAccuracy) -> Gpu {
+        let mut gpu = Gpu::new();
+        gpu.accuracy = accuracy;
+        gpu.control_from_byte(0xF3);
+        gpu.set_scx(3);
+        gpu.set_scy(5);
+        gpu.set_window_x(87);
+        gpu.set_window_y(40);
+        gpu.set_background_palette(0xE4);
+        gpu.set_object_palette_0(0xD2);
+        gpu.set_object_palette_1(0x1B);
+
+        let vram: Vec<u8> = (0..VRAM_SIZE as usize).map(|i| (i * 31 + i / 7) as u8).collect();
+        gpu.load_vram(0, &vram);
+        let mut oam = [0x00; OAM_SIZE as usize];
+        for (i, byte) in oam.iter_mut().enumerate() {
+            *byte = (i * 53 + i / 3) as u8 % 168;
+        }
+        gpu.load_oam(&oam);
+
+        gpu
+    }
+
+    #[test]
+    fn test_accuracy_paths() {
+        let mut nvic = Nvic::new();
+        let mut fast = create_scene(PpuAccuracy::Fast);
+        let mut accurate = create_scene(PpuAccuracy::Accurate);
+
+        while fast.frame_count == 0 {
+            fast.run(4, &mut nvic);
+            accurate.run(4, &mut nvic);
+        }
+
+        // the frames are the same when the registers aren't modified during the lines
+        assert_eq!(accurate.frame_count, 1);
+        assert!(fast.frame_buffer.iter().any(|shade| *shade != fast.frame_buffer[0]));
+        assert!(fast.frame_buffer[..] == accurate.frame_buffer[..]);
+        assert!(fast.palette_buffer[..] == accurate.palette_buffer[..]);
+        assert!(fast.palette_buffer.iter().any(|palette| *palette != PALETTE_BG));
+        assert_eq!(fast.window_line_counter, accurate.window_line_counter);
+
+        // the CGB background attributes are fetched with the tiles by the accurate path
+        let mut fast = create_scene(PpuAccuracy::Fast);
+        let mut accurate = create_scene(PpuAccuracy::Accurate);
+        let attributes: Vec<u8> = (0..VRAM_SIZE as usize).map(|i| (i * 17 + i / 5) as u8).collect();
+        for gpu in [&mut fast, &mut accurate] {
+            gpu.cgb_mode = true;
+            gpu.vram_bank_1.copy_from_slice(&attributes);
+        }
+        while fast.frame_count == 0 {
+            fast.run(4, &mut nvic);
+            accurate.run(4, &mut nvic);
+        }
+        assert!(fast.frame_buffer[..] == accurate.frame_buffer[..]);
+        assert!(fast.palette_buffer[..] == accurate.palette_buffer[..]);
+
+        // a palette written while a line is drawn only affects the next pixels of the accurate path
+        let mut fast = create_scene(PpuAccuracy::Fast);
+        let mut accurate = create_scene(PpuAccuracy::Accurate);
+        fast.control_from_byte(0x81);
+        accurate.control_from_byte(0x81);
+        fast.load_vram(0, &[0x00; VRAM_SIZE as usize]);
+        accurate.load_vram(0, &[0x00; VRAM_SIZE as usize]);
+        fast.set_background_palette(0x00);
+        accurate.set_background_palette(0x00);
+
+        while fast.mode != GpuMode::DrawPixel {
+            fast.run(4, &mut nvic);
+            accurate.run(4, &mut nvic);
+        }
+        for _ in 0..(2 * FETCH_TILE_DOTS + 80) / 4 {
+            fast.run(4, &mut nvic);
+            accurate.run(4, &mut nvic);
+        }
+        fast.set_background_palette(0xFF);
+        accurate.set_background_palette(0xFF);
+        while fast.mode != GpuMode::HorizontalBlank {
+            fast.run(4, &mut nvic);
+            accurate.run(4, &mut nvic);
+        }
+
+        assert!(fast.frame_buffer[..SCREEN_WIDTH].iter().all(|shade| *shade == u8::from(PixelColor::BLACK)));
+        assert_eq!(accurate.frame_buffer[0], u8::from(PixelColor::WHITE));
+        assert_eq!(accurate.frame_buffer[SCREEN_WIDTH - 1], u8::from(PixelColor::BLACK));
+    }
+
+    #[test]
+    fn test_mid_line_scroll() {
+        let mut nvic = Nvic::new();
+        let mut gpus = [Gpu::new(), Gpu::new()];
+        gpus[1].accuracy = PpuAccuracy::Accurate;
+
+        for gpu in gpus.iter_mut() {
+            // the right half of the first tile row is black, tile 1, the left half white
+            gpu.load_vram(0, &[0x00; VRAM_SIZE as usize]);
+            gpu.load_vram(0x0010, &[0xFF; TILE_SIZE_IN_BYTES as usize]);
+            gpu.load_vram(0x1810, &[0x01; 16]);
+            gpu.control_from_byte(0x91);
+            gpu.set_background_palette(0xE4);
+
+            while gpu.mode != GpuMode::DrawPixel {
+                gpu.run(4, &mut nvic);
+            }
+            // SCX is written once about 80 pixels are drawn
+            for _ in 0..(2 * FETCH_TILE_DOTS + 80) / 4 {
+                gpu.run(4, &mut nvic);
+            }
+            gpu.set_scx(128);
+            while gpu.mode != GpuMode::HorizontalBlank {
+                gpu.run(4, &mut nvic);
+            }
+        }
+
+        // the whole line is scrolled by the fast path
+        let white = u8::from(PixelColor::WHITE);
+        let black = u8::from(PixelColor::BLACK);
+        assert!(gpus[0].frame_buffer[..128].iter().all(|shade| *shade == black));
+
+        // only the tiles fetched after the write are scrolled by the accurate path
+        let line = &gpus[1].frame_buffer[..SCREEN_WIDTH];
+        assert!(line[..80].iter().all(|shade| *shade == white));
+        assert!(line[112..128].iter().all(|shade| *shade == black));
+        assert!(line[128..].iter().all(|shade| *shade == white));
+    }
+
+    #[test]
+    fn test_fine_scroll() {
+        let mut nvic = Nvic::new();
+
+        for scx in [0, 5, 8 + 3] {
+            let mut fast = create_scene(PpuAccuracy::Fast);
+            let mut accurate = create_scene(PpuAccuracy::Accurate);
+            // background only, without the window nor the sprites
+            fast.control_from_byte(0x91);
+            accurate.control_from_byte(0x91);
+            fast.set_scx(scx);
+            accurate.set_scx(scx);
+
+            // the fine scroll lengthens the draw pixel mode of both paths
+            while accurate.mode != GpuMode::HorizontalBlank {
+                fast.run(1, &mut nvic);
+                accurate.run(1, &mut nvic);
+            }
+
+            // the pixels are aligned like the fast path, the fine scroll costs a dot per pixel
+            assert!(fast.frame_buffer[..SCREEN_WIDTH] == accurate.frame_buffer[..SCREEN_WIDTH], "SCX {}", scx);
+            assert_eq!(accurate.draw_pixel_cycles, DRAW_PIXEL_CYCLES + (scx % 8) as u16);
+            assert_eq!(fast.draw_pixel_cycles, accurate.draw_pixel_cycles);
+            assert_eq!(fast.mode, GpuMode::HorizontalBlank);
+        }
+    }
+
+    #[test]
+    fn test_sprite_mode_timing() {
+        let mut nvic = Nvic::new();
+
+        // sprites on the first line, each at the start of its own tile: a fetch lasts 11 dots
+        // only the 10 first sprites are fetched. The ones at X = 0 also last 11 dots, the ones
+        // off the right edge aren't fetched, a second sprite in a tile doesn't wait for it
+        let aligned_sprites: Vec<u8> = (0..12).map(|index| 8 + 16 * index).collect();
+        for (sprites_x, scx, fetch_dots) in [
+            (vec![], 0, 0),
+            (aligned_sprites[..1].to_vec(), 0, 11),
+            (aligned_sprites[..5].to_vec(), 0, 55),
+            (aligned_sprites[..10].to_vec(), 0, 110),
+            (aligned_sprites.clone(), 0, 110),
+            (vec![0, 168, 170], 0, 11),
+            (vec![8, 10], 0, 11 + 6),
+            (vec![8], 3, 6 + 2),
+            (vec![13], 0, 6),
+        ] {
+            for accuracy in [PpuAccuracy::Fast, PpuAccuracy::Accurate] {
+                let mut gpu = Gpu::new();
+                gpu.accuracy = accuracy;
+                let mut oam = [0x00; OAM_SIZE as usize];
+                for (index, sprite_x) in sprites_x.iter().enumerate() {
+                    oam[index * 4..index * 4 + 2].copy_from_slice(&[SPRITE_Y_OFFSET as u8, *sprite_x]);
+                }
+                gpu.load_oam(&oam);
+                gpu.control_from_byte(0x93);
+                gpu.set_scx(scx);
+
+                // count the dots of each mode of the first line
+                let mut mode_dots = [0u16; 3];
+                while gpu.current_line == 0 {
+                    match gpu.mode {
+                        GpuMode::OAMScan => mode_dots[0] += 1,
+                        GpuMode::DrawPixel => mode_dots[1] += 1,
+                        _ => mode_dots[2] += 1,
+                    }
+                    gpu.run(1, &mut nvic);
+                }
+
+                let context = format!("{:?} sprites {:?} SCX {}", accuracy, sprites_x, scx);
+                assert_eq!(gpu.line_sprites.len(), sprites_x.len().min(10), "{}", context);
+                assert_eq!(mode_dots[0], OAM_SCAN_CYCLES, "{}", context);
+                assert_eq!(mode_dots[1], DRAW_PIXEL_CYCLES + scx as u16 % 8 + fetch_dots, "{}", context);
+                assert_eq!(mode_dots.iter().sum::<u16>(), ONE_LINE_CYCLES, "{}", context);
+            }
+        }
+
+        // the disabled sprites aren't fetched
+        let mut gpu = Gpu::new();
+        gpu.load_oam(&[SPRITE_Y_OFFSET as u8; OAM_SIZE as usize]);
+        gpu.control_from_byte(0x91);
+        while gpu.mode != GpuMode::HorizontalBlank {
+            gpu.run(4, &mut nvic);
+        }
+        assert_eq!(gpu.draw_pixel_cycles, DRAW_PIXEL_CYCLES);
+    }
+
+    #[test]
+    fn test_render_frame() {
+        let mut gpu = Gpu::new();
+        gpu.load_vram(0, &[0x00; VRAM_SIZE as usize]);
+        gpu.control_from_byte(0xF1);
+        gpu.set_background_palette(0xE4);
+        gpu.set_window_x(WINDOW_X_OFFSET + 80);
+        gpu.set_window_y(72);
+        gpu.current_line = 42;
+
+        // white background with a black tile at the top left, the window is black, tile 2
+        gpu.load_vram(0x0010, &[0xFF; TILE_SIZE_IN_BYTES as usize]);
+        gpu.load_vram(0x0020, &[0xFF; TILE_SIZE_IN_BYTES as usize]);
+        gpu.load_vram(0x1800, &[0x01]);
+        gpu.load_vram(0x1C00, &[0x02; 0x400]);
+
+        gpu.render_frame();
+
+        let white = u8::from(PixelColor::WHITE);
+        let black = u8::from(PixelColor::BLACK);
+        assert_eq!(gpu.frame_buffer[0], black);
+        assert_eq!(gpu.frame_buffer[7 * SCREEN_WIDTH + 7], black);
+        assert_eq!(gpu.frame_buffer[8], white);
+        assert_eq!(gpu.frame_buffer[71 * SCREEN_WIDTH + 80], white);
+        // the window covers the bottom right quarter
+        assert_eq!(gpu.frame_buffer[72 * SCREEN_WIDTH + 79], white);
+        assert_eq!(gpu.frame_buffer[72 * SCREEN_WIDTH + 80], black);
+        assert_eq!(gpu.frame_buffer[SCREEN_WIDTH * SCREEN_HEIGHT - 1], black);
+
+        // the gpu keeps running from its current line
+        assert_eq!(gpu.current_line, 42);
+        assert_eq!(gpu.frame_count, 0);
+    }
+
+    #[test]
+    fn test_frame_count() {
+        let mut gpu = Gpu::new();
+        let mut nvic = Nvic::new();
+        gpu.lcd_display_enabled = true;
+
+        // the frame is complete when the vblank starts, not at the end of it
+        for _ in 0..(ONE_LINE_CYCLES as usize * SCREEN_HEIGHT / 4) {
+            gpu.run(4, &mut nvic);
+        }
+        assert_eq!(gpu.mode, GpuMode::VerticalBlank);
+        assert_eq!(gpu.frame_count, 1);
+
+        for _ in 0..(VERTICAL_BLANK_LINES * ONE_LINE_CYCLES as usize / 4) {
+            gpu.run(4, &mut nvic);
+        }
+        assert_eq!(gpu.frame_count, 1);
+    }
+
+    #[test]
+    fn test_load_legacy_vblank_line() {
+        let mut gpu = Gpu::new();
+        let mut nvic = Nvic::new();
+        gpu.lcd_display_enabled = true;
+        while gpu.current_line != 147 {
+            gpu.run(4, &mut nvic);
+        }
+
+        // the version 2 states count the vblank lines just before the window flag
+        let mut state: Vec<u8> = Vec::new();
+        gpu.save_state_version(&mut state, 2).unwrap();
+        let mut window_state: Vec<u8> = Vec::new();
+        gpu.window_flag = true;
+        gpu.save_state_version(&mut window_state, 2).unwrap();
+        gpu.window_flag = false;
+        let offset = (0..state.len()).find(|index| state[*index] != window_state[*index]).unwrap() - 2;
+        assert_eq!(state[offset..offset + 2], [3, 0]);
+
+        let mut loaded = Gpu::new();
+        loaded.load_state_version(&mut &state[..], 2).unwrap();
+        assert_eq!((loaded.current_line, loaded.cycles), (gpu.current_line, gpu.cycles));
+
+        // a vblank line out of the vblank, or after more cycles than counted, is rejected
+        for vblank_line in [10, 0xFFFF] {
+            state[offset..offset + 2].copy_from_slice(&u16::to_le_bytes(vblank_line));
+            let error = loaded.load_state_version(&mut &state[..], 2).unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        }
+        state[offset..offset + 2].copy_from_slice(&[9, 0]);
+        assert!(loaded.load_state_version(&mut &state[..], 2).is_err());
+    }
+
+    #[test]
+    fn test_vblank_lines() {
+        let mut gpu = Gpu::new();
+        let mut nvic = Nvic::new();
+        gpu.lcd_display_enabled = true;
+        gpu.compare_line = 153;
+
+        // LY reads each vblank line for its 456 cycles, then the next frame starts at 0
+        let mut lines = Vec::new();
+        for _ in 0..(154 * ONE_LINE_CYCLES as usize / 4) {
+            gpu.run(4, &mut nvic);
+            if lines.last() != Some(&gpu.current_line) {
+                lines.push(gpu.current_line);
+                assert_eq!(gpu.mode == GpuMode::VerticalBlank, gpu.current_line >= SCREEN_HEIGHT as u8, "LY {}", gpu.current_line);
+                // the last vblank line matches LYC like the other ones
+                assert_eq!(gpu.line_compare_state, gpu.current_line == 153, "LY {}", gpu.current_line);
+            }
+        }
+        assert_eq!(lines, (0..=153).chain(0..=0).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_vblank_watchdog() {
+        // a running ppu never trips the watchdog, whatever its rendering path
+        for accuracy in [PpuAccuracy::Fast, PpuAccuracy::Accurate] {
+            let mut gpu = Gpu::new();
+            let mut nvic = Nvic::new();
+            gpu.accuracy = accuracy;
+            gpu.control_from_byte(0x80);
+
+            for _ in 0..(5 * 154 * ONE_LINE_CYCLES as usize / 4) {
+                gpu.run(4, &mut nvic);
+            }
+            assert_eq!(gpu.frame_count, 5);
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "ppu stall: no vblank in 140452 cycles, mode DrawPixel, LY 3")]
+    fn test_vblank_watchdog_stall() {
+        let mut gpu = Gpu::new();
+        let mut nvic = Nvic::new();
+        gpu.accuracy = PpuAccuracy::Accurate;
+        gpu.control_from_byte(0x80);
+        gpu.mode = GpuMode::DrawPixel;
+        gpu.current_line = 3;
+        // the end of the line is never detected past the right edge, the draw pixel mode never
+        // completes
+        gpu.line_x = SCREEN_WIDTH as u8 + 1;
+
+        loop {
+            gpu.run(4, &mut nvic);
+        }
     }
 
     #[test]
@@ -971,12 +2530,65 @@ mod gpu_tests {
             runned_cycles += 1;
         }
 
-        // check that we are in vblank mode and vblank interrupt has been asserted
+        // the horizontal blank keeps the STAT line high into the OAM scan, no interrupt is requested
         assert_eq!(gpu.mode, GpuMode::OAMScan);
+        assert_eq!(nvic.get_interrupt(), None);
+
+        // the line falls in the draw pixel mode and rises again in the horizontal blank
+        runned_cycles = 0;
+        while runned_cycles < (OAM_SCAN_CYCLES + DRAW_PIXEL_CYCLES + 1) as u32 {
+            gpu.run(1, &mut nvic);
+            runned_cycles += 1;
+        }
+        assert_eq!(gpu.mode, GpuMode::HorizontalBlank);
         assert_eq!(nvic.get_interrupt().unwrap(), InterruptSources::STAT);
         assert_eq!(nvic.get_interrupt(), None);
     }
 
+    #[test]
+    fn test_stat_irq_blocking() {
+        let mut gpu = Gpu::new();
+        let mut nvic = Nvic::new();
+
+        nvic.master_enable(true);
+        nvic.enable_interrupt(InterruptSources::STAT, true);
+        gpu.line_compare_it_enable = true;
+        gpu.hblank_interrupt_enabled = true;
+        gpu.compare_line = 1;
+        gpu.lcd_display_enabled = true;
+
+        // count the interrupts requested on each cycle
+        let mut run_line = |gpu: &mut Gpu| {
+            let mut interrupts = 0;
+            for _ in 0..ONE_LINE_CYCLES {
+                gpu.run(1, &mut nvic);
+                if nvic.get_interrupt().is_some() {
+                    interrupts += 1;
+                }
+            }
+            interrupts
+        };
+
+        // line 0 requests the horizontal blank interrupt
+        assert_eq!(run_line(&mut gpu), 1);
+        // the LY=LYC match of line 1 starts while the horizontal blank of line 0 keeps the line high
+        // and the horizontal blank of line 1 overlaps the match: no interrupt
+        assert_eq!(gpu.current_line, 1);
+        assert!(gpu.line_compare_state);
+        assert_eq!(run_line(&mut gpu), 0);
+        // the match ends with line 2, its horizontal blank rises the line again
+        assert_eq!(run_line(&mut gpu), 1);
+
+        // enabling a source while another one keeps the line high doesn't request an interrupt
+        while gpu.mode != GpuMode::HorizontalBlank {
+            gpu.run(1, &mut nvic);
+        }
+        nvic.get_interrupt();
+        gpu.status_from_byte(0x68);
+        gpu.run(1, &mut nvic);
+        assert_eq!(nvic.get_interrupt(), None);
+    }
+
     #[test]
     fn test_vblank_stat_interrupts() {
         let mut gpu = Gpu::new();
@@ -1079,4 +2691,29 @@ mod gpu_tests {
         assert_eq!(gpu.object_palette_1.color_1, PixelColor::WHITE);
         assert_eq!(gpu.object_palette_1.color_0, PixelColor::DARK_GRAY);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_pixel_color_conversions() {
+        for index in 0..4 {
+            let color = PixelColor::from_index(index);
+
+            assert_eq!(color.to_index(), index);
+            assert_eq!(PixelColor::try_from(u8::from(color)), Ok(color));
+        }
+
+        assert_eq!(u8::from(PixelColor::WHITE), 255);
+        assert_eq!(u8::from(PixelColor::BLACK), 0);
+        assert_eq!(PixelColor::from_index(0x07), PixelColor::BLACK);
+        assert_eq!(PixelColor::try_from(0x42), Err(0x42));
+    }
+
+    #[test]
+    fn test_palette_get_color() {
+        let mut gpu = Gpu::new();
+
+        gpu.set_background_palette(0x1B);
+        assert_eq!(gpu.background_palette.get_color(0), PixelColor::BLACK);
+        assert_eq!(gpu.background_palette.get_color(3), PixelColor::WHITE);
+        assert_eq!(gpu.get_bg_pixel_color_from_palette(1), u8::from(PixelColor::DARK_GRAY));
+    }
+}
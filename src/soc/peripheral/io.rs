@@ -0,0 +1,232 @@
+use std::fmt;
+
+use crate::soc::peripheral::{IO_REGISTERS_BEGIN, IO_REGISTERS_END};
+
+// I/O registers dispatch table, each register of the 0xFF00-0xFF7F range is
+// mapped to the peripheral handling it, to the mask of its unused bits and to its name
+
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum IoHandler {
+    JOYPAD,
+    SERIAL,
+    TIMER,
+    INTERRUPT,
+    APU,
+    GPU,
+    DMA,
+    BOOT_ROM,
+    // no register at this address, reads return 0xFF and writes are ignored
+    UNMAPPED,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct IoRegister {
+    pub handler: IoHandler,
+    // unused bits are always read as 1
+    pub unused_bits: u8,
+    pub name: &'static str,
+}
+
+const fn register(handler: IoHandler, unused_bits: u8, name: &'static str) -> IoRegister {
+    IoRegister { handler, unused_bits, name }
+}
+
+pub fn io_register(address: u16) -> IoRegister {
+    match address {
+        0xFF00 => register(IoHandler::JOYPAD, 0xC0, "P1"),
+        0xFF01 => register(IoHandler::SERIAL, 0x00, "SB"),
+        0xFF02 => register(IoHandler::SERIAL, 0x7E, "SC"),
+        0xFF04 => register(IoHandler::TIMER, 0x00, "DIV"),
+        0xFF05 => register(IoHandler::TIMER, 0x00, "TIMA"),
+        0xFF06 => register(IoHandler::TIMER, 0x00, "TMA"),
+        0xFF07 => register(IoHandler::TIMER, 0xF8, "TAC"),
+        0xFF0F => register(IoHandler::INTERRUPT, 0xE0, "IF"),
+        0xFF10 => register(IoHandler::APU, 0x80, "NR10"),
+        0xFF11 => register(IoHandler::APU, 0x3F, "NR11"),           // the length is write only
+        0xFF12 => register(IoHandler::APU, 0x00, "NR12"),
+        0xFF13 => register(IoHandler::APU, 0xFF, "NR13"),           // write only
+        0xFF14 => register(IoHandler::APU, 0xBF, "NR14"),
+        0xFF16 => register(IoHandler::APU, 0x3F, "NR21"),
+        0xFF17 => register(IoHandler::APU, 0x00, "NR22"),
+        0xFF18 => register(IoHandler::APU, 0xFF, "NR23"),
+        0xFF19 => register(IoHandler::APU, 0xBF, "NR24"),
+        0xFF1A => register(IoHandler::APU, 0x7F, "NR30"),
+        0xFF1B => register(IoHandler::APU, 0xFF, "NR31"),
+        0xFF1C => register(IoHandler::APU, 0x9F, "NR32"),
+        0xFF1D => register(IoHandler::APU, 0xFF, "NR33"),
+        0xFF1E => register(IoHandler::APU, 0xBF, "NR34"),
+        0xFF20 => register(IoHandler::APU, 0xFF, "NR41"),
+        0xFF21 => register(IoHandler::APU, 0x00, "NR42"),
+        0xFF22 => register(IoHandler::APU, 0x00, "NR43"),
+        0xFF23 => register(IoHandler::APU, 0xBF, "NR44"),
+        0xFF24 => register(IoHandler::APU, 0x00, "NR50"),
+        0xFF25 => register(IoHandler::APU, 0x00, "NR51"),
+        0xFF26 => register(IoHandler::APU, 0x70, "NR52"),
+        0xFF30..=0xFF3F => register(IoHandler::APU, 0x00, "WAVE"),  // wave pattern RAM
+        0xFF40 => register(IoHandler::GPU, 0x00, "LCDC"),
+        0xFF41 => register(IoHandler::GPU, 0x80, "STAT"),
+        0xFF42 => register(IoHandler::GPU, 0x00, "SCY"),
+        0xFF43 => register(IoHandler::GPU, 0x00, "SCX"),
+        0xFF44 => register(IoHandler::GPU, 0x00, "LY"),
+        0xFF45 => register(IoHandler::GPU, 0x00, "LYC"),
+        0xFF46 => register(IoHandler::DMA, 0x00, "DMA"),
+        0xFF47 => register(IoHandler::GPU, 0x00, "BGP"),
+        0xFF48 => register(IoHandler::GPU, 0x00, "OBP0"),
+        0xFF49 => register(IoHandler::GPU, 0x00, "OBP1"),
+        0xFF4A => register(IoHandler::GPU, 0x00, "WY"),
+        0xFF4B => register(IoHandler::GPU, 0x00, "WX"),
+        0xFF4C => register(IoHandler::BOOT_ROM, 0xFF, "KEY0"),      // CGB mode select, write only while the boot rom is mapped
+        0xFF4F => register(IoHandler::GPU, 0xFE, "VBK"),            // CGB mode only, reads 0xFF on DMG
+        0xFF50 => register(IoHandler::BOOT_ROM, 0xFF, "BOOT"),      // boot rom latch, write only
+        0xFF6C => register(IoHandler::GPU, 0xFE, "OPRI"),           // CGB mode only, reads 0xFF on DMG
+        _ => register(IoHandler::UNMAPPED, 0xFF, "UNKNOWN"),
+    }
+}
+
+// DMG I/O registers values when the boot rom hands over to the game, as read by the cpu
+// NR52 comes first since the sound registers can't be written while the sound is off
+pub const POST_BOOT_IO_REGISTERS: [(u16, u8); 42] = [
+    (0xFF26, 0xF1), // NR52, channel 1 is still playing the boot sound
+    (0xFF00, 0xCF), // P1
+    (0xFF01, 0x00), // SB
+    (0xFF02, 0x7E), // SC
+    (0xFF04, 0xAB), // DIV
+    (0xFF05, 0x00), // TIMA
+    (0xFF06, 0x00), // TMA
+    (0xFF07, 0xF8), // TAC
+    (0xFF0F, 0xE1), // IF
+    (0xFF10, 0x80), // NR10
+    (0xFF11, 0xBF), // NR11
+    (0xFF12, 0xF3), // NR12
+    (0xFF13, 0xFF), // NR13
+    (0xFF14, 0xBF), // NR14
+    (0xFF16, 0x3F), // NR21
+    (0xFF17, 0x00), // NR22
+    (0xFF18, 0xFF), // NR23
+    (0xFF19, 0xBF), // NR24
+    (0xFF1A, 0x7F), // NR30
+    (0xFF1B, 0xFF), // NR31
+    (0xFF1C, 0x9F), // NR32
+    (0xFF1D, 0xFF), // NR33
+    (0xFF1E, 0xBF), // NR34
+    (0xFF20, 0xFF), // NR41
+    (0xFF21, 0x00), // NR42
+    (0xFF22, 0x00), // NR43
+    (0xFF23, 0xBF), // NR44
+    (0xFF24, 0x77), // NR50
+    (0xFF25, 0xF3), // NR51
+    (0xFF40, 0x91), // LCDC
+    (0xFF41, 0x85), // STAT, the mode and coincidence bits depend on the gpu position
+    (0xFF42, 0x00), // SCY
+    (0xFF43, 0x00), // SCX
+    (0xFF44, 0x00), // LY, read only
+    (0xFF45, 0x00), // LYC
+    (0xFF46, 0xFF), // DMA, no transfer is started
+    (0xFF47, 0xFC), // BGP
+    (0xFF48, 0xFF), // OBP0, not initialized by the boot rom
+    (0xFF49, 0xFF), // OBP1, not initialized by the boot rom
+    (0xFF4A, 0x00), // WY
+    (0xFF4B, 0x00), // WX
+    (0xFF50, 0xFF), // boot rom disabled
+];
+
+// registers the CGB boot rom leaves in another state for the CGB games, written over the DMG values
+pub const POST_BOOT_CGB_IO_REGISTERS: [(u16, u8); 4] = [
+    (0xFF02, 0x7F), // SC
+    (0xFF46, 0x00), // DMA
+    (0xFF4C, 0x80), // KEY0, CGB mode
+    (0xFF6C, 0xFE), // OPRI, the sprites are drawn by OAM order
+];
+
+// find a register from its name, case insensitive, or from its hexadecimal address
+pub fn io_register_address(name: &str) -> Option<u16> {
+    let address = (IO_REGISTERS_BEGIN..=IO_REGISTERS_END)
+        .find(|address| io_register(*address).name.eq_ignore_ascii_case(name))
+        .or_else(|| u16::from_str_radix(name.trim_start_matches("0x"), 16).ok())?;
+
+    if (IO_REGISTERS_BEGIN..=IO_REGISTERS_END).contains(&address) {
+        Some(address)
+    } else {
+        None
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct IoWrite {
+    // address of the instruction which wrote the register
+    pub pc: u16,
+    pub address: u16,
+    pub data: u8,
+}
+
+impl fmt::Display for IoWrite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "pc {:#06x}: {} ({:#06x}) <- {:#04x}", self.pc, io_register(self.address).name, self.address, self.data)
+    }
+}
+
+// record the writes to the I/O registers, all of them or only the filtered ones
+pub struct IoWriteLog {
+    filter: Vec<u16>,
+    // updated by the soc before each cpu step
+    pub pc: u16,
+    pub writes: Vec<IoWrite>,
+}
+
+impl IoWriteLog {
+    // an empty filter logs every register
+    pub fn new(filter: Vec<u16>) -> IoWriteLog {
+        IoWriteLog {
+            filter,
+            pc: 0,
+            writes: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, address: u16, data: u8) {
+        if self.filter.is_empty() || self.filter.contains(&address) {
+            self.writes.push(IoWrite { pc: self.pc, address, data });
+        }
+    }
+}
+
+#[cfg(test)]
+mod io_tests {
+    use super::*;
+
+    #[test]
+    fn test_io_register_table() {
+        assert_eq!(io_register(0xFF00), register(IoHandler::JOYPAD, 0xC0, "P1"));
+        assert_eq!(io_register(0xFF07), register(IoHandler::TIMER, 0xF8, "TAC"));
+        assert_eq!(io_register(0xFF35).handler, IoHandler::APU);
+        assert_eq!(io_register(0xFF46).handler, IoHandler::DMA);
+        assert_eq!(io_register(0xFF50).handler, IoHandler::BOOT_ROM);
+
+        // CGB registers and holes in the map
+        assert_eq!(io_register(0xFF03), register(IoHandler::UNMAPPED, 0xFF, "UNKNOWN"));
+        assert_eq!(io_register(0xFF4D), register(IoHandler::UNMAPPED, 0xFF, "UNKNOWN"));
+        assert_eq!(io_register(0xFF7F), register(IoHandler::UNMAPPED, 0xFF, "UNKNOWN"));
+    }
+
+    #[test]
+    fn test_io_register_address() {
+        assert_eq!(io_register_address("NR12"), Some(0xFF12));
+        assert_eq!(io_register_address("lcdc"), Some(0xFF40));
+        assert_eq!(io_register_address("FF47"), Some(0xFF47));
+        assert_eq!(io_register_address("0xff47"), Some(0xFF47));
+        assert_eq!(io_register_address("C000"), None);
+        assert_eq!(io_register_address("NR99"), None);
+    }
+
+    #[test]
+    fn test_io_write_log_filter() {
+        let mut log = IoWriteLog::new(vec![0xFF40]);
+        log.pc = 0x0150;
+        log.record(0xFF42, 0x10);
+        log.record(0xFF40, 0x91);
+
+        assert_eq!(log.writes, vec![IoWrite { pc: 0x0150, address: 0xFF40, data: 0x91 }]);
+        assert_eq!(log.writes[0].to_string(), "pc 0x0150: LCDC (0xff40) <- 0x91");
+    }
+}
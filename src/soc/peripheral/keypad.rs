@@ -1,3 +1,10 @@
+use std::io::{Read, Result, Write};
+
+use crate::savestate::{SaveState, read_bool, write_bool};
+
+const NB_KEYS: usize = 8;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum GameBoyKey {
     START,
     SELECT,
@@ -22,6 +29,13 @@ pub struct Keypad {
     up: bool,
     left: bool,
     right: bool,
+    // debounce: minimum number of frames a press is latched for
+    min_hold_frames: [u8; NB_KEYS],
+    hold_counters: [u8; NB_KEYS],
+    // auto-fire: a held key is released and pressed again every this number of frames, 0 when disabled
+    turbo_frames: [u8; NB_KEYS],
+    // frames since the key has been pressed, modulo a press and release period
+    turbo_counters: [u16; NB_KEYS],
 }
 
 impl Keypad {
@@ -39,6 +53,12 @@ impl Keypad {
             up: false,
             left: false,
             right: false,
+            // debounce
+            min_hold_frames: [0; NB_KEYS],
+            hold_counters: [0; NB_KEYS],
+            // auto-fire
+            turbo_frames: [0; NB_KEYS],
+            turbo_counters: [0; NB_KEYS],
         }
     }
 
@@ -47,30 +67,30 @@ impl Keypad {
         self.direction_buttons = ((data >> 4) & 0x01) == 0;
     }
 
+    // the register is active low: the select bits read back as written and an input line is low
+    // when a key of a selected group is pressed, all the lines are high when no group is selected
     pub fn get(&self) -> u8 {
-        match (self.action_buttons, self.direction_buttons) {
-            (true, false) => {
-                (!self.action_buttons as u8) << 5
-                | (!self.direction_buttons as u8) << 4
-                | (!self.start as u8) << 3
-                | (!self.select as u8) << 2
-                | (!self.b as u8) << 1
-                | (!self.a as u8) << 0
-            },
-            (false, true) => {
-                (!self.action_buttons as u8) << 5
-                | (!self.direction_buttons as u8) << 4
-                | (!self.down as u8) << 3
-                | (!self.up as u8) << 2
-                | (!self.left as u8) << 1
-                | (!self.right as u8) << 0  
-            },
-            (false, false) => 0x00, // nothing to return
-            (true, true) => panic!("Cannot read action and direction buttons at the same time"),
+        let action_keys = [GameBoyKey::START, GameBoyKey::SELECT, GameBoyKey::B, GameBoyKey::A];
+        let direction_keys = [GameBoyKey::DOWN, GameBoyKey::UP, GameBoyKey::LEFT, GameBoyKey::RIGHT];
+
+        let mut pressed_lines = 0x00;
+        for (line, (action_key, direction_key)) in action_keys.iter().zip(direction_keys.iter()).enumerate() {
+            if (self.action_buttons && self.is_pressed(*action_key)) || (self.direction_buttons && self.is_pressed(*direction_key)) {
+                pressed_lines |= 0x08 >> line;
+            }
         }
+
+        (!self.action_buttons as u8) << 5 | (!self.direction_buttons as u8) << 4 | (!pressed_lines & 0x0F)
     }
 
     pub fn set(&mut self, key: GameBoyKey, value: bool) {
+        // latch the press on its rising edge so short taps last at least min_hold_frames
+        // the auto-fire starts with a press
+        if value && !self.is_raw_pressed(key) {
+            self.hold_counters[key as usize] = self.min_hold_frames[key as usize];
+            self.turbo_counters[key as usize] = 0;
+        }
+
         match key {
             GameBoyKey::START => self.start = value,
             GameBoyKey::SELECT => self.select = value,
@@ -82,6 +102,63 @@ impl Keypad {
             GameBoyKey::RIGHT => self.right = value,
         }
     }
+
+    pub fn set_min_hold_frames(&mut self, key: GameBoyKey, frames: u8) {
+        self.min_hold_frames[key as usize] = frames;
+    }
+
+    // 0 disables the auto-fire of the key
+    pub fn set_turbo(&mut self, key: GameBoyKey, frames: u8) {
+        self.turbo_frames[key as usize] = frames;
+        self.turbo_counters[key as usize] = 0;
+    }
+
+    pub fn end_of_frame(&mut self) {
+        for counter in self.hold_counters.iter_mut() {
+            *counter = counter.saturating_sub(1);
+        }
+
+        for (counter, frames) in self.turbo_counters.iter_mut().zip(self.turbo_frames.iter()) {
+            if *frames > 0 {
+                *counter = (*counter + 1) % (2 * *frames as u16);
+            }
+        }
+    }
+
+    // a key with auto-fire is pressed for the first frames of each period while it's held
+    pub fn is_pressed(&self, key: GameBoyKey) -> bool {
+        let turbo_frames = self.turbo_frames[key as usize] as u16;
+        let turbo_pressed = turbo_frames == 0 || self.turbo_counters[key as usize] < turbo_frames;
+
+        (self.is_raw_pressed(key) && turbo_pressed) || self.hold_counters[key as usize] > 0
+    }
+
+    fn is_raw_pressed(&self, key: GameBoyKey) -> bool {
+        match key {
+            GameBoyKey::START => self.start,
+            GameBoyKey::SELECT => self.select,
+            GameBoyKey::B => self.b,
+            GameBoyKey::A => self.a,
+            GameBoyKey::DOWN => self.down,
+            GameBoyKey::UP => self.up,
+            GameBoyKey::LEFT => self.left,
+            GameBoyKey::RIGHT => self.right,
+        }
+    }
+}
+
+// pressed keys come from the host and are not part of the state
+impl SaveState for Keypad {
+    fn save_state(&self, writer: &mut dyn Write) -> Result<()> {
+        write_bool(writer, self.action_buttons)?;
+        write_bool(writer, self.direction_buttons)
+    }
+
+    fn load_state(&mut self, reader: &mut dyn Read) -> Result<()> {
+        self.action_buttons = read_bool(reader)?;
+        self.direction_buttons = read_bool(reader)?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -114,4 +191,86 @@ mod keypad_tests {
         keypad.set(GameBoyKey::RIGHT, false);
         assert_eq!(keypad.get(), 0x25);
     }
+
+    #[test]
+    fn test_both_groups_selected() {
+        let mut keypad = Keypad::new();
+
+        // games may select both groups, a line is low if a key of either group is pressed
+        keypad.control(0x00);
+        assert_eq!(keypad.get(), 0x0F);
+
+        keypad.set(GameBoyKey::B, true);
+        assert_eq!(keypad.get(), 0x0D);
+        keypad.set(GameBoyKey::DOWN, true);
+        assert_eq!(keypad.get(), 0x05);
+
+        // the same line pulled low by both groups
+        keypad.set(GameBoyKey::LEFT, true);
+        assert_eq!(keypad.get(), 0x05);
+
+        // deselecting a group releases its lines
+        keypad.control(0x10);
+        assert_eq!(keypad.get(), 0x1D);
+    }
+
+    #[test]
+    fn test_min_hold_frames() {
+        let mut keypad = Keypad::new();
+
+        keypad.control(0x10);
+        keypad.set_min_hold_frames(GameBoyKey::A, 3);
+
+        // one frame tap: pressed and released before the end of the frame
+        keypad.set(GameBoyKey::A, true);
+        keypad.set(GameBoyKey::A, false);
+        assert_eq!(keypad.get(), 0x1E);
+
+        // the press is latched for the configured number of frames
+        keypad.end_of_frame();
+        assert_eq!(keypad.get(), 0x1E);
+        keypad.end_of_frame();
+        assert_eq!(keypad.get(), 0x1E);
+        keypad.end_of_frame();
+        assert_eq!(keypad.get(), 0x1F);
+
+        // buttons without hold configuration are released immediately
+        keypad.set(GameBoyKey::B, true);
+        keypad.set(GameBoyKey::B, false);
+        assert_eq!(keypad.get(), 0x1F);
+    }
+
+    #[test]
+    fn test_turbo() {
+        let mut keypad = Keypad::new();
+
+        keypad.control(0x10);
+        keypad.set_turbo(GameBoyKey::A, 2);
+
+        // while A is held, it's pressed for 2 frames then released for 2 frames
+        keypad.set(GameBoyKey::A, true);
+        keypad.set(GameBoyKey::B, true);
+        let mut a_states = Vec::new();
+        for _ in 0..9 {
+            a_states.push(keypad.is_pressed(GameBoyKey::A));
+            // B without auto-fire stays pressed
+            assert!(keypad.is_pressed(GameBoyKey::B));
+            keypad.end_of_frame();
+        }
+        assert_eq!(a_states, [true, true, false, false, true, true, false, false, true]);
+
+        // a new press restarts the period, a released key isn't pressed by the auto-fire
+        keypad.set(GameBoyKey::A, false);
+        assert_eq!(keypad.get(), 0x1D);
+        keypad.end_of_frame();
+        keypad.set(GameBoyKey::A, true);
+        assert_eq!(keypad.get(), 0x1C);
+
+        // the auto-fire is disabled per key
+        keypad.set_turbo(GameBoyKey::A, 0);
+        for _ in 0..4 {
+            keypad.end_of_frame();
+            assert!(keypad.is_pressed(GameBoyKey::A));
+        }
+    }
 }
\ No newline at end of file
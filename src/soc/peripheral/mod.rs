@@ -1,16 +1,28 @@
 pub mod gpu;
 pub mod nvic;
 mod timer;
+mod serial;
+pub mod apu;
 pub mod keypad;
-mod bootrom;
+pub mod bootrom;
+pub mod io;
+mod scheduler;
 
 use gpu::Gpu;
 use nvic::{Nvic, InterruptSources};
 use timer::Timer;
+use apu::Apu;
+use serial::{Serial, CYCLES_PER_BIT};
+use scheduler::{Event, Scheduler};
 use bootrom::BootRom;
 use keypad::Keypad;
+use io::{IoHandler, IoWriteLog, POST_BOOT_IO_REGISTERS, POST_BOOT_CGB_IO_REGISTERS, io_register};
 
-use crate::cartridge::Cartridge;
+use crate::cartridge::{Cartridge, HardwareMode};
+use crate::soc::CLOCK_TICK_PER_MACHINE_CYCLE;
+use crate::savestate::{SaveState, SAVE_STATE_VERSION, read_bool, read_bytes, read_u16, write_bool, write_bytes, write_u16};
+
+use std::io::{Read, Result, Write};
 
 pub const BOOT_ROM_BEGIN: u16 = 0x0000;
 pub const BOOT_ROM_END: u16 = 0x00FF;
@@ -58,10 +70,48 @@ pub const VBLANK_VECTOR: u16 = 0x40;
 pub const LCDSTAT_VECTOR: u16 = 0x48;
 pub const TIMER_VECTOR: u16 = 0x50;
 
+// read by the cpu outside of the high ram during an OAM DMA transfer. The real hardware reads the
+// byte the DMA is copying on its bus, the value is fixed here whatever the DMA source
+pub const DMA_BLOCKED_READ: u8 = 0xFF;
+
+// power on content of the RAMs, real hardware doesn't clear them
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum MemoryFill {
+    Zero,
+    Ones,
+    // pseudo random pattern, the same seed always gives the same content
+    Random(u32),
+}
+
+impl MemoryFill {
+    fn fill(&self, memory: &mut [u8], seed_offset: u32) {
+        match *self {
+            MemoryFill::Zero => memory.iter_mut().for_each(|byte| *byte = 0x00),
+            MemoryFill::Ones => memory.iter_mut().for_each(|byte| *byte = 0xFF),
+            MemoryFill::Random(seed) => {
+                // xorshift32, its state shall never be 0
+                let mut state = (seed ^ seed_offset).max(1);
+                for byte in memory.iter_mut() {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    *byte = state as u8;
+                }
+            }
+        }
+    }
+}
+
+// memory interface shared by the cpu, the debugger and the scripts
 pub trait IoAccess {
-    fn read(&self, address: u16) -> u8;
+    fn read_byte(&self, address: u16) -> u8;
+
+    fn write_byte(&mut self, address: u16, data: u8);
 
-    fn write(&mut self, address: u16, data: u8);
+    // read for the debugger, without the restrictions of the cpu accesses
+    fn peek_byte(&self, address: u16) -> u8 {
+        self.read_byte(address)
+    }
 }
 
 pub trait Interrupt {
@@ -74,24 +124,31 @@ pub trait Interrupt {
     fn master_enable(&mut self, enable: bool);
 }
 
-pub struct Peripheral {
+// route the cpu accesses to the cartridge, the memories and the I/O registers
+pub struct Bus {
     boot_rom: BootRom,
-    cartridge: Cartridge,
+    pub cartridge: Cartridge,
     working_ram: [u8; WORKING_RAM_SIZE as usize],
     zero_page: [u8; ZERO_PAGE_SIZE as usize],
     pub gpu: Gpu,
     pub nvic: Nvic,
     timer: Timer,
+    serial: Serial,
+    pub apu: Apu,
     pub keypad: Keypad,
-    // dma
-    dma_cycles: u8,
+    // events of the peripherals due after a number of clock ticks
+    scheduler: Scheduler,
+    // dma, the cycles run since the transfer start
+    dma_cycles: u16,
     dma_start_adress: u16,
     dma_enabled: bool,
+    // debug log of the I/O registers writes, disabled when None
+    pub io_write_log: Option<IoWriteLog>,
 }
 
-impl Peripheral {
-    pub fn new(cartridge: Cartridge) -> Peripheral {
-        Peripheral {
+impl Bus {
+    pub fn new(cartridge: Cartridge) -> Bus {
+        Bus {
             boot_rom: BootRom::new(),
             cartridge: cartridge,
             working_ram: [0xFF; WORKING_RAM_SIZE as usize],
@@ -99,10 +156,14 @@ impl Peripheral {
             gpu: Gpu::new(),
             nvic: Nvic::new(),
             timer: Timer::new(),
+            serial: Serial::new(),
+            apu: Apu::new(),
             keypad: Keypad::new(),
+            scheduler: Scheduler::new(),
             dma_cycles: 0,
             dma_start_adress: 0xFFFF,
             dma_enabled: false,
+            io_write_log: None,
         }
     }
 
@@ -110,19 +171,27 @@ impl Peripheral {
         // run the timer
         self.timer.run(runned_cycles, &mut self.nvic);
 
+        // fire the events of the peripherals due meanwhile
+        self.scheduler.advance(runned_cycles);
+        while let Some((due_cycle, event)) = self.scheduler.pop_due() {
+            self.fire_event(due_cycle, event);
+        }
+
+        // the gpu can't read OAM while the DMA writes it
+        self.gpu.oam_dma_active = self.dma_enabled;
+
         // run the DMA
         if self.dma_enabled {
-            // copy data
-            for mem_index in 0..runned_cycles {
-                if self.dma_cycles + mem_index < OAM_SIZE as u8 {
-                    let data = self.read(self.dma_start_adress + (self.dma_cycles + mem_index) as u16);
-                    self.gpu.write_oam((mem_index + self.dma_cycles) as usize, data);
-                }
-            }
+            // copy one byte per machine cycle
+            let copied_bytes = self.dma_cycles / CLOCK_TICK_PER_MACHINE_CYCLE as u16;
             // update internal timer
-            self.dma_cycles += runned_cycles;
+            self.dma_cycles += runned_cycles as u16;
+            for mem_index in copied_bytes..(self.dma_cycles / CLOCK_TICK_PER_MACHINE_CYCLE as u16).min(OAM_SIZE) {
+                let data = self.read_memory(self.dma_start_adress + mem_index);
+                self.gpu.write_oam(mem_index as usize, data);
+            }
             // check if we reached the end of the dma transfert
-            if self.dma_cycles >= OAM_SIZE as u8{
+            if self.dma_cycles >= OAM_SIZE * CLOCK_TICK_PER_MACHINE_CYCLE as u16 {
                 // disable dma
                 self.dma_enabled = false;
                 self.dma_cycles = 0;
@@ -132,120 +201,179 @@ impl Peripheral {
         // run the GPU 
         self.gpu.run(runned_cycles, &mut self.nvic);
 
+        // run the APU
+        self.apu.run(runned_cycles);
+
         // run the cartridge
         self.cartridge.run(runned_cycles);
     }
 
+    fn fire_event(&mut self, due_cycle: u64, event: Event) {
+        match event {
+            // the bits are shifted at a steady rate from the transfer start
+            Event::SERIAL_BIT => {
+                if self.serial.shift_bit(&mut self.nvic) {
+                    self.scheduler.schedule_at(due_cycle + CYCLES_PER_BIT as u64, Event::SERIAL_BIT);
+                }
+            }
+        }
+    }
+
+    // a new transfer shifts its first bit after a bit duration, a stopped one is cancelled
+    fn schedule_serial_transfer(&mut self, transfer_cycles: u16) {
+        self.scheduler.cancel(Event::SERIAL_BIT);
+        if self.serial.is_shifting() {
+            self.scheduler.schedule(CYCLES_PER_BIT.saturating_sub(transfer_cycles) as u64, Event::SERIAL_BIT);
+        }
+    }
+
+    pub fn fill_memory(&mut self, fill: MemoryFill) {
+        // each memory gets its own pattern in random mode
+        fill.fill(&mut self.working_ram, 0x0000_C000);
+        fill.fill(&mut self.zero_page, 0x0000_FF80);
+        fill.fill(&mut self.gpu.vram, 0x0000_8000);
+        fill.fill(&mut self.gpu.vram_bank_1, 0x0001_8000);
+    }
+
+    // keep the bytes sent through the link port until the host takes them
+    pub fn set_serial_output(&mut self, enabled: bool) {
+        self.serial.output = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    // bytes sent through the link port since the last call, none when the output is disabled
+    pub fn take_serial_output(&mut self) -> Option<Vec<u8>> {
+        self.serial.output.as_mut().map(std::mem::take)
+    }
+
     pub fn load_bootrom(&mut self, boot_rom: &[u8]){
         self.boot_rom.load(boot_rom);
     }
 
-    fn read_io_register(&self, address: usize) -> u8 {
-        match address {
-            0xFF00 => self.keypad.get(),
-            0xFF01 => 0, // TODO: serial
-            0xFF02 => 0, // TODO: serial
-            0xFF04 => self.timer.get_divider(),
-            0xFF05 => self.timer.get_value(),
-            0xFF06 => self.timer.get_modulo(),
-            0xFF0F => self.nvic.get_it_flag(),
-            0xFF10 => 0xFF, // Channel 1 Sweep register 
-            0xFF11 => 0xFF, /* Channel 1 Sound Length and Wave */ 
-            0xFF12 => 0xFF, /* Channel 1 Sound Control */ 
-            0xFF13 => 0xFF, /* Channel 1 Frequency lo */ 
-            0xFF14 => 0xFF, /* Channel 1 Control */ 
-            0xFF16 => 0xFF, /* Channel 2 Sound Control */ 
-            0xFF17 => 0xFF, /* Channel 2 Sound Control */ 
-            0xFF18 => 0xFF, /* Channel 2 Sound Control */ 
-            0xFF19 => 0xFF, /* Channel 2 Frequency hi data*/ 
-            0xFF1A => 0xFF, /* Channel 3 Sound on/off */ 
-            0xFF1B => 0xFF, /* Channel 3 Sound on/off */ 
-            0xFF1C => 0xFF, /* Channel 3 Sound on/off */ 
-            0xFF1D => 0xFF, /* Channel 3 Sound on/off */ 
-            0xFF1E => 0xFF, /* Channel 3 Sound on/off */ 
-            0xFF20 => 0xFF, /* Channel 4 Volumn */ 
-            0xFF21 => 0xFF, /* Channel 4 Volumn */ 
-            0xFF22 => 0xFF, /* Channel 4 Volumn */ 
-            0xFF23 => 0xFF, /* Channel 4 Counter/consecutive */ 
-            0xFF24 => 0xFF, /* Sound  Volume */ 
-            0xFF25 => 0xFF, /* Sound output terminal selection */ 
-            0xFF26 => 0xFF, /* Sound on/off */ 
-            0xff30..=0xff3f => 0xFF, //Wave Pattern RAM
-            0xFF40 => self.gpu.control_to_byte(),
-            0xFF41 => self.gpu.status_to_byte(),
-            0xFF42 => self.gpu.get_scy(),
-            0xFF43 => self.gpu.get_scx(),
-            0xFF44 => self.gpu.get_current_line(),
-            0xFF45 => self.gpu.get_compare_line(),
-            0xFF4A => self.gpu.get_window_y(),
-            0xFF4B => self.gpu.get_window_x(),
-            0xFF4D => 0xFF, // CGB SPEED SWITCH register, not supported
-            0xFF48 => 0xFF, // pokemon tries to read this registers
-            0xFF49 => 0xFF, // pokemon tries to read this registers
-            _ => panic!("Reading from an unknown I/O register {:x}", address),
-        }
-    }
-
-    fn write_io_register(&mut self, address: usize, data: u8) {
-        match address {
-            0xFF00 => self.keypad.control(data),
-            0xFF01 => { /* Serial Transfer */ }
-            0xFF02 => { /* Serial Transfer Control */ }
-            0xFF04 => self.timer.set_divider(),
-            0xFF05 => self.timer.set_value(data),
-            0xFF06 => self.timer.set_modulo(data),
-            0xFF07 => self.timer.settings_from_byte(data),
-            0xFF0F => self.nvic.set_it_flag(data),
-            0xFF10 => { /* Channel 1 Sweep register */ }
-            0xFF11 => { /* Channel 1 Sound Length and Wave */ }
-            0xFF12 => { /* Channel 1 Sound Control */ }
-            0xFF13 => { /* Channel 1 Frequency lo */ }
-            0xFF14 => { /* Channel 1 Control */ }
-            0xFF16 => { /* Channel 2 Sound Control */ }
-            0xFF17 => { /* Channel 2 Sound Control */ }
-            0xFF18 => { /* Channel 2 Sound Control */ }
-            0xFF19 => { /* Channel 2 Frequency hi data*/ }
-            0xFF1A => { /* Channel 3 Sound on/off */ }
-            0xFF1B => { /* Channel 3 Sound on/off */ }
-            0xFF1C => { /* Channel 3 Sound on/off */ }
-            0xFF1D => { /* Channel 3 Sound on/off */ }
-            0xFF1E => { /* Channel 3 Sound on/off */ }
-            0xFF20 => { /* Channel 4 Volumn */ }
-            0xFF21 => { /* Channel 4 Volumn */ }
-            0xFF22 => { /* Channel 4 Volumn */ }
-            0xFF23 => { /* Channel 4 Counter/consecutive */ }
-            0xFF24 => { /* Sound  Volume */ }
-            0xFF25 => { /* Sound output terminal selection */ }
-            0xFF26 => { /* Sound on/off */ }
-            0xff30..=0xff3f => { /* Wave Pattern RAM */ }
-            0xFF40 => self.gpu.control_from_byte(data),
-            0xFF41 => self.gpu.status_from_byte(data),
-            0xFF42 => self.gpu.set_scy(data),
-            0xFF43 => self.gpu.set_scx(data),
-            0xFF45 => self.gpu.set_compare_line(data),
-            0xFF46 => {
+    // set the I/O registers as the boot rom leaves them, the boot rom is disabled
+    pub fn skip_boot(&mut self, hardware_mode: HardwareMode) {
+        let cgb_registers: &[(u16, u8)] = match hardware_mode {
+            HardwareMode::DMG => &[],
+            HardwareMode::CGB => &POST_BOOT_CGB_IO_REGISTERS,
+        };
+
+        for (address, data) in POST_BOOT_IO_REGISTERS.iter().chain(cgb_registers.iter()) {
+            match *address {
+                // DIV is the upper byte of the internal counter
+                0xFF04 => self.timer.system_counter = (*data as u16) << 8,
+                // LY is read only
+                0xFF44 => {}
+                // a write to DMA starts a transfer
+                0xFF46 => self.dma_start_adress = (*data as u16) << 8,
+                // KEY0 is locked once the boot rom is disabled
+                0xFF4C => self.gpu.set_hardware_mode(*data),
+                _ => self.write_io_register(*address, *data),
+            }
+        }
+
+        self.nvic.set_it_enable(0x00);
+    }
+
+    fn read_io_register(&self, address: u16) -> u8 {
+        let register = io_register(address);
+
+        let data = match register.handler {
+            IoHandler::JOYPAD => self.keypad.get(),
+            IoHandler::SERIAL => match address {
+                0xFF01 => self.serial.get_data(),
+                _ => self.serial.get_control(),
+            },
+            IoHandler::TIMER => match address {
+                0xFF04 => self.timer.get_divider(),
+                0xFF05 => self.timer.get_value(),
+                0xFF06 => self.timer.get_modulo(),
+                _ => self.timer.settings_to_byte(),
+            },
+            IoHandler::INTERRUPT => self.nvic.get_it_flag(),
+            IoHandler::APU => self.apu.read(address),
+            IoHandler::GPU => match address {
+                0xFF40 => self.gpu.control_to_byte(),
+                0xFF41 => self.gpu.status_to_byte(),
+                0xFF42 => self.gpu.get_scy(),
+                0xFF43 => self.gpu.get_scx(),
+                0xFF44 => self.gpu.get_current_line(),
+                0xFF45 => self.gpu.get_compare_line(),
+                0xFF47 => self.gpu.get_background_palette(),
+                0xFF48 => self.gpu.get_object_palette_0(),
+                0xFF49 => self.gpu.get_object_palette_1(),
+                0xFF4A => self.gpu.get_window_y(),
+                0xFF4F if self.gpu.cgb_mode => self.gpu.get_vram_bank(),
+                // VBK only exists in CGB mode
+                0xFF4F => 0xFF,
+                0xFF6C if self.gpu.cgb_mode => self.gpu.get_object_priority(),
+                // OPRI only exists in CGB mode, the DMG draws the sprites by X coordinate
+                0xFF6C => 0xFF,
+                _ => self.gpu.get_window_x(),
+            },
+            IoHandler::DMA => (self.dma_start_adress >> 8) as u8,
+            IoHandler::BOOT_ROM | IoHandler::UNMAPPED => 0xFF,
+        };
+
+        data | register.unused_bits
+    }
+
+    fn write_io_register(&mut self, address: u16, data: u8) {
+        if let Some(io_write_log) = self.io_write_log.as_mut() {
+            io_write_log.record(address, data);
+        }
+
+        match io_register(address).handler {
+            IoHandler::JOYPAD => self.keypad.control(data),
+            IoHandler::SERIAL => match address {
+                0xFF01 => self.serial.set_data(data),
+                _ => {
+                    self.serial.set_control(data);
+                    self.schedule_serial_transfer(0);
+                }
+            },
+            IoHandler::TIMER => match address {
+                0xFF04 => self.timer.set_divider(),
+                0xFF05 => self.timer.set_value(data),
+                0xFF06 => self.timer.set_modulo(data),
+                _ => self.timer.settings_from_byte(data),
+            },
+            IoHandler::INTERRUPT => self.nvic.set_it_flag(data),
+            IoHandler::APU => self.apu.write(address, data),
+            IoHandler::GPU => match address {
+                0xFF40 => self.gpu.control_from_byte(data),
+                0xFF41 => self.gpu.status_from_byte(data),
+                0xFF42 => self.gpu.set_scy(data),
+                0xFF43 => self.gpu.set_scx(data),
+                0xFF44 => { /* LY is read only */ }
+                0xFF45 => self.gpu.set_compare_line(data),
+                0xFF47 => self.gpu.set_background_palette(data),
+                0xFF48 => self.gpu.set_object_palette_0(data),
+                0xFF49 => self.gpu.set_object_palette_1(data),
+                0xFF4A => self.gpu.set_window_y(data),
+                0xFF4F if self.gpu.cgb_mode => self.gpu.set_vram_bank(data),
+                0xFF4F => { /* VBK only exists in CGB mode, the DMG has a single VRAM bank */ }
+                0xFF6C if self.gpu.cgb_mode => self.gpu.set_object_priority(data),
+                0xFF6C => { /* OPRI only exists in CGB mode, the DMG ignores it */ }
+                _ => self.gpu.set_window_x(data),
+            },
+            IoHandler::DMA => {
                 self.dma_start_adress = (data as u16) << 8;
                 self.dma_enabled = true;
             }
-            0xFF47 => self.gpu.set_background_palette(data),
-            0xFF48 => self.gpu.set_object_palette_0(data),
-            0xFF49 => self.gpu.set_object_palette_1(data),
-            0xFF4A => self.gpu.set_window_y(data),
-            0xFF4B => self.gpu.set_window_x(data),
-            0xFF50 => self.boot_rom.set_state(false),
-            0xFF7f => {
-                // Writing to here does nothing
-            }
-            _ => panic!(
-                "Writting '0b{:b}' to an unknown I/O register {:x}",
-                data, address
-            ),
+            IoHandler::BOOT_ROM => match address {
+                // the boot rom selects the CGB or the DMG compatibility mode, KEY0 is locked after
+                0xFF4C => if self.boot_rom.get_state() {
+                    self.gpu.set_hardware_mode(data);
+                },
+                _ => self.boot_rom.set_state(false),
+            },
+            IoHandler::UNMAPPED => { /* Writing to here does nothing */ }
         }
     }
 }
 
-impl IoAccess for Peripheral {
-    fn read(&self, address: u16) -> u8 {
+impl Bus {
+    // the memory map without the OAM DMA restriction
+    fn read_memory(&self, address: u16) -> u8 {
         match address {
             ROM_BANK_0_BEGIN..=ROM_BANK_0_END => {
                 match address {
@@ -259,23 +387,23 @@ impl IoAccess for Peripheral {
                 }
             }
             ROM_BANK_N_BEGIN..=ROM_BANK_N_END => self.cartridge.read_bank_n(address as usize),
-            VRAM_BEGIN..=VRAM_END => self.gpu.read_vram(address - VRAM_BEGIN),
+            VRAM_BEGIN..=VRAM_END => self.gpu.read_mapped_vram(address - VRAM_BEGIN),
             EXTERNAL_RAM_BEGIN..=EXTERNAL_RAM_END => self.cartridge.read_ram(address as usize),
             WORKING_RAM_BEGIN..=WORKING_RAM_END => self.working_ram[(address - WORKING_RAM_BEGIN) as usize],
             ECHO_RAM_BEGIN..=ECHO_RAM_END => self.working_ram[(address - ECHO_RAM_BEGIN) as usize],
             OAM_BEGIN..=OAM_END => self.gpu.read_oam((address - OAM_BEGIN) as usize),
-            IO_REGISTERS_BEGIN..=IO_REGISTERS_END => self.read_io_register(address as usize),
+            IO_REGISTERS_BEGIN..=IO_REGISTERS_END => self.read_io_register(address),
             UNUSED_BEGIN..=UNUSED_END => 0, // unused memory
             ZERO_PAGE_BEGIN..=ZERO_PAGE_END => self.zero_page[(address - ZERO_PAGE_BEGIN) as usize],
             INTERRUPT_ENABLE_REGISTER => self.nvic.get_it_enable(),
         }
     }
 
-    fn write(&mut self, address: u16, data: u8) {
+    fn write_memory(&mut self, address: u16, data: u8) {
         match address {
             ROM_BANK_0_BEGIN..=ROM_BANK_0_END => self.cartridge.write_bank_0(address as usize, data),
             ROM_BANK_N_BEGIN..=ROM_BANK_N_END => self.cartridge.write_bank_n(address as usize, data),
-            VRAM_BEGIN..=VRAM_END => self.gpu.write_vram(address - VRAM_BEGIN, data),
+            VRAM_BEGIN..=VRAM_END => self.gpu.write_mapped_vram(address - VRAM_BEGIN, data),
             EXTERNAL_RAM_BEGIN..=EXTERNAL_RAM_END => self.cartridge.write_ram(address as usize, data),
             WORKING_RAM_BEGIN..=WORKING_RAM_END => {
                 self.working_ram[(address - WORKING_RAM_BEGIN) as usize] = data;
@@ -284,7 +412,7 @@ impl IoAccess for Peripheral {
                 self.working_ram[(address - ECHO_RAM_BEGIN) as usize] = data;
             }
             OAM_BEGIN..=OAM_END => self.gpu.write_oam((address - OAM_BEGIN) as usize, data),
-            IO_REGISTERS_BEGIN..=IO_REGISTERS_END => self.write_io_register(address as usize, data),
+            IO_REGISTERS_BEGIN..=IO_REGISTERS_END => self.write_io_register(address, data),
             UNUSED_BEGIN..=UNUSED_END => { /* Writing to here does nothing */ }
             ZERO_PAGE_BEGIN..=ZERO_PAGE_END => {
                 self.zero_page[(address - ZERO_PAGE_BEGIN) as usize] = data;
@@ -294,7 +422,47 @@ impl IoAccess for Peripheral {
     }
 }
 
-impl Interrupt for Peripheral {
+impl Bus {
+    // during an OAM DMA transfer the cpu can't access the bus the DMA reads its source from, the
+    // VRAM bus or the external one (ROM, external and working RAMs), nor the OAM the DMA writes.
+    // Games run their DMA wait loop from the high ram, the I/O registers stay accessible too
+    fn is_blocked_by_dma(&self, address: u16) -> bool {
+        if !self.dma_enabled {
+            return false;
+        }
+
+        let source_on_vram_bus = (VRAM_BEGIN..=VRAM_END).contains(&self.dma_start_adress);
+        match address {
+            VRAM_BEGIN..=VRAM_END => source_on_vram_bus,
+            ROM_BANK_0_BEGIN..=ECHO_RAM_END => !source_on_vram_bus,
+            OAM_BEGIN..=OAM_END => true,
+            _ => false,
+        }
+    }
+}
+
+// the blocked regions are read as DMA_BLOCKED_READ and writes to them are ignored
+impl IoAccess for Bus {
+    fn read_byte(&self, address: u16) -> u8 {
+        if self.is_blocked_by_dma(address) {
+            DMA_BLOCKED_READ
+        } else {
+            self.read_memory(address)
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, data: u8) {
+        if !self.is_blocked_by_dma(address) {
+            self.write_memory(address, data);
+        }
+    }
+
+    fn peek_byte(&self, address: u16) -> u8 {
+        self.read_memory(address)
+    }
+}
+
+impl Interrupt for Bus {
     fn is_an_interrupt_to_run(&self) -> bool {
         self.nvic.is_an_interrupt_to_run()
     }
@@ -312,10 +480,56 @@ impl Interrupt for Peripheral {
     }
 }
 
+impl SaveState for Bus {
+    fn save_state(&self, writer: &mut dyn Write) -> Result<()> {
+        self.boot_rom.save_state(writer)?;
+        self.cartridge.save_state(writer)?;
+        write_bytes(writer, &self.working_ram)?;
+        write_bytes(writer, &self.zero_page)?;
+        self.gpu.save_state(writer)?;
+        self.nvic.save_state(writer)?;
+        self.timer.save_state(writer)?;
+        self.apu.save_state(writer)?;
+        self.keypad.save_state(writer)?;
+        write_u16(writer, self.dma_cycles)?;
+        write_u16(writer, self.dma_start_adress)?;
+        write_bool(writer, self.dma_enabled)?;
+        // clock ticks since the last shifted bit
+        let transfer_cycles = self.scheduler.remaining_cycles(Event::SERIAL_BIT)
+            .map_or(0, |remaining_cycles| CYCLES_PER_BIT - remaining_cycles as u16);
+        self.serial.save_state(writer, transfer_cycles)
+    }
+
+    fn load_state(&mut self, reader: &mut dyn Read) -> Result<()> {
+        self.load_state_version(reader, SAVE_STATE_VERSION)
+    }
+
+    fn load_state_version(&mut self, reader: &mut dyn Read, version: u8) -> Result<()> {
+        self.boot_rom.load_state(reader)?;
+        self.cartridge.load_state(reader)?;
+        read_bytes(reader, &mut self.working_ram)?;
+        read_bytes(reader, &mut self.zero_page)?;
+        self.gpu.load_state_version(reader, version)?;
+        self.nvic.load_state(reader)?;
+        self.timer.load_state(reader)?;
+        self.apu.load_state(reader)?;
+        self.keypad.load_state(reader)?;
+        self.dma_cycles = read_u16(reader)?;
+        self.dma_start_adress = read_u16(reader)?;
+        self.dma_enabled = read_bool(reader)?;
+        let transfer_cycles = self.serial.load_state(reader)?;
+        self.schedule_serial_transfer(transfer_cycles);
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod peripheral_tests {
     use super::*;
     use crate::cartridge::{Cartridge, CARTRIDGE_TYPE_OFFSET, CARTRIDGE_RAM_SIZE_OFFSET, CARTRIDGE_ROM_SIZE_OFFSET};
+    use crate::emulator::SCREEN_WIDTH;
+    use gpu::{GpuMode, ObjectPriority, PixelColor, PpuAccuracy, BG_ATTRIBUTE_PRIORITY};
+    use keypad::GameBoyKey;
 
     #[test]
     fn test_read_write() {
@@ -323,13 +537,398 @@ mod peripheral_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
-        peripheral.write(0x0001 + 0xC000, 0xAA);
-        peripheral.write(0x0002 + 0xC000, 0x55);
-        peripheral.write(0x0010 + 0xC000, 0xAA);
-        assert_eq!(peripheral.read(0x0001 + 0xC000), 0xAA);
-        assert_eq!(peripheral.read(0x0002 + 0xC000), 0x55);
-        assert_eq!(peripheral.read(0x0010 + 0xC000), 0xAA);
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
+        peripheral.write_byte(0x0001 + 0xC000, 0xAA);
+        peripheral.write_byte(0x0002 + 0xC000, 0x55);
+        peripheral.write_byte(0x0010 + 0xC000, 0xAA);
+        assert_eq!(peripheral.read_byte(0x0001 + 0xC000), 0xAA);
+        assert_eq!(peripheral.read_byte(0x0002 + 0xC000), 0x55);
+        assert_eq!(peripheral.read_byte(0x0010 + 0xC000), 0xAA);
+    }
+
+    #[test]
+    fn test_bus_routing() {
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        rom[0x0000] = 0x12;
+        rom[0x4000] = 0x34;
+        let mut bus = Bus::new(Cartridge::new(&rom));
+
+        // the boot rom is mapped over the cartridge until it's disabled
+        let mut boot_rom = [0x00; BOOT_ROM_SIZE as usize];
+        boot_rom[0x00] = 0x31;
+        bus.load_bootrom(&boot_rom);
+        assert_eq!(bus.read_byte(0x0000), 0x31);
+        bus.write_byte(0xFF50, 0x01);
+
+        // rom
+        assert_eq!(bus.read_byte(ROM_BANK_0_BEGIN), 0x12);
+        assert_eq!(bus.read_byte(ROM_BANK_N_BEGIN), 0x34);
+
+        // vram
+        bus.write_byte(VRAM_BEGIN, 0x56);
+        assert_eq!(bus.gpu.read_vram(0x0000), 0x56);
+
+        // wram and its echo
+        bus.write_byte(WORKING_RAM_BEGIN + 0x10, 0x78);
+        assert_eq!(bus.read_byte(WORKING_RAM_BEGIN + 0x10), 0x78);
+        assert_eq!(bus.read_byte(ECHO_RAM_BEGIN + 0x10), 0x78);
+
+        // oam
+        bus.write_byte(OAM_BEGIN + 0x04, 0x9A);
+        assert_eq!(bus.gpu.read_oam(0x04), 0x9A);
+        assert_eq!(bus.read_byte(OAM_BEGIN + 0x04), 0x9A);
+
+        // io registers, SCY
+        bus.write_byte(0xFF42, 0xBC);
+        assert_eq!(bus.gpu.get_scy(), 0xBC);
+        assert_eq!(bus.read_byte(0xFF42), 0xBC);
+
+        // hram
+        bus.write_byte(ZERO_PAGE_END, 0xDE);
+        assert_eq!(bus.read_byte(ZERO_PAGE_END), 0xDE);
+
+        // interrupt enable register
+        bus.write_byte(INTERRUPT_ENABLE_REGISTER, 0x05);
+        assert_eq!(bus.nvic.get_it_enable(), 0x05);
+        assert_eq!(bus.read_byte(INTERRUPT_ENABLE_REGISTER), 0x05);
+    }
+
+    #[test]
+    fn test_write_side_effects() {
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        let mut bus = Bus::new(Cartridge::new(&rom));
+
+        // DIV: any write resets the internal counter
+        for _ in 0..0x100 {
+            bus.run(4);
+        }
+        assert_eq!(bus.read_byte(0xFF04), 0x04);
+        bus.write_byte(0xFF04, 0x12);
+        assert_eq!(bus.read_byte(0xFF04), 0x00);
+
+        // LCDC: turning the LCD off stops the ppu at line 0, turning it on restarts the frame
+        bus.write_byte(0xFF40, 0x80);
+        for _ in 0..(3 * 456 / 4) {
+            bus.run(4);
+        }
+        assert_eq!(bus.read_byte(0xFF44), 3);
+        bus.write_byte(0xFF40, 0x00);
+        assert_eq!(bus.read_byte(0xFF44), 0);
+        assert_eq!(bus.read_byte(0xFF41) & 0x03, 0);
+        for _ in 0..(3 * 456 / 4) {
+            bus.run(4);
+        }
+        assert_eq!(bus.read_byte(0xFF44), 0);
+        bus.write_byte(0xFF40, 0x80);
+        assert_eq!(bus.read_byte(0xFF41) & 0x03, 2);
+
+        // SC: a transfer on the internal clock receives 0xFF and requests the serial interrupt
+        bus.write_byte(0xFF0F, 0x00);
+        bus.write_byte(0xFF01, 0x42);
+        bus.write_byte(0xFF02, 0x81);
+        assert_eq!(bus.read_byte(0xFF02), 0xFF);
+        for _ in 0..(8 * 512 / 4) {
+            bus.run(4);
+        }
+        assert_eq!(bus.read_byte(0xFF01), 0xFF);
+        assert_eq!(bus.read_byte(0xFF02), 0x7F);
+        assert_eq!(bus.read_byte(0xFF0F) & 0x08, 0x08);
+
+        // SC: a transfer on the external clock never ends, no other Game Boy drives the clock
+        bus.write_byte(0xFF0F, 0x00);
+        bus.write_byte(0xFF01, 0x42);
+        bus.write_byte(0xFF02, 0x80);
+        for _ in 0..(16 * 512 / 4) {
+            bus.run(4);
+        }
+        assert_eq!(bus.read_byte(0xFF01), 0x42);
+        assert_eq!(bus.read_byte(0xFF02), 0xFE);
+        assert_eq!(bus.read_byte(0xFF0F) & 0x08, 0x00);
+
+        // DMA: the source page is copied to the OAM, one byte per machine cycle
+        bus.write_byte(WORKING_RAM_BEGIN, 0x9A);
+        bus.write_byte(0xFF46, (WORKING_RAM_BEGIN >> 8) as u8);
+        assert!(bus.dma_enabled);
+        for _ in 0..OAM_SIZE {
+            bus.run(CLOCK_TICK_PER_MACHINE_CYCLE);
+        }
+        assert!(!bus.dma_enabled);
+        assert_eq!(bus.gpu.read_oam(0), 0x9A);
+    }
+
+    #[test]
+    fn test_io_register_read_masks() {
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        let mut bus = Bus::new(Cartridge::new(&rom));
+
+        // TAC only uses its 3 lower bits
+        bus.write_byte(0xFF07, 0x06);
+        assert_eq!(bus.read_byte(0xFF07), 0xFE);
+
+        // IF only uses its 5 lower bits
+        bus.write_byte(0xFF0F, 0x01);
+        assert_eq!(bus.read_byte(0xFF0F), 0xE1);
+
+        // SC only uses its first and last bits
+        assert_eq!(bus.read_byte(0xFF02), 0x7E);
+
+        // STAT bit 7 is unused
+        bus.write_byte(0xFF41, 0x00);
+        assert_eq!(bus.read_byte(0xFF41) & 0x80, 0x80);
+
+        // palettes and DMA are read back as written
+        bus.write_byte(0xFF47, 0xE4);
+        assert_eq!(bus.read_byte(0xFF47), 0xE4);
+        bus.write_byte(0xFF46, 0xC0);
+        for _ in 0..OAM_SIZE {
+            bus.run(CLOCK_TICK_PER_MACHINE_CYCLE);
+        }
+        assert_eq!(bus.read_byte(0xFF46), 0xC0);
+
+        // the boot rom latch and unmapped registers are read as 0xFF
+        assert_eq!(bus.read_byte(0xFF50), 0xFF);
+        bus.write_byte(0xFF4D, 0x01);
+        assert_eq!(bus.read_byte(0xFF4D), 0xFF);
+        assert_eq!(bus.read_byte(0xFF7F), 0xFF);
+    }
+
+    #[test]
+    fn test_serial_transfer_state() {
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        let mut bus = Bus::new(Cartridge::new(&rom));
+
+        // the state is saved in the middle of the fourth bit
+        bus.write_byte(0xFF01, 0x00);
+        bus.write_byte(0xFF02, 0x81);
+        for _ in 0..(3 * 512 + 256) / 4 {
+            bus.run(4);
+        }
+        assert_eq!(bus.read_byte(0xFF01), 0x07);
+        let mut state = Vec::new();
+        bus.save_state(&mut state).unwrap();
+
+        // the loaded transfer shifts the next bits at the same cycles
+        let mut loaded_bus = Bus::new(Cartridge::new(&rom));
+        loaded_bus.load_state(&mut &state[..]).unwrap();
+        for bus in [&mut bus, &mut loaded_bus] {
+            for _ in 0..252 / 4 {
+                bus.run(4);
+            }
+            assert_eq!(bus.read_byte(0xFF01), 0x07);
+            bus.run(4);
+            assert_eq!(bus.read_byte(0xFF01), 0x0F);
+        }
+    }
+
+    #[test]
+    fn test_joypad_register() {
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        let mut bus = Bus::new(Cartridge::new(&rom));
+
+        // no key pressed: only the select bits are low
+        for (select, expected) in [(0x30, 0xFF), (0x20, 0xEF), (0x10, 0xDF), (0x00, 0xCF)] {
+            bus.write_byte(0xFF00, select);
+            assert_eq!(bus.read_byte(0xFF00), expected);
+        }
+
+        // the lines of the pressed keys are low in the selected groups only
+        bus.keypad.set(GameBoyKey::A, true);
+        bus.keypad.set(GameBoyKey::START, true);
+        bus.keypad.set(GameBoyKey::LEFT, true);
+        for (select, expected) in [(0x30, 0xFF), (0x20, 0xED), (0x10, 0xD6), (0x00, 0xC4)] {
+            bus.write_byte(0xFF00, select);
+            assert_eq!(bus.read_byte(0xFF00), expected);
+        }
+
+        // the input lines and unused bits can't be written
+        bus.write_byte(0xFF00, 0xDF);
+        assert_eq!(bus.read_byte(0xFF00), 0xD6);
+    }
+
+    #[test]
+    fn test_interrupt_enable_register() {
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        let mut bus = Bus::new(Cartridge::new(&rom));
+        bus.master_enable(true);
+
+        // the 8 bits are stored, the last high ram byte isn't modified
+        bus.write_byte(ZERO_PAGE_END, 0x00);
+        bus.write_byte(INTERRUPT_ENABLE_REGISTER, 0xE4);
+        assert_eq!(bus.read_byte(INTERRUPT_ENABLE_REGISTER), 0xE4);
+        assert_eq!(bus.read_byte(ZERO_PAGE_END), 0x00);
+
+        // only the timer interrupt is enabled, the unused bits don't enable anything
+        bus.write_byte(0xFF0F, 0xE1);
+        assert_eq!(bus.is_an_interrupt_to_run(), false);
+        bus.write_byte(0xFF0F, 0x04);
+        assert_eq!(bus.is_an_interrupt_to_run(), true);
+        assert_eq!(bus.get_interrupt(), Some(InterruptSources::TIMER));
+
+        // disabling it in IE masks the request
+        bus.write_byte(0xFF0F, 0x04);
+        bus.write_byte(INTERRUPT_ENABLE_REGISTER, 0x00);
+        assert_eq!(bus.is_an_interrupt_to_run(), false);
+    }
+
+    #[test]
+    fn test_io_write_log() {
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        let mut bus = Bus::new(Cartridge::new(&rom));
+
+        // disabled by default
+        bus.write_byte(0xFF12, 0xF3);
+        assert!(bus.io_write_log.is_none());
+
+        bus.io_write_log = Some(IoWriteLog::new(Vec::new()));
+        bus.io_write_log.as_mut().unwrap().pc = 0x0150;
+        bus.write_byte(0xFF12, 0xF3);
+        bus.write_byte(WORKING_RAM_BEGIN, 0x01);
+
+        // only the I/O registers are logged, with their name
+        let writes = &bus.io_write_log.as_ref().unwrap().writes;
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0].to_string(), "pc 0x0150: NR12 (0xff12) <- 0xf3");
+    }
+
+    #[test]
+    fn test_write_only_registers() {
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        let mut bus = Bus::new(Cartridge::new(&rom));
+        bus.write_byte(0xFF26, 0x80);
+
+        // NR13 and NR23 frequencies are write only
+        bus.write_byte(0xFF13, 0x42);
+        assert_eq!(bus.read_byte(0xFF13), 0xFF);
+        bus.write_byte(0xFF18, 0x42);
+        assert_eq!(bus.read_byte(0xFF18), 0xFF);
+
+        // only the duty of NR11 is readable
+        bus.write_byte(0xFF11, 0x85);
+        assert_eq!(bus.read_byte(0xFF11), 0xBF);
+
+        // only the length enable of NR14 is readable
+        bus.write_byte(0xFF14, 0x47);
+        assert_eq!(bus.read_byte(0xFF14), 0xFF);
+        bus.write_byte(0xFF14, 0x07);
+        assert_eq!(bus.read_byte(0xFF14), 0xBF);
+
+        // fully readable registers
+        bus.write_byte(0xFF12, 0xF3);
+        assert_eq!(bus.read_byte(0xFF12), 0xF3);
+        bus.write_byte(0xFF30, 0x5A);
+        assert_eq!(bus.read_byte(0xFF30), 0x5A);
+
+        // NR52 bits 4-6 are unused, no channel is running
+        assert_eq!(bus.read_byte(0xFF26), 0xF0);
+
+        // holes in the sound registers
+        bus.write_byte(0xFF15, 0x00);
+        assert_eq!(bus.read_byte(0xFF15), 0xFF);
+        assert_eq!(bus.read_byte(0xFF27), 0xFF);
+    }
+
+    #[test]
+    fn test_skip_boot_io_registers() {
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        let mut bus = Bus::new(Cartridge::new(&rom));
+        bus.load_bootrom(&[0x00; BOOT_ROM_SIZE as usize]);
+
+        bus.skip_boot(HardwareMode::DMG);
+
+        for (address, data) in POST_BOOT_IO_REGISTERS.iter() {
+            let mask = if *address == 0xFF41 { 0xF8 } else { 0xFF };
+            assert_eq!(bus.read_byte(*address) & mask, *data & mask, "register {:#06x}", address);
+        }
+        assert_eq!(bus.read_byte(INTERRUPT_ENABLE_REGISTER), 0x00);
+
+        // the cartridge is mapped instead of the boot rom
+        assert_eq!(bus.read_byte(0x0000), 0xFF);
+
+        // the DMG has no OPRI, the sprites stay drawn by X coordinate whatever the game writes
+        assert_eq!(bus.read_byte(0xFF6C), 0xFF);
+        bus.write_byte(0xFF6C, 0x00);
+        assert_eq!(bus.read_byte(0xFF6C), 0xFF);
+        assert_eq!(bus.gpu.object_priority, ObjectPriority::XCoordinate);
+        assert!(!bus.gpu.cgb_mode);
+
+        // the CGB games are started in CGB mode
+        let mut bus = Bus::new(Cartridge::new(&rom));
+        bus.load_bootrom(&[0x00; BOOT_ROM_SIZE as usize]);
+        bus.skip_boot(HardwareMode::CGB);
+        assert!(bus.gpu.cgb_mode);
+        assert_eq!(bus.read_byte(0xFF02), 0x7F);
+        assert_eq!(bus.read_byte(0xFF46), 0x00);
+        assert_eq!(bus.read_byte(0xFF4F), 0xFE);
+        assert_eq!(bus.read_byte(0xFF40), 0x91);
+        assert_eq!(bus.read_byte(0xFF6C), 0xFE);
+        assert_eq!(bus.gpu.object_priority, ObjectPriority::OamIndex);
+    }
+
+    #[test]
+    fn test_object_priority_register() {
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        let mut bus = Bus::new(Cartridge::new(&rom));
+        bus.load_bootrom(&[0x00; BOOT_ROM_SIZE as usize]);
+        bus.skip_boot(HardwareMode::CGB);
+
+        // sprites over the background, the first one in OAM is dark gray from X = 4, the second
+        // one is black from X = 0
+        bus.write_byte(0xFF40, 0x92);
+        bus.write_byte(0xFF48, 0xE4);
+        for (address, data) in [(0x8010, 0x00), (0x8011, 0xFF), (0x8020, 0xFF), (0x8021, 0xFF)] {
+            bus.write_byte(address, data);
+        }
+        for (address, data) in [(OAM_BEGIN, 24), (OAM_BEGIN + 1, 12), (OAM_BEGIN + 2, 0x01), (OAM_BEGIN + 3, 0x00),
+                                (OAM_BEGIN + 4, 24), (OAM_BEGIN + 5, 8), (OAM_BEGIN + 6, 0x02), (OAM_BEGIN + 7, 0x00)] {
+            bus.write_byte(address, data);
+        }
+        bus.gpu.current_line = 8;
+        let overlap = |bus: &mut Bus| {
+            bus.gpu.draw_static_line();
+            bus.gpu.frame_buffer[0x0506]
+        };
+
+        // the CGB boot rom leaves the OAM order, the first sprite is on top
+        assert_eq!(overlap(&mut bus), u8::from(PixelColor::DARK_GRAY));
+
+        // the game selects the X coordinate order, the leftmost sprite is on top
+        bus.write_byte(0xFF6C, 0x01);
+        assert_eq!(bus.read_byte(0xFF6C), 0xFF);
+        assert_eq!(overlap(&mut bus), u8::from(PixelColor::BLACK));
+
+        bus.write_byte(0xFF6C, 0x00);
+        assert_eq!(bus.read_byte(0xFF6C), 0xFE);
+        assert_eq!(overlap(&mut bus), u8::from(PixelColor::DARK_GRAY));
     }
 
     #[test]
@@ -338,13 +937,53 @@ mod peripheral_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
-        peripheral.write(0x0001 + VRAM_BEGIN, 0xAA);
-        peripheral.write(0x0002 + VRAM_BEGIN, 0x55);
-        peripheral.write(0x0010 + VRAM_BEGIN, 0xAA);
-        assert_eq!(peripheral.read(0x0001 + VRAM_BEGIN), 0xAA);
-        assert_eq!(peripheral.read(0x0002 + VRAM_BEGIN), 0x55);
-        assert_eq!(peripheral.read(0x0010 + VRAM_BEGIN), 0xAA);
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
+        peripheral.write_byte(0x0001 + VRAM_BEGIN, 0xAA);
+        peripheral.write_byte(0x0002 + VRAM_BEGIN, 0x55);
+        peripheral.write_byte(0x0010 + VRAM_BEGIN, 0xAA);
+        assert_eq!(peripheral.read_byte(0x0001 + VRAM_BEGIN), 0xAA);
+        assert_eq!(peripheral.read_byte(0x0002 + VRAM_BEGIN), 0x55);
+        assert_eq!(peripheral.read_byte(0x0010 + VRAM_BEGIN), 0xAA);
+    }
+
+    #[test]
+    fn test_cgb_mode_select() {
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        let mut bus = Bus::new(Cartridge::new(&rom));
+        bus.load_bootrom(&[0x00; BOOT_ROM_SIZE as usize]);
+
+        // DMG mode until the boot rom selects the CGB mode, there is a single VRAM bank
+        bus.write_byte(0xFF4F, 0x01);
+        assert_eq!(bus.read_byte(0xFF4F), 0xFF);
+        bus.write_byte(VRAM_BEGIN + 0x1800, 0x12);
+        assert_eq!(bus.gpu.read_vram(0x1800), 0x12);
+
+        bus.write_byte(0xFF4C, 0x80);
+        assert!(bus.gpu.cgb_mode);
+
+        // the cpu sees the bank selected by VBK, the tile map stays in bank 0
+        bus.write_byte(0xFF4F, 0x01);
+        assert_eq!(bus.read_byte(0xFF4F), 0xFF);
+        bus.write_byte(VRAM_BEGIN + 0x1800, BG_ATTRIBUTE_PRIORITY);
+        assert_eq!(bus.read_byte(VRAM_BEGIN + 0x1800), BG_ATTRIBUTE_PRIORITY);
+        assert_eq!(bus.gpu.vram_bank_1[0x1800], BG_ATTRIBUTE_PRIORITY);
+        bus.write_byte(0xFF4F, 0x00);
+        assert_eq!(bus.read_byte(0xFF4F), 0xFE);
+        assert_eq!(bus.read_byte(VRAM_BEGIN + 0x1800), 0x12);
+
+        // KEY0 is locked once the boot rom is disabled
+        bus.write_byte(0xFF50, 0x01);
+        bus.write_byte(0xFF4C, 0x04);
+        assert!(bus.gpu.cgb_mode);
+
+        // the DMG compatibility mode selected by the boot rom
+        let mut bus = Bus::new(Cartridge::new(&rom));
+        bus.load_bootrom(&[0x00; BOOT_ROM_SIZE as usize]);
+        bus.write_byte(0xFF4C, 0x04);
+        assert!(!bus.gpu.cgb_mode);
     }
 
     #[test]
@@ -353,19 +992,19 @@ mod peripheral_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
         let address = 0xC000;
         // init data
-        peripheral.write(address, 0xAA);
-        peripheral.write(address + 0x007F, 0xAA);
-        peripheral.write(address + 0x009F, 0x55);
+        peripheral.write_byte(address, 0xAA);
+        peripheral.write_byte(address + 0x007F, 0xAA);
+        peripheral.write_byte(address + 0x009F, 0x55);
 
         // set dma
-        peripheral.write(0xFF46, (address >> 8) as u8);
+        peripheral.write_byte(0xFF46, (address >> 8) as u8);
 
-        // run peripheral for 160 cycles
+        // run peripheral for 160 machine cycles
         for _ in 0..OAM_SIZE {
-            peripheral.run(1);
+            peripheral.run(CLOCK_TICK_PER_MACHINE_CYCLE);
         }
 
         // check oam memory
@@ -373,4 +1012,153 @@ mod peripheral_tests {
         assert_eq!(peripheral.gpu.read_oam(0x7F), 0xAA);
         assert_eq!(peripheral.gpu.read_oam(0x9F), 0x55);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_hram_access_during_dma() {
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        rom[0x0150] = 0x00;
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
+        peripheral.write_byte(WORKING_RAM_BEGIN, 0xAA);
+        peripheral.write_byte(ZERO_PAGE_BEGIN, 0x55);
+
+        // start the dma from the working ram
+        peripheral.write_byte(0xFF46, (WORKING_RAM_BEGIN >> 8) as u8);
+        peripheral.run(CLOCK_TICK_PER_MACHINE_CYCLE);
+
+        // the high ram, the I/O registers and IE are accessible
+        assert_eq!(peripheral.read_byte(ZERO_PAGE_BEGIN), 0x55);
+        peripheral.write_byte(ZERO_PAGE_BEGIN + 1, 0x66);
+        assert_eq!(peripheral.read_byte(ZERO_PAGE_BEGIN + 1), 0x66);
+        peripheral.write_byte(0xFF42, 0x12);
+        assert_eq!(peripheral.read_byte(0xFF42), 0x12);
+        peripheral.write_byte(INTERRUPT_ENABLE_REGISTER, 0x01);
+        assert_eq!(peripheral.read_byte(INTERRUPT_ENABLE_REGISTER), 0x01);
+
+        // so is the VRAM, the DMA reads the external bus
+        peripheral.write_byte(VRAM_BEGIN, 0x77);
+        assert_eq!(peripheral.read_byte(VRAM_BEGIN), 0x77);
+
+        // the external bus and the OAM are blocked
+        assert_eq!(peripheral.read_byte(0x0150), 0xFF);
+        assert_eq!(peripheral.read_byte(WORKING_RAM_BEGIN), 0xFF);
+        assert_eq!(peripheral.read_byte(OAM_BEGIN), 0xFF);
+        peripheral.write_byte(WORKING_RAM_BEGIN, 0x11);
+
+        // the debugger still sees the memory
+        assert_eq!(peripheral.peek_byte(0x0150), 0x00);
+        assert_eq!(peripheral.peek_byte(WORKING_RAM_BEGIN), 0xAA);
+
+        // the dma still reads its source
+        for _ in 1..OAM_SIZE {
+            peripheral.run(CLOCK_TICK_PER_MACHINE_CYCLE);
+        }
+        assert_eq!(peripheral.gpu.read_oam(0x00), 0xAA);
+
+        // everything is accessible again, the write during the dma was ignored
+        assert_eq!(peripheral.read_byte(0x0150), 0x00);
+        assert_eq!(peripheral.read_byte(WORKING_RAM_BEGIN), 0xAA);
+    }
+
+    #[test]
+    fn test_vram_access_during_dma() {
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
+        peripheral.write_byte(WORKING_RAM_BEGIN, 0xAA);
+        peripheral.write_byte(VRAM_BEGIN, 0x55);
+
+        // start the dma from the VRAM, only its bus is blocked
+        peripheral.write_byte(0xFF46, (VRAM_BEGIN >> 8) as u8);
+        peripheral.run(CLOCK_TICK_PER_MACHINE_CYCLE);
+
+        assert_eq!(peripheral.read_byte(VRAM_BEGIN), 0xFF);
+        assert_eq!(peripheral.read_byte(WORKING_RAM_BEGIN), 0xAA);
+        assert_eq!(peripheral.peek_byte(VRAM_BEGIN), 0x55);
+    }
+
+    #[test]
+    fn test_oam_dma_during_rendering() {
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+
+        for accuracy in [PpuAccuracy::Fast, PpuAccuracy::Accurate] {
+            let mut peripheral = Bus::new(Cartridge::new(&rom));
+            peripheral.gpu.accuracy = accuracy;
+            // black 8x8 sprite on the lines 2 to 9, the other OAM entries are out of the screen
+            peripheral.gpu.load_vram(0x0000, &[0xFF; 16]);
+            for (offset, data) in [18, 8, 0, 0].iter().enumerate() {
+                peripheral.write_byte(WORKING_RAM_BEGIN + offset as u16, *data);
+            }
+
+            // LCD and sprites on, the background is off
+            peripheral.write_byte(0xFF40, 0x82);
+            while !(peripheral.gpu.current_line == 2 && peripheral.gpu.mode == GpuMode::OAMScan) {
+                peripheral.run(CLOCK_TICK_PER_MACHINE_CYCLE);
+            }
+
+            // the transfer lasts 160 machine cycles, until the OAM scan of line 3 is over
+            peripheral.write_byte(0xFF46, (WORKING_RAM_BEGIN >> 8) as u8);
+            for _ in 1..OAM_SIZE {
+                peripheral.run(CLOCK_TICK_PER_MACHINE_CYCLE);
+            }
+            assert_eq!(peripheral.read_byte(WORKING_RAM_BEGIN), 0xFF);
+            assert_eq!(peripheral.gpu.current_line, 3);
+            peripheral.run(CLOCK_TICK_PER_MACHINE_CYCLE);
+            assert_eq!(peripheral.read_byte(WORKING_RAM_BEGIN), 18);
+
+            while peripheral.gpu.current_line != 12 {
+                peripheral.run(CLOCK_TICK_PER_MACHINE_CYCLE);
+            }
+
+            // the lines scanned during the transfer find no sprite, the next ones find the new one
+            let pixel = |line: usize| peripheral.gpu.frame_buffer[line * SCREEN_WIDTH];
+            assert_eq!(pixel(2), u8::from(PixelColor::WHITE));
+            assert_eq!(pixel(3), u8::from(PixelColor::WHITE));
+            assert!((4..10).all(|line| pixel(line) == u8::from(PixelColor::BLACK)));
+            assert_eq!(pixel(10), u8::from(PixelColor::WHITE));
+        }
+    }
+
+    #[test]
+    fn test_fill_memory() {
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        let mut peripheral = Bus::new(Cartridge::new(&rom));
+
+        peripheral.fill_memory(MemoryFill::Zero);
+        assert_eq!(peripheral.read_byte(WORKING_RAM_BEGIN), 0x00);
+        assert_eq!(peripheral.read_byte(WORKING_RAM_END), 0x00);
+        assert_eq!(peripheral.read_byte(ZERO_PAGE_END), 0x00);
+        assert_eq!(peripheral.read_byte(VRAM_BEGIN), 0x00);
+
+        peripheral.fill_memory(MemoryFill::Ones);
+        assert_eq!(peripheral.read_byte(WORKING_RAM_BEGIN), 0xFF);
+        assert_eq!(peripheral.read_byte(WORKING_RAM_END), 0xFF);
+        assert_eq!(peripheral.read_byte(ZERO_PAGE_END), 0xFF);
+        assert_eq!(peripheral.read_byte(VRAM_BEGIN), 0xFF);
+
+        // the random pattern only depends on the seed
+        peripheral.fill_memory(MemoryFill::Random(42));
+        let wram: Vec<u8> = (WORKING_RAM_BEGIN..=WORKING_RAM_END).map(|address| peripheral.read_byte(address)).collect();
+        let vram: Vec<u8> = (VRAM_BEGIN..=VRAM_END).map(|address| peripheral.read_byte(address)).collect();
+        assert!(wram.iter().any(|byte| *byte != wram[0]));
+        assert_ne!(wram[..0x100], vram[..0x100]);
+
+        peripheral.fill_memory(MemoryFill::Zero);
+        peripheral.fill_memory(MemoryFill::Random(42));
+        assert!((WORKING_RAM_BEGIN..=WORKING_RAM_END).all(|address| peripheral.read_byte(address) == wram[(address - WORKING_RAM_BEGIN) as usize]));
+        assert!((VRAM_BEGIN..=VRAM_END).all(|address| peripheral.read_byte(address) == vram[(address - VRAM_BEGIN) as usize]));
+
+        peripheral.fill_memory(MemoryFill::Random(43));
+        assert!((WORKING_RAM_BEGIN..=WORKING_RAM_END).any(|address| peripheral.read_byte(address) != wram[(address - WORKING_RAM_BEGIN) as usize]));
+    }
+}
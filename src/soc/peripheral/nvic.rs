@@ -1,3 +1,7 @@
+use std::io::{Read, Result, Write};
+
+use crate::savestate::{SaveState, read_bool, read_u8, write_bool, write_u8};
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum InterruptSources {
     VBLANK,
@@ -10,6 +14,9 @@ pub enum InterruptSources {
 const FIRST_INTERRUPT_SOURCE: u8 = InterruptSources::VBLANK as u8;
 const LAST_INTERRUPT_SOURCE: u8 = InterruptSources::JOYPAD as u8;
 
+// IE and IF bits of the 5 interrupt sources, the upper bits never request an interrupt
+const INTERRUPT_SOURCES_MASK: u8 = 0x1F;
+
 pub struct Nvic {
     pub interrupt_master_enable: bool,
     pub interrupt_enable: u8,
@@ -79,19 +86,16 @@ impl Nvic {
     }
 
     pub fn is_an_interrupt_pending(&self) -> bool {
-        if (self.interrupt_enable & self.interrupt_flag) != 0 {
-            true
-        } else {
-            false
-        }
+        (self.interrupt_enable & self.interrupt_flag & INTERRUPT_SOURCES_MASK) != 0
     }
 
     pub fn set_it_enable(&mut self, data: u8) {
         self.interrupt_enable = data;
     }
 
+    // the 8 bits of IE are stored, even the unused ones
     pub fn get_it_enable(&self) -> u8 {
-        0b11100000 | self.interrupt_enable
+        self.interrupt_enable
     }
 
     pub fn set_it_flag(&mut self, data: u8) {
@@ -103,6 +107,21 @@ impl Nvic {
     }
 }
 
+impl SaveState for Nvic {
+    fn save_state(&self, writer: &mut dyn Write) -> Result<()> {
+        write_bool(writer, self.interrupt_master_enable)?;
+        write_u8(writer, self.interrupt_enable)?;
+        write_u8(writer, self.interrupt_flag)
+    }
+
+    fn load_state(&mut self, reader: &mut dyn Read) -> Result<()> {
+        self.interrupt_master_enable = read_bool(reader)?;
+        self.interrupt_enable = read_u8(reader)?;
+        self.interrupt_flag = read_u8(reader)?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod nvic_tests {
     use super::*;
@@ -255,6 +274,10 @@ mod nvic_tests {
         let mut nvic = Nvic::new();
 
         nvic.set_it_enable(0b00001100);
-        assert_eq!(nvic.get_it_enable(), 0b11101100);
+        assert_eq!(nvic.get_it_enable(), 0b00001100);
+
+        // the unused bits are stored too
+        nvic.set_it_enable(0b11100001);
+        assert_eq!(nvic.get_it_enable(), 0b11100001);
     }
 }
\ No newline at end of file
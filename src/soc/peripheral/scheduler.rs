@@ -0,0 +1,103 @@
+// events the peripherals wait for, due after a number of clock ticks. The bus dispatches them
+// once they're due instead of each peripheral counting the ticks until its next event
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[allow(non_camel_case_types)]
+pub enum Event {
+    // the link port shifts the next bit of the running transfer
+    SERIAL_BIT,
+}
+
+pub struct Scheduler {
+    // clock ticks run since power on
+    cycles: u64,
+    // pending events with their due cycle, sorted by due cycle then by scheduling order
+    events: Vec<(u64, Event)>,
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler {
+            cycles: 0,
+            events: Vec::new(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // the event is due at the given cycle, the events due at the same cycle fire in the order
+    // they were scheduled
+    pub fn schedule_at(&mut self, cycle: u64, event: Event) {
+        let index = self.events.partition_point(|(due_cycle, _)| *due_cycle <= cycle);
+        self.events.insert(index, (cycle, event));
+    }
+
+    pub fn schedule(&mut self, delay: u64, event: Event) {
+        self.schedule_at(self.cycles + delay, event);
+    }
+
+    pub fn cancel(&mut self, event: Event) {
+        self.events.retain(|(_, pending_event)| *pending_event != event);
+    }
+
+    // clock ticks left until the event is due, none if it isn't scheduled
+    pub fn remaining_cycles(&self, event: Event) -> Option<u64> {
+        self.events
+            .iter()
+            .find(|(_, pending_event)| *pending_event == event)
+            .map(|(due_cycle, _)| due_cycle.saturating_sub(self.cycles))
+    }
+
+    pub fn advance(&mut self, cycles: u8) {
+        self.cycles += cycles as u64;
+    }
+
+    // the next due event and its due cycle, to be called until none is left once the scheduler
+    // has advanced. The events scheduled meanwhile fire too if they're already due
+    pub fn pop_due(&mut self) -> Option<(u64, Event)> {
+        match self.events.first() {
+            Some((due_cycle, _)) if *due_cycle <= self.cycles => Some(self.events.remove(0)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod scheduler_tests {
+    use super::*;
+
+    // the events fired while advancing the scheduler, with the cycle they fired at
+    fn run(scheduler: &mut Scheduler, cycles: u64) -> Vec<(u64, u64, Event)> {
+        let mut fired_events = Vec::new();
+        for _ in 0..cycles / 4 {
+            scheduler.advance(4);
+            while let Some((due_cycle, event)) = scheduler.pop_due() {
+                fired_events.push((scheduler.cycles(), due_cycle, event));
+            }
+        }
+        fired_events
+    }
+
+    #[test]
+    fn test_scheduler() {
+        let mut scheduler = Scheduler::new();
+        scheduler.advance(102);
+
+        // the events fire in due order, at the first step reaching their due cycle
+        scheduler.schedule(512, Event::SERIAL_BIT);
+        scheduler.schedule_at(200, Event::SERIAL_BIT);
+        assert_eq!(scheduler.remaining_cycles(Event::SERIAL_BIT), Some(98));
+        assert_eq!(run(&mut scheduler, 96), []);
+        assert_eq!(run(&mut scheduler, 8), [(202, 200, Event::SERIAL_BIT)]);
+        assert_eq!(scheduler.remaining_cycles(Event::SERIAL_BIT), Some(408));
+        assert_eq!(run(&mut scheduler, 1000), [(614, 614, Event::SERIAL_BIT)]);
+        assert_eq!(scheduler.remaining_cycles(Event::SERIAL_BIT), None);
+
+        // a cancelled event never fires
+        scheduler.schedule(8, Event::SERIAL_BIT);
+        scheduler.cancel(Event::SERIAL_BIT);
+        assert_eq!(run(&mut scheduler, 100), []);
+    }
+}
@@ -0,0 +1,167 @@
+use crate::soc::peripheral::nvic::{Nvic, InterruptSources};
+use crate::savestate::{read_u8, read_u16, write_u8, write_u16};
+
+use std::io::{Read, Result, Write};
+
+// SC bits, the other ones are unused
+const TRANSFER_START: u8 = 0x80;
+const INTERNAL_CLOCK: u8 = 0x01;
+const CONTROL_BITS: u8 = TRANSFER_START | INTERNAL_CLOCK;
+
+// the internal clock shifts the bits at 8192 Hz
+pub const CYCLES_PER_BIT: u16 = 512;
+const BITS_PER_TRANSFER: u8 = 8;
+
+// link port without a connected Game Boy: the bits received are all 1
+// a transfer on the external clock never ends since no other Game Boy drives the clock
+// the bus schedules the shift of each bit while a transfer runs on the internal clock
+pub struct Serial {
+    // SB register, shifted out from its msb while the received bits are shifted in
+    pub data: u8,
+    // SC register
+    pub control: u8,
+    shifted_bits: u8,
+    // bytes sent on the internal clock, kept for the host until it takes them, disabled when None
+    // the test roms print their results this way
+    pub output: Option<Vec<u8>>,
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            data: 0,
+            control: 0,
+            shifted_bits: 0,
+            output: None,
+        }
+    }
+
+    // a transfer is running on the internal clock, its bits are shifted every CYCLES_PER_BIT
+    pub fn is_shifting(&self) -> bool {
+        self.control & CONTROL_BITS == CONTROL_BITS
+    }
+
+    // exchange the next bit of the transfer, true while bits are left to shift
+    pub fn shift_bit(&mut self, nvic: &mut Nvic) -> bool {
+        self.data = (self.data << 1) | 0x01;
+        self.shifted_bits += 1;
+
+        // the transfer is done once the 8 bits are exchanged
+        if self.shifted_bits == BITS_PER_TRANSFER {
+            self.control &= !TRANSFER_START;
+            nvic.set_interrupt(InterruptSources::SERIAL);
+            return false;
+        }
+
+        true
+    }
+
+    pub fn set_data(&mut self, data: u8) {
+        self.data = data;
+    }
+
+    pub fn get_data(&self) -> u8 {
+        self.data
+    }
+
+    // setting the start bit starts a new transfer
+    pub fn set_control(&mut self, data: u8) {
+        self.control = data & CONTROL_BITS;
+
+        if self.control & TRANSFER_START != 0 {
+            self.shifted_bits = 0;
+
+            // the byte is sent when no other Game Boy drives the clock
+            if self.control & INTERNAL_CLOCK != 0 {
+                if let Some(output) = self.output.as_mut() {
+                    output.push(self.data);
+                }
+            }
+        }
+    }
+
+    pub fn get_control(&self) -> u8 {
+        self.control
+    }
+}
+
+// the clock ticks since the last shifted bit are known by the scheduler of the bus, they're
+// given to save the state and returned once loaded to schedule the next bit
+impl Serial {
+    pub fn save_state(&self, writer: &mut dyn Write, transfer_cycles: u16) -> Result<()> {
+        write_u8(writer, self.data)?;
+        write_u8(writer, self.control)?;
+        write_u16(writer, transfer_cycles)?;
+        write_u8(writer, self.shifted_bits)
+    }
+
+    pub fn load_state(&mut self, reader: &mut dyn Read) -> Result<u16> {
+        self.data = read_u8(reader)?;
+        self.control = read_u8(reader)?;
+        let transfer_cycles = read_u16(reader)?;
+        self.shifted_bits = read_u8(reader)?;
+        Ok(transfer_cycles)
+    }
+}
+
+#[cfg(test)]
+mod serial_tests {
+    use super::*;
+
+    #[test]
+    fn test_internal_clock_transfer() {
+        let mut serial = Serial::new();
+        let mut nvic = Nvic::new();
+
+        serial.set_data(0x42);
+        serial.set_control(TRANSFER_START | INTERNAL_CLOCK);
+
+        assert!(serial.is_shifting());
+
+        // 7 bits are shifted, the transfer is still running
+        for _ in 0..7 {
+            assert!(serial.shift_bit(&mut nvic));
+        }
+        assert_eq!(serial.get_data(), 0x7F);
+        assert_eq!(serial.get_control(), TRANSFER_START | INTERNAL_CLOCK);
+        assert_eq!(nvic.get_it_flag() & 0x08, 0x00);
+
+        // the last bit ends the transfer with an interrupt
+        assert!(!serial.shift_bit(&mut nvic));
+        assert!(!serial.is_shifting());
+        assert_eq!(serial.get_data(), 0xFF);
+        assert_eq!(serial.get_control(), INTERNAL_CLOCK);
+        assert_eq!(nvic.get_it_flag() & 0x08, 0x08);
+    }
+
+    #[test]
+    fn test_external_clock_transfer() {
+        let mut serial = Serial::new();
+
+        // no other Game Boy drives the clock, no bit is shifted
+        serial.set_data(0x42);
+        serial.set_control(TRANSFER_START);
+        assert!(!serial.is_shifting());
+        assert_eq!(serial.get_data(), 0x42);
+        assert_eq!(serial.get_control(), TRANSFER_START);
+    }
+
+    #[test]
+    fn test_output() {
+        let mut serial = Serial::new();
+
+        // nothing is kept until the output is enabled
+        serial.set_data(0x41);
+        serial.set_control(TRANSFER_START | INTERNAL_CLOCK);
+        assert_eq!(serial.output, None);
+
+        // only the bytes sent on the internal clock are kept
+        serial.output = Some(Vec::new());
+        serial.set_data(0x42);
+        serial.set_control(TRANSFER_START | INTERNAL_CLOCK);
+        serial.set_data(0x43);
+        serial.set_control(TRANSFER_START);
+        serial.set_control(INTERNAL_CLOCK);
+        assert_eq!(serial.output, Some(vec![0x42]));
+    }
+}
@@ -1,5 +1,8 @@
 use crate::soc::peripheral::nvic::{Nvic, InterruptSources};
 use crate::soc::CLOCK_TICK_PER_MACHINE_CYCLE;
+use crate::savestate::{SaveState, invalid_data, read_bool, read_u8, read_u16, read_u64, write_bool, write_u8, write_u16, write_u64};
+
+use std::io::{Read, Result, Write};
 
 pub enum Frequency {
     F4096,
@@ -9,31 +12,48 @@ pub enum Frequency {
 }
 
 impl Frequency {
-    // The number of CPU cycles that occur per tick of the clock.
-    // This is equal to the number of cpu cycles per second (4194304)
-    // divided by the timer frequency.
-    fn cycles_per_tick(&self) -> usize {
+    // The bit of the internal counter selected by TAC, TIMA is incremented
+    // on its falling edge, i.e. every (cpu cycles per second / frequency) clocks.
+    fn counter_mask(&self) -> u16 {
         match self {
-            Frequency::F4096 => 1024,
-            Frequency::F16384 => 256,
-            Frequency::F262144 => 16,
-            Frequency::F65536 => 64,
+            Frequency::F4096 => 1 << 9,
+            Frequency::F16384 => 1 << 7,
+            Frequency::F262144 => 1 << 3,
+            Frequency::F65536 => 1 << 5,
+        }
+    }
+
+    fn save_state(&self, writer: &mut dyn Write) -> Result<()> {
+        write_u8(writer, match self {
+            Frequency::F4096 => 0,
+            Frequency::F16384 => 1,
+            Frequency::F262144 => 2,
+            Frequency::F65536 => 3,
+        })
+    }
+
+    fn load_state(reader: &mut dyn Read) -> Result<Frequency> {
+        match read_u8(reader)? {
+            0 => Ok(Frequency::F4096),
+            1 => Ok(Frequency::F16384),
+            2 => Ok(Frequency::F262144),
+            3 => Ok(Frequency::F65536),
+            frequency => Err(invalid_data(&format!("invalid timer frequency {}", frequency))),
         }
     }
 }
 
 pub struct Timer {
     // internal parameters
-    main_timer_cycles: usize,
-    divider_timer_cycles: usize,
+    overflow_cycles: usize,
     tima_overflow: bool,
-    // DIV / TIMA / TMA registers
-    pub divider: u8,
+    // internal counter incremented at each clock, DIV is its upper byte
+    pub system_counter: u16,
+    // TIMA / TMA registers
     pub value: u8,
     pub modulo: u8,
     // TAC registers values
     pub main_timer_frequency: Frequency,
-    pub divider_timer_frequency: Frequency,
     pub enabled: bool,
 }
 
@@ -41,83 +61,69 @@ impl Timer {
     pub fn new() -> Timer {
         Timer {
             // internal parameters
-            main_timer_cycles: 0,
-            divider_timer_cycles: 0,
+            overflow_cycles: 0,
             tima_overflow: false,
-            // DIV / TIMA / TMA registers
-            divider: 0,
+            system_counter: 0,
+            // TIMA / TMA registers
             value: 0,
             modulo: 0,
             // TAC registers values
             main_timer_frequency: Frequency::F4096,
-            divider_timer_frequency: Frequency::F16384,
             enabled: false,
         }
     }
 
     pub fn run(&mut self, cycles: u8, nvic: &mut Nvic) {
-        
-        // update internal divider timer clock
-        self.divider_timer_cycles += cycles as usize;
-        // check if the divider timer reached its maximum value
-        let divider_cycles_per_tick = self.divider_timer_frequency.cycles_per_tick();
-        if self.divider_timer_cycles > divider_cycles_per_tick {
-            let add_divider = (self.divider_timer_cycles / divider_cycles_per_tick) as u8;
-            self.divider_timer_cycles = self.divider_timer_cycles % divider_cycles_per_tick;
-
-            // check if the main timer reached its maximum value
-            let (new_divider, _overflow) = self.divider.overflowing_add(add_divider);
-            self.divider = new_divider;
-        } 
-
-        if self.enabled {
-            // update internal main timer clock
-            self.main_timer_cycles += cycles as usize;
+        for _ in 0..cycles {
+            // DIV ticks at 16384 Hz as it's the upper byte of the counter
+            let signal = self.timer_signal();
+            self.system_counter = self.system_counter.wrapping_add(1);
 
             // delay interrupt by 1 machine cycle / 4 clocks
-            if self.tima_overflow && self.has_timer_passed_1_cycle() {
-                self.tima_overflow = false;
-                nvic.set_interrupt(InterruptSources::TIMER);
-                self.value = self.modulo;
-            }
-
-            // divide the main cpu clock
-            let main_cycles_per_tick = self.main_timer_frequency.cycles_per_tick();
-            if self.main_timer_cycles > main_cycles_per_tick {
-                let add_timer = (self.main_timer_cycles / main_cycles_per_tick) as u8;
-                self.main_timer_cycles = self.main_timer_cycles % main_cycles_per_tick;
-
-                // check if the main timer reached its maximum value
-                let (new_value, overflow) = self.value.overflowing_add(add_timer);
-                self.value = new_value;
-
-                // register overflow for next cycle if any
-                // see https://gbdev.io/pandocs/Timer_Obscure_Behaviour.html 
-                if overflow {
-                    self.tima_overflow = true;
-                    self.value = 0;
-                }
-
-                // delay interrupt by 1 machine cycle / 4 clocks
-                if self.tima_overflow && self.has_timer_passed_1_cycle() {
+            // see https://gbdev.io/pandocs/Timer_Obscure_Behaviour.html
+            if self.tima_overflow {
+                self.overflow_cycles += 1;
+                if self.overflow_cycles >= CLOCK_TICK_PER_MACHINE_CYCLE as usize {
                     self.tima_overflow = false;
                     nvic.set_interrupt(InterruptSources::TIMER);
                     self.value = self.modulo;
                 }
             }
+
+            if signal && !self.timer_signal() {
+                self.increment();
+            }
         }
     }
 
-    pub fn has_timer_passed_1_cycle(&self) -> bool {
-        self.main_timer_cycles / CLOCK_TICK_PER_MACHINE_CYCLE as usize > 0
+    // the selected counter bit ANDed with the timer enable bit
+    fn timer_signal(&self) -> bool {
+        self.enabled && (self.system_counter & self.main_timer_frequency.counter_mask()) != 0
     }
 
+    fn increment(&mut self) {
+        let (new_value, overflow) = self.value.overflowing_add(1);
+        self.value = new_value;
+
+        // register overflow, TMA is reloaded and the interrupt raised 1 machine cycle later
+        if overflow {
+            self.tima_overflow = true;
+            self.overflow_cycles = 0;
+        }
+    }
+
+    // any write resets the whole internal counter, which increments TIMA
+    // if the selected bit was set
     pub fn set_divider(&mut self) {
-        self.divider = 0;
+        let signal = self.timer_signal();
+        self.system_counter = 0;
+        if signal {
+            self.increment();
+        }
     }
 
     pub fn get_divider(&self) -> u8 {
-        self.divider
+        (self.system_counter >> 8) as u8
     }
 
     pub fn set_value(&mut self, data: u8) {
@@ -137,6 +143,8 @@ impl Timer {
     }
 
     pub fn settings_from_byte(&mut self, data: u8) {
+        let signal = self.timer_signal();
+
         // timer enable
         self.enabled = ((data >> 2) & 0x01) != 0;
 
@@ -144,9 +152,48 @@ impl Timer {
         self.main_timer_frequency = match data & 0x03 {
             0x00 => Frequency::F4096,
             0x01 => Frequency::F262144,
-            0x10 => Frequency::F65536,
+            0x02 => Frequency::F65536,
             _ => Frequency::F16384,
         };
+
+        // disabling the timer or selecting a cleared bit is a falling edge too
+        if signal && !self.timer_signal() {
+            self.increment();
+        }
+    }
+
+    pub fn settings_to_byte(&self) -> u8 {
+        let frequency_bits = match self.main_timer_frequency {
+            Frequency::F4096 => 0x00,
+            Frequency::F262144 => 0x01,
+            Frequency::F65536 => 0x02,
+            Frequency::F16384 => 0x03,
+        };
+
+        ((self.enabled as u8) << 2) | frequency_bits
+    }
+}
+
+impl SaveState for Timer {
+    fn save_state(&self, writer: &mut dyn Write) -> Result<()> {
+        write_u64(writer, self.overflow_cycles as u64)?;
+        write_bool(writer, self.tima_overflow)?;
+        write_u16(writer, self.system_counter)?;
+        write_u8(writer, self.value)?;
+        write_u8(writer, self.modulo)?;
+        self.main_timer_frequency.save_state(writer)?;
+        write_bool(writer, self.enabled)
+    }
+
+    fn load_state(&mut self, reader: &mut dyn Read) -> Result<()> {
+        self.overflow_cycles = read_u64(reader)? as usize;
+        self.tima_overflow = read_bool(reader)?;
+        self.system_counter = read_u16(reader)?;
+        self.value = read_u8(reader)?;
+        self.modulo = read_u8(reader)?;
+        self.main_timer_frequency = Frequency::load_state(reader)?;
+        self.enabled = read_bool(reader)?;
+        Ok(())
     }
 }
 
@@ -198,4 +245,102 @@ mod timer_tests {
 
         assert_eq!(timer.value, 0xF5);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_divider() {
+        let mut timer = Timer::new();
+        let mut nvic = Nvic::new();
+
+        // DIV is incremented every 256 clocks
+        for _ in 0..63 {
+            timer.run(CLOCK_TICK_PER_MACHINE_CYCLE, &mut nvic);
+        }
+        assert_eq!(timer.get_divider(), 0x00);
+        timer.run(CLOCK_TICK_PER_MACHINE_CYCLE, &mut nvic);
+        assert_eq!(timer.get_divider(), 0x01);
+
+        // 1000 machine cycles later, the lower byte of the counter is kept between ticks
+        for _ in 0..1000 {
+            timer.run(CLOCK_TICK_PER_MACHINE_CYCLE, &mut nvic);
+        }
+        assert_eq!(timer.system_counter, 4256);
+        assert_eq!(timer.get_divider(), 0x10);
+
+        // the counter wraps around and a write resets it
+        timer.system_counter = 0xFFFC;
+        timer.run(CLOCK_TICK_PER_MACHINE_CYCLE, &mut nvic);
+        assert_eq!(timer.get_divider(), 0x00);
+        timer.run(0xFF, &mut nvic);
+        timer.set_divider();
+        assert_eq!(timer.system_counter, 0);
+    }
+
+    #[test]
+    fn test_divider_write_falling_edge() {
+        let mut timer = Timer::new();
+        let mut nvic = Nvic::new();
+
+        // 262144 Hz selects bit 3 of the counter
+        timer.settings_from_byte(0x05);
+        timer.run(8, &mut nvic);
+        assert_eq!(timer.value, 0);
+        assert_ne!(timer.system_counter & 0x08, 0);
+
+        // resetting the counter while the selected bit is high increments TIMA
+        timer.set_divider();
+        assert_eq!(timer.value, 1);
+
+        // but not while it's low
+        timer.run(4, &mut nvic);
+        timer.set_divider();
+        assert_eq!(timer.value, 1);
+
+        // the next increment comes a full period after the reset
+        timer.run(15, &mut nvic);
+        assert_eq!(timer.value, 1);
+        timer.run(1, &mut nvic);
+        assert_eq!(timer.value, 2);
+    }
+
+    #[test]
+    fn test_settings_falling_edge() {
+        let mut timer = Timer::new();
+        let mut nvic = Nvic::new();
+
+        // bit 3 is set, bit 9 isn't: changing the frequency is a falling edge
+        timer.settings_from_byte(0x05);
+        timer.run(8, &mut nvic);
+        timer.settings_from_byte(0x04);
+        assert_eq!(timer.value, 1);
+
+        // so is disabling the timer while the selected bit is set
+        timer.settings_from_byte(0x05);
+        timer.settings_from_byte(0x01);
+        assert_eq!(timer.value, 2);
+    }
+
+    #[test]
+    fn test_settings() {
+        let mut timer = Timer::new();
+        let mut nvic = Nvic::new();
+
+        // the 2 lower bits select the frequency, bit 2 enables the timer
+        for (data, clocks) in [(0x04, 1024), (0x05, 16), (0x06, 64), (0x07, 256)] {
+            timer.settings_from_byte(data);
+            assert_eq!(timer.settings_to_byte(), data);
+
+            timer.set_divider();
+            timer.set_value(0);
+            for _ in 1..clocks {
+                timer.run(1, &mut nvic);
+            }
+            assert_eq!(timer.get_value(), 0, "TAC {:#04x}", data);
+            timer.run(1, &mut nvic);
+            assert_eq!(timer.get_value(), 1, "TAC {:#04x}", data);
+        }
+
+        timer.settings_from_byte(0x02);
+        assert!(!timer.enabled);
+        assert_eq!(timer.settings_to_byte(), 0x02);
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::emulator::{ONE_FRAME_IN_CYCLES, ONE_SECOND_IN_CYCLES};
+
+// number of frames used to compute the rolling statistics
+const NB_FRAMES_IN_WINDOW: usize = 60;
+
+pub struct FrameStats {
+    // wall clock duration of the last frames
+    frame_intervals: VecDeque<Duration>,
+}
+
+impl FrameStats {
+    pub fn new() -> FrameStats {
+        FrameStats {
+            frame_intervals: VecDeque::with_capacity(NB_FRAMES_IN_WINDOW),
+        }
+    }
+
+    pub fn record_frame(&mut self, interval: Duration) {
+        if self.frame_intervals.len() == NB_FRAMES_IN_WINDOW {
+            self.frame_intervals.pop_front();
+        }
+
+        self.frame_intervals.push_back(interval);
+    }
+
+    // emulated frames per second, skipped ones included, 0 until a frame has been recorded
+    pub fn fps(&self) -> f64 {
+        let elapsed: Duration = self.frame_intervals.iter().sum();
+
+        if elapsed.as_secs_f64() > 0.0 {
+            self.frame_intervals.len() as f64 / elapsed.as_secs_f64()
+        } else {
+            0.0
+        }
+    }
+
+    // emulation speed in percent of the real hardware frame rate (~59.73 fps)
+    pub fn speed(&self) -> f64 {
+        let hardware_fps = ONE_SECOND_IN_CYCLES as f64 / ONE_FRAME_IN_CYCLES as f64;
+
+        self.fps() * 100.0 / hardware_fps
+    }
+}
+
+#[cfg(test)]
+mod stats_tests {
+    use super::*;
+
+    #[test]
+    fn test_no_frame() {
+        let stats = FrameStats::new();
+
+        assert_eq!(stats.fps(), 0.0);
+        assert_eq!(stats.speed(), 0.0);
+    }
+
+    #[test]
+    fn test_fps() {
+        let mut stats = FrameStats::new();
+
+        // 50 fps
+        for _ in 0..10 {
+            stats.record_frame(Duration::from_millis(20));
+        }
+        assert!((stats.fps() - 50.0).abs() < 0.001);
+
+        // full speed
+        let hardware_frame = Duration::from_nanos(1_000_000_000 * ONE_FRAME_IN_CYCLES as u64 / ONE_SECOND_IN_CYCLES as u64);
+        for _ in 0..NB_FRAMES_IN_WINDOW {
+            stats.record_frame(hardware_frame);
+        }
+        assert!((stats.speed() - 100.0).abs() < 0.01);
+
+        // only the last frames are taken into account
+        for _ in 0..NB_FRAMES_IN_WINDOW {
+            stats.record_frame(hardware_frame * 2);
+        }
+        assert!((stats.speed() - 50.0).abs() < 0.01);
+    }
+}
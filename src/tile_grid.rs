@@ -0,0 +1,58 @@
+use crate::overlay::GLYPH_SIZE;
+
+// tiles are 8x8 pixels, like the overlay glyphs
+const TILE_SIZE: usize = GLYPH_SIZE;
+
+// magenta can't be confused with a shade nor with the red viewport of the map view
+pub const GRID_COLOR: u32 = 0xFFFF00FF;
+
+// draw the tile boundaries over host pixels, after the rendering so that neither the
+// emulated frame nor the save states are affected. The grid follows the background scrolled
+// by the viewport offsets, only their fine part shifts it
+pub fn draw_tile_grid(buffer: &mut [u32], width: usize, scx: u8, scy: u8) {
+    let (offset_x, offset_y) = (scx as usize % TILE_SIZE, scy as usize % TILE_SIZE);
+
+    for (index, pixel) in buffer.iter_mut().enumerate() {
+        let (x, y) = (index % width + offset_x, index / width + offset_y);
+
+        if x % TILE_SIZE == 0 || y % TILE_SIZE == 0 {
+            *pixel = GRID_COLOR;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tile_grid_tests {
+    use super::*;
+    use crate::emulator::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    #[test]
+    fn test_draw_tile_grid() {
+        let background = 0xFFFFFFFF;
+        let mut buffer = [background; SCREEN_WIDTH * SCREEN_HEIGHT];
+        draw_tile_grid(&mut buffer, SCREEN_WIDTH, 0, 0);
+
+        // lines are drawn at every 8th pixel, the tiles inside are left untouched
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let expected = if x % 8 == 0 || y % 8 == 0 { GRID_COLOR } else { background };
+                assert_eq!(buffer[y * SCREEN_WIDTH + x], expected, "pixel ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_draw_scrolled_tile_grid() {
+        let background = 0xFFFFFFFF;
+        let mut buffer = [background; SCREEN_WIDTH * SCREEN_HEIGHT];
+        draw_tile_grid(&mut buffer, SCREEN_WIDTH, 0x13, 0x0E);
+
+        // the background tiles start 3 pixels left and 6 pixels above the screen
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let expected = if x % 8 == 5 || y % 8 == 2 { GRID_COLOR } else { background };
+                assert_eq!(buffer[y * SCREEN_WIDTH + x], expected, "pixel ({}, {})", x, y);
+            }
+        }
+    }
+}
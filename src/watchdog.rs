@@ -0,0 +1,146 @@
+use std::fmt;
+
+use crate::emulator::{ONE_FRAME_IN_CYCLES, SCREEN_HEIGHT};
+
+// why the emulated system is considered locked up
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Lockup {
+    // the cpu jumps on itself and no interrupt can get it out of the loop
+    SelfLoop(u16),
+    // the gpu didn't enter the VBlank period for this number of frames
+    NoVblank(u32),
+    // the cpu hangs on an undefined opcode, at this address
+    UndefinedOpcode(u16, u8),
+}
+
+impl fmt::Display for Lockup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Lockup::SelfLoop(pc) => write!(f, "cpu stuck in a self loop at {:#06x}", pc),
+            Lockup::NoVblank(frames) => write!(f, "no VBlank during {} frames", frames),
+            Lockup::UndefinedOpcode(pc, opcode) => write!(f, "cpu hung by the undefined opcode {:#04x} at {:#06x}", opcode, pc),
+        }
+    }
+}
+
+// detect a stuck emulated system during unattended runs
+pub struct Watchdog {
+    // a lockup is reported once it lasts this number of frames
+    timeout_frames: u32,
+    self_loop_cycles: u64,
+    frames_without_vblank: u32,
+    vblank_in_frame: bool,
+    previous_line: u8,
+}
+
+impl Watchdog {
+    pub fn new(timeout_frames: u32) -> Watchdog {
+        Watchdog {
+            timeout_frames,
+            self_loop_cycles: 0,
+            frames_without_vblank: 0,
+            vblank_in_frame: false,
+            previous_line: 0,
+        }
+    }
+
+    // forget the current loop and frames, ie when the emulated system is reset
+    pub fn reset(&mut self) {
+        *self = Watchdog::new(self.timeout_frames);
+    }
+
+    // called after each cpu step with the pc before and after this step
+    // a loop isn't a lockup as long as an interrupt can get the cpu out of it
+    pub fn step(&mut self, previous_pc: u16, pc: u16, can_exit_loop: bool, current_line: u8, cycles: u8) -> Option<Lockup> {
+        if previous_pc == pc && !can_exit_loop {
+            self.self_loop_cycles += cycles as u64;
+        } else {
+            self.self_loop_cycles = 0;
+        }
+
+        if current_line == SCREEN_HEIGHT as u8 && self.previous_line != current_line {
+            self.vblank_in_frame = true;
+        }
+        self.previous_line = current_line;
+
+        if self.self_loop_cycles >= self.timeout_frames as u64 * ONE_FRAME_IN_CYCLES as u64 {
+            Some(Lockup::SelfLoop(pc))
+        } else {
+            None
+        }
+    }
+
+    // the frames run with the LCD off end on their duration without VBlank, they aren't counted
+    pub fn end_of_frame(&mut self, lcd_enabled: bool) -> Option<Lockup> {
+        if self.vblank_in_frame {
+            self.frames_without_vblank = 0;
+        } else if lcd_enabled {
+            self.frames_without_vblank += 1;
+        }
+        self.vblank_in_frame = false;
+
+        if self.frames_without_vblank >= self.timeout_frames {
+            Some(Lockup::NoVblank(self.frames_without_vblank))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod watchdog_tests {
+    use super::*;
+
+    #[test]
+    fn test_self_loop() {
+        let mut watchdog = Watchdog::new(1);
+
+        // a loop which can be interrupted is not a lockup
+        for _ in 0..ONE_FRAME_IN_CYCLES {
+            assert_eq!(watchdog.step(0x0100, 0x0100, true, 0, 12), None);
+        }
+
+        // the cpu moves forward
+        for _ in 0..ONE_FRAME_IN_CYCLES {
+            assert_eq!(watchdog.step(0x0100, 0x0101, false, 0, 4), None);
+        }
+
+        for _ in 0..(ONE_FRAME_IN_CYCLES / 12 - 1) {
+            assert_eq!(watchdog.step(0x0100, 0x0100, false, 0, 12), None);
+        }
+        assert_eq!(watchdog.step(0x0100, 0x0100, false, 0, 12), Some(Lockup::SelfLoop(0x0100)));
+    }
+
+    #[test]
+    fn test_no_vblank() {
+        let mut watchdog = Watchdog::new(3);
+
+        // VBlank entered every frame
+        for _ in 0..10 {
+            watchdog.step(0x0100, 0x0101, false, SCREEN_HEIGHT as u8 - 1, 4);
+            watchdog.step(0x0101, 0x0102, false, SCREEN_HEIGHT as u8, 4);
+            assert_eq!(watchdog.end_of_frame(true), None);
+        }
+
+        // the gpu stays on the same line
+        assert_eq!(watchdog.end_of_frame(true), None);
+        assert_eq!(watchdog.end_of_frame(true), None);
+        assert_eq!(watchdog.end_of_frame(true), Some(Lockup::NoVblank(3)));
+    }
+
+    #[test]
+    fn test_lcd_off() {
+        let mut watchdog = Watchdog::new(3);
+
+        // the game loads the VRAM with the LCD off for longer than the timeout
+        for _ in 0..10 {
+            watchdog.step(0x0100, 0x0101, false, 0, 4);
+            assert_eq!(watchdog.end_of_frame(false), None);
+        }
+
+        // the frames without VBlank are counted again once the LCD is on
+        assert_eq!(watchdog.end_of_frame(true), None);
+        assert_eq!(watchdog.end_of_frame(true), None);
+        assert_eq!(watchdog.end_of_frame(true), Some(Lockup::NoVblank(3)));
+    }
+}
@@ -0,0 +1,150 @@
+use std::fs::File;
+use std::io::{BufWriter, Error, Result, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::audio::AudioSink;
+
+// 16 bits PCM stereo, the mono APU output is written in both channels
+pub const WAV_CHANNELS: u16 = 2;
+const BITS_PER_SAMPLE: u16 = 16;
+const BLOCK_SIZE: u32 = WAV_CHANNELS as u32 * BITS_PER_SAMPLE as u32 / 8;
+
+// the sizes of the RIFF and data chunks are written when the recording is finished
+const RIFF_SIZE_OFFSET: u64 = 4;
+const DATA_SIZE_OFFSET: u64 = 40;
+const HEADER_SIZE: u32 = 44;
+
+// audio sink recording the samples in a WAV file
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    // size in bytes of the samples written
+    data_size: u32,
+    // first write error, the recording is stopped and it's reported when finishing
+    error: Option<Error>,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create(path: &Path, sample_rate: u32) -> Result<WavWriter<BufWriter<File>>> {
+        WavWriter::new(BufWriter::new(File::create(path)?), sample_rate)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W, sample_rate: u32) -> Result<WavWriter<W>> {
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&(HEADER_SIZE - 8).to_le_bytes())?;
+        writer.write_all(b"WAVE")?;
+
+        // format chunk
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?; // PCM
+        writer.write_all(&WAV_CHANNELS.to_le_bytes())?;
+        writer.write_all(&sample_rate.to_le_bytes())?;
+        writer.write_all(&(sample_rate * BLOCK_SIZE).to_le_bytes())?; // bytes per second
+        writer.write_all(&(BLOCK_SIZE as u16).to_le_bytes())?;
+        writer.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+
+        // data chunk, the samples follow
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter {
+            writer,
+            data_size: 0,
+            error: None,
+        })
+    }
+
+    // write the chunk sizes in the header and flush the file
+    pub fn finish(mut self) -> Result<W> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+
+        self.writer.seek(SeekFrom::Start(RIFF_SIZE_OFFSET))?;
+        self.writer.write_all(&(HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(DATA_SIZE_OFFSET))?;
+        self.writer.write_all(&self.data_size.to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+
+    fn write_samples(&mut self, samples: &[f32]) -> Result<()> {
+        for sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            for _ in 0..WAV_CHANNELS {
+                self.writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+
+        self.data_size += samples.len() as u32 * BLOCK_SIZE;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavWriter<W> {
+    fn push_samples(&mut self, samples: &[f32]) {
+        if self.error.is_none() {
+            if let Err(error) = self.write_samples(samples) {
+                self.error = Some(error);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod wav_tests {
+    use super::*;
+    use crate::emulator::ONE_SECOND_IN_CYCLES;
+    use crate::emulator::test_fixtures::looping_emulator;
+    use crate::soc::peripheral::IoAccess;
+    use crate::soc::peripheral::apu::SAMPLE_RATE;
+
+    fn read_u16(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes([data[offset], data[offset + 1]])
+    }
+
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+    }
+
+    #[test]
+    fn test_record_wav() {
+        let mut emulator = looping_emulator();
+        emulator.soc.peripheral.write_byte(0xFF40, 0x80);
+
+        let wav_dir = std::env::temp_dir().join(format!("qoboy_test_record_wav_{}", std::process::id()));
+        std::fs::create_dir_all(&wav_dir).unwrap();
+        let wav_path = wav_dir.join("audio.wav");
+        let mut wav = WavWriter::create(&wav_path, SAMPLE_RATE as u32).unwrap();
+
+        for _ in 0..10 {
+            emulator.run_frame();
+            emulator.output_audio(&mut wav);
+        }
+        wav.finish().unwrap();
+        let nb_samples = emulator.cycles() as usize * SAMPLE_RATE / ONE_SECOND_IN_CYCLES;
+        let data = std::fs::read(&wav_path).unwrap();
+
+        // RIFF header of a 16 bits PCM stereo file at the APU sample rate
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(read_u32(&data, 4) as usize, data.len() - 8);
+        assert_eq!(&data[8..16], b"WAVEfmt ");
+        assert_eq!(read_u16(&data, 20), 1);
+        assert_eq!(read_u16(&data, 22), WAV_CHANNELS);
+        assert_eq!(read_u32(&data, 24), SAMPLE_RATE as u32);
+        assert_eq!(read_u32(&data, 28), SAMPLE_RATE as u32 * 4);
+        assert_eq!(read_u16(&data, 32), 4);
+        assert_eq!(read_u16(&data, 34), 16);
+
+        // each sample is written in both channels
+        assert_eq!(&data[36..40], b"data");
+        assert_eq!(read_u32(&data, 40) as usize, data.len() - HEADER_SIZE as usize);
+        assert_eq!(data.len() - HEADER_SIZE as usize, nb_samples * 4);
+
+        std::fs::remove_dir_all(&wav_dir).unwrap();
+    }
+}